@@ -0,0 +1,145 @@
+use crate::{Repository, Result, config::Config};
+use std::path::Path;
+use std::process::Command;
+use walkdir::WalkDir;
+
+// Authorization for repositories served to other clients. The served
+// repository's config decides who may fetch or push which refs:
+//
+//   [access "refs/heads/main"]
+//       push = <token>
+//       fetch = <token>
+//   [access]
+//       command = /path/to/authorize
+//
+// A ref pattern (`*` matches anything) that lists tokens for an operation
+// only admits clients presenting one of them in MINI_GIT_TOKEN. The optional
+// command runs as `authorize <fetch|push> <ref>` with MINI_GIT_TOKEN and
+// MINI_GIT_REPOSITORY in its environment; a non-zero exit rejects the request.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Fetch,
+    Push,
+}
+
+impl Operation {
+    fn as_str(self) -> &'static str {
+        match self {
+            Operation::Fetch => "fetch",
+            Operation::Push => "push",
+        }
+    }
+}
+
+pub fn authorize(served_git_dir: &Path, operation: Operation, refname: &str) -> Result<()> {
+    let served = Repository {
+        git_dir: served_git_dir.to_path_buf(),
+        work_dir: served_git_dir
+            .parent()
+            .unwrap_or(served_git_dir)
+            .to_path_buf(),
+    };
+    let config = Config::load(&served)?;
+    let token = std::env::var("MINI_GIT_TOKEN").ok();
+
+    for pattern in config.subsections("access") {
+        if !ref_matches(&pattern, refname) {
+            continue;
+        }
+
+        let allowed = config.get_all(&format!("access.{}.{}", pattern, operation.as_str()));
+        if !allowed.is_empty() && !token.as_deref().is_some_and(|t| allowed.contains(&t)) {
+            return Err(denied(operation, refname));
+        }
+    }
+
+    if let Some(command) = config.get("access.command") {
+        let status = Command::new(command)
+            .arg(operation.as_str())
+            .arg(refname)
+            .env("MINI_GIT_TOKEN", token.unwrap_or_default())
+            .env("MINI_GIT_REPOSITORY", &served.work_dir)
+            // Keep stdout clean for `serve --stdio`
+            .stdout(std::io::stderr())
+            .status()
+            .map_err(|e| format!("Failed to run access command '{}': {}", command, e))?;
+        if !status.success() {
+            return Err(denied(operation, refname));
+        }
+    }
+
+    Ok(())
+}
+
+// Check every branch the served repository exposes under `refs_dir`
+pub fn authorize_all_heads(
+    served_git_dir: &Path,
+    refs_dir: &Path,
+    operation: Operation,
+) -> Result<()> {
+    let heads = refs_dir.join("heads");
+    if !heads.is_dir() {
+        return Ok(());
+    }
+
+    for entry in WalkDir::new(&heads)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let relative = entry.path().strip_prefix(&heads)?;
+        let refname = format!(
+            "refs/heads/{}",
+            relative.to_string_lossy().replace('\\', "/")
+        );
+        authorize(served_git_dir, operation, &refname)?;
+    }
+    Ok(())
+}
+
+fn denied(operation: Operation, refname: &str) -> Box<dyn std::error::Error> {
+    format!(
+        "Access denied: {} of {} rejected by remote",
+        operation.as_str(),
+        refname
+    )
+    .into()
+}
+
+fn ref_matches(pattern: &str, refname: &str) -> bool {
+    match pattern.split_once('*') {
+        Some((prefix, suffix)) => {
+            refname.len() >= prefix.len() + suffix.len()
+                && refname.starts_with(prefix)
+                && refname.ends_with(suffix)
+        }
+        None => pattern == refname,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn nested_heads_are_authorized() {
+        let git_dir = std::env::temp_dir().join(format!("mini_git-access-{}", std::process::id()));
+        let refs_dir = git_dir.join("refs");
+        fs::create_dir_all(refs_dir.join("heads/secret")).unwrap();
+        fs::write(refs_dir.join("heads/main"), "0".repeat(40)).unwrap();
+        fs::write(refs_dir.join("heads/secret/plan"), "1".repeat(40)).unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[access \"refs/heads/secret/*\"]\n\tfetch = letmein\n",
+        )
+        .unwrap();
+
+        let result = authorize_all_heads(&git_dir, &refs_dir, Operation::Fetch);
+        fs::remove_dir_all(&git_dir).unwrap();
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("refs/heads/secret/plan"), "{}", error);
+    }
+}
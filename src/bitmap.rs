@@ -0,0 +1,337 @@
+use crate::{
+    Result, delta, graph::ObjectId, hash::HashAlgorithm, index::hex_to_bytes,
+    object_store::ObjectStore, packfile::PackIndex,
+};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Reachability bitmaps: for some of the commits in a pack, the set of every
+// object reachable from them, one bit per object in the pack's index order.
+// A walk that meets such a commit takes its whole history from the bitmap
+// instead of loading it, so working out what a have already covers, or how
+// many commits a tip has, costs about as much as the history written since
+// the pack. `repack -a` writes them next to the pack it leaves, as
+// pack-<checksum>.reach (git's own .bitmap format is a different one):
+//
+//   MGRB <version: u32 = 1> <bitmap count: u32>
+//   <bitmap of the pack's commits>
+//   for each bitmap: <position of its commit: u32> <bitmap>
+//   <checksum of everything above>
+//
+// A bitmap is its encoded length (u32) and then alternating runs of clear
+// and set bits, as varints, starting with a clear run.
+
+const SIGNATURE: &[u8] = b"MGRB";
+const VERSION: u32 = 1;
+// Besides each tip, a bitmap every this many commits down the history
+const SPACING: usize = 100;
+
+pub fn path(pack_path: &Path) -> PathBuf {
+    pack_path.with_extension("reach")
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn set(&mut self, bit: usize) {
+        let word = bit / 64;
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        self.words[word] |= 1 << (bit % 64);
+    }
+
+    pub fn get(&self, bit: usize) -> bool {
+        self.words
+            .get(bit / 64)
+            .is_some_and(|word| word & (1 << (bit % 64)) != 0)
+    }
+
+    pub fn union_with(&mut self, other: &Bitmap) {
+        if other.words.len() > self.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(&other.words) {
+            *word |= other;
+        }
+    }
+
+    // Bits set in both
+    pub fn count_common(&self, other: &Bitmap) -> usize {
+        self.words
+            .iter()
+            .zip(&other.words)
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        let mut runs = Vec::new();
+        let (mut value, mut run) = (false, 0);
+        for bit in 0..self.words.len() * 64 {
+            if self.get(bit) == value {
+                run += 1;
+            } else {
+                delta::push_varint(&mut runs, run);
+                (value, run) = (!value, 1);
+            }
+        }
+        if value {
+            delta::push_varint(&mut runs, run);
+        }
+        out.extend_from_slice(&(runs.len() as u32).to_be_bytes());
+        out.extend(runs);
+    }
+
+    fn decode(data: &[u8], at: &mut usize) -> Option<Bitmap> {
+        let len = u32::from_be_bytes(data.get(*at..*at + 4)?.try_into().ok()?) as usize;
+        let runs = data.get(*at + 4..*at + 4 + len)?;
+        *at += 4 + len;
+        let mut bitmap = Bitmap::default();
+        let (mut pos, mut bit, mut value) = (0, 0, false);
+        while pos < runs.len() {
+            let run = delta::read_varint(runs, &mut pos).ok()?;
+            if value {
+                for i in bit..bit + run {
+                    bitmap.set(i);
+                }
+            }
+            bit += run;
+            value = !value;
+        }
+        Some(bitmap)
+    }
+}
+
+pub struct PackBitmaps {
+    commits: Bitmap,
+    // By the position of their commit in the pack
+    reachable: HashMap<usize, Bitmap>,
+}
+
+impl PackBitmaps {
+    // None when there are no bitmaps for `pack` or they can't be read; the
+    // walks they'd shortcut then just take longer
+    pub fn open(pack: &PackIndex, algorithm: HashAlgorithm) -> Option<Self> {
+        let data = fs::read(path(&pack.pack_path)).ok()?;
+        let raw_len = algorithm.raw_len();
+        if data.len() < 12 + raw_len || &data[..4] != SIGNATURE {
+            return None;
+        }
+        let (body, checksum) = data.split_at(data.len() - raw_len);
+        if hex_to_bytes(&algorithm.hash(body)).ok()? != checksum
+            || u32::from_be_bytes(body[4..8].try_into().ok()?) != VERSION
+        {
+            return None;
+        }
+        let count = u32::from_be_bytes(body[8..12].try_into().ok()?) as usize;
+        let mut at = 12;
+        let commits = Bitmap::decode(body, &mut at)?;
+        let mut reachable = HashMap::with_capacity(count);
+        for _ in 0..count {
+            let position = u32::from_be_bytes(body.get(at..at + 4)?.try_into().ok()?) as usize;
+            at += 4;
+            reachable.insert(position, Bitmap::decode(body, &mut at)?);
+        }
+        Some(Self { commits, reachable })
+    }
+}
+
+// Objects reachable from some commits: what bitmaps cover, as a bitmap over
+// their pack, and everything walked past them, as a set
+pub struct Reachable<'a> {
+    object_store: &'a ObjectStore,
+    packed: Option<(&'a PackIndex, &'a PackBitmaps, Bitmap)>,
+    other: HashSet<ObjectId>,
+}
+
+impl<'a> Reachable<'a> {
+    pub fn new(object_store: &'a ObjectStore) -> Self {
+        Reachable {
+            object_store,
+            packed: object_store
+                .bitmaps()
+                .map(|(pack, bitmaps)| (pack, bitmaps, Bitmap::default())),
+            other: HashSet::new(),
+        }
+    }
+
+    pub fn contains(&self, hash: &str) -> Result<bool> {
+        if let Some((pack, _, bitmap)) = &self.packed
+            && pack
+                .position(hash)
+                .is_some_and(|position| bitmap.get(position))
+        {
+            return Ok(true);
+        }
+        Ok(self.other.contains(&ObjectId::from_hex(hash)?))
+    }
+
+    // Add `hash`, returning false if it was already there
+    pub fn insert(&mut self, hash: &str) -> Result<bool> {
+        if self.contains(hash)? {
+            return Ok(false);
+        }
+        Ok(self.other.insert(ObjectId::from_hex(hash)?))
+    }
+
+    // Add the commits reachable from `commit` and, with `objects`, their
+    // trees and blobs too
+    pub fn add_history(&mut self, commit: &str, objects: bool) -> Result<()> {
+        let object_store = self.object_store;
+        let mut pending = vec![commit.to_string()];
+        while let Some(hash) = pending.pop() {
+            if self.contains(&hash)? {
+                continue;
+            }
+            if let Some((pack, bitmaps, bitmap)) = &mut self.packed
+                && let Some(reachable) = pack
+                    .position(&hash)
+                    .and_then(|position| bitmaps.reachable.get(&position))
+            {
+                bitmap.union_with(reachable);
+                continue;
+            }
+            self.insert(&hash)?;
+            let commit = object_store.load_commit(&hash)?;
+            if objects && self.insert(&commit.tree)? {
+                for object in tree_closure(object_store, &commit.tree)? {
+                    self.insert(&object)?;
+                }
+            }
+            pending.extend(commit.parents);
+        }
+        Ok(())
+    }
+
+    // How many commits there are, when only commits were added (see
+    // add_history)
+    pub fn count_commits(&self) -> usize {
+        let Some((pack, bitmaps, bitmap)) = &self.packed else {
+            return self.other.len();
+        };
+        let walked = self
+            .other
+            .iter()
+            .filter(|id| {
+                !pack
+                    .position(&id.to_hex())
+                    .is_some_and(|position| bitmap.get(position))
+            })
+            .count();
+        bitmap.count_common(&bitmaps.commits) + walked
+    }
+}
+
+// Every tree and blob under `tree`, besides itself
+fn tree_closure(object_store: &ObjectStore, tree: &str) -> Result<Vec<String>> {
+    let mut objects: Vec<String> = object_store
+        .tree_objects(tree)?
+        .into_iter()
+        .skip(1)
+        .collect();
+    objects.extend(
+        object_store
+            .load_tree(tree)?
+            .entries
+            .into_values()
+            .map(|entry| entry.hash),
+    );
+    Ok(objects)
+}
+
+// Write bitmaps for `pack`, which must hold everything reachable from
+// `tips`: one for each tip and one every SPACING commits below them, oldest
+// first so each can start from those already done. Returns how many.
+pub fn write(object_store: &ObjectStore, pack: &PackIndex, tips: &[String]) -> Result<usize> {
+    // Commits parents first
+    let mut order = Vec::new();
+    let mut seen = HashSet::new();
+    let mut stack: Vec<(String, bool)> = tips.iter().map(|tip| (tip.clone(), false)).collect();
+    let mut parents: HashMap<String, Vec<String>> = HashMap::new();
+    while let Some((hash, expanded)) = stack.pop() {
+        if expanded {
+            order.push(hash);
+            continue;
+        }
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let commit = object_store.load_commit(&hash)?;
+        stack.push((hash.clone(), true));
+        for parent in commit.parents.iter().rev() {
+            if !seen.contains(parent) {
+                stack.push((parent.clone(), false));
+            }
+        }
+        parents.insert(hash, commit.parents);
+    }
+
+    let tips: HashSet<&String> = tips.iter().collect();
+    let selected: Vec<&String> = order
+        .iter()
+        .enumerate()
+        .filter(|(i, hash)| (i + 1) % SPACING == 0 || tips.contains(hash))
+        .map(|(_, hash)| hash)
+        .collect();
+
+    let mut commits = Bitmap::default();
+    for hash in &order {
+        if let Some(position) = pack.position(hash) {
+            commits.set(position);
+        }
+    }
+    let mut done: HashMap<&String, Bitmap> = HashMap::new();
+    'selected: for hash in selected {
+        let mut bitmap = Bitmap::default();
+        let mut visited = HashSet::new();
+        let mut pending = vec![hash];
+        while let Some(commit) = pending.pop() {
+            if !visited.insert(commit) {
+                continue;
+            }
+            if let Some(reachable) = done.get(commit) {
+                bitmap.union_with(reachable);
+                continue;
+            }
+            let tree = object_store.load_commit(commit)?.tree;
+            for object in [commit.clone(), tree.clone()]
+                .into_iter()
+                .chain(tree_closure(object_store, &tree)?)
+            {
+                // Objects outside the pack can't be recorded
+                let Some(position) = pack.position(&object) else {
+                    continue 'selected;
+                };
+                bitmap.set(position);
+            }
+            pending.extend(&parents[commit]);
+        }
+        done.insert(hash, bitmap);
+    }
+
+    let mut out = SIGNATURE.to_vec();
+    out.extend_from_slice(&VERSION.to_be_bytes());
+    out.extend_from_slice(&(done.len() as u32).to_be_bytes());
+    commits.encode(&mut out);
+    let mut entries: Vec<(usize, &Bitmap)> = done
+        .iter()
+        .filter_map(|(hash, bitmap)| Some((pack.position(hash)?, bitmap)))
+        .collect();
+    entries.sort_by_key(|(position, _)| *position);
+    for (position, bitmap) in entries {
+        out.extend_from_slice(&(position as u32).to_be_bytes());
+        bitmap.encode(&mut out);
+    }
+    out.extend(hex_to_bytes(&object_store.algorithm().hash(&out))?);
+
+    let path = path(&pack.pack_path);
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, &out)?;
+    fs::rename(&tmp, &path)?;
+    Ok(done.len())
+}
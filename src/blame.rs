@@ -0,0 +1,102 @@
+use crate::{
+    Result,
+    commands::diff::{DiffType, compute_diff},
+    object_store::ObjectStore,
+    worktree,
+};
+
+// Line blame: which commit last changed each line of a file. History is
+// followed along first parents from the starting commit; at each step the
+// lines not yet accounted for are diffed against the parent's version of the
+// file, and those the parent doesn't have belong to the commit.
+
+pub struct BlameLine {
+    pub commit: String,
+    // 1-based line number in that commit's version of the file
+    pub line: usize,
+}
+
+// For each line of `new`, the (0-based) line of `old` it was carried over
+// from unchanged, or None for lines `new` added
+pub fn line_origins(old: &str, new: &str) -> Vec<Option<usize>> {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut origins = Vec::with_capacity(new_lines.len());
+    let mut old_index = 0;
+    for change in compute_diff(&old_lines, &new_lines) {
+        match change {
+            DiffType::Equal => {
+                origins.push(Some(old_index));
+                old_index += 1;
+            }
+            DiffType::Delete => old_index += 1,
+            DiffType::Insert => origins.push(None),
+        }
+    }
+    origins
+}
+
+// The blob `path` has in `commit`, if any
+fn file_at(object_store: &ObjectStore, commit: &str, path: &str) -> Result<Option<String>> {
+    let tree = worktree::load_commit_tree(object_store, commit)?;
+    Ok(tree.entries.get(path).map(|entry| entry.hash.clone()))
+}
+
+// Blame every line of `path` as it is in `commit`
+pub fn blame_file(object_store: &ObjectStore, commit: &str, path: &str) -> Result<Vec<BlameLine>> {
+    let Some(mut hash) = file_at(object_store, commit, path)? else {
+        return Err(format!("'{}' is not in {}", path, &commit[..8]).into());
+    };
+    let mut content = text(object_store, &hash)?;
+    let line_count = content.lines().count();
+    let mut result: Vec<Option<BlameLine>> = (0..line_count).map(|_| None).collect();
+    // (line of the final file, line of the current commit's version)
+    let mut pending: Vec<(usize, usize)> = (0..line_count).map(|i| (i, i)).collect();
+
+    let mut current = commit.to_string();
+    while !pending.is_empty() {
+        let parent = object_store.load_commit(&current)?.parent().cloned();
+        let parent_hash = match &parent {
+            Some(parent) => file_at(object_store, parent, path)?,
+            None => None,
+        };
+        let (Some(parent), Some(parent_hash)) = (parent, parent_hash) else {
+            // Everything left was added by this commit
+            for (line, origin) in pending.drain(..) {
+                result[line] = Some(BlameLine {
+                    commit: current.clone(),
+                    line: origin + 1,
+                });
+            }
+            break;
+        };
+
+        // An untouched file carries every line over as it is
+        if parent_hash != hash {
+            let parent_content = text(object_store, &parent_hash)?;
+            let origins = line_origins(&parent_content, &content);
+            let mut carried = Vec::new();
+            for (line, origin) in pending {
+                match origins.get(origin).copied().flatten() {
+                    Some(parent_line) => carried.push((line, parent_line)),
+                    None => {
+                        result[line] = Some(BlameLine {
+                            commit: current.clone(),
+                            line: origin + 1,
+                        })
+                    }
+                }
+            }
+            pending = carried;
+            content = parent_content;
+            hash = parent_hash;
+        }
+        current = parent;
+    }
+
+    Ok(result.into_iter().flatten().collect())
+}
+
+fn text(object_store: &ObjectStore, hash: &str) -> Result<String> {
+    Ok(String::from_utf8_lossy(&object_store.load_blob(hash)?.content).into_owned())
+}
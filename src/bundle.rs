@@ -0,0 +1,164 @@
+use crate::{
+    Result,
+    hash::HashAlgorithm,
+    object_store::ObjectStore,
+    packfile::{self, PackEntry},
+    utils,
+};
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+// Bundles, as `git bundle create` writes them: some refs and the objects
+// they need in one file, for carrying history where no server reaches.
+//
+//   # v2 git bundle
+//   [@<capability>]           v3 only; object-format=<name> is understood
+//   [-<id> [<comment>]]       prerequisites: commits the receiver must have
+//   <id> <refname>
+//   <blank line>
+//   <pack>
+//
+// The pack is thin when there are prerequisites: it can leave out, and
+// delta against, objects reachable from them.
+
+const V2_SIGNATURE: &str = "# v2 git bundle";
+const V3_SIGNATURE: &str = "# v3 git bundle";
+
+pub fn is_bundle(path: &Path) -> bool {
+    let Ok(file) = File::open(path) else {
+        return false;
+    };
+    let mut first = String::new();
+    if BufReader::new(file).read_line(&mut first).is_err() {
+        return false;
+    }
+    matches!(first.trim_end(), V2_SIGNATURE | V3_SIGNATURE)
+}
+
+pub struct Bundle {
+    pub algorithm: HashAlgorithm,
+    pub prerequisites: Vec<String>,
+    // (refname, id), as listed
+    pub refs: Vec<(String, String)>,
+    pack: Vec<u8>,
+}
+
+impl Bundle {
+    pub fn open(path: &Path) -> Result<Self> {
+        let data = fs::read(path)?;
+        let invalid = |reason: &str| format!("'{}' is not a bundle: {}", path.display(), reason);
+
+        let mut lines = Vec::new();
+        let mut at = 0;
+        loop {
+            let end = data[at..]
+                .iter()
+                .position(|&byte| byte == b'\n')
+                .ok_or(invalid("its header never ends"))?;
+            let line = std::str::from_utf8(&data[at..at + end])
+                .map_err(|_| invalid("its header isn't text"))?;
+            at += end + 1;
+            if line.is_empty() {
+                break;
+            }
+            lines.push(line);
+        }
+
+        let mut lines = lines.into_iter();
+        let version = match lines.next() {
+            Some(V2_SIGNATURE) => 2,
+            Some(V3_SIGNATURE) => 3,
+            _ => return Err(invalid("unknown signature").into()),
+        };
+        let mut bundle = Bundle {
+            algorithm: HashAlgorithm::Sha1,
+            prerequisites: Vec::new(),
+            refs: Vec::new(),
+            pack: data[at..].to_vec(),
+        };
+        for line in lines {
+            if let Some(capability) = line.strip_prefix('@') {
+                if version < 3 {
+                    return Err(invalid("capabilities in a version 2 bundle").into());
+                }
+                match capability.split_once('=') {
+                    Some(("object-format", name)) => {
+                        bundle.algorithm = HashAlgorithm::from_name(name)?;
+                    }
+                    _ => {
+                        return Err(
+                            format!("Unsupported bundle capability '{}'", capability).into()
+                        );
+                    }
+                }
+            } else if let Some(prerequisite) = line.strip_prefix('-') {
+                let id = prerequisite.split(' ').next().unwrap_or_default();
+                bundle.prerequisites.push(id.to_string());
+            } else {
+                let (id, name) = line
+                    .split_once(' ')
+                    .ok_or(invalid(&format!("bad ref line '{}'", line)))?;
+                if name != "HEAD" {
+                    let relative = name
+                        .strip_prefix("refs/")
+                        .ok_or(invalid(&format!("'{}' is not under refs/", name)))?;
+                    utils::validate_ref_name("ref", relative)?;
+                }
+                bundle.refs.push((name.to_string(), id.to_string()));
+            }
+        }
+
+        let hex_len = bundle.algorithm.hex_len();
+        if bundle
+            .prerequisites
+            .iter()
+            .chain(bundle.refs.iter().map(|(_, id)| id))
+            .any(|id| id.len() != hex_len || !id.bytes().all(|b| b.is_ascii_hexdigit()))
+        {
+            return Err(invalid(&format!("ids must be {}", bundle.algorithm)).into());
+        }
+        Ok(bundle)
+    }
+
+    // The bundle's branches, by name without refs/heads/
+    pub fn heads(&self) -> Vec<(String, String)> {
+        self.refs
+            .iter()
+            .filter_map(|(name, id)| {
+                Some((name.strip_prefix("refs/heads/")?.to_string(), id.clone()))
+            })
+            .collect()
+    }
+
+    // The branch a clone checks out: main if the bundle has it, else the one
+    // its HEAD is at, else the first
+    pub fn default_branch(&self) -> Option<String> {
+        let heads = self.heads();
+        let head = self
+            .refs
+            .iter()
+            .find(|(name, _)| name == "HEAD")
+            .map(|(_, id)| id);
+        heads
+            .iter()
+            .find(|(name, _)| name == "main")
+            .or_else(|| heads.iter().find(|(_, id)| Some(id) == head))
+            .or(heads.first())
+            .map(|(name, _)| name.clone())
+    }
+
+    // Write the bundle's objects into `objects_dir` as one pack, and return
+    // how many there were. Bases a thin pack left out must be there already.
+    pub fn unpack(&self, objects_dir: &Path) -> Result<usize> {
+        let object_store = ObjectStore::at(objects_dir.to_path_buf());
+        let entries: Vec<PackEntry> = packfile::read_pack(&self.pack, self.algorithm, |id| {
+            let (kind, content) = object_store.read_typed(id).ok()?;
+            Some((packfile::known_kind(&kind)?, content))
+        })?;
+        if !entries.is_empty() {
+            packfile::write_pack(objects_dir, self.algorithm, &entries, None)?;
+        }
+        Ok(entries.len())
+    }
+}
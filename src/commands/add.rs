@@ -1,75 +1,215 @@
-use crate::{Index, IndexEntry, Repository, Result, object_store::ObjectStore, utils};
+use crate::{
+    Index, IndexEntry, Repository, Result,
+    ignore::IgnoreRules,
+    index::{self, LazyIndex, forget_parent_dirs},
+    object_store::ObjectStore,
+    profile::{self, Phase},
+    repo_path, utils, worktree,
+};
+use std::collections::HashSet;
 use std::fs;
+use std::path::Path;
 use walkdir::WalkDir;
 
-pub fn add(repo: &Repository, paths: Vec<String>) -> Result<()> {
-    let mut index = utils::load_index(repo)?;
+// With `intent_to_add` (`add -N`), new paths are only recorded, with empty
+// content, so they show up in `diff` and `commit -a` before being staged
+pub fn add(repo: &Repository, paths: Vec<String>, intent_to_add: bool) -> Result<()> {
     let object_store = ObjectStore::new(repo);
+    let precompose = repo_path::precompose_unicode(repo)?;
+    let paths = repo_path::precompose_args(repo, paths)?;
 
-    for path_str in paths {
-        if path_str == "." {
-            // Add all files
-            for entry in WalkDir::new(&repo.work_dir)
-                .into_iter()
-                .filter_map(|e| e.ok())
-                .filter(|e| e.file_type().is_file())
+    // Naming only files, the rest of the index is neither decoded nor
+    // rewritten
+    if !intent_to_add
+        && paths
+            .iter()
+            .all(|path| path != "." && repo.work_dir.join(path).is_file())
+        && let Some(view) = LazyIndex::open(repo)?
+    {
+        let mut files = Vec::new();
+        for path in paths {
+            if view
+                .get(&path)?
+                .is_some_and(|entry| entry.ignores_worktree())
             {
-                let path = entry.path();
-                if path.starts_with(&repo.git_dir) {
-                    continue;
-                }
-
-                let relative_path = path
-                    .strip_prefix(&repo.work_dir)?
-                    .to_string_lossy()
-                    .replace('\\', "/");
-
-                add_file_to_index(&mut index, &object_store, path, &relative_path)?;
+                println!(
+                    "Skipping '{}': marked assume-unchanged or skip-worktree",
+                    path
+                );
+            } else {
+                files.push(path);
             }
-        } else {
-            let full_path = repo.work_dir.join(&path_str);
-            if full_path.is_file() {
-                add_file_to_index(&mut index, &object_store, &full_path, &path_str)?;
-            } else if full_path.is_dir() {
-                for entry in WalkDir::new(&full_path)
-                    .into_iter()
-                    .filter_map(|e| e.ok())
-                    .filter(|e| e.file_type().is_file())
-                {
-                    let path = entry.path();
-                    let relative_path = path
-                        .strip_prefix(&repo.work_dir)?
-                        .to_string_lossy()
-                        .replace('\\', "/");
+        }
+        stage_files(repo, &object_store, &files)?;
+        println!("Added files to staging area");
+        return Ok(());
+    }
 
-                    add_file_to_index(&mut index, &object_store, path, &relative_path)?;
-                }
+    let mut index = utils::lock_index(repo)?;
+    for path_str in paths {
+        let full_path = if path_str == "." {
+            repo.work_dir.clone()
+        } else {
+            repo.work_dir.join(&path_str)
+        };
+        if full_path.is_file() {
+            if intent_to_add {
+                add_intent(&mut index, &object_store, &full_path, &path_str)?;
+                continue;
+            }
+            if is_flagged(&index, &path_str) {
+                println!(
+                    "Skipping '{}': marked assume-unchanged or skip-worktree",
+                    path_str
+                );
+                continue;
             }
+            add_file_to_index(&mut index, &object_store, &full_path, &path_str)?;
+        } else if full_path.is_dir() {
+            add_directory(
+                repo,
+                &mut index,
+                &object_store,
+                &full_path,
+                intent_to_add,
+                precompose,
+            )?;
         }
     }
 
-    utils::save_index(repo, &index)?;
+    utils::save_locked_index(repo, index)?;
     println!("Added files to staging area");
     Ok(())
 }
 
-fn add_file_to_index(
+// Add every file under `dir`, and record directories with nothing in them.
+// Ignored files (see ignore) are skipped unless already tracked.
+fn add_directory(
+    repo: &Repository,
+    index: &mut Index,
+    object_store: &ObjectStore,
+    dir: &Path,
+    intent_to_add: bool,
+    precompose: bool,
+) -> Result<()> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let rules = IgnoreRules::load(repo)?;
+    // Ignored directories holding tracked files still have to be walked
+    let tracked_dirs: HashSet<String> = index
+        .entries
+        .keys()
+        .flat_map(|path| path.match_indices('/').map(|(i, _)| path[..i].to_string()))
+        .collect();
+    let relative = |path: &Path| {
+        path.strip_prefix(&repo.work_dir)
+            .map(|path| repo_path::from_path(path, precompose))
+    };
+
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| {
+            e.path() != repo.git_dir
+                && !(e.file_type().is_dir()
+                    && relative(e.path())
+                        .is_ok_and(|dir| rules.matches(&dir, true) && !tracked_dirs.contains(&dir)))
+        })
+        .filter_map(|e| e.ok())
+    {
+        let path = entry.path();
+        let relative_path = relative(path)?;
+        let ignored = rules.ignored_parent(&relative_path).is_some()
+            || rules.matches(&relative_path, entry.file_type().is_dir());
+        if ignored && !index.entries.contains_key(&relative_path) {
+            continue;
+        }
+
+        if entry.file_type().is_dir() {
+            if !relative_path.is_empty() && fs::read_dir(path)?.next().is_none() {
+                let entry = worktree::empty_dir_entry(object_store, &relative_path)?;
+                index.entries.insert(relative_path, entry);
+            }
+        } else if entry.file_type().is_file() {
+            if intent_to_add {
+                add_intent(index, object_store, path, &relative_path)?;
+            } else if !is_flagged(index, &relative_path) {
+                add_file_to_index(index, object_store, path, &relative_path)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_flagged(index: &Index, path: &str) -> bool {
+    index
+        .entries
+        .get(path)
+        .is_some_and(|entry| entry.ignores_worktree())
+}
+
+// Record `relative_path` with an empty blob unless it is already tracked
+fn add_intent(
     index: &mut Index,
     object_store: &ObjectStore,
     file_path: &std::path::Path,
     relative_path: &str,
 ) -> Result<()> {
-    let content = fs::read(file_path)?;
-    let hash = object_store.store_blob(&content)?;
+    if index.entries.contains_key(relative_path) {
+        return Ok(());
+    }
 
-    index.entries.insert(
-        relative_path.to_string(),
-        IndexEntry {
-            hash,
-            mode: "100644".to_string(),
-            path: relative_path.to_string(),
-        },
-    );
+    let entry = IndexEntry {
+        hash: object_store.store_blob(b"")?,
+        mode: worktree::file_mode(file_path)?,
+        path: relative_path.to_string(),
+        intent_to_add: true,
+        ..Default::default()
+    };
+    index.entries.insert(relative_path.to_string(), entry);
+    forget_parent_dirs(index, relative_path);
+    Ok(())
+}
 
+pub(crate) fn add_file_to_index(
+    index: &mut Index,
+    object_store: &ObjectStore,
+    file_path: &std::path::Path,
+    relative_path: &str,
+) -> Result<()> {
+    // Staging a conflicted path marks it resolved
+    index::stage_entry(index, file_entry(object_store, file_path, relative_path)?);
     Ok(())
 }
+
+// Store the working tree files at `paths` and stage them, with the index
+// locked only while their entries are written
+pub(crate) fn stage_files(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    paths: &[String],
+) -> Result<()> {
+    let mut entries = Vec::with_capacity(paths.len());
+    for path in paths {
+        entries.push(file_entry(
+            object_store,
+            &repo_path::to_path(&repo.work_dir, path),
+            path,
+        )?);
+    }
+    index::stage_entries(repo, entries)
+}
+
+fn file_entry(
+    object_store: &ObjectStore,
+    file_path: &std::path::Path,
+    relative_path: &str,
+) -> Result<IndexEntry> {
+    let content = fs::read(file_path)?;
+    let mut entry = IndexEntry {
+        hash: object_store.store_blob(&content)?,
+        mode: worktree::file_mode(file_path)?,
+        path: relative_path.to_string(),
+        ..Default::default()
+    };
+    utils::record_stat(&mut entry, file_path)?;
+    Ok(entry)
+}
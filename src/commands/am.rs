@@ -0,0 +1,802 @@
+use crate::{
+    Repository, Result, Tree, TreeEntry,
+    commands::commit::create_commit,
+    commands::merge::{self, merge_clean_contents, merge_trees, write_conflicts},
+    commands::{cherry_pick, revert},
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils, worktree,
+};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::fs;
+
+// Applying patches mailed by `git format-patch`, one commit each. The
+// session is kept so it can stop on a patch that won't apply and be
+// resumed or abandoned:
+//
+//   .mini_git/rebase-apply/head       the commit HEAD was at before the first patch
+//   .mini_git/rebase-apply/0001 ...   each patch, as it was in the mailbox
+//   .mini_git/rebase-apply/next       the number of the patch being applied
+//   .mini_git/rebase-apply/last       the number of the last patch
+//   .mini_git/rebase-apply/threeway   present when --3way was given
+//
+// With --3way, a patch that doesn't apply to HEAD is applied instead to the
+// blobs its `index <old>..<new>` lines name, when they are here, and the
+// result merged into HEAD as a cherry-pick would be.
+
+const AM_DIR: &str = "rebase-apply";
+
+pub fn am(
+    repo: &Repository,
+    mailboxes: Vec<String>,
+    three_way: bool,
+    continue_am: bool,
+    skip: bool,
+    abort: bool,
+) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let state = repo.git_dir.join(AM_DIR);
+    if abort {
+        return abort_am(repo, &object_store);
+    }
+    if continue_am || skip {
+        if !state.exists() {
+            return Err("No am session in progress".into());
+        }
+        if skip {
+            let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+            let tree = worktree::load_commit_tree(&object_store, &head)?;
+            worktree::reset_to_tree(repo, &object_store, &tree)?;
+        } else {
+            commit_resolved(repo, &object_store)?;
+        }
+        let next = read_number(repo, "next")?;
+        fs::write(state.join("next"), format!("{}\n", next + 1))?;
+        return apply_sequence(repo, &object_store);
+    }
+
+    if state.exists() {
+        return Err("An am session is in progress; use --continue, --skip or --abort".into());
+    }
+    if merge::merge_head(repo)?.is_some()
+        || revert::revert_head(repo)?.is_some()
+        || cherry_pick::cherry_pick_head(repo)?.is_some()
+    {
+        return Err("A merge, revert or cherry-pick is in progress; finish it first".into());
+    }
+    let index = utils::load_index(repo)?;
+    if !index.unmerged.is_empty() {
+        return Err("Cannot apply patches with unmerged paths; resolve them first".into());
+    }
+    let head = utils::get_head_commit(repo)?.ok_or("Cannot apply patches: HEAD has no commits")?;
+    if worktree::has_local_changes(repo, &object_store, &index, false)? {
+        return Err(
+            "Cannot apply patches: you have uncommitted changes. Commit or stash them first."
+                .into(),
+        );
+    }
+
+    let mut patches = Vec::new();
+    if mailboxes.is_empty() {
+        patches.extend(split_mailbox(&std::io::read_to_string(std::io::stdin())?));
+    }
+    for mailbox in &mailboxes {
+        let text =
+            fs::read_to_string(mailbox).map_err(|e| format!("Cannot read '{}': {}", mailbox, e))?;
+        patches.extend(split_mailbox(&text));
+    }
+    if patches.is_empty() {
+        return Err("No patches found".into());
+    }
+    // Every patch is read before any is applied, so a malformed one at the
+    // end doesn't leave the others half done
+    for (i, text) in patches.iter().enumerate() {
+        Patch::parse(text).map_err(|e| format!("Patch {}: {}", i + 1, e))?;
+    }
+
+    fs::create_dir_all(&state)?;
+    for (i, text) in patches.iter().enumerate() {
+        fs::write(state.join(format!("{:04}", i + 1)), text)?;
+    }
+    if three_way {
+        fs::write(state.join("threeway"), "")?;
+    }
+    fs::write(state.join("last"), format!("{}\n", patches.len()))?;
+    fs::write(state.join("next"), "1\n")?;
+    fs::write(state.join("head"), format!("{}\n", head))?;
+    apply_sequence(repo, &object_store)
+}
+
+// Apply the session's patches from `next` on, stopping at the first that
+// won't apply
+fn apply_sequence(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let state = repo.git_dir.join(AM_DIR);
+    let three_way = state.join("threeway").exists();
+    let last = read_number(repo, "last")?;
+    for number in read_number(repo, "next")?..=last {
+        fs::write(state.join("next"), format!("{}\n", number))?;
+        let patch = read_patch(repo, number)?;
+        println!("Applying: {}", patch.subject());
+        if !apply(repo, object_store, &patch, three_way)? {
+            return Err(format!(
+                "Patch failed at {:04} {}; fix it, `add` the result and run `am --continue` \
+                 (or `am --skip` to drop the patch, `am --abort` to go back)",
+                number,
+                patch.subject()
+            )
+            .into());
+        }
+    }
+    fs::remove_dir_all(&state)?;
+    Ok(())
+}
+
+// Apply and commit one patch; false when it stopped, leaving conflicts in
+// the index or, if nothing could be applied, the tree as it was
+fn apply(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    patch: &Patch,
+    three_way: bool,
+) -> Result<bool> {
+    let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+    let our_tree = worktree::load_commit_tree(object_store, &head)?;
+
+    let tree = match apply_files(object_store, &our_tree, &patch.files)? {
+        Some(tree) => tree,
+        None if three_way => {
+            let Some(base_tree) = preimage_tree(repo, object_store, &patch.files)? else {
+                println!("The blobs the patch was made against aren't here; cannot fall back");
+                return Ok(false);
+            };
+            let Some(their_tree) = apply_files(object_store, &base_tree, &patch.files)? else {
+                println!("The patch doesn't apply to the blobs it names; cannot fall back");
+                return Ok(false);
+            };
+            println!("Falling back to a three-way merge...");
+            let result = merge_trees(object_store, &base_tree, &our_tree, &their_tree)?;
+            let result = merge_clean_contents(
+                object_store,
+                result,
+                &base_tree,
+                &our_tree,
+                &their_tree,
+                None,
+            )?;
+            if !result.conflicts.is_empty() {
+                worktree::check_checkout_safety(repo, &result.tree)?;
+                worktree::switch_to_tree(repo, object_store, &result.tree)?;
+                let conflicts =
+                    merge::conflict_stages(&result.conflicts, &base_tree, &our_tree, &their_tree);
+                for (path, _) in &conflicts {
+                    println!("CONFLICT: Merge conflict in {}", path);
+                }
+                write_conflicts(repo, object_store, conflicts, "patch base", patch.subject())?;
+                return Ok(false);
+            }
+            result.tree
+        }
+        None => return Ok(false),
+    };
+
+    if tree.hash == our_tree.hash {
+        println!("Skipping (already applied): {}", patch.subject());
+        return Ok(true);
+    }
+    worktree::check_checkout_safety(repo, &tree)?;
+    worktree::switch_to_tree(repo, object_store, &tree)?;
+    commit_patch(repo, object_store, patch, &head, &tree)?;
+    Ok(true)
+}
+
+fn commit_patch(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    patch: &Patch,
+    head: &str,
+    tree: &Tree,
+) -> Result<()> {
+    let new_commit = create_commit(
+        object_store,
+        &tree.hash,
+        vec![head.to_string()],
+        patch.author.clone(),
+        patch.message.clone(),
+        patch.date,
+    )?;
+    utils::update_head_commit(repo, &new_commit)?;
+    journal::record(
+        repo,
+        "am",
+        patch.subject(),
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: Some(head.to_string()),
+            new: Some(new_commit.clone()),
+        }],
+    )?;
+    println!("[{}] {}", &new_commit[..8], patch.subject());
+    Ok(())
+}
+
+// Commit what the user staged for the patch the session stopped at
+fn commit_resolved(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let index = utils::load_index(repo)?;
+    if !index.unmerged.is_empty() {
+        let mut paths: Vec<&str> = index.unmerged.keys().map(String::as_str).collect();
+        paths.sort();
+        return Err(format!(
+            "Unmerged paths ({}); resolve them and `add` them first",
+            paths.join(", ")
+        )
+        .into());
+    }
+    let patch = read_patch(repo, read_number(repo, "next")?)?;
+    let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+    let tree = worktree::tree_from_index(object_store, &index)?;
+    if tree.hash == worktree::load_commit_tree(object_store, &head)?.hash {
+        return Err("Nothing staged for this patch; `add` the changes or run `am --skip`".into());
+    }
+    commit_patch(repo, object_store, &patch, &head, &tree)
+}
+
+// Put HEAD, the index and the working tree back to where the session
+// started
+fn abort_am(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let state = repo.git_dir.join(AM_DIR);
+    let original = match fs::read_to_string(state.join("head")) {
+        Ok(head) => head.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err("No am session in progress".into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let current = utils::get_head_commit(repo)?;
+    let tree = worktree::load_commit_tree(object_store, &original)?;
+    worktree::reset_to_tree(repo, object_store, &tree)?;
+    utils::update_head_commit(repo, &original)?;
+    journal::record(
+        repo,
+        "am",
+        "abort",
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: current,
+            new: Some(original.clone()),
+        }],
+    )?;
+    fs::remove_dir_all(&state)?;
+
+    println!("am aborted; HEAD is back at {}", &original[..8]);
+    Ok(())
+}
+
+fn read_number(repo: &Repository, name: &str) -> Result<usize> {
+    let path = repo.git_dir.join(AM_DIR).join(name);
+    fs::read_to_string(&path)?
+        .trim()
+        .parse()
+        .map_err(|_| format!("Corrupt am state in {}", path.display()).into())
+}
+
+fn read_patch(repo: &Repository, number: usize) -> Result<Patch> {
+    Patch::parse(&fs::read_to_string(
+        repo.git_dir.join(AM_DIR).join(format!("{:04}", number)),
+    )?)
+}
+
+// Split a mailbox at its `From <id> <date>` separator lines; text without
+// any is taken as one patch
+fn split_mailbox(text: &str) -> Vec<String> {
+    let mut patches: Vec<String> = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let separator = line.strip_prefix("From ").is_some_and(|rest| {
+            let id = rest.split(' ').next().unwrap_or("");
+            id.len() >= 40 && id.bytes().all(|b| b.is_ascii_hexdigit())
+        });
+        match patches.last_mut() {
+            Some(patch) if !separator => patch.push_str(line),
+            _ if separator || !line.trim().is_empty() => patches.push(line.to_string()),
+            _ => {}
+        }
+    }
+    patches
+}
+
+struct Patch {
+    author: String,
+    date: DateTime<Utc>,
+    message: String,
+    files: Vec<FilePatch>,
+}
+
+// The change to one file. A path is None on the side where the file
+// doesn't exist (a new or deleted file).
+struct FilePatch {
+    old_path: Option<String>,
+    new_path: Option<String>,
+    // From the `index` line, usually abbreviated
+    old_id: Option<String>,
+    old_mode: Option<String>,
+    new_mode: Option<String>,
+    hunks: Vec<Hunk>,
+}
+
+// `lines` keep their ' ', '-' or '+' prefix and their line ending
+struct Hunk {
+    old_start: usize,
+    lines: Vec<String>,
+}
+
+impl Patch {
+    fn parse(text: &str) -> Result<Self> {
+        let mut lines = text.split_inclusive('\n').peekable();
+        if lines.peek().is_some_and(|line| line.starts_with("From ")) {
+            lines.next();
+        }
+
+        // Headers, with folded continuation lines joined back on
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in lines.by_ref() {
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = headers.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            } else if let Some((name, value)) = line.split_once(':') {
+                headers.push((name.to_lowercase(), value.trim().to_string()));
+            }
+        }
+        let header = |name: &str| {
+            headers
+                .iter()
+                .find(|(header, _)| header == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let author = header("from").ok_or("no From: header naming the author")?;
+        let date = header("date")
+            .and_then(|date| DateTime::parse_from_rfc2822(date).ok())
+            .map_or_else(Utc::now, |date| date.with_timezone(&Utc));
+        let mut subject = header("subject").unwrap_or("").trim();
+        while subject.starts_with('[') {
+            let Some(end) = subject.find(']') else {
+                break;
+            };
+            subject = subject[end + 1..].trim_start();
+        }
+
+        // The message body runs to the `---` before the diffstat, or to the
+        // diff itself
+        let mut body = String::new();
+        while let Some(line) = lines.next_if(|line| !line.starts_with("diff --git ")) {
+            if line.trim_end() == "---" {
+                break;
+            }
+            body.push_str(line);
+        }
+        let body = body.trim();
+        let message = if body.is_empty() {
+            subject.to_string()
+        } else {
+            format!("{}\n\n{}", subject, body)
+        };
+
+        let mut files: Vec<FilePatch> = Vec::new();
+        while let Some(line) = lines.next() {
+            if let Some(paths) = line.trim_end().strip_prefix("diff --git ") {
+                let (old, new) = paths
+                    .split_once(" b/")
+                    .ok_or(format!("can't read the paths in '{}'", line.trim_end()))?;
+                let old = old.strip_prefix("a/").unwrap_or(old);
+                files.push(FilePatch {
+                    old_path: Some(old.to_string()),
+                    new_path: Some(new.to_string()),
+                    old_id: None,
+                    old_mode: None,
+                    new_mode: None,
+                    hunks: Vec::new(),
+                });
+                continue;
+            }
+            // Anything before the first diff, or after the last hunk (the
+            // signature), isn't part of a file's change
+            let Some(file) = files.last_mut() else {
+                continue;
+            };
+            let line = line.trim_end_matches(['\n', '\r']);
+            if let Some(mode) = line.strip_prefix("new file mode ") {
+                file.old_path = None;
+                file.new_mode = Some(mode.to_string());
+            } else if let Some(mode) = line.strip_prefix("deleted file mode ") {
+                file.new_path = None;
+                file.old_mode = Some(mode.to_string());
+            } else if let Some(mode) = line.strip_prefix("old mode ") {
+                file.old_mode = Some(mode.to_string());
+            } else if let Some(mode) = line.strip_prefix("new mode ") {
+                file.new_mode = Some(mode.to_string());
+            } else if let Some(ids) = line.strip_prefix("index ") {
+                let (old, rest) = ids.split_once("..").ok_or("bad index line")?;
+                if !old.bytes().all(|b| b == b'0') {
+                    file.old_id = Some(old.to_string());
+                }
+                // The mode here is one the change left alone
+                if let Some((_, mode)) = rest.split_once(' ') {
+                    file.old_mode.get_or_insert(mode.to_string());
+                    file.new_mode.get_or_insert(mode.to_string());
+                }
+            } else if line.starts_with("Binary files ") || line == "GIT binary patch" {
+                return Err(format!(
+                    "binary changes to {} can't be applied",
+                    file.new_path.as_ref().or(file.old_path.as_ref()).unwrap()
+                )
+                .into());
+            } else if let Some(ranges) = line.strip_prefix("@@ -") {
+                let range = |range: &str| -> Option<(usize, usize)> {
+                    match range.split_once(',') {
+                        Some((start, len)) => Some((start.parse().ok()?, len.parse().ok()?)),
+                        None => Some((range.parse().ok()?, 1)),
+                    }
+                };
+                let (old_range, new_range) = ranges
+                    .split_once(" @@")
+                    .and_then(|(ranges, _)| ranges.split_once(" +"))
+                    .ok_or(format!("bad hunk header '{}'", line))?;
+                let ((old_start, mut old_len), (_, mut new_len)) = range(old_range)
+                    .zip(range(new_range))
+                    .ok_or(format!("bad hunk header '{}'", line))?;
+
+                let mut hunk = Hunk {
+                    old_start,
+                    lines: Vec::new(),
+                };
+                while old_len > 0
+                    || new_len > 0
+                    || lines.peek().is_some_and(|l| l.starts_with('\\'))
+                {
+                    let line = lines.next().ok_or("a hunk is cut short")?;
+                    match line.as_bytes().first() {
+                        Some(b' ') | Some(b'\n') if old_len > 0 && new_len > 0 => {
+                            old_len -= 1;
+                            new_len -= 1;
+                        }
+                        Some(b'-') if old_len > 0 => old_len -= 1,
+                        Some(b'+') if new_len > 0 => new_len -= 1,
+                        // `\ No newline at end of file`: the line before has none
+                        Some(b'\\') => {
+                            if let Some(last) = hunk.lines.last_mut() {
+                                last.truncate(last.trim_end_matches(['\n', '\r']).len());
+                            }
+                            continue;
+                        }
+                        _ => {
+                            return Err(format!("malformed hunk line '{}'", line.trim_end()).into());
+                        }
+                    }
+                    // An empty context line may have lost its leading space
+                    let line = if line == "\n" { " \n" } else { line };
+                    hunk.lines.push(line.to_string());
+                }
+                file.hunks.push(hunk);
+            }
+        }
+        if files.is_empty() {
+            return Err("no changes in it".into());
+        }
+
+        Ok(Patch {
+            author: author.to_string(),
+            date,
+            message,
+            files,
+        })
+    }
+
+    fn subject(&self) -> &str {
+        self.message.lines().next().unwrap_or("")
+    }
+}
+
+// The patch applied to `tree`'s files, or None when any hunk doesn't fit
+fn apply_files(
+    object_store: &ObjectStore,
+    tree: &Tree,
+    files: &[FilePatch],
+) -> Result<Option<Tree>> {
+    let mut entries = tree.entries.clone();
+    for file in files {
+        let old_entry = match &file.old_path {
+            Some(path) => match entries.remove(path) {
+                Some(entry) => Some(entry),
+                None => {
+                    println!("error: {}: does not exist in HEAD", path);
+                    return Ok(None);
+                }
+            },
+            None => None,
+        };
+        let old_content = match &old_entry {
+            Some(entry) => match String::from_utf8(object_store.load_blob(&entry.hash)?.content) {
+                Ok(content) => content,
+                Err(_) => {
+                    println!("error: {}: binary files can't be patched", entry.name);
+                    return Ok(None);
+                }
+            },
+            None => String::new(),
+        };
+        let path = file.new_path.as_ref().or(file.old_path.as_ref()).unwrap();
+        let Some(new_content) = apply_hunks(&old_content, &file.hunks) else {
+            println!("error: patch failed: {}", path);
+            return Ok(None);
+        };
+
+        let Some(new_path) = &file.new_path else {
+            if !new_content.is_empty() {
+                println!("error: {}: removal patch leaves file contents", path);
+                return Ok(None);
+            }
+            continue;
+        };
+        if entries.contains_key(new_path) {
+            println!("error: {}: already exists", new_path);
+            return Ok(None);
+        }
+        let mode = file
+            .new_mode
+            .clone()
+            .or(old_entry.map(|entry| entry.mode))
+            .unwrap_or_else(|| "100644".to_string());
+        entries.insert(
+            new_path.clone(),
+            TreeEntry {
+                mode,
+                hash: object_store.store_blob(new_content.as_bytes())?,
+                name: new_path.clone(),
+                is_file: true,
+            },
+        );
+    }
+    Ok(Some(object_store.write_tree(entries)?))
+}
+
+// A tree of just the files the patch was made against, from the blob ids
+// its index lines record; None if any of them isn't here
+fn preimage_tree(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    files: &[FilePatch],
+) -> Result<Option<Tree>> {
+    let mut entries = HashMap::new();
+    for file in files {
+        let Some(path) = &file.old_path else {
+            continue;
+        };
+        let Some(id) = &file.old_id else {
+            return Ok(None);
+        };
+        let Ok(Some(hash)) = utils::find_object_by_prefix(repo, id) else {
+            return Ok(None);
+        };
+        if object_store.load_blob(&hash).is_err() {
+            return Ok(None);
+        }
+        let entry = TreeEntry {
+            mode: file
+                .old_mode
+                .clone()
+                .unwrap_or_else(|| "100644".to_string()),
+            hash,
+            name: path.clone(),
+            is_file: true,
+        };
+        entries.insert(path.clone(), entry);
+    }
+    Ok(Some(object_store.write_tree(entries)?))
+}
+
+// Apply the hunks to `content` in order, each where its header says or, if
+// the file has moved on since, at the nearest place its old lines match
+fn apply_hunks(content: &str, hunks: &[Hunk]) -> Option<String> {
+    let lines: Vec<&str> = content.split_inclusive('\n').collect();
+    let mut out = String::new();
+    let mut done = 0;
+    let mut offset: isize = 0;
+    for hunk in hunks {
+        let old: Vec<&str> = hunk
+            .lines
+            .iter()
+            .filter(|line| !line.starts_with('+'))
+            .map(|line| &line[1..])
+            .collect();
+        // A hunk that only adds lines is placed after its start line
+        let expected = if old.is_empty() {
+            hunk.old_start
+        } else {
+            hunk.old_start.saturating_sub(1)
+        };
+        let wanted =
+            (expected as isize + offset).clamp(done as isize, lines.len() as isize) as usize;
+        let fits =
+            |at: usize| at + old.len() <= lines.len() && lines[at..at + old.len()] == old[..];
+        let at = (0..=lines.len()).find_map(|distance| {
+            [wanted.checked_sub(distance), Some(wanted + distance)]
+                .into_iter()
+                .flatten()
+                .find(|&at| at >= done && fits(at))
+        })?;
+
+        out.push_str(&lines[done..at].concat());
+        for line in hunk.lines.iter().filter(|line| !line.starts_with('-')) {
+            out.push_str(&line[1..]);
+        }
+        offset = at as isize - expected as isize;
+        done = at + old.len();
+    }
+    out.push_str(&lines[done..].concat());
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Commit, commands};
+
+    const NUMBERS: &str = "1\n2\n3\n4\n5\n6\n7\n8\n";
+    const SEVEN: &str = "@@ -4,5 +4,5 @@\n 4\n 5\n 6\n-7\n+seven\n 8\n";
+    const FIVE: &str = "@@ -3,5 +3,5 @@\n 3\n 4\n-5\n+FIVE\n 6\n 7\n";
+
+    // A format-patch mail changing a.txt from the blob `old_id`
+    fn mail(subject: &str, old_id: &str, hunk: &str) -> String {
+        format!(
+            "From 0123456789abcdef0123456789abcdef01234567 Mon Sep 17 00:00:00 2001\n\
+             From: Ann Example <ann@example.com>\n\
+             Date: Mon, 5 Oct 2026 10:00:00 +0000\n\
+             Subject: [PATCH] {}\n\
+             \n\
+             ---\n\
+             diff --git a/a.txt b/a.txt\n\
+             index {}..1111111 100644\n\
+             --- a/a.txt\n\
+             +++ b/a.txt\n\
+             {}\
+             -- \n\
+             2.43.0\n\n",
+            subject,
+            &old_id[..7],
+            hunk
+        )
+    }
+
+    fn repo(name: &str) -> Repository {
+        let dir = std::env::temp_dir().join(format!("mini_git-am-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        commands::init(Some(dir.clone()), None, None, None).unwrap();
+        utils::get_repository(Some(dir)).unwrap()
+    }
+
+    // Commit a.txt with `content`, returning its blob id
+    fn commit_file(repo: &Repository, content: &str, message: &str) -> String {
+        fs::write(repo.work_dir.join("a.txt"), content).unwrap();
+        commands::add(repo, vec!["a.txt".to_string()], false).unwrap();
+        commands::commit(
+            repo,
+            Some(message.to_string()),
+            None,
+            None,
+            false,
+            Vec::new(),
+            false,
+        )
+        .unwrap();
+        ObjectStore::new(repo)
+            .store_blob(content.as_bytes())
+            .unwrap()
+    }
+
+    // Kept under .mini_git, out of the way of the working tree
+    fn write_mailbox(repo: &Repository, text: &str) -> String {
+        let path = repo.git_dir.join("patches.mbox");
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn read(repo: &Repository) -> String {
+        fs::read_to_string(repo.work_dir.join("a.txt")).unwrap()
+    }
+
+    fn head(repo: &Repository) -> Commit {
+        let head = utils::get_head_commit(repo).unwrap().unwrap();
+        ObjectStore::new(repo).load_commit(&head).unwrap()
+    }
+
+    #[test]
+    fn mailboxes_split_at_from_lines() {
+        let first = mail("One", &"a".repeat(40), SEVEN);
+        let second = mail("Two", &"b".repeat(40), FIVE);
+        assert_eq!(split_mailbox(&(first.clone() + &second)), [first, second]);
+        // Without separators the whole text is one patch, and a body line
+        // starting with "From " doesn't split it
+        let text = "Subject: One\n\nFrom here on\n";
+        assert_eq!(split_mailbox(text), [text]);
+    }
+
+    #[test]
+    fn hunks_apply_where_their_lines_moved_to() {
+        let patch = Patch::parse(&mail("Spell out seven", &"a".repeat(40), SEVEN)).unwrap();
+        assert_eq!(patch.subject(), "Spell out seven");
+        assert_eq!(patch.author, "Ann Example <ann@example.com>");
+        let hunks = &patch.files[0].hunks;
+
+        let seven = NUMBERS.replace("7\n", "seven\n");
+        assert_eq!(apply_hunks(NUMBERS, hunks).unwrap(), seven);
+        let shifted = format!("0\n0\n{}", NUMBERS);
+        assert_eq!(
+            apply_hunks(&shifted, hunks).unwrap(),
+            format!("0\n0\n{}", seven)
+        );
+        // A changed context line stops it applying
+        assert!(apply_hunks(&NUMBERS.replace("5\n", "five\n"), hunks).is_none());
+    }
+
+    #[test]
+    fn three_way_falls_back_to_the_blobs_the_patch_names() {
+        let repo = repo("three-way");
+        let base = commit_file(&repo, NUMBERS, "Numbers");
+        commit_file(&repo, &NUMBERS.replace("5\n", "five\n"), "Spell out five");
+        let mailbox = write_mailbox(&repo, &mail("Spell out seven", &base, SEVEN));
+
+        // Line 5 is context for the patch, so it only applies as a merge
+        assert!(am(&repo, vec![mailbox.clone()], false, false, false, false).is_err());
+        am(&repo, vec![], false, false, false, true).unwrap();
+        am(&repo, vec![mailbox], true, false, false, false).unwrap();
+
+        assert_eq!(read(&repo), "1\n2\n3\n4\nfive\n6\nseven\n8\n");
+        assert_eq!(head(&repo).message, "Spell out seven");
+        fs::remove_dir_all(&repo.work_dir).unwrap();
+    }
+
+    #[test]
+    fn stopped_sessions_continue_skip_or_abort() {
+        let repo = repo("stopped");
+        let base = commit_file(&repo, NUMBERS, "Numbers");
+        commit_file(&repo, &NUMBERS.replace("5\n", "five\n"), "Spell out five");
+        let start = utils::get_head_commit(&repo).unwrap();
+        let mailbox = write_mailbox(&repo, &mail("Shout five", &base, FIVE));
+        let apply = || am(&repo, vec![mailbox.clone()], true, false, false, false);
+
+        // Both sides changed line 5
+        assert!(apply().is_err());
+        let conflict = "<<<<<<< HEAD\nfive\n=======\nFIVE\n>>>>>>> Shout five\n";
+        assert!(read(&repo).contains(conflict), "{}", read(&repo));
+        am(&repo, vec![], false, false, false, true).unwrap();
+        assert_eq!(utils::get_head_commit(&repo).unwrap(), start);
+        assert_eq!(read(&repo), NUMBERS.replace("5\n", "five\n"));
+
+        assert!(apply().is_err());
+        am(&repo, vec![], false, false, true, false).unwrap();
+        assert_eq!(utils::get_head_commit(&repo).unwrap(), start);
+        assert!(!repo.git_dir.join(AM_DIR).exists());
+
+        assert!(apply().is_err());
+        fs::write(
+            repo.work_dir.join("a.txt"),
+            NUMBERS.replace("5\n", "Five\n"),
+        )
+        .unwrap();
+        commands::add(&repo, vec!["a.txt".to_string()], false).unwrap();
+        am(&repo, vec![], false, true, false, false).unwrap();
+        let commit = head(&repo);
+        assert_eq!(commit.message, "Shout five");
+        assert_eq!(commit.author, "Ann Example <ann@example.com>");
+        assert_eq!(commit.parent(), start.as_ref());
+        assert!(!repo.git_dir.join(AM_DIR).exists());
+        fs::remove_dir_all(&repo.work_dir).unwrap();
+    }
+}
@@ -0,0 +1,218 @@
+use crate::{Repository, Result, commands::fsck::fsck, hash::HashAlgorithm, utils};
+use chrono::{DateTime, Utc};
+use flate2::Compression;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// A backup is one gzip-compressed file holding the whole .mini_git directory:
+//
+//   mini_git backup 1
+//   <manifest, one line of JSON>
+//   <the contents of every file in the manifest, one after another>
+//
+// The manifest records HEAD, every ref, and each file's size and SHA-256, so
+// an archive can be checked before anything is restored from it.
+
+const HEADER: &str = "mini_git backup 1";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    created: DateTime<Utc>,
+    object_format: String,
+    head: String,
+    refs: BTreeMap<String, String>,
+    files: Vec<ManifestFile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ManifestFile {
+    // Relative to .mini_git, `/`-separated
+    path: String,
+    size: u64,
+    sha256: String,
+}
+
+pub fn backup(
+    repo: Option<&Repository>,
+    action: &str,
+    file: String,
+    directory: Option<String>,
+) -> Result<()> {
+    match action {
+        "create" => create(repo.ok_or("Not a mini_git repository")?, Path::new(&file)),
+        "verify" => verify(Path::new(&file)),
+        "restore" => restore(
+            Path::new(&file),
+            &PathBuf::from(directory.unwrap_or_else(|| ".".to_string())),
+        ),
+        _ => Err("Invalid backup action. Use: create, verify, restore".into()),
+    }
+}
+
+fn create(repo: &Repository, archive: &Path) -> Result<()> {
+    let mut files = Vec::new();
+    for entry in WalkDir::new(&repo.git_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|e| !is_transient(e.path()))
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .strip_prefix(&repo.git_dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let content = fs::read(entry.path())?;
+        files.push(ManifestFile {
+            path,
+            size: content.len() as u64,
+            sha256: HashAlgorithm::Sha256.hash(&content),
+        });
+    }
+
+    let manifest = Manifest {
+        created: Utc::now(),
+        object_format: HashAlgorithm::of_repo(repo)?.name().to_string(),
+        head: fs::read_to_string(repo.git_dir.join("HEAD"))?
+            .trim()
+            .to_string(),
+        refs: utils::list_refs(repo, "refs/")?.into_iter().collect(),
+        files,
+    };
+
+    let mut encoder = GzEncoder::new(File::create(archive)?, Compression::default());
+    writeln!(encoder, "{}", HEADER)?;
+    writeln!(encoder, "{}", serde_json::to_string(&manifest)?)?;
+    for file in &manifest.files {
+        let content = fs::read(repo.git_dir.join(&file.path))?;
+        if content.len() as u64 != file.size {
+            return Err(format!("{} changed while the backup was being made", file.path).into());
+        }
+        encoder.write_all(&content)?;
+    }
+    encoder.finish()?;
+
+    let total: u64 = manifest.files.iter().map(|file| file.size).sum();
+    println!(
+        "Backed up {} files ({} bytes, {} refs) to {}",
+        manifest.files.len(),
+        total,
+        manifest.refs.len(),
+        archive.display()
+    );
+    Ok(())
+}
+
+// Scratch directories and lock files belong to whatever was running at the
+// time, not to the repository
+fn is_transient(path: &Path) -> bool {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    name.starts_with("quarantine-") || name.starts_with("fetch-dry-run-") || name.ends_with(".lock")
+}
+
+// Read the archive, checking every file against the manifest, and hand each
+// one to `write`
+fn read_archive(
+    archive: &Path,
+    mut write: impl FnMut(&str, &[u8]) -> Result<()>,
+) -> Result<Manifest> {
+    let mut reader = BufReader::new(GzDecoder::new(File::open(archive)?));
+    let mut line = String::new();
+    if reader.read_line(&mut line).is_err() || line.trim_end() != HEADER {
+        return Err(format!("{} is not a mini_git backup", archive.display()).into());
+    }
+    line.clear();
+    reader
+        .read_line(&mut line)
+        .map_err(|e| format!("Backup manifest is unreadable: {}", e))?;
+    let manifest: Manifest =
+        serde_json::from_str(&line).map_err(|e| format!("Backup manifest is corrupt: {}", e))?;
+
+    for file in &manifest.files {
+        if file
+            .path
+            .split('/')
+            .any(|part| part == ".." || part.is_empty())
+        {
+            return Err(format!("Backup holds an unsafe path '{}'", file.path).into());
+        }
+        // The manifest's sizes aren't trusted until the bytes are there, so
+        // nothing is allocated up front
+        let mut content = Vec::new();
+        (&mut reader)
+            .take(file.size)
+            .read_to_end(&mut content)
+            .map_err(|e| format!("Backup is unreadable at {}: {}", file.path, e))?;
+        if content.len() as u64 != file.size {
+            return Err(format!("Backup is truncated at {}", file.path).into());
+        }
+        if HashAlgorithm::Sha256.hash(&content) != file.sha256 {
+            return Err(format!("{} does not match its checksum in the backup", file.path).into());
+        }
+        write(&file.path, &content)?;
+    }
+    Ok(manifest)
+}
+
+fn verify(archive: &Path) -> Result<()> {
+    let manifest = read_archive(archive, |_, _| Ok(()))?;
+    println!(
+        "Backup from {}: {} files, {} refs, HEAD {}; all checksums match",
+        manifest.created.format("%Y-%m-%d %H:%M:%S UTC"),
+        manifest.files.len(),
+        manifest.refs.len(),
+        manifest.head
+    );
+    Ok(())
+}
+
+// Recreate .mini_git in `directory` and check the result with fsck. The
+// working tree isn't part of a backup; `restore .` brings back the indexed
+// files.
+fn restore(archive: &Path, directory: &Path) -> Result<()> {
+    let git_dir = directory.join(".mini_git");
+    if git_dir.exists() {
+        return Err(format!("{} already exists", git_dir.display()).into());
+    }
+
+    let staging = directory.join(format!(".mini_git-restore-{}", std::process::id()));
+    let write = |path: &str, content: &[u8]| -> Result<()> {
+        let target = staging.join(path);
+        fs::create_dir_all(target.parent().unwrap())?;
+        fs::write(target, content)?;
+        Ok(())
+    };
+    let manifest = match read_archive(archive, write) {
+        Ok(manifest) => manifest,
+        Err(e) => {
+            let _ = fs::remove_dir_all(&staging);
+            return Err(e);
+        }
+    };
+    // Directories git_dir code expects even when they held no files
+    for dir in ["objects", "refs/heads", "refs/tags"] {
+        fs::create_dir_all(staging.join(dir))?;
+    }
+    fs::rename(&staging, &git_dir)?;
+
+    println!(
+        "Restored {} files and {} refs from backup of {}",
+        manifest.files.len(),
+        manifest.refs.len(),
+        manifest.created.format("%Y-%m-%d %H:%M:%S UTC")
+    );
+    let repo = Repository {
+        git_dir,
+        work_dir: directory.to_path_buf(),
+    };
+    fsck(&repo, true)
+}
@@ -0,0 +1,56 @@
+use crate::{
+    Commit, Repository, Result,
+    blame::{self, BlameLine},
+    object_store::ObjectStore,
+    pretty, repo_path, utils, worktree,
+};
+use std::collections::HashMap;
+
+// Show each line of `path` as it is in `revision` (HEAD by default) with the
+// commit that last changed it, that commit's author and date, and the line
+// number in the final file, as `git blame` lays them out
+pub fn blame(repo: &Repository, path: String, revision: Option<String>) -> Result<()> {
+    let [path] = <[String; 1]>::try_from(repo_path::precompose_args(repo, vec![path])?)
+        .map_err(|_| "Name one file to blame")?;
+    let path = path.trim_start_matches("./").to_string();
+    let start = match revision {
+        Some(revision) => utils::resolve_revision(repo, &revision)?,
+        None => utils::get_head_commit(repo)?.ok_or("No commits yet")?,
+    };
+
+    let object_store = ObjectStore::new(repo);
+    let entry = worktree::load_commit_tree(&object_store, &start)?
+        .entries
+        .remove(&path)
+        .ok_or(format!("'{}' is not in {}", path, &start[..8]))?;
+    let blob = object_store.load_blob(&entry.hash)?;
+    let content = String::from_utf8_lossy(&blob.content);
+    let lines: Vec<&str> = content.lines().collect();
+    let origins = blame::blame_file(&object_store, &start, &path)?;
+
+    let mut commits: HashMap<String, Commit> = HashMap::new();
+    for BlameLine { commit, .. } in &origins {
+        if !commits.contains_key(commit) {
+            commits.insert(commit.clone(), object_store.load_commit(commit)?);
+        }
+    }
+    let name = |commit: &str| pretty::split_identity(&commits[commit].author).0;
+    let name_width = origins
+        .iter()
+        .map(|origin| name(&origin.commit).chars().count())
+        .max()
+        .unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+
+    for (i, (origin, line)) in origins.iter().zip(&lines).enumerate() {
+        println!(
+            "{} ({:<name_width$} {} {:>number_width$}) {}",
+            &origin.commit[..8],
+            name(&origin.commit),
+            pretty::date(&commits[&origin.commit].timestamp),
+            i + 1,
+            line
+        );
+    }
+    Ok(())
+}
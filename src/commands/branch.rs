@@ -1,26 +1,87 @@
-use crate::{Repository, Result, utils};
+use crate::{
+    Repository, Result, graph,
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils,
+};
 use std::fs;
+use std::path::Path;
 
-pub fn branch(repo: &Repository, name: Option<String>, delete: bool) -> Result<()> {
+pub fn branch(
+    repo: &Repository,
+    name: Option<String>,
+    delete: bool,
+    force_delete: bool,
+    force: bool,
+    allow_protected: bool,
+) -> Result<()> {
     let refs_heads = repo.git_dir.join("refs").join("heads");
 
     if let Some(branch_name) = name {
-        if delete {
+        utils::validate_ref_name("branch", &branch_name)?;
+        if delete || force_delete {
             // Delete branch
             let branch_path = refs_heads.join(&branch_name);
-            if branch_path.exists() {
-                fs::remove_file(branch_path)?;
+            if branch_path.is_file() {
+                if utils::get_current_branch(repo)? == branch_name {
+                    return Err(format!(
+                        "Cannot delete branch '{}' checked out at '{}'",
+                        branch_name,
+                        repo.work_dir.display()
+                    )
+                    .into());
+                }
+                utils::check_branch_protection(repo, &branch_name, "delete it", allow_protected)?;
+                if !force_delete && !is_merged(repo, &branch_name)? {
+                    return Err(format!(
+                        "The branch '{}' is not fully merged.\nIf you are sure you want to delete it, run 'mini_git branch -D {}'.",
+                        branch_name, branch_name
+                    )
+                    .into());
+                }
+                let old = utils::get_branch_commit(repo, &branch_name)?;
+                fs::remove_file(&branch_path)?;
+                // Drop the directories of a nested name it left empty, so the
+                // name can be used for a branch again
+                let mut dir = branch_path.parent();
+                while let Some(current) = dir {
+                    if current == refs_heads || fs::remove_dir(current).is_err() {
+                        break;
+                    }
+                    dir = current.parent();
+                }
+                journal::record(
+                    repo,
+                    "branch",
+                    &format!("delete branch {}", branch_name),
+                    vec![RefUpdate::branch(&branch_name, old, None)],
+                )?;
                 println!("Deleted branch {}", branch_name);
             } else {
                 return Err(format!("Branch {} does not exist", branch_name).into());
             }
         } else {
-            // Create branch
-            let current_branch = utils::get_current_branch(repo)?;
-            let current_commit = utils::get_branch_commit(repo, &current_branch)?;
+            // Create branch; moving an existing one needs --force
+            check_name_conflict(&refs_heads, &branch_name)?;
+            if refs_heads.join(&branch_name).is_file() {
+                if !force {
+                    return Err(format!("A branch named '{}' already exists", branch_name).into());
+                }
+                utils::check_branch_protection(repo, &branch_name, "move it", allow_protected)?;
+            }
+
+            let current_commit = utils::get_head_commit(repo)?;
 
             if let Some(commit) = current_commit {
+                let old = utils::get_branch_commit(repo, &branch_name)?;
                 utils::update_branch(repo, &branch_name, &commit)?;
+                let action = if old.is_some() { "move" } else { "create" };
+                journal::record(
+                    repo,
+                    "branch",
+                    &format!("{} branch {}", action, branch_name),
+                    vec![RefUpdate::branch(&branch_name, old, Some(commit))],
+                )?;
                 println!("Created branch {}", branch_name);
             } else {
                 return Err("No commits yet, cannot create branch".into());
@@ -28,21 +89,71 @@ pub fn branch(repo: &Repository, name: Option<String>, delete: bool) -> Result<(
         }
     } else {
         // List branches
-        let current_branch = utils::get_current_branch(repo)?;
-
-        if refs_heads.exists() {
-            for entry in fs::read_dir(refs_heads)? {
-                let entry = entry?;
-                let branch_name = entry.file_name().to_string_lossy().to_string();
+        let current_branch = utils::head_branch(repo)?;
+        if current_branch.is_none()
+            && let Some(head) = utils::get_head_commit(repo)?
+        {
+            println!("* (HEAD detached at {})", &head[..8]);
+        }
 
-                if branch_name == current_branch {
-                    println!("* {}", branch_name);
-                } else {
-                    println!("  {}", branch_name);
-                }
+        for (name, _) in utils::list_refs(repo, "refs/heads/")? {
+            let branch_name = &name["refs/heads/".len()..];
+            if current_branch.as_deref() == Some(branch_name) {
+                println!("* {}", branch_name);
+            } else {
+                println!("  {}", branch_name);
             }
         }
     }
 
     Ok(())
 }
+
+// A branch is merged when its tip is reachable from HEAD or from its upstream
+// A branch is a file under refs/heads, so `a/b` can't exist beside `a`
+fn check_name_conflict(refs_heads: &Path, name: &str) -> Result<()> {
+    if refs_heads.join(name).is_dir() {
+        return Err(format!(
+            "Cannot create branch '{}': branches named '{}/...' exist",
+            name, name
+        )
+        .into());
+    }
+    let mut prefix = Path::new(name).parent();
+    while let Some(current) = prefix.filter(|p| !p.as_os_str().is_empty()) {
+        if refs_heads.join(current).is_file() {
+            return Err(format!(
+                "Cannot create branch '{}': branch '{}' exists",
+                name,
+                current.display()
+            )
+            .into());
+        }
+        prefix = current.parent();
+    }
+    Ok(())
+}
+
+fn is_merged(repo: &Repository, branch_name: &str) -> Result<bool> {
+    let Some(tip) = utils::get_branch_commit(repo, branch_name)? else {
+        return Ok(true);
+    };
+    let object_store = ObjectStore::new(repo);
+
+    let mut targets = Vec::new();
+    if let Ok(head) = utils::resolve_revision(repo, "HEAD") {
+        targets.push(head);
+    }
+    if let Some(upstream) = utils::get_upstream(repo, branch_name)?
+        && let Ok(commit) = utils::resolve_revision(repo, &upstream)
+    {
+        targets.push(commit);
+    }
+
+    for target in targets {
+        if graph::is_ancestor(&object_store, &tip, &target)? {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
@@ -0,0 +1,85 @@
+use crate::{Repository, Result, git_object, object_store::ObjectStore, repo_path, utils};
+use std::io::{self, BufRead, BufWriter, Write};
+
+pub enum CatFileMode {
+    Type,
+    Size,
+    Pretty,
+}
+
+pub fn cat_file(repo: &Repository, mode: CatFileMode, object: &str) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let hash = resolve_object(repo, &object_store, object)
+        .ok_or(format!("Not a valid object name {}", object))?;
+    let (kind, content) = object_store.read_typed(&hash)?;
+
+    let mut out = io::stdout().lock();
+    match mode {
+        CatFileMode::Type => writeln!(out, "{}", kind)?,
+        CatFileMode::Size => writeln!(out, "{}", content.len())?,
+        CatFileMode::Pretty => match kind.as_str() {
+            "tree" => {
+                let tree = object_store.load_tree(&hash)?;
+                let mut entries: Vec<_> = tree.entries.values().collect();
+                entries.sort_by(|a, b| a.name.cmp(&b.name));
+                for entry in entries {
+                    let kind = if entry.is_file { "blob" } else { "tree" };
+                    writeln!(
+                        out,
+                        "{} {} {}\t{}",
+                        entry.mode,
+                        kind,
+                        entry.hash,
+                        repo_path::quote(&entry.name)
+                    )?;
+                }
+            }
+            "commit" => {
+                let commit = object_store.load_commit(&hash)?;
+                out.write_all(&git_object::encode_commit(&commit))?;
+                writeln!(out)?;
+            }
+            _ => out.write_all(&content)?,
+        },
+    }
+    Ok(())
+}
+
+// Read object names from stdin, one per line, and answer each with
+// `<hash> <type> <size>`, followed by the content and a newline unless
+// `check_only`. Names that don't resolve get `<name> missing`. Output is
+// flushed after every object so a tool can hold the pipe open and query
+// objects one at a time.
+pub fn cat_file_batch(repo: &Repository, check_only: bool) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let mut out = BufWriter::new(io::stdout().lock());
+
+    for line in io::stdin().lock().lines() {
+        let line = line?;
+        let name = line.trim();
+        if name.is_empty() {
+            continue;
+        }
+
+        match resolve_object(repo, &object_store, name) {
+            Some(hash) => {
+                let (kind, content) = object_store.read_typed(&hash)?;
+                writeln!(out, "{} {} {}", hash, kind, content.len())?;
+                if !check_only {
+                    out.write_all(&content)?;
+                    writeln!(out)?;
+                }
+            }
+            None => writeln!(out, "{} missing", name)?,
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// Any revision or (abbreviated) object id naming an object we have
+fn resolve_object(repo: &Repository, object_store: &ObjectStore, name: &str) -> Option<String> {
+    utils::resolve_revision(repo, name)
+        .ok()
+        .filter(|hash| object_store.object_exists(hash))
+}
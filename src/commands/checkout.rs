@@ -1,73 +1,132 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::{Repository, Result, object_store::ObjectStore, reflog, utils, worktree};
 use std::fs;
 
 pub fn checkout(repo: &Repository, branch_or_commit: String) -> Result<()> {
+    // `-` and `@{-N}` name a previously checked-out branch or commit
+    let branch_or_commit = match branch_or_commit.as_str() {
+        "-" => utils::previous_checkout(repo, 1)?,
+        target => match target.strip_prefix("@{-").and_then(|n| n.strip_suffix('}')) {
+            Some(n) => utils::previous_checkout(repo, n.parse()?)?,
+            None => branch_or_commit,
+        },
+    };
+    let previous = current_head_name(repo);
+    let previous_commit = utils::resolve_revision(repo, "HEAD").ok();
+
     let refs_heads = repo.git_dir.join("refs").join("heads");
     let branch_path = refs_heads.join(&branch_or_commit);
+    let object_store = ObjectStore::new(repo);
 
     if branch_path.exists() {
+        // Refuse before touching HEAD if local work would be lost
+        let commit_hash = utils::get_branch_commit(repo, &branch_or_commit)?;
+        if let Some(commit) = &commit_hash {
+            let tree = worktree::load_commit_tree(&object_store, commit)?;
+            worktree::check_checkout_safety(repo, &tree)?;
+        }
+
         // Checkout branch
         utils::update_head(repo, &branch_or_commit)?;
 
         // Update working directory
-        let commit_hash = utils::get_branch_commit(repo, &branch_or_commit)?;
-        if let Some(commit) = commit_hash {
-            restore_working_directory(repo, &commit)?;
+        if let Some(commit) = &commit_hash {
+            switch_working_directory(repo, commit)?;
+            reflog::append(
+                repo,
+                "HEAD",
+                previous_commit.as_deref(),
+                commit,
+                &format!("checkout: moving from {} to {}", previous, branch_or_commit),
+            )?;
         }
 
         println!("Switched to branch '{}'", branch_or_commit);
     } else {
-        // Try to checkout commit
-        let object_store = ObjectStore::new(repo);
-        if object_store.object_exists(&branch_or_commit) {
-            // Update HEAD to point directly to commit (detached HEAD)
-            fs::write(repo.git_dir.join("HEAD"), &branch_or_commit)?;
-            restore_working_directory(repo, &branch_or_commit)?;
-
-            println!("HEAD is now at {} (detached HEAD)", &branch_or_commit[..8]);
-        } else {
-            return Err(format!("Branch or commit '{}' not found", branch_or_commit).into());
-        }
+        // Tags, remote-tracking branches and commits detach HEAD
+        let commit = utils::resolve_revision(repo, &branch_or_commit)
+            .map_err(|_| format!("Branch or commit '{}' not found", branch_or_commit))?;
+        detach_head(repo, &branch_or_commit, &commit, &previous, previous_commit)?;
     }
 
     Ok(())
 }
 
-fn restore_working_directory(repo: &Repository, commit_hash: &str) -> Result<()> {
+// Like checkout, but only switches branches unless `detach` is given
+pub fn switch(repo: &Repository, target: String, detach: bool) -> Result<()> {
+    let is_branch = repo
+        .git_dir
+        .join("refs")
+        .join("heads")
+        .join(&target)
+        .is_file();
+    if detach {
+        let commit = utils::resolve_revision(repo, &target)?;
+        let previous = current_head_name(repo);
+        let previous_commit = utils::resolve_revision(repo, "HEAD").ok();
+        return detach_head(repo, &target, &commit, &previous, previous_commit);
+    }
+    if !is_branch {
+        return Err(format!(
+            "'{}' is not a branch; use --detach to check it out in detached HEAD state",
+            target
+        )
+        .into());
+    }
+    checkout(repo, target)
+}
+
+fn detach_head(
+    repo: &Repository,
+    target: &str,
+    commit: &str,
+    previous: &str,
+    previous_commit: Option<String>,
+) -> Result<()> {
     let object_store = ObjectStore::new(repo);
-    let commit = object_store.load_commit(commit_hash)?;
-    let tree = object_store.load_tree(&commit.tree)?;
+    let tree = worktree::load_commit_tree(&object_store, commit)?;
+    worktree::check_checkout_safety(repo, &tree)?;
 
-    // Clear working directory (except .mini_git)
-    for entry in fs::read_dir(&repo.work_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    // Update HEAD to point directly to commit (detached HEAD)
+    fs::write(repo.git_dir.join("HEAD"), commit)?;
+    switch_working_directory(repo, commit)?;
+    reflog::append(
+        repo,
+        "HEAD",
+        previous_commit.as_deref(),
+        commit,
+        &format!("checkout: moving from {} to {}", previous, target),
+    )?;
 
-        if path.file_name().unwrap() == ".mini_git" {
-            continue;
-        }
-
-        if path.is_file() {
-            fs::remove_file(&path)?;
-        } else if path.is_dir() {
-            fs::remove_dir_all(&path)?;
-        }
+    if target != commit {
+        println!("Note: switching to '{}'.", target);
+        println!();
+        println!("You are in 'detached HEAD' state: HEAD points at a commit rather");
+        println!("than a branch. Check out a branch to get back, e.g. 'mini_git checkout -'.");
+        println!();
     }
+    println!("HEAD is now at {} (detached HEAD)", &commit[..8]);
+    Ok(())
+}
 
-    // Restore files from tree
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
-
-            // Create parent directories if needed
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
+// Make the working directory and index match a commit, removing tracked files
+// it doesn't contain and discarding local edits
+pub(crate) fn restore_working_directory(repo: &Repository, commit_hash: &str) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let tree = worktree::load_commit_tree(&object_store, commit_hash)?;
+    worktree::reset_to_tree(repo, &object_store, &tree)
+}
 
-            fs::write(file_path, &blob.content)?;
-        }
-    }
+fn switch_working_directory(repo: &Repository, commit_hash: &str) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let tree = worktree::load_commit_tree(&object_store, commit_hash)?;
+    worktree::switch_to_tree(repo, &object_store, &tree)
+}
 
-    Ok(())
+// Branch name HEAD points at, or the commit hash when detached
+fn current_head_name(repo: &Repository) -> String {
+    let head = fs::read_to_string(repo.git_dir.join("HEAD")).unwrap_or_default();
+    let head = head.trim();
+    head.strip_prefix("ref: refs/heads/")
+        .unwrap_or(head)
+        .to_string()
 }
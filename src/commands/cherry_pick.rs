@@ -0,0 +1,271 @@
+use crate::{
+    Repository, Result, Tree,
+    commands::commit::create_commit,
+    commands::merge::{self, merge_clean_contents, merge_trees, write_conflicts},
+    commands::revert,
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils, worktree,
+};
+use std::collections::HashMap;
+use std::fs;
+
+// Sequencer state kept while a cherry-pick of several commits runs, so it
+// can stop on a conflict and be resumed or abandoned:
+//
+//   .mini_git/sequencer/head   the commit HEAD was at before the first pick
+//   .mini_git/sequencer/todo   the commits still to pick, one per line
+//
+// The commit a conflict stopped at is named by CHERRY_PICK_HEAD, with its
+// message in MERGE_MSG, as for a stopped merge.
+
+const SEQUENCER_DIR: &str = "sequencer";
+
+// Apply the changes each of `commits` (revisions or `a..b` ranges) made on
+// top of HEAD, oldest first, keeping their authors, dates and messages
+pub fn cherry_pick(
+    repo: &Repository,
+    commits: Vec<String>,
+    continue_pick: bool,
+    abort: bool,
+) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    if abort {
+        return abort_pick(repo, &object_store);
+    }
+    if continue_pick {
+        return continue_pick_sequence(repo, &object_store);
+    }
+
+    if repo.git_dir.join(SEQUENCER_DIR).exists() {
+        return Err("A cherry-pick is in progress; use --continue or --abort".into());
+    }
+    if merge::merge_head(repo)?.is_some() || revert::revert_head(repo)?.is_some() {
+        return Err("A merge or revert is in progress; commit or reset it first".into());
+    }
+    if !utils::load_index(repo)?.unmerged.is_empty() {
+        return Err("Cannot cherry-pick with unmerged paths; resolve them first".into());
+    }
+    if commits.is_empty() {
+        return Err("Name at least one commit to cherry-pick".into());
+    }
+    let head = utils::get_head_commit(repo)?.ok_or("Cannot cherry-pick: HEAD has no commits")?;
+
+    let mut todo = Vec::new();
+    for revision in &commits {
+        let hashes = if revision.contains("..") {
+            let mut range = utils::commit_range(repo, revision)?;
+            range.reverse();
+            range
+        } else {
+            vec![utils::resolve_revision(repo, revision)?]
+        };
+        for hash in hashes {
+            let commit = object_store
+                .load_commit(&hash)
+                .map_err(|_| format!("'{}' is not a commit", revision))?;
+            if commit.parents.len() > 1 {
+                return Err(format!(
+                    "Commit {} is a merge; cherry-picking merges is not supported",
+                    &hash[..8]
+                )
+                .into());
+            }
+            todo.push(hash);
+        }
+    }
+    if todo.is_empty() {
+        return Err(format!("Nothing to cherry-pick in {}", commits.join(" ")).into());
+    }
+
+    let sequencer = repo.git_dir.join(SEQUENCER_DIR);
+    fs::create_dir_all(&sequencer)?;
+    fs::write(sequencer.join("head"), format!("{}\n", head))?;
+    pick_sequence(repo, &object_store, todo)
+}
+
+// Pick each commit in turn, stopping at the first conflict
+fn pick_sequence(repo: &Repository, object_store: &ObjectStore, todo: Vec<String>) -> Result<()> {
+    let sequencer = repo.git_dir.join(SEQUENCER_DIR);
+    for (i, hash) in todo.iter().enumerate() {
+        // The commit being picked stays listed until it is done, so a
+        // failure part way through can be retried with --continue
+        fs::write(sequencer.join("todo"), todo[i..].join("\n") + "\n")?;
+        if !pick(repo, object_store, hash)? {
+            fs::write(sequencer.join("todo"), todo[i + 1..].join("\n") + "\n")?;
+            println!("Fix the conflicts, `add` them and run `cherry-pick --continue`");
+            println!("(or `cherry-pick --abort` to go back to where you started)");
+            return Ok(());
+        }
+    }
+    fs::remove_dir_all(&sequencer)?;
+    Ok(())
+}
+
+// Apply one commit; false when it stopped on conflicts
+fn pick(repo: &Repository, object_store: &ObjectStore, hash: &str) -> Result<bool> {
+    let commit = object_store.load_commit(hash)?;
+    let subject = commit.message.lines().next().unwrap_or("");
+    let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+
+    let base_tree = match commit.parent() {
+        Some(parent) => worktree::load_commit_tree(object_store, parent)?,
+        None => Tree {
+            hash: String::new(),
+            entries: HashMap::new(),
+        },
+    };
+    let our_tree = worktree::load_commit_tree(object_store, &head)?;
+    let their_tree = object_store.load_tree(&commit.tree)?;
+    let result = merge_trees(object_store, &base_tree, &our_tree, &their_tree)?;
+    let result = merge_clean_contents(
+        object_store,
+        result,
+        &base_tree,
+        &our_tree,
+        &their_tree,
+        None,
+    )?;
+
+    if result.conflicts.is_empty() && result.tree.hash == our_tree.hash {
+        println!("Skipping {} (already applied): {}", &hash[..8], subject);
+        return Ok(true);
+    }
+    worktree::check_checkout_safety(repo, &result.tree)?;
+    worktree::switch_to_tree(repo, object_store, &result.tree)?;
+
+    if !result.conflicts.is_empty() {
+        let conflicts =
+            merge::conflict_stages(&result.conflicts, &base_tree, &our_tree, &their_tree);
+        for (path, _) in &conflicts {
+            println!("CONFLICT: Cherry-pick conflict in {}", path);
+        }
+        let base_label = format!("parent of {}", &hash[..8]);
+        write_conflicts(repo, object_store, conflicts, &base_label, &hash[..8])?;
+        merge::write_merge_state(repo, "CHERRY_PICK_HEAD", hash, &commit.message)?;
+        println!("Could not apply {}... {}", &hash[..8], subject);
+        return Ok(false);
+    }
+
+    let new_commit = create_commit(
+        object_store,
+        &result.tree.hash,
+        vec![head.clone()],
+        commit.author.clone(),
+        commit.message.clone(),
+        commit.timestamp,
+    )?;
+    record_pick(repo, &head, &new_commit, subject)?;
+    println!("[{}] {}", &new_commit[..8], subject);
+    Ok(true)
+}
+
+fn record_pick(repo: &Repository, old: &str, new: &str, subject: &str) -> Result<()> {
+    utils::update_head_commit(repo, new)?;
+    journal::record(
+        repo,
+        "cherry-pick",
+        subject,
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: Some(old.to_string()),
+            new: Some(new.to_string()),
+        }],
+    )
+}
+
+// Commit the resolved conflict (unless `commit` already did) and pick the
+// rest of the commits
+fn continue_pick_sequence(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let sequencer = repo.git_dir.join(SEQUENCER_DIR);
+    let stopped_at = cherry_pick_head(repo)?;
+    if !sequencer.exists() && stopped_at.is_none() {
+        return Err("No cherry-pick in progress".into());
+    }
+    let index = utils::load_index(repo)?;
+    if !index.unmerged.is_empty() {
+        let mut paths: Vec<&str> = index.unmerged.keys().map(String::as_str).collect();
+        paths.sort();
+        return Err(format!(
+            "Unmerged paths ({}); resolve them and `add` them first",
+            paths.join(", ")
+        )
+        .into());
+    }
+
+    if let Some(hash) = stopped_at {
+        let commit = object_store.load_commit(&hash)?;
+        let subject = commit.message.lines().next().unwrap_or("").to_string();
+        let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+        let tree = worktree::tree_from_index(object_store, &index)?;
+        if tree.hash == worktree::load_commit_tree(object_store, &head)?.hash {
+            println!(
+                "Skipping {} (nothing left to commit): {}",
+                &hash[..8],
+                subject
+            );
+        } else {
+            let message = merge::merge_msg(repo).unwrap_or(commit.message);
+            let new_commit = create_commit(
+                object_store,
+                &tree.hash,
+                vec![head.clone()],
+                commit.author,
+                message,
+                commit.timestamp,
+            )?;
+            record_pick(repo, &head, &new_commit, &subject)?;
+            println!("[{}] {}", &new_commit[..8], subject);
+        }
+        merge::clear_merge_state(repo)?;
+    }
+
+    let todo = match fs::read_to_string(sequencer.join("todo")) {
+        Ok(todo) => todo.lines().map(str::to_string).collect(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    if sequencer.exists() {
+        pick_sequence(repo, object_store, todo)?;
+    }
+    Ok(())
+}
+
+// Put HEAD, the index and the working tree back to where the cherry-pick
+// started
+fn abort_pick(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let sequencer = repo.git_dir.join(SEQUENCER_DIR);
+    let original = match fs::read_to_string(sequencer.join("head")) {
+        Ok(head) => head.trim().to_string(),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err("No cherry-pick in progress".into());
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let current = utils::get_head_commit(repo)?;
+    let tree = worktree::load_commit_tree(object_store, &original)?;
+    worktree::reset_to_tree(repo, object_store, &tree)?;
+    merge::clear_merge_state(repo)?;
+    utils::update_head_commit(repo, &original)?;
+    journal::record(
+        repo,
+        "cherry-pick",
+        "abort",
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: current,
+            new: Some(original.clone()),
+        }],
+    )?;
+    fs::remove_dir_all(&sequencer)?;
+
+    println!("Cherry-pick aborted; HEAD is back at {}", &original[..8]);
+    Ok(())
+}
+
+// A cherry-pick stopped on conflicts: CHERRY_PICK_HEAD names the commit
+// being picked
+pub(crate) fn cherry_pick_head(repo: &Repository) -> Result<Option<String>> {
+    merge::read_state_file(repo, "CHERRY_PICK_HEAD")
+}
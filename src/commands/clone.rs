@@ -1,8 +1,29 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::{
+    Repository, Result, TreeEntry,
+    access::{self, Operation},
+    bundle::Bundle,
+    commands::{checkout::restore_working_directory, init::initial_config},
+    config,
+    fsck::{self, Side},
+    hash::{self, HashAlgorithm},
+    mirror::{self, MirrorUpdate},
+    object_store::{
+        ObjectStorage, ObjectStore, add_alternate, copy_object_file, copy_packs, read_alternates,
+    },
+    repo_path,
+    transport::{self, Transport},
+    utils, worktree,
+};
 use std::fs;
-use std::path::PathBuf;
-
-pub fn clone(url: String, directory: Option<String>) -> Result<()> {
+use std::path::{Path, PathBuf};
+
+pub fn clone(
+    url: String,
+    directory: Option<String>,
+    reference: Option<String>,
+    no_hardlinks: bool,
+    mirror: bool,
+) -> Result<()> {
     let source_path = PathBuf::from(&url);
 
     // Check if source is a local path
@@ -13,111 +34,477 @@ pub fn clone(url: String, directory: Option<String>) -> Result<()> {
         return Ok(());
     }
 
-    let dir_name = directory.unwrap_or_else(|| {
-        source_path
-            .file_name()
-            .unwrap_or_default()
-            .to_string_lossy()
-            .to_string()
-    });
+    let dir_name = directory.unwrap_or_else(|| default_directory(&source_path));
 
-    let target_dir = PathBuf::from(&dir_name);
-    if target_dir.exists() {
+    // A clone that was interrupted leaves this marker behind; cloning the same
+    // source into the same directory again picks up where it stopped
+    let marker = clone_marker(&dir_name);
+    let resuming = fs::read_to_string(&marker).is_ok_and(|source| source.trim() == url);
+    if Path::new(&dir_name).exists() && !resuming {
         return Err(format!("Directory '{}' already exists", dir_name).into());
     }
 
-    println!(
-        "Cloning local repository from '{}' into '{}'...",
-        url, dir_name
-    );
+    let options = CloneOptions {
+        reference,
+        hardlinks: !no_hardlinks,
+        mirror,
+        resuming,
+    };
+    match Transport::for_url(&url) {
+        // An archived repository is cloned from a scratch copy, which goes
+        // again once the clone is done
+        Transport::Archive(archive) => {
+            println!("Unpacking archive '{}'...", url);
+            let scratch =
+                std::env::temp_dir().join(format!("mini_git-clone-{}", std::process::id()));
+            let result = transport::unpack_archive(&archive, &scratch).and_then(|git_dir| {
+                clone_into(&url, Source::Repository(&git_dir), &dir_name, options)
+            });
+            let _ = fs::remove_dir_all(&scratch);
+            result
+        }
+        Transport::Bundle(path) => {
+            let bundle = Bundle::open(&path)?;
+            clone_into(&url, Source::Bundle(&bundle), &dir_name, options)
+        }
+        Transport::Local(_) | Transport::Ext(_) => clone_into(
+            &url,
+            Source::Repository(&source_path.join(".mini_git")),
+            &dir_name,
+            options,
+        ),
+    }
+}
+
+// What a clone copies: a repository's git directory, or a bundle
+enum Source<'a> {
+    Repository(&'a Path),
+    Bundle(&'a Bundle),
+}
 
-    // Create target directory
-    fs::create_dir_all(&target_dir)?;
+struct CloneOptions {
+    reference: Option<String>,
+    hardlinks: bool,
+    mirror: bool,
+    resuming: bool,
+}
 
-    // Initialize repository in target directory
-    let git_dir = target_dir.join(".mini_git");
-    fs::create_dir_all(&git_dir)?;
-    fs::create_dir_all(git_dir.join("objects"))?;
-    fs::create_dir_all(git_dir.join("refs").join("heads"))?;
-    fs::create_dir_all(git_dir.join("refs").join("remotes").join("origin"))?;
+fn clone_marker(dir_name: &str) -> PathBuf {
+    Path::new(dir_name)
+        .join(".mini_git")
+        .join("CLONE_IN_PROGRESS")
+}
+
+// The source's name, less any extension saying what kind of file it is
+fn default_directory(source_path: &Path) -> String {
+    let name = source_path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_string();
+    [".tar.zst", ".tzst", ".tar.gz", ".tgz", ".tar", ".bundle"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix).filter(|stem| !stem.is_empty()))
+        .map_or(name.clone(), str::to_string)
+}
+
+fn clone_into(url: &str, source: Source, dir_name: &str, options: CloneOptions) -> Result<()> {
+    let target_dir = PathBuf::from(dir_name);
+    let marker = clone_marker(dir_name);
+
+    // A bundle's refs must be complete histories to start a repository from
+    if let Source::Bundle(bundle) = source
+        && !bundle.prerequisites.is_empty()
+    {
+        return Err(format!(
+            "Bundle '{}' needs {} commit(s) it doesn't carry; fetch it into a repository that has them",
+            url,
+            bundle.prerequisites.len()
+        )
+        .into());
+    }
+
+    // Resolve the reference repository before creating anything on disk
+    let reference_objects = match options.reference {
+        Some(reference) => {
+            let Source::Repository(source_git_dir) = source else {
+                return Err("--reference needs a repository to clone, not a bundle".into());
+            };
+            let objects = PathBuf::from(&reference).join(".mini_git").join("objects");
+            if !objects.is_dir() {
+                return Err(
+                    format!("Reference '{}' is not a mini-git repository", reference).into(),
+                );
+            }
+            hash::ensure_same_format(
+                source_git_dir,
+                &PathBuf::from(&reference).join(".mini_git"),
+                "Reference repository",
+            )?;
+            Some(fs::canonicalize(objects)?)
+        }
+        None => None,
+    };
 
-    // Set up repository structure
+    // The branch checked out, and HEAD's target in a mirror
+    let branch = match source {
+        Source::Repository(_) => "main".to_string(),
+        Source::Bundle(bundle) => bundle.default_branch().unwrap_or("main".to_string()),
+    };
+
+    let git_dir = target_dir.join(".mini_git");
     let repo = Repository {
         git_dir: git_dir.clone(),
         work_dir: target_dir.clone(),
     };
 
-    // Add remote origin
-    add_remote(&repo, "origin".to_string(), url.clone())?;
+    if options.resuming {
+        println!(
+            "Resuming interrupted clone of '{}' into '{}'...",
+            url, dir_name
+        );
+    } else {
+        match source {
+            Source::Repository(_) => println!(
+                "Cloning local repository from '{}' into '{}'...",
+                url, dir_name
+            ),
+            Source::Bundle(_) => {
+                println!("Cloning from bundle '{}' into '{}'...", url, dir_name)
+            }
+        }
 
-    // Create HEAD pointing to main
-    fs::write(git_dir.join("HEAD"), "ref: refs/heads/main")?;
+        // Initialize repository in target directory
+        fs::create_dir_all(git_dir.join("objects"))?;
+        fs::create_dir_all(git_dir.join("refs").join("heads"))?;
+        fs::create_dir_all(git_dir.join("refs").join("remotes").join("origin"))?;
+        fs::write(&marker, url)?;
+
+        // The clone names objects the way its source does; a bundle's
+        // objects are git's
+        let (source_format, source_storage) = match source {
+            Source::Repository(source_git_dir) => (
+                HashAlgorithm::of_git_dir(source_git_dir)?,
+                ObjectStorage::of_git_dir(source_git_dir)?,
+            ),
+            Source::Bundle(bundle) => (bundle.algorithm, ObjectStorage::Git),
+        };
+        fs::write(
+            git_dir.join("config"),
+            initial_config(source_format, source_storage),
+        )?;
+
+        // Add remote origin
+        add_remote(&repo, "origin".to_string(), url.to_string())?;
+
+        if options.mirror {
+            // Later fetches replace every ref, as the source has them
+            let config_path = git_dir.join("config");
+            config::set_value(&config_path, "remote.origin.fetch", "+refs/*:refs/*")?;
+            config::set_value(&config_path, "remote.origin.mirror", "true")?;
+            let source_head = match source {
+                Source::Repository(source_git_dir) => {
+                    fs::read_to_string(source_git_dir.join("HEAD"))?
+                }
+                Source::Bundle(_) => format!("ref: refs/heads/{}", branch),
+            };
+            fs::write(git_dir.join("HEAD"), source_head.trim())?;
+        } else {
+            fs::write(git_dir.join("HEAD"), format!("ref: refs/heads/{}", branch))?;
+        }
+    }
 
-    // Clone from local repository
-    clone_local(&repo, &url)?;
+    match source {
+        Source::Repository(source_git_dir) => clone_local(
+            &repo,
+            source_git_dir,
+            reference_objects.as_deref(),
+            options.hardlinks,
+            options.mirror,
+        )?,
+        Source::Bundle(bundle) => clone_bundle(&repo, bundle, &branch, options.mirror)?,
+    }
+    fs::remove_file(&marker)?;
 
     println!("Clone completed successfully");
     Ok(())
 }
 
-fn clone_local(repo: &Repository, source_path: &str) -> Result<()> {
-    let source_git_dir = PathBuf::from(source_path).join(".mini_git");
+fn clone_local(
+    repo: &Repository,
+    source_git_dir: &Path,
+    reference_objects: Option<&Path>,
+    hardlinks: bool,
+    mirror: bool,
+) -> Result<()> {
     if !source_git_dir.exists() {
         return Err("Source is not a mini-git repository".into());
     }
 
+    access::authorize_all_heads(
+        source_git_dir,
+        &utils::served_refs_dir(source_git_dir),
+        Operation::Fetch,
+    )?;
+
     println!("Copying repository data...");
 
     // Copy objects
     let source_objects = source_git_dir.join("objects");
     let target_objects = repo.git_dir.join("objects");
 
-    let mut objects_copied = 0;
+    // Objects the source itself borrows must stay reachable from the clone
+    let mut borrowed = Vec::new();
+    for alternate in read_alternates(&source_objects) {
+        add_alternate(&target_objects, &fs::canonicalize(&alternate)?)?;
+        borrowed.push(alternate);
+    }
+
+    if let Some(reference_objects) = reference_objects {
+        add_alternate(&target_objects, reference_objects)?;
+        borrowed.push(reference_objects.to_path_buf());
+        borrowed.extend(read_alternates(reference_objects));
+        println!(
+            "Borrowing objects from reference repository {}",
+            reference_objects.display()
+        );
+    }
+
+    let mut stats = CopyStats::default();
     if source_objects.exists() {
-        objects_copied = copy_dir_recursive(&source_objects, &target_objects)?;
+        stats = copy_objects(&source_objects, &target_objects, &borrowed, hardlinks)?;
     }
 
     // Copy refs
-    let source_refs = source_git_dir.join("refs");
+    let source_refs = utils::served_refs_dir(source_git_dir);
     let target_refs = repo.git_dir.join("refs");
 
+    if fsck::transfer_checks_enabled(repo, Side::Fetch)? {
+        let mut tips = Vec::new();
+        if let Ok(entries) = fs::read_dir(source_refs.join("heads")) {
+            for entry in entries {
+                let path = entry?.path();
+                if path.is_file() {
+                    tips.push(fs::read_to_string(path)?.trim().to_string());
+                }
+            }
+        }
+        let checked = fsck::check_connectivity(&ObjectStore::new(repo), &tips)
+            .map_err(|e| format!("Cloned objects failed verification: {}", e))?;
+        println!("Verified {} objects", checked);
+    }
+
+    // A mirror takes every ref as it is and checks nothing out: it is a
+    // copy of the repository, not somewhere to work
+    if mirror {
+        let updates = mirror::plan(&source_refs, &target_refs)?;
+        mirror::apply(&target_refs, &updates)?;
+        println!(
+            "Mirrored {} refs; copied {} objects from source repository",
+            updates.len(),
+            stats.copied + stats.linked
+        );
+        return Ok(());
+    }
+
     if source_refs.exists() {
         copy_refs(&source_refs, &target_refs)?;
     }
 
-    // Get the main branch commit and checkout
-    if let Some(main_commit) = utils::get_branch_commit(repo, "main")? {
-        checkout_commit(repo, &main_commit)?;
-        println!("Checked out main branch at commit {}", &main_commit[..8]);
+    check_out(repo, "main")?;
+
+    if stats.linked > 0 {
+        println!(
+            "Copied {} objects from source repository ({} hardlinked)",
+            stats.copied + stats.linked,
+            stats.linked
+        );
     } else {
+        println!("Copied {} objects from source repository", stats.copied);
+    }
+    if stats.resumed > 0 {
+        println!(
+            "Skipped {} objects received before the interruption",
+            stats.resumed
+        );
+    }
+    Ok(())
+}
+
+// Take a bundle's objects and branches, as clone_local does a repository's
+fn clone_bundle(repo: &Repository, bundle: &Bundle, branch: &str, mirror: bool) -> Result<()> {
+    println!("Unpacking bundle...");
+    let objects = bundle.unpack(&repo.git_dir.join("objects"))?;
+    let heads = bundle.heads();
+
+    if fsck::transfer_checks_enabled(repo, Side::Fetch)? {
+        let tips: Vec<String> = heads.iter().map(|(_, id)| id.clone()).collect();
+        let checked = fsck::check_connectivity(&ObjectStore::new(repo), &tips)
+            .map_err(|e| format!("Cloned objects failed verification: {}", e))?;
+        println!("Verified {} objects", checked);
+    }
+
+    let refs_dir = repo.git_dir.join("refs");
+    if mirror {
+        let updates: Vec<MirrorUpdate> = bundle
+            .refs
+            .iter()
+            .filter(|(name, _)| name != "HEAD")
+            .map(|(name, id)| MirrorUpdate {
+                name: name.clone(),
+                old: None,
+                new: Some(id.clone()),
+            })
+            .collect();
+        mirror::apply(&refs_dir, &updates)?;
+        println!(
+            "Mirrored {} refs; unpacked {} objects from bundle",
+            updates.len(),
+            objects
+        );
+        return Ok(());
+    }
+
+    // Each branch, and origin's copy of it
+    for (name, id) in &heads {
+        for dir in [
+            refs_dir.join("heads"),
+            refs_dir.join("remotes").join("origin"),
+        ] {
+            let path = dir.join(name);
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(path, id)?;
+        }
+    }
+
+    check_out(repo, branch)?;
+    println!("Unpacked {} objects from bundle", objects);
+    Ok(())
+}
+
+// Check out `branch`, tracking origin's, and make sure of what was written
+fn check_out(repo: &Repository, branch: &str) -> Result<()> {
+    let Some(commit) = utils::get_branch_commit(repo, branch)? else {
         println!("No commits found in source repository");
+        return Ok(());
+    };
+    set_upstream(repo, branch, "origin")?;
+    restore_working_directory(repo, &commit)?;
+    println!("Checked out {} branch at commit {}", branch, &commit[..8]);
+    verify_checkout(repo, &commit)
+}
+
+// Read back every file the checkout wrote and hash it against the commit's
+// tree, so a copy cut short by a flaky disk or share fails the clone here
+// rather than surfacing later as a mysterious local change
+fn verify_checkout(repo: &Repository, commit: &str) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let tree = worktree::load_commit_tree(&object_store, commit)?;
+
+    let mut files: Vec<(&String, &TreeEntry)> = tree
+        .entries
+        .iter()
+        .filter(|(_, entry)| entry.is_file)
+        .collect();
+    files.sort_by_key(|(path, _)| *path);
+
+    let mut bytes = 0;
+    let mut problems = Vec::new();
+    for (path, entry) in files.iter().copied() {
+        match fs::read(repo_path::to_path(&repo.work_dir, path)) {
+            Ok(content) => {
+                bytes += content.len();
+                if object_store.hash_content(&content) != entry.hash {
+                    problems.push(format!("{}: content does not match the commit", path));
+                }
+            }
+            Err(e) => problems.push(format!("{}: {}", path, e)),
+        }
     }
 
-    println!("Copied {} objects from source repository", objects_copied);
+    if !problems.is_empty() {
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        return Err(format!(
+            "{} of {} checked-out files failed verification",
+            problems.len(),
+            files.len()
+        )
+        .into());
+    }
+    println!(
+        "Verified {} files ({} bytes) against commit {}",
+        files.len(),
+        bytes,
+        &commit[..8]
+    );
     Ok(())
 }
 
-fn copy_dir_recursive(src: &PathBuf, dst: &PathBuf) -> Result<usize> {
+#[derive(Default)]
+struct CopyStats {
+    copied: usize,
+    linked: usize,
+    resumed: usize,
+}
+
+fn copy_objects(
+    src: &Path,
+    dst: &Path,
+    borrowed: &[PathBuf],
+    hardlinks: bool,
+) -> Result<CopyStats> {
     fs::create_dir_all(dst)?;
-    let mut files_copied = 0;
+    let mut stats = CopyStats::default();
 
     for entry in fs::read_dir(src)? {
         let entry = entry?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
+        let src_dir = entry.path();
+        let dir_name = entry.file_name();
 
-        if src_path.is_dir() {
-            files_copied += copy_dir_recursive(&src_path, &dst_path)?;
-        } else {
-            fs::copy(&src_path, &dst_path)?;
-            files_copied += 1;
+        // objects/info holds alternates, which were already rewritten above,
+        // and packs are copied whole below
+        if !src_dir.is_dir() || dir_name == "info" || dir_name == "pack" {
+            continue;
+        }
+
+        let dst_dir = dst.join(&dir_name);
+        for obj_entry in fs::read_dir(&src_dir)? {
+            let obj_entry = obj_entry?;
+            let file_name = obj_entry.file_name();
+
+            if borrowed
+                .iter()
+                .any(|alt| alt.join(&dir_name).join(&file_name).exists())
+            {
+                continue;
+            }
+
+            fs::create_dir_all(&dst_dir)?;
+            let dst_obj = dst_dir.join(&file_name);
+            if dst_obj.exists() {
+                // Already received by an interrupted earlier attempt
+                stats.resumed += 1;
+                continue;
+            }
+
+            // Objects are immutable once written, so sharing the inode is safe.
+            // Fall back to a copy when crossing filesystems or on unsupported platforms.
+            if hardlinks && fs::hard_link(obj_entry.path(), &dst_obj).is_ok() {
+                stats.linked += 1;
+            } else {
+                copy_object_file(&obj_entry.path(), &dst_obj)?;
+                stats.copied += 1;
+            }
         }
     }
+    stats.copied += copy_packs(src, dst, borrowed)?;
 
-    Ok(files_copied)
+    Ok(stats)
 }
 
-fn copy_refs(src_refs: &PathBuf, dst_refs: &PathBuf) -> Result<()> {
+fn copy_refs(src_refs: &Path, dst_refs: &Path) -> Result<()> {
     // Copy heads (branches)
     let src_heads = src_refs.join("heads");
     let dst_heads = dst_refs.join("heads");
@@ -145,25 +532,19 @@ fn copy_refs(src_refs: &PathBuf, dst_refs: &PathBuf) -> Result<()> {
     Ok(())
 }
 
-fn checkout_commit(repo: &Repository, commit_hash: &str) -> Result<()> {
-    let object_store = ObjectStore::new(repo);
-    let commit = object_store.load_commit(commit_hash)?;
-    let tree = object_store.load_tree(&commit.tree)?;
-
-    // Restore files from tree
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
-
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-
-            fs::write(file_path, &blob.content)?;
-        }
+fn set_upstream(repo: &Repository, branch: &str, remote: &str) -> Result<()> {
+    let config_path = repo.git_dir.join("config");
+    let mut config_content = fs::read_to_string(&config_path).unwrap_or_default();
+    let header = format!("[branch \"{}\"]", branch);
+    if config_content.contains(&header) {
+        // Set by an earlier, interrupted attempt
+        return Ok(());
     }
-
+    config_content.push_str(&format!(
+        "\n{}\n\tremote = {}\n\tmerge = refs/heads/{}\n",
+        header, remote, branch
+    ));
+    fs::write(config_path, config_content)?;
     Ok(())
 }
 
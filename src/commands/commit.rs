@@ -1,67 +1,251 @@
-use crate::{Commit, Repository, Result, Tree, TreeEntry, object_store::ObjectStore, utils};
-use chrono::Utc;
-use std::collections::HashMap;
+use crate::{
+    Commit, Index, Repository, Result, Tree, TreeEntry,
+    commands::{add::add_file_to_index, cherry_pick, merge, revert},
+    config::Config,
+    hooks::{self, Hook, HookContext},
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    repo_path, utils,
+    worktree::{load_commit_tree, tree_from_index},
+};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
 
-pub fn commit(repo: &Repository, message: String, author: Option<String>) -> Result<()> {
-    let index = utils::load_index(repo)?;
-    if index.entries.is_empty() {
+pub fn commit(
+    repo: &Repository,
+    message: Option<String>,
+    author: Option<String>,
+    fixup: Option<String>,
+    all: bool,
+    paths: Vec<String>,
+    no_verify: bool,
+) -> Result<()> {
+    let paths = repo_path::precompose_args(repo, paths)?;
+    let object_store = ObjectStore::new(repo);
+    let index = if all {
+        stage_tracked_files(repo, &object_store)?
+    } else {
+        utils::load_index(repo)?
+    };
+    // An empty index is still a change when it deletes everything HEAD had
+    if index.entries.is_empty()
+        && paths.is_empty()
+        && match utils::get_head_commit(repo)? {
+            Some(head) => load_commit_tree(&object_store, &head)?.entries.is_empty(),
+            None => true,
+        }
+    {
         return Err("Nothing to commit".into());
     }
 
-    let object_store = ObjectStore::new(repo);
-
-    // Create tree from index
-    let mut tree_entries = HashMap::new();
-    for (path, entry) in &index.entries {
-        tree_entries.insert(
-            path.clone(),
-            TreeEntry {
-                mode: entry.mode.clone(),
-                hash: entry.hash.clone(),
-                name: path.clone(),
-                is_file: true,
-            },
-        );
+    // Concluding a merge stopped by conflicts or `merge --no-commit`
+    let merge_head = merge::merge_head(repo)?;
+    if merge_head.is_some() && !paths.is_empty() {
+        return Err("Cannot do a partial commit during a merge".into());
+    }
+    if !index.unmerged.is_empty() {
+        let mut unmerged: Vec<&str> = index.unmerged.keys().map(String::as_str).collect();
+        unmerged.sort();
+        return Err(format!(
+            "Cannot commit with unmerged paths ({}); resolve them and `add` them first",
+            unmerged.join(", ")
+        )
+        .into());
     }
 
-    let tree_content = serde_json::to_vec(&tree_entries)?;
-    let tree_hash = ObjectStore::hash_content(&tree_content);
-    let tree = Tree {
-        hash: tree_hash.clone(),
-        entries: tree_entries,
+    // A merge, revert or cherry-pick stopped on conflicts has its message
+    // prepared, as does a `merge --squash`
+    let prepared_message = if merge_head.is_some()
+        || revert::revert_head(repo)?.is_some()
+        || cherry_pick::cherry_pick_head(repo)?.is_some()
+    {
+        merge::merge_msg(repo)
+    } else {
+        merge::squash_msg(repo)
+    };
+    let message = match fixup {
+        Some(target) => {
+            let target_hash = utils::resolve_revision(repo, &target)?;
+            let target_commit = object_store.load_commit(&target_hash)?;
+            let subject = target_commit.message.lines().next().unwrap_or("");
+            format!("fixup! {}", subject)
+        }
+        None => message
+            .or(prepared_message)
+            .ok_or("Commit message required")?,
     };
 
-    object_store.store_tree(&tree)?;
+    // Get parent commit; on a detached HEAD the commit only moves HEAD
+    let current_branch = utils::head_branch(repo)?.unwrap_or_else(|| "HEAD".to_string());
+    let parent = utils::get_head_commit(repo)?;
 
-    // Get parent commit
-    let current_branch = utils::get_current_branch(repo)?;
-    let parent = utils::get_branch_commit(repo, &current_branch)?;
+    hooks::run(
+        repo,
+        &HookContext::new(Hook::PreCommit, &current_branch),
+        no_verify,
+    )?;
+    let message = hooks::commit_msg(repo, &current_branch, message, no_verify)?;
+
+    // Create tree from the index, or from the parent plus the named paths
+    let tree = if paths.is_empty() {
+        tree_from_index(&object_store, &index)?
+    } else {
+        tree_from_paths(repo, &object_store, parent.as_deref(), &paths)?
+    };
 
     // Create commit
-    let author = author.unwrap_or_else(|| "Unknown <unknown@example.com>".to_string());
-    let commit_content = format!(
-        "{}{}{}{}",
-        tree_hash,
-        parent.as_ref().unwrap_or(&String::new()),
-        author,
-        message
-    );
-    let commit_hash = ObjectStore::hash_content(commit_content.as_bytes());
-
-    let commit = Commit {
-        hash: commit_hash.clone(),
-        parent,
-        tree: tree_hash,
+    let author = match author {
+        Some(author) => author,
+        None => Config::load(repo)?.author_ident(),
+    };
+    let parent_for_journal = parent.clone();
+    let subject = message.lines().next().unwrap_or("").to_string();
+    let commit_hash = create_commit(
+        &object_store,
+        &tree.hash,
+        parent.into_iter().chain(merge_head).collect(),
         author,
         message,
-        timestamp: Utc::now(),
-    };
-
-    object_store.store_commit(&commit)?;
+        Utc::now(),
+    )?;
 
     // Update branch
-    utils::update_branch(repo, &current_branch, &commit_hash)?;
+    utils::update_head_commit(repo, &commit_hash)?;
+    merge::clear_merge_state(repo)?;
+    journal::record(
+        repo,
+        "commit",
+        &subject,
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: parent_for_journal,
+            new: Some(commit_hash.clone()),
+        }],
+    )?;
 
     println!("Created commit {}", &commit_hash[..8]);
     Ok(())
 }
+
+// `commit <paths>` (git's `--only`): the parent's tree with the named paths
+// replaced by their working tree state. The index picks up the same paths;
+// anything else staged stays staged for a later commit.
+fn tree_from_paths(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    parent: Option<&str>,
+    pathspecs: &[String],
+) -> Result<Tree> {
+    let mut index = utils::lock_index(repo)?;
+    let mut entries: HashMap<String, TreeEntry> = match parent {
+        Some(parent) => load_commit_tree(object_store, parent)?.entries,
+        None => HashMap::new(),
+    };
+
+    // Only tracked paths can be committed this way, as in git
+    let known: BTreeSet<String> = entries
+        .keys()
+        .chain(index.entries.keys())
+        .cloned()
+        .collect();
+    let mut selected = BTreeSet::new();
+    for spec in pathspecs {
+        let spec = spec.trim_end_matches('/');
+        let matches: Vec<&String> = known
+            .iter()
+            .filter(|path| spec == "." || *path == spec || path.starts_with(&format!("{}/", spec)))
+            .collect();
+        if matches.is_empty() {
+            return Err(format!(
+                "pathspec '{}' did not match any file(s) known to mini_git",
+                spec
+            )
+            .into());
+        }
+        selected.extend(matches);
+    }
+
+    for path in selected {
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if file_path.is_file() {
+            add_file_to_index(&mut index, object_store, &file_path, path)?;
+            let entry = &index.entries[path];
+            entries.insert(
+                path.clone(),
+                TreeEntry {
+                    mode: entry.mode.clone(),
+                    hash: entry.hash.clone(),
+                    name: path.clone(),
+                    is_file: true,
+                },
+            );
+        } else if file_path.is_dir() {
+            // A tracked empty directory that is still there
+            if let Some(entry) = index.entries.get(path).filter(|entry| entry.is_dir()) {
+                entries.insert(
+                    path.clone(),
+                    TreeEntry {
+                        mode: entry.mode.clone(),
+                        hash: entry.hash.clone(),
+                        name: path.clone(),
+                        is_file: false,
+                    },
+                );
+            }
+        } else {
+            index.entries.remove(path);
+            entries.remove(path);
+        }
+    }
+
+    utils::save_locked_index(repo, index)?;
+    object_store.write_tree(entries)
+}
+
+// `commit -a`: stage the working copy of every tracked file (including
+// `add -N` paths) and drop entries whose files were deleted. Returns the
+// index as saved.
+fn stage_tracked_files(repo: &Repository, object_store: &ObjectStore) -> Result<Index> {
+    let mut index = utils::lock_index(repo)?;
+    let paths: Vec<String> = index
+        .entries
+        .iter()
+        .filter(|(_, entry)| !entry.ignores_worktree())
+        .map(|(path, _)| path.clone())
+        .collect();
+
+    for path in paths {
+        let file_path = repo_path::to_path(&repo.work_dir, &path);
+        if file_path.is_file() {
+            add_file_to_index(&mut index, object_store, &file_path, &path)?;
+        } else if !(index.entries[&path].is_dir() && file_path.is_dir()) {
+            index.entries.remove(&path);
+            index.unmerged.remove(&path);
+        }
+    }
+    let staged = Index::clone(&index);
+    utils::save_locked_index(repo, index)?;
+    Ok(staged)
+}
+
+pub(crate) fn create_commit(
+    object_store: &ObjectStore,
+    tree_hash: &str,
+    parents: Vec<String>,
+    author: String,
+    message: String,
+    timestamp: DateTime<Utc>,
+) -> Result<String> {
+    let commit_content = format!("{}{}{}{}", tree_hash, parents.concat(), author, message);
+    let mut commit = Commit {
+        hash: String::new(),
+        parents,
+        tree: tree_hash.to_string(),
+        author,
+        message,
+        timestamp,
+    };
+    commit.hash = object_store.commit_id(&commit, &commit_content);
+
+    object_store.store_commit(&commit)
+}
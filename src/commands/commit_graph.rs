@@ -0,0 +1,115 @@
+use crate::{
+    Repository, Result,
+    commit_graph::{CommitGraphFile, GraphCommit},
+    index::bytes_to_hex,
+    object_store::ObjectStore,
+    utils,
+};
+use std::collections::{HashMap, HashSet};
+
+pub fn commit_graph(repo: &Repository, action: &str) -> Result<()> {
+    match action {
+        "write" => write(repo),
+        "verify" => verify(repo),
+        _ => Err("Invalid commit-graph action. Use: write, verify".into()),
+    }
+}
+
+// Cache every commit reachable from a ref or HEAD, read from the commit
+// objects themselves rather than any earlier graph
+fn write(repo: &Repository) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let mut pending = utils::tip_commits(repo)?;
+
+    let mut seen = HashSet::new();
+    let mut commits = Vec::new();
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(hash.clone()) {
+            continue;
+        }
+        let commit = object_store.load_commit(&hash)?;
+        pending.extend(commit.parents.iter().cloned());
+        commits.push(GraphCommit {
+            hash,
+            tree: commit.tree,
+            parents: commit.parents,
+            time: commit.timestamp.timestamp(),
+        });
+    }
+
+    object_store.write_commit_graph(&commits)?;
+    println!("Wrote commit-graph with {} commits", commits.len());
+    Ok(())
+}
+
+// Check the graph against the commits it describes: each must exist with the
+// recorded tree, parents and time, and every generation must be one more
+// than the highest of its parents'
+fn verify(repo: &Repository) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let path = object_store.commit_graph_path();
+    if !path.exists() {
+        return Err("No commit-graph to verify; run `commit-graph write` first".into());
+    }
+    let file = CommitGraphFile::open(&path, object_store.algorithm())?;
+
+    let mut problems = Vec::new();
+    let mut generations = HashMap::new();
+    for position in 0..file.len() {
+        let hash = bytes_to_hex(file.id(position));
+        if position > 0 && file.id(position - 1) >= file.id(position) {
+            problems.push(format!("Commit {} is out of order", hash));
+        }
+        let commit = match object_store.load_commit(&hash) {
+            Ok(commit) => commit,
+            Err(e) => {
+                problems.push(format!("Commit {} can't be read: {}", hash, e));
+                continue;
+            }
+        };
+        if bytes_to_hex(file.tree(position)) != commit.tree {
+            problems.push(format!("Commit {} has the wrong tree", hash));
+        }
+        let Some(parents) = file.parents(position) else {
+            problems.push(format!("Commit {} has parents outside the graph", hash));
+            continue;
+        };
+        let parent_ids: Vec<String> = parents
+            .iter()
+            .map(|&parent| bytes_to_hex(file.id(parent)))
+            .collect();
+        if parent_ids != commit.parents {
+            problems.push(format!("Commit {} has the wrong parents", hash));
+        }
+        if file.time(position) != commit.timestamp.timestamp().max(0) {
+            problems.push(format!("Commit {} has the wrong time", hash));
+        }
+        generations.insert(position, (parents, file.generation(position)));
+    }
+    for (position, (parents, generation)) in &generations {
+        let expected = parents
+            .iter()
+            .filter_map(|parent| generations.get(parent).map(|(_, generation)| *generation))
+            .max()
+            .unwrap_or(0)
+            + 1;
+        if *generation != expected.min(0x3fff_ffff) {
+            problems.push(format!(
+                "Commit {} has generation {}, expected {}",
+                bytes_to_hex(file.id(*position)),
+                generation,
+                expected
+            ));
+        }
+    }
+
+    if !problems.is_empty() {
+        problems.sort();
+        for problem in &problems {
+            println!("{}", problem);
+        }
+        return Err(format!("The commit-graph has {} problem(s)", problems.len()).into());
+    }
+    println!("Verified commit-graph with {} commits", file.len());
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use crate::{
+    Repository, Result, commands::commit::create_commit, config::Config, object_store::ObjectStore,
+    utils,
+};
+use std::io::Read;
+
+pub fn commit_tree(
+    repo: &Repository,
+    tree: String,
+    parents: Vec<String>,
+    message: Option<String>,
+    author: Option<String>,
+) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+
+    let tree_hash = utils::find_object_by_prefix(repo, &tree)?
+        .ok_or(format!("Not a valid object name {}", tree))?;
+    object_store
+        .load_tree(&tree_hash)
+        .map_err(|_| format!("{} is not a valid tree object", tree))?;
+
+    // Each -p adds a parent, in order, so several make a merge commit
+    let parents = parents
+        .iter()
+        .map(|parent| utils::resolve_revision(repo, parent))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Like git, read the message from stdin when -m isn't given
+    let message = match message {
+        Some(message) => message,
+        None => {
+            let mut message = String::new();
+            std::io::stdin().read_to_string(&mut message)?;
+            message.trim_end().to_string()
+        }
+    };
+
+    let author = match author {
+        Some(author) => author,
+        None => Config::load(repo)?.author_ident(),
+    };
+    let commit = create_commit(
+        &object_store,
+        &tree_hash,
+        parents,
+        author,
+        message,
+        chrono::Utc::now(),
+    )?;
+
+    println!("{}", commit);
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use crate::{
+    Repository, Result,
+    config::{Config, global_config_path, set_value},
+};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+pub struct ConfigOptions {
+    pub name: Option<String>,
+    pub value: Option<String>,
+    pub list: bool,
+    pub show_origin: bool,
+    pub edit: bool,
+    pub global: bool,
+}
+
+pub fn config(repo: Option<&Repository>, options: ConfigOptions) -> Result<()> {
+    let file = if options.global {
+        global_config_path().ok_or("Cannot locate the global config file; set HOME")?
+    } else {
+        repo.ok_or("Not a mini-git repository (use --global outside a repository)")?
+            .git_dir
+            .join("config")
+    };
+
+    if options.edit {
+        // A config that fails to load still has to be editable to fix it
+        let editor = effective_config(options.global, repo)
+            .unwrap_or_default()
+            .editor();
+        return edit_file(&editor, &file);
+    }
+
+    let effective = effective_config(options.global, repo)?;
+
+    if options.list {
+        for entry in &effective.entries {
+            if options.show_origin {
+                print!("file:{}\t", origin_display(entry.origin.as_ref()));
+            }
+            println!("{}={}", entry.name(), entry.value);
+        }
+        return Ok(());
+    }
+
+    let name = options
+        .name
+        .ok_or("Specify a key, or use --list or --edit")?;
+    match options.value {
+        Some(value) => set_value(&file, &name, &value),
+        None => {
+            let entry = effective
+                .entries
+                .iter()
+                .rev()
+                .find(|entry| entry.name().eq_ignore_ascii_case(&name))
+                .ok_or(format!("Key '{}' is not set", name))?;
+            if options.show_origin {
+                print!("file:{}\t", origin_display(entry.origin.as_ref()));
+            }
+            println!("{}", entry.value);
+            Ok(())
+        }
+    }
+}
+
+// --global narrows reads to the global file; otherwise use the effective,
+// layered configuration
+fn effective_config(global: bool, repo: Option<&Repository>) -> Result<Config> {
+    match (global, repo) {
+        (false, Some(repo)) => Config::load(repo),
+        _ => Config::load_global(),
+    }
+}
+
+fn origin_display(origin: Option<&PathBuf>) -> String {
+    origin
+        .map(|path| path.display().to_string())
+        .unwrap_or_default()
+}
+
+// Open `file` in `editor` (see Config::editor)
+pub(crate) fn edit_file(editor: &str, file: &Path) -> Result<()> {
+    // Allow editors configured with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts.next().ok_or("No editor configured")?;
+    let status = Command::new(program)
+        .args(parts)
+        .arg(file)
+        .status()
+        .map_err(|e| format!("Failed to launch editor '{}': {}", editor, e))?;
+
+    if !status.success() {
+        return Err(format!("Editor '{}' exited with {}", editor, status).into());
+    }
+    Ok(())
+}
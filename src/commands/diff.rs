@@ -1,43 +1,428 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::{
+    Index, Repository, Result,
+    config::Config,
+    object_store::ObjectStore,
+    profile::{self, Phase},
+    renames::{self, Candidate, RenameOptions},
+    repo_path, utils, worktree,
+};
 use std::collections::HashMap;
 use std::fs;
+use std::io::IsTerminal;
 
-pub fn diff(repo: &Repository, files: Vec<String>) -> Result<()> {
+// Compares the working tree against the index, or with `cached` the index
+// against HEAD. A leading revision replaces the index (or, with `cached`,
+// HEAD) as the old side. `find_renames` sets how similar a deleted file and
+// a newly tracked one must be for the pair to be shown as a rename, when
+// comparing the working tree against a revision.
+pub fn diff(
+    repo: &Repository,
+    files: Vec<String>,
+    cached: bool,
+    check: bool,
+    find_renames: Option<String>,
+) -> Result<()> {
     let index = utils::load_index(repo)?;
     let object_store = ObjectStore::new(repo);
+    let config = Config::load(repo)?;
+    let rules = WhitespaceRules::from_config(&config);
 
-    if files.is_empty() {
-        // Show diff for all tracked files
-        for (path, index_entry) in &index.entries {
-            show_file_diff(repo, &object_store, path, &index_entry.hash)?;
+    // A leading revision (`HEAD~1`, `@{u}`, ...) names the commit to compare
+    // against
+    let mut files = files;
+    let revision = match files.first() {
+        Some(first)
+            if !index.entries.contains_key(first) && !repo.work_dir.join(first).exists() =>
+        {
+            utils::resolve_revision(repo, first).ok()
         }
+        _ => None,
+    };
+    if revision.is_some() {
+        files.remove(0);
+    }
+    let against_revision = revision.is_some() && !cached;
+    let base: HashMap<String, String> = match revision {
+        Some(commit) => commit_hashes(&object_store, &commit)?,
+        None if cached => match utils::get_head_commit(repo)? {
+            Some(commit) => commit_hashes(&object_store, &commit)?,
+            None => HashMap::new(),
+        },
+        None => index_hashes(&index),
+    };
+    let target = if cached {
+        Target::Index(index_hashes(&index))
+    } else {
+        Target::WorkTree
+    };
+
+    // Staged files are new to the old side too
+    let mut candidates: Vec<&String> = base.keys().collect();
+    if let Target::Index(staged) = &target {
+        candidates.extend(staged.keys().filter(|path| !base.contains_key(*path)));
+    }
+    let mut paths: Vec<&String> = if files.is_empty() {
+        // Show diff for all tracked files
+        candidates
     } else {
         // Show diff for specific files
-        for file in files {
-            if let Some(index_entry) = index.entries.get(&file) {
-                show_file_diff(repo, &object_store, &file, &index_entry.hash)?;
-            } else {
-                println!("File '{}' is not tracked", file);
+        let mut tracked = Vec::new();
+        for file in &files {
+            match candidates.iter().find(|path| **path == file) {
+                Some(path) => tracked.push(*path),
+                None => println!("File '{}' is not tracked", file),
             }
         }
+        tracked
+    };
+    match &target {
+        // Only staged changes
+        Target::Index(staged) => paths.retain(|path| base.get(*path) != staged.get(*path)),
+        // Working copies of flagged entries are not looked at
+        Target::WorkTree => paths.retain(|path| {
+            index
+                .entries
+                .get(*path)
+                .is_none_or(|entry| !entry.ignores_worktree())
+        }),
+    }
+    paths.sort();
+
+    if check {
+        let mut problems = 0;
+        for path in paths {
+            let old_hash = base.get(path).map(String::as_str);
+            problems +=
+                check_file_whitespace(repo, &object_store, &target, path, old_hash, &rules)?;
+        }
+
+        if problems > 0 {
+            return Err(format!("{} whitespace error(s) found", problems).into());
+        }
+        return Ok(());
+    }
+
+    // Files deleted since the revision may live on under a path it doesn't
+    // have; those are shown as renames, in place of the deletion
+    let mut renamed: HashMap<String, renames::Rename> = HashMap::new();
+    if against_revision && files.is_empty() {
+        let options = RenameOptions::new(&config, "diff.renameLimit", find_renames.as_deref())?;
+        for rename in find_work_tree_renames(repo, &object_store, &index, &base, &paths, &options)?
+        {
+            renamed.insert(rename.from.clone(), rename);
+        }
+    }
+
+    // Highlight whitespace errors in added lines when writing to a terminal
+    let highlight = std::io::stdout().is_terminal().then_some(&rules);
+    for path in paths {
+        let old_hash = base.get(path).map(String::as_str);
+        match (renamed.get(path.as_str()), old_hash) {
+            (Some(rename), Some(old_hash)) => {
+                show_rename(repo, &object_store, rename, old_hash, highlight)?
+            }
+            _ => show_file_diff(repo, &object_store, &target, path, old_hash, highlight)?,
+        }
     }
 
     Ok(())
 }
 
+// Where the new side of a diff comes from: the working tree, or the index
+// (blob hashes by path)
+enum Target {
+    WorkTree,
+    Index(HashMap<String, String>),
+}
+
+impl Target {
+    // The new content of `path` and its hash, None when it's gone
+    fn content(
+        &self,
+        repo: &Repository,
+        object_store: &ObjectStore,
+        path: &str,
+    ) -> Result<Option<(Vec<u8>, String)>> {
+        match self {
+            Target::WorkTree => {
+                let file_path = repo_path::to_path(&repo.work_dir, path);
+                if !file_path.exists() {
+                    return Ok(None);
+                }
+                let content = fs::read(&file_path)?;
+                let hash = object_store.hash_content(&content);
+                Ok(Some((content, hash)))
+            }
+            Target::Index(staged) => match staged.get(path) {
+                Some(hash) => Ok(Some((object_store.load_blob(hash)?.content, hash.clone()))),
+                None => Ok(None),
+            },
+        }
+    }
+}
+
+fn commit_hashes(object_store: &ObjectStore, commit: &str) -> Result<HashMap<String, String>> {
+    Ok(worktree::load_commit_tree(object_store, commit)?
+        .entries
+        .into_iter()
+        .filter(|(_, entry)| entry.is_file)
+        .map(|(path, entry)| (path, entry.hash))
+        .collect())
+}
+
+// Pair the `paths` of `base` missing from the working tree with tracked files
+// `base` doesn't have
+fn find_work_tree_renames(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    index: &Index,
+    base: &HashMap<String, String>,
+    paths: &[&String],
+    options: &RenameOptions,
+) -> Result<Vec<renames::Rename>> {
+    let deleted: Vec<Candidate> = paths
+        .iter()
+        .filter(|path| !repo_path::to_path(&repo.work_dir, path).exists())
+        .map(|path| Candidate {
+            path: path.to_string(),
+            hash: base[*path].clone(),
+        })
+        .collect();
+    if deleted.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut added = Vec::new();
+    let mut new_paths: Vec<&String> = index
+        .entries
+        .iter()
+        .filter(|(path, entry)| {
+            !entry.is_dir() && !entry.ignores_worktree() && !base.contains_key(*path)
+        })
+        .map(|(path, _)| path)
+        .collect();
+    new_paths.sort();
+    for path in new_paths {
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if file_path.is_file() {
+            added.push(Candidate {
+                path: path.clone(),
+                hash: object_store.hash_content(&fs::read(&file_path)?),
+            });
+        }
+    }
+
+    renames::detect(&deleted, &added, options, |candidate| {
+        let file_path = repo_path::to_path(&repo.work_dir, &candidate.path);
+        if file_path.exists() {
+            Ok(fs::read(file_path)?)
+        } else {
+            Ok(object_store.load_blob(&candidate.hash)?.content)
+        }
+    })
+}
+
+fn show_rename(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    rename: &renames::Rename,
+    old_hash: &str,
+    highlight: Option<&WhitespaceRules>,
+) -> Result<()> {
+    let old_name = repo_path::quote(&format!("a/{}", rename.from));
+    let new_name = repo_path::quote(&format!("b/{}", rename.to));
+    println!("diff --git {} {}", old_name, new_name);
+    println!("similarity index {}%", rename.score);
+    println!("rename from {}", repo_path::quote(&rename.from));
+    println!("rename to {}", repo_path::quote(&rename.to));
+
+    let new_content = fs::read(repo_path::to_path(&repo.work_dir, &rename.to))?;
+    let new_hash = object_store.hash_content(&new_content);
+    if new_hash == old_hash {
+        return Ok(());
+    }
+    let old_content = object_store.load_blob(old_hash)?.content;
+    println!("index {}..{} 100644", &old_hash[..7], &new_hash[..7]);
+    println!("--- {}", old_name);
+    println!("+++ {}", new_name);
+    show_unified_diff(
+        &String::from_utf8_lossy(&old_content),
+        &String::from_utf8_lossy(&new_content),
+        highlight,
+    );
+    Ok(())
+}
+
+fn index_hashes(index: &Index) -> HashMap<String, String> {
+    index
+        .entries
+        .iter()
+        .filter(|(_, entry)| !entry.is_dir())
+        .map(|(path, entry)| (path.clone(), entry.hash.clone()))
+        .collect()
+}
+
+pub struct WhitespaceRules {
+    blank_at_eol: bool,
+    blank_at_eof: bool,
+    space_before_tab: bool,
+    indent_with_non_tab: bool,
+    tab_in_indent: bool,
+}
+
+impl WhitespaceRules {
+    // Parses `core.whitespace`, a comma separated list of rules where a
+    // leading `-` disables a rule that is on by default
+    pub fn from_config(config: &Config) -> Self {
+        let mut rules = WhitespaceRules {
+            blank_at_eol: true,
+            blank_at_eof: true,
+            space_before_tab: true,
+            indent_with_non_tab: false,
+            tab_in_indent: false,
+        };
+
+        for rule in config.get("core.whitespace").unwrap_or("").split(',') {
+            let rule = rule.trim();
+            let (enabled, name) = match rule.strip_prefix('-') {
+                Some(name) => (false, name),
+                None => (true, rule),
+            };
+
+            match name {
+                "blank-at-eol" => rules.blank_at_eol = enabled,
+                "blank-at-eof" => rules.blank_at_eof = enabled,
+                "trailing-space" => {
+                    rules.blank_at_eol = enabled;
+                    rules.blank_at_eof = enabled;
+                }
+                "space-before-tab" => rules.space_before_tab = enabled,
+                "indent-with-non-tab" => rules.indent_with_non_tab = enabled,
+                "tab-in-indent" => rules.tab_in_indent = enabled,
+                _ => {}
+            }
+        }
+
+        rules
+    }
+
+    pub fn line_errors(&self, line: &str) -> Vec<&'static str> {
+        let mut errors = Vec::new();
+        let indent: &str = &line[..line.len() - line.trim_start_matches([' ', '\t']).len()];
+
+        if self.blank_at_eol && line.ends_with([' ', '\t']) {
+            errors.push("trailing whitespace.");
+        }
+        if self.space_before_tab && indent.contains(" \t") {
+            errors.push("space before tab in indent.");
+        }
+        if self.indent_with_non_tab && indent.starts_with("        ") {
+            errors.push("indent with spaces.");
+        }
+        if self.tab_in_indent && indent.contains('\t') {
+            errors.push("tab in indent.");
+        }
+
+        errors
+    }
+}
+
+// Reports whitespace problems on lines `target` adds relative to the old
+// content (none for a new file), returning how many were found
+fn check_file_whitespace(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    target: &Target,
+    path: &str,
+    old_hash: Option<&str>,
+    rules: &WhitespaceRules,
+) -> Result<usize> {
+    let Some((current_content, current_hash)) = target.content(repo, object_store, path)? else {
+        return Ok(0);
+    };
+    if Some(current_hash.as_str()) == old_hash {
+        return Ok(0);
+    }
+
+    let old_content = match old_hash {
+        Some(hash) => object_store.load_blob(hash)?.content,
+        None => Vec::new(),
+    };
+    let staged_content = String::from_utf8_lossy(&old_content);
+    let current_content = String::from_utf8_lossy(&current_content);
+    let old_lines: Vec<&str> = staged_content.lines().collect();
+    let new_lines: Vec<&str> = current_content.lines().collect();
+
+    let mut problems = 0;
+    let mut new_line_num = 0;
+    for d in compute_diff(&old_lines, &new_lines) {
+        match d {
+            DiffType::Insert => {
+                let line = new_lines[new_line_num];
+                new_line_num += 1;
+                for error in rules.line_errors(line) {
+                    println!("{}:{}: {}", repo_path::quote(path), new_line_num, error);
+                    println!("+{}", line);
+                    problems += 1;
+                }
+            }
+            DiffType::Equal => new_line_num += 1,
+            DiffType::Delete => {}
+        }
+    }
+
+    if rules.blank_at_eof {
+        let trailing_blank = |lines: &[&str]| {
+            lines
+                .iter()
+                .rev()
+                .take_while(|line| line.trim().is_empty())
+                .count()
+        };
+        if trailing_blank(&new_lines) > trailing_blank(&old_lines) {
+            let first_blank = new_lines.len() - trailing_blank(&new_lines) + 1;
+            println!(
+                "{}:{}: new blank line at EOF.",
+                repo_path::quote(path),
+                first_blank
+            );
+            problems += 1;
+        }
+    }
+
+    Ok(problems)
+}
+
 fn show_file_diff(
     repo: &Repository,
     object_store: &ObjectStore,
+    target: &Target,
     path: &str,
-    staged_hash: &str,
+    old_hash: Option<&str>,
+    highlight: Option<&WhitespaceRules>,
 ) -> Result<()> {
-    let file_path = repo.work_dir.join(path);
+    let old_name = repo_path::quote(&format!("a/{}", path));
+    let new_name = repo_path::quote(&format!("b/{}", path));
+    let current = target.content(repo, object_store, path)?;
+
+    let Some(staged_hash) = old_hash else {
+        let Some((current_content, current_hash)) = current else {
+            return Ok(());
+        };
+        println!("diff --git {} {}", old_name, new_name);
+        println!("new file mode 100644");
+        println!("index 0000000..{}", &current_hash[..7]);
+        println!("--- /dev/null");
+        println!("+++ {}", new_name);
+        show_unified_diff("", &String::from_utf8_lossy(&current_content), highlight);
+        return Ok(());
+    };
 
-    if !file_path.exists() {
-        println!("diff --git a/{} b/{}", path, path);
+    let Some((current_content, current_hash)) = current else {
+        println!("diff --git {} {}", old_name, new_name);
         println!("deleted file mode 100644");
         println!("index {}..0000000", &staged_hash[..7]);
-        println!("--- a/{}", path);
+        println!("--- {}", old_name);
         println!("+++ /dev/null");
 
         // Show deleted content
@@ -47,10 +432,7 @@ fn show_file_diff(
             println!("-{}: {}", i + 1, line);
         }
         return Ok(());
-    }
-
-    let current_content = fs::read(&file_path)?;
-    let current_hash = ObjectStore::hash_content(&current_content);
+    };
 
     if current_hash == staged_hash {
         return Ok(()); // No differences
@@ -60,22 +442,33 @@ fn show_file_diff(
     let staged_content = String::from_utf8_lossy(&blob.content);
     let current_content_str = String::from_utf8_lossy(&current_content);
 
-    println!("diff --git a/{} b/{}", path, path);
+    println!("diff --git {} {}", old_name, new_name);
     println!("index {}..{} 100644", &staged_hash[..7], &current_hash[..7]);
-    println!("--- a/{}", path);
-    println!("+++ b/{}", path);
+    println!("--- {}", old_name);
+    println!("+++ {}", new_name);
 
-    show_unified_diff(&staged_content, &current_content_str);
+    show_unified_diff(&staged_content, &current_content_str, highlight);
 
     Ok(())
 }
 
-fn show_unified_diff(old_content: &str, new_content: &str) {
+fn show_unified_diff(old_content: &str, new_content: &str, highlight: Option<&WhitespaceRules>) {
+    for line in unified_diff(old_content, new_content) {
+        match line.strip_prefix('+') {
+            Some(added) => print_added_line(added, highlight),
+            None => println!("{}", line),
+        }
+    }
+}
+
+// The hunks turning `old_content` into `new_content`, a line each
+pub(crate) fn unified_diff(old_content: &str, new_content: &str) -> Vec<String> {
     let old_lines: Vec<&str> = old_content.lines().collect();
     let new_lines: Vec<&str> = new_content.lines().collect();
 
     let diff = compute_diff(&old_lines, &new_lines);
 
+    let mut out = Vec::new();
     let mut old_line_num = 1;
     let mut new_line_num = 1;
     let mut i = 0;
@@ -105,24 +498,24 @@ fn show_unified_diff(old_content: &str, new_content: &str) {
                 .filter(|&&d| d == DiffType::Insert || d == DiffType::Equal)
                 .count();
 
-            println!(
+            out.push(format!(
                 "@@ -{},{} +{},{} @@",
                 old_start, old_count, new_start, new_count
-            );
+            ));
 
             // Show the actual differences
-            for j in chunk_start..chunk_end {
-                match diff[j] {
+            for d in &diff[chunk_start..chunk_end] {
+                match d {
                     DiffType::Delete => {
-                        println!("-{}", old_lines[old_line_num - 1]);
+                        out.push(format!("-{}", old_lines[old_line_num - 1]));
                         old_line_num += 1;
                     }
                     DiffType::Insert => {
-                        println!("+{}", new_lines[new_line_num - 1]);
+                        out.push(format!("+{}", new_lines[new_line_num - 1]));
                         new_line_num += 1;
                     }
                     DiffType::Equal => {
-                        println!(" {}", old_lines[old_line_num - 1]);
+                        out.push(format!(" {}", old_lines[old_line_num - 1]));
                         old_line_num += 1;
                         new_line_num += 1;
                     }
@@ -134,16 +527,30 @@ fn show_unified_diff(old_content: &str, new_content: &str) {
             i += 1;
         }
     }
+    out
+}
+
+fn print_added_line(line: &str, highlight: Option<&WhitespaceRules>) {
+    match highlight {
+        Some(rules) if !rules.line_errors(line).is_empty() => {
+            // Show offending trailing whitespace on a red background
+            let content = line.trim_end_matches([' ', '\t']);
+            let trailing = &line[content.len()..];
+            println!("+{}\x1b[41m{}\x1b[0m", content, trailing);
+        }
+        _ => println!("+{}", line),
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
-enum DiffType {
+pub(crate) enum DiffType {
     Equal,
     Delete,
     Insert,
 }
 
-fn compute_diff(old_lines: &[&str], new_lines: &[&str]) -> Vec<DiffType> {
+pub(crate) fn compute_diff(old_lines: &[&str], new_lines: &[&str]) -> Vec<DiffType> {
+    let _span = profile::span(Phase::Diffing);
     // Simple LCS-based diff algorithm
     let mut dp = vec![vec![0; new_lines.len() + 1]; old_lines.len() + 1];
 
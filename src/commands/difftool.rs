@@ -0,0 +1,231 @@
+use crate::{
+    Repository, Result, config::Config, object_store::ObjectStore, repo_path, utils, worktree,
+};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// One side of the comparison: file contents by path, from a commit, the
+// index or the working tree
+struct Side {
+    label: String,
+    hashes: HashMap<String, String>,
+    work_tree: bool,
+}
+
+// Compare two revisions (or a revision and the working tree, or the index
+// and the working tree) with an external tool. The changed files of each
+// side are copied into a temporary `left`/`right` pair; with `dir_diff` the
+// tool gets the two directories once, otherwise it runs once per file. With
+// no tool configured (`--tool` or `diff.tool`), a tree-level summary of the
+// changes is printed instead.
+pub fn difftool(
+    repo: &Repository,
+    revisions: Vec<String>,
+    dir_diff: bool,
+    tool: Option<String>,
+) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    // `a..b` names both sides at once, HEAD standing in for an empty end
+    let revisions = match revisions.as_slice() {
+        [range] if range.contains("..") => {
+            let (from, to) = range.split_once("..").unwrap();
+            [from, to]
+                .iter()
+                .map(|rev| if rev.is_empty() { "HEAD" } else { rev }.to_string())
+                .collect()
+        }
+        _ => revisions,
+    };
+    let (left, right) = match revisions.as_slice() {
+        [] => (index_side(repo)?, work_tree_side(repo, &object_store)?),
+        [rev] => (
+            commit_side(repo, &object_store, rev)?,
+            work_tree_side(repo, &object_store)?,
+        ),
+        [from, to] => (
+            commit_side(repo, &object_store, from)?,
+            commit_side(repo, &object_store, to)?,
+        ),
+        _ => return Err("difftool takes at most two revisions".into()),
+    };
+
+    let paths: BTreeSet<&String> = left.hashes.keys().chain(right.hashes.keys()).collect();
+    let changes: Vec<(char, &String)> = paths
+        .into_iter()
+        .filter_map(
+            |path| match (left.hashes.get(path), right.hashes.get(path)) {
+                (None, Some(_)) => Some(('A', path)),
+                (Some(_), None) => Some(('D', path)),
+                (Some(old), Some(new)) if old != new => Some(('M', path)),
+                _ => None,
+            },
+        )
+        .collect();
+    if changes.is_empty() {
+        println!("No differences between {} and {}", left.label, right.label);
+        return Ok(());
+    }
+
+    let config = Config::load(repo)?;
+    let Some(tool) = tool.or_else(|| config.get("diff.tool").map(str::to_string)) else {
+        print_summary(&left, &right, &changes);
+        return Ok(());
+    };
+
+    let scratch = std::env::temp_dir().join(format!("mini_git-difftool-{}", std::process::id()));
+    let result = (|| -> Result<()> {
+        let (left_dir, right_dir) = (scratch.join("left"), scratch.join("right"));
+        fs::create_dir_all(&left_dir)?;
+        fs::create_dir_all(&right_dir)?;
+        for (_, path) in &changes {
+            materialize(repo, &object_store, &left, path, &left_dir)?;
+            materialize(repo, &object_store, &right, path, &right_dir)?;
+        }
+
+        if dir_diff {
+            return run_tool(&tool, &left_dir, &right_dir);
+        }
+        for (_, path) in &changes {
+            // A file missing on one side is compared against an empty one
+            let (old, new) = (left_dir.join(path), right_dir.join(path));
+            for file in [&old, &new] {
+                if !file.exists() {
+                    fs::create_dir_all(file.parent().unwrap())?;
+                    fs::write(file, "")?;
+                }
+            }
+            run_tool(&tool, &old, &new)?;
+        }
+        Ok(())
+    })();
+    let _ = fs::remove_dir_all(&scratch);
+    result
+}
+
+fn commit_side(repo: &Repository, object_store: &ObjectStore, revision: &str) -> Result<Side> {
+    let commit = utils::resolve_revision(repo, revision)?;
+    let hashes = worktree::load_commit_tree(object_store, &commit)?
+        .entries
+        .into_iter()
+        .filter(|(_, entry)| entry.is_file)
+        .map(|(path, entry)| (path, entry.hash))
+        .collect();
+    Ok(Side {
+        label: revision.to_string(),
+        hashes,
+        work_tree: false,
+    })
+}
+
+fn index_side(repo: &Repository) -> Result<Side> {
+    let hashes = utils::load_index(repo)?
+        .entries
+        .into_iter()
+        .filter(|(_, entry)| !entry.is_dir())
+        .map(|(path, entry)| (path, entry.hash))
+        .collect();
+    Ok(Side {
+        label: "the index".to_string(),
+        hashes,
+        work_tree: false,
+    })
+}
+
+// Tracked files as they are on disk
+fn work_tree_side(repo: &Repository, object_store: &ObjectStore) -> Result<Side> {
+    let mut hashes = HashMap::new();
+    for (path, entry) in utils::load_index(repo)?.entries {
+        let file_path = repo_path::to_path(&repo.work_dir, &path);
+        if entry.is_dir() || !file_path.is_file() {
+            continue;
+        }
+        let hash = if entry.ignores_worktree() || utils::stat_matches(&entry, &file_path) {
+            entry.hash
+        } else {
+            object_store.hash_content(&fs::read(&file_path)?)
+        };
+        hashes.insert(path, hash);
+    }
+    Ok(Side {
+        label: "the working tree".to_string(),
+        hashes,
+        work_tree: true,
+    })
+}
+
+// Write `side`'s version of `path`, if it has one, under `dir`
+fn materialize(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    side: &Side,
+    path: &str,
+    dir: &Path,
+) -> Result<()> {
+    if !side.hashes.contains_key(path) {
+        return Ok(());
+    }
+    let content = if side.work_tree {
+        fs::read(repo_path::to_path(&repo.work_dir, path))?
+    } else {
+        object_store.load_blob(&side.hashes[path])?.content
+    };
+    let target = dir.join(path);
+    fs::create_dir_all(target.parent().unwrap())?;
+    fs::write(target, content)?;
+    Ok(())
+}
+
+// Run `tool` (which may carry arguments, e.g. "code --wait --diff") on a pair
+// of files or directories. As with git, its exit status is not an error:
+// many tools exit non-zero when they find differences.
+fn run_tool(tool: &str, left: &Path, right: &Path) -> Result<()> {
+    let mut parts = tool.split_whitespace();
+    let program = parts.next().ok_or("No diff tool configured")?;
+    Command::new(program)
+        .args(parts)
+        .arg(left)
+        .arg(right)
+        .status()
+        .map_err(|e| format!("Failed to launch diff tool '{}': {}", tool, e))?;
+    Ok(())
+}
+
+// Each changed file, then how many were added, modified and deleted in
+// each directory
+fn print_summary(left: &Side, right: &Side, changes: &[(char, &String)]) {
+    println!("Changes from {} to {}:", left.label, right.label);
+    let mut directories: BTreeMap<&str, [usize; 3]> = BTreeMap::new();
+    let mut totals = [0; 3];
+    for (status, path) in changes {
+        println!("  {}  {}", status, path);
+        let directory = path.rsplit_once('/').map_or(".", |(dir, _)| dir);
+        let slot = match status {
+            'A' => 0,
+            'M' => 1,
+            _ => 2,
+        };
+        directories.entry(directory).or_default()[slot] += 1;
+        totals[slot] += 1;
+    }
+
+    let counts = |[added, modified, deleted]: [usize; 3]| {
+        format!(
+            "{} added, {} modified, {} deleted",
+            added, modified, deleted
+        )
+    };
+    println!();
+    let width = directories.keys().map(|dir| dir.len()).max().unwrap_or(0);
+    for (directory, count) in &directories {
+        println!(
+            "  {:<width$}  {}",
+            format!("{}/", directory),
+            counts(*count),
+            width = width + 1
+        );
+    }
+    println!();
+    println!("{} files changed: {}", changes.len(), counts(totals));
+}
@@ -0,0 +1,531 @@
+use crate::{
+    Repository, Result,
+    access::{self, Operation},
+    bundle::Bundle,
+    config::Config,
+    fsck::{self, Side},
+    graph, hash,
+    mirror::{self, MirrorUpdate},
+    object_store::{ObjectStorage, ObjectStore, add_alternate, copy_missing_objects},
+    protocol::{self, Connection},
+    transport::Transport,
+    utils,
+};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use walkdir::WalkDir;
+
+// What a fetch from one remote brought in (or would have, for a dry run)
+#[derive(Default)]
+struct FetchOutcome {
+    // Remote-tracking refs that changed, by branch name
+    changes: Vec<RefChange>,
+    // Refs replaced verbatim, for a remote with `remote.<name>.mirror`
+    mirrored: Vec<MirrorUpdate>,
+    objects: usize,
+}
+
+impl FetchOutcome {
+    fn summary_lines(&self, remote_name: &str) -> Vec<String> {
+        self.changes
+            .iter()
+            .map(|change| change.summary(remote_name))
+            .chain(self.mirrored.iter().map(MirrorUpdate::summary))
+            .collect()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.changes.is_empty() && self.mirrored.is_empty()
+    }
+}
+
+struct RefChange {
+    branch: String,
+    // None for a new branch
+    old: Option<String>,
+    // None for a branch the remote no longer has (pruned)
+    new: Option<String>,
+    // The new commit doesn't descend from the old one
+    forced: bool,
+}
+
+impl RefChange {
+    // A line of git's fetch summary, e.g.
+    //    1a2b3c4..5d6e7f8  main       -> origin/main
+    fn summary(&self, remote_name: &str) -> String {
+        let (flag, range, note) = match (&self.old, &self.new) {
+            (None, _) => ('*', "[new branch]".to_string(), ""),
+            (Some(_), None) => ('-', "[deleted]".to_string(), ""),
+            (Some(old), Some(new)) if self.forced => (
+                '+',
+                format!("{}...{}", &old[..7], &new[..7]),
+                "  (forced update)",
+            ),
+            (Some(old), Some(new)) => (' ', format!("{}..{}", &old[..7], &new[..7]), ""),
+        };
+        let source = if self.new.is_some() {
+            self.branch.as_str()
+        } else {
+            "(none)"
+        };
+        format!(
+            " {} {:<17} {:<10} -> {}/{}{}",
+            flag, range, source, remote_name, self.branch, note
+        )
+    }
+}
+
+pub fn fetch(
+    repo: &Repository,
+    remote: Option<String>,
+    branch: Option<String>,
+    dry_run: bool,
+    prune: bool,
+) -> Result<()> {
+    let remote_name = remote.unwrap_or_else(|| "origin".to_string());
+    let branch_name = branch.unwrap_or_else(|| "main".to_string());
+
+    let remote_url = utils::get_remote_url(repo, &remote_name)?;
+    println!("Fetching from {} ({})", remote_name, remote_url);
+
+    if !is_supported_url(&remote_url) {
+        println!("Note: Mini Git only supports local repository fetching.");
+        println!("Remote URL: {}", remote_url);
+        println!(
+            "For network remotes, use standard Git: git fetch {} {}",
+            remote_name, branch_name
+        );
+        return Ok(());
+    }
+
+    let outcome = fetch_remote(
+        repo,
+        &remote_name,
+        &remote_url,
+        Some(&branch_name),
+        dry_run,
+        prune,
+    )?;
+    let Some(outcome) = outcome else {
+        println!("Remote has no branch '{}'", branch_name);
+        return Ok(());
+    };
+    for line in outcome.summary_lines(&remote_name) {
+        println!("{}", line);
+    }
+    if outcome.is_empty() {
+        println!("Already up to date");
+    }
+    print_object_count(outcome.objects, dry_run);
+    Ok(())
+}
+
+fn print_object_count(objects: usize, dry_run: bool) {
+    if dry_run {
+        println!(
+            "Would fetch {} objects (dry run: no objects or refs written)",
+            objects
+        );
+    } else {
+        println!("Fetched {} objects from remote repository", objects);
+    }
+}
+
+// Fetch every branch of every configured remote, running up to `jobs`
+// transfers at once
+pub fn fetch_all(repo: &Repository, jobs: usize, dry_run: bool, prune: bool) -> Result<()> {
+    let config = Config::load(repo)?;
+    let remotes: Vec<(String, String)> = config
+        .subsections("remote")
+        .into_iter()
+        .filter_map(|name| {
+            let url = config.get(&format!("remote.{}.url", name))?.to_string();
+            Some((name, url))
+        })
+        .filter(|(_, url)| is_supported_url(url))
+        .collect();
+
+    if remotes.is_empty() {
+        println!("No fetchable remotes configured");
+        return Ok(());
+    }
+
+    let queue = Mutex::new(remotes.iter());
+    let results = Mutex::new(Vec::new());
+    thread::scope(|scope| {
+        for _ in 0..jobs.clamp(1, remotes.len()) {
+            scope.spawn(|| {
+                loop {
+                    let next = queue.lock().unwrap().next();
+                    let Some((name, url)) = next else {
+                        break;
+                    };
+                    // Box<dyn Error> isn't Send, so errors cross threads as text
+                    let result = fetch_remote(repo, name, url, None, dry_run, prune)
+                        .map(Option::unwrap_or_default)
+                        .map_err(|e| e.to_string());
+                    match &result {
+                        Ok(outcome) => println!(
+                            "{}: {} ref(s) changed, {} objects",
+                            name,
+                            outcome.changes.len() + outcome.mirrored.len(),
+                            outcome.objects
+                        ),
+                        Err(e) => println!("{}: failed: {}", name, e),
+                    }
+                    results.lock().unwrap().push((name, result));
+                }
+            });
+        }
+    });
+
+    let mut results = results.into_inner().unwrap();
+    results.sort_by(|a, b| a.0.cmp(b.0));
+
+    let mut failed = 0;
+    let mut objects = 0;
+    for (name, result) in &results {
+        match result {
+            Ok(outcome) => {
+                objects += outcome.objects;
+                if !outcome.is_empty() {
+                    println!("From {}", name);
+                }
+                for line in outcome.summary_lines(name) {
+                    println!("{}", line);
+                }
+            }
+            Err(_) => failed += 1,
+        }
+    }
+
+    println!(
+        "{} {} remote(s), {} objects",
+        if dry_run { "Would fetch" } else { "Fetched" },
+        results.len() - failed,
+        objects
+    );
+    if failed > 0 {
+        return Err(format!("{} remote(s) failed to fetch", failed).into());
+    }
+    Ok(())
+}
+
+fn is_supported_url(url: &str) -> bool {
+    PathBuf::from(url).exists() || protocol::is_remote_url(url)
+}
+
+// Fetch `branch` (or every branch) of one remote into refs/remotes/<name>/,
+// or every ref of a mirror remote into refs/. None when the remote has no
+// such branch. `prune` drops remote-tracking branches the remote no longer
+// has. A dry run fetches into a scratch object directory that borrows ours,
+// to tell fast-forwards from forced updates, and throws it away.
+fn fetch_remote(
+    repo: &Repository,
+    remote_name: &str,
+    remote_url: &str,
+    branch: Option<&str>,
+    dry_run: bool,
+    prune: bool,
+) -> Result<Option<FetchOutcome>> {
+    let objects_dir = repo.git_dir.join("objects");
+    let target_dir = if dry_run {
+        let scratch = repo
+            .git_dir
+            .join(format!("fetch-dry-run-{}", std::process::id()));
+        add_alternate(&scratch, &fs::canonicalize(&objects_dir)?)?;
+        scratch
+    } else {
+        objects_dir
+    };
+
+    let mirror = Config::load(repo)?
+        .get_bool(&format!("remote.{}.mirror", remote_name))
+        .unwrap_or(false);
+    let result = if mirror {
+        fetch_mirror_into(repo, remote_url, &target_dir).map(Some)
+    } else {
+        let tracking_dir = repo.git_dir.join("refs").join("remotes").join(remote_name);
+        fetch_into(repo, remote_url, branch, prune, &target_dir, &tracking_dir)
+    };
+    if dry_run {
+        fs::remove_dir_all(&target_dir)?;
+    }
+    let Some(outcome) = result? else {
+        return Ok(None);
+    };
+
+    if !dry_run {
+        mirror::apply(&repo.git_dir.join("refs"), &outcome.mirrored)?;
+        for change in &outcome.changes {
+            let message = match (&change.old, change.forced) {
+                (None, _) => "fetch: storing head",
+                (Some(_), true) => "fetch: forced-update",
+                (Some(_), false) => "fetch: fast-forward",
+            };
+            utils::update_remote_ref(
+                repo,
+                remote_name,
+                &change.branch,
+                change.new.as_deref(),
+                message,
+            )?;
+        }
+    }
+    Ok(Some(outcome))
+}
+
+// Fetch every branch of a remote into refs/prefetch/remotes/<name>/, for
+// `maintenance prefetch`: the objects arrive, while the remote-tracking
+// branches and their reflogs stay as the user last saw them. Returns how
+// many prefetch refs moved.
+pub(crate) fn prefetch_remote(
+    repo: &Repository,
+    remote_name: &str,
+    remote_url: &str,
+) -> Result<usize> {
+    let prefix = format!("refs/prefetch/remotes/{}", remote_name);
+    let outcome = fetch_into(
+        repo,
+        remote_url,
+        None,
+        true,
+        &repo.git_dir.join("objects"),
+        &repo.git_dir.join(&prefix),
+    )?;
+    let updates: Vec<MirrorUpdate> = outcome
+        .map(|outcome| outcome.changes)
+        .unwrap_or_default()
+        .into_iter()
+        .map(|change| MirrorUpdate {
+            name: format!("{}/{}", prefix, change.branch),
+            old: change.old,
+            new: change.new,
+        })
+        .collect();
+    mirror::apply(&repo.git_dir.join("refs"), &updates)?;
+    Ok(updates.len())
+}
+
+// Bring a local mirror remote's objects into `objects_dir` and work out how
+// our refs must change to match all of its refs
+fn fetch_mirror_into(
+    repo: &Repository,
+    remote_url: &str,
+    objects_dir: &Path,
+) -> Result<FetchOutcome> {
+    if !matches!(Transport::for_url(remote_url), Transport::Local(_)) {
+        return Err("Mirroring needs a local remote repository".into());
+    }
+    let remote_git_dir = PathBuf::from(remote_url).join(".mini_git");
+    if !remote_git_dir.exists() {
+        return Err("Remote is not a mini-git repository".into());
+    }
+    hash::ensure_same_format(&repo.git_dir, &remote_git_dir, "Remote")?;
+    let remote_refs = utils::served_refs_dir(&remote_git_dir);
+    access::authorize_all_heads(&remote_git_dir, &remote_refs, Operation::Fetch)?;
+
+    let objects = copy_missing_objects(&remote_git_dir.join("objects"), objects_dir)?;
+    let mirrored = mirror::plan(&remote_refs, &repo.git_dir.join("refs"))?;
+    if fsck::transfer_checks_enabled(repo, Side::Fetch)? {
+        let tips: Vec<String> = mirrored.iter().filter_map(|u| u.new.clone()).collect();
+        fsck::check_connectivity(&ObjectStore::at(objects_dir.to_path_buf()), &tips)
+            .map_err(|e| format!("Fetched objects failed verification: {}", e))?;
+    }
+    Ok(FetchOutcome {
+        changes: Vec::new(),
+        mirrored,
+        objects,
+    })
+}
+
+// Bring the remote's objects into `objects_dir` and work out how the
+// remote-tracking branches under `tracking_dir` should change
+fn fetch_into(
+    repo: &Repository,
+    remote_url: &str,
+    branch: Option<&str>,
+    prune: bool,
+    objects_dir: &Path,
+    tracking_dir: &Path,
+) -> Result<Option<FetchOutcome>> {
+    let object_store = ObjectStore::at(objects_dir.to_path_buf());
+
+    // Every branch the remote has, and the ones being fetched
+    let (remote_heads, objects) = match Transport::for_url(remote_url) {
+        Transport::Bundle(path) => {
+            let bundle = Bundle::open(&path)?;
+            if object_store.storage() != ObjectStorage::Git
+                || object_store.algorithm() != bundle.algorithm
+            {
+                return Err(format!(
+                    "Bundle holds {} git objects, which this repository doesn't store",
+                    bundle.algorithm
+                )
+                .into());
+            }
+            if let Some(missing) = bundle
+                .prerequisites
+                .iter()
+                .find(|id| !object_store.object_exists(id))
+            {
+                return Err(format!("Bundle needs commit {}, which we don't have", missing).into());
+            }
+            let remote_heads = bundle.heads();
+            if !remote_heads
+                .iter()
+                .any(|(name, _)| branch.is_none_or(|b| b == name))
+            {
+                return Ok(None);
+            }
+            (remote_heads, bundle.unpack(objects_dir)?)
+        }
+        Transport::Archive(_) => {
+            return Err("Archives can only be cloned, not fetched from".into());
+        }
+        Transport::Ext(_) => {
+            let mut connection = Connection::open(remote_url, &object_store)?;
+            let remote_heads: Vec<(String, String)> = connection
+                .list_refs()?
+                .into_iter()
+                .filter_map(|(name, commit)| {
+                    Some((name.strip_prefix("refs/heads/")?.to_string(), commit))
+                })
+                .collect();
+            let wants: Vec<String> = remote_heads
+                .iter()
+                .filter(|(name, _)| branch.is_none_or(|b| b == name))
+                .map(|(_, commit)| commit.clone())
+                .collect();
+            if wants.is_empty() {
+                return Ok(None);
+            }
+
+            let objects = connection.fetch(&object_store, wants, local_tips(repo)?)?;
+            (remote_heads, objects)
+        }
+        Transport::Local(path) => {
+            let remote_git_dir = path.join(".mini_git");
+            if !remote_git_dir.exists() {
+                return Err("Remote is not a mini-git repository".into());
+            }
+            hash::ensure_same_format(&repo.git_dir, &remote_git_dir, "Remote")?;
+
+            let remote_refs = utils::served_refs_dir(&remote_git_dir);
+            match branch {
+                Some(branch) => access::authorize(
+                    &remote_git_dir,
+                    Operation::Fetch,
+                    &format!("refs/heads/{}", branch),
+                )?,
+                None => {
+                    access::authorize_all_heads(&remote_git_dir, &remote_refs, Operation::Fetch)?
+                }
+            }
+
+            let heads_dir = remote_refs.join("heads");
+            let mut remote_heads = Vec::new();
+            for entry in WalkDir::new(&heads_dir).into_iter().filter_map(|e| e.ok()) {
+                if !entry.file_type().is_file() {
+                    continue;
+                }
+                let name = entry
+                    .path()
+                    .strip_prefix(&heads_dir)?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                remote_heads.push((name, fs::read_to_string(entry.path())?.trim().to_string()));
+            }
+            if !remote_heads
+                .iter()
+                .any(|(name, _)| branch.is_none_or(|b| b == name))
+            {
+                return Ok(None);
+            }
+
+            let objects = copy_missing_objects(&remote_git_dir.join("objects"), objects_dir)?;
+            (remote_heads, objects)
+        }
+    };
+    let heads: Vec<&(String, String)> = remote_heads
+        .iter()
+        .filter(|(name, _)| branch.is_none_or(|b| b == name))
+        .collect();
+
+    if fsck::transfer_checks_enabled(repo, Side::Fetch)? {
+        let tips: Vec<String> = heads.iter().map(|(_, commit)| commit.clone()).collect();
+        fsck::check_connectivity(&object_store, &tips)
+            .map_err(|e| format!("Fetched objects failed verification: {}", e))?;
+    }
+
+    let mut changes = Vec::new();
+    for (name, commit) in heads {
+        let old = fs::read_to_string(tracking_dir.join(name))
+            .ok()
+            .map(|c| c.trim().to_string());
+        if old.as_ref() == Some(commit) {
+            continue;
+        }
+        let forced = match &old {
+            Some(old) => !graph::is_ancestor(&object_store, old, commit).unwrap_or(false),
+            None => false,
+        };
+        changes.push(RefChange {
+            branch: name.clone(),
+            old,
+            new: Some(commit.clone()),
+            forced,
+        });
+    }
+
+    if prune {
+        for entry in WalkDir::new(tracking_dir)
+            .into_iter()
+            .filter_map(|e| e.ok())
+        {
+            if !entry.file_type().is_file() {
+                continue;
+            }
+            let name = entry
+                .path()
+                .strip_prefix(tracking_dir)?
+                .to_string_lossy()
+                .replace('\\', "/");
+            if !remote_heads.iter().any(|(head, _)| *head == name) {
+                changes.push(RefChange {
+                    branch: name,
+                    old: Some(fs::read_to_string(entry.path())?.trim().to_string()),
+                    new: None,
+                    forced: false,
+                });
+            }
+        }
+    }
+    Ok(Some(FetchOutcome {
+        changes,
+        mirrored: Vec::new(),
+        objects,
+    }))
+}
+
+// Commits we already have, so the server can leave out their objects. What
+// `maintenance prefetch` brought in counts, so a later fetch only asks for
+// what arrived since.
+fn local_tips(repo: &Repository) -> Result<Vec<String>> {
+    let refs_dir = repo.git_dir.join("refs");
+    let mut tips = Vec::new();
+    for dir in [
+        refs_dir.join("heads"),
+        refs_dir.join("remotes"),
+        refs_dir.join("prefetch/remotes"),
+    ] {
+        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                tips.push(fs::read_to_string(entry.path())?.trim().to_string());
+            }
+        }
+    }
+    Ok(tips)
+}
@@ -0,0 +1,150 @@
+use crate::{Commit, Repository, Result, object_store::ObjectStore, pretty, utils};
+
+const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+// Fields a ref has besides those of the commit it points at
+const REF_FIELDS: &[&str] = &[
+    "refname",
+    "refname:short",
+    "objectname",
+    "objectname:short",
+    "objecttype",
+    "HEAD",
+];
+const COMMIT_FIELDS: &[&str] = &[
+    "authorname",
+    "authoremail",
+    "authordate",
+    "committername",
+    "committeremail",
+    "committerdate",
+    "subject",
+    "body",
+    "tree",
+    "parent",
+];
+
+struct RefInfo {
+    name: String,
+    hash: String,
+    kind: String,
+    // The object the ref points at, when it's a commit
+    commit: Option<Commit>,
+}
+
+// Value a sort key compares; dates compare by time rather than as text
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum SortValue {
+    Time(i64),
+    Text(String),
+}
+
+// List refs matching `patterns` (all of them when empty), each printed with
+// `format`. Every `--sort` key is applied in turn, so the last one given
+// decides first; a leading `-` sorts that key in descending order.
+pub fn for_each_ref(
+    repo: &Repository,
+    patterns: Vec<String>,
+    format: Option<String>,
+    sort: Vec<String>,
+    count: Option<usize>,
+) -> Result<()> {
+    let format = format.unwrap_or_else(|| DEFAULT_FORMAT.to_string());
+    let sort_keys: Vec<(&str, bool)> = sort
+        .iter()
+        .map(|key| match key.strip_prefix('-') {
+            Some(key) => (key, true),
+            None => (key.as_str(), false),
+        })
+        .collect();
+    for name in pretty::fields(&format)
+        .iter()
+        .map(String::as_str)
+        .chain(sort_keys.iter().map(|(key, _)| *key))
+    {
+        if !REF_FIELDS.contains(&name) && !COMMIT_FIELDS.contains(&name) {
+            return Err(format!("Unknown field name: {}", name).into());
+        }
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let mut refs = Vec::new();
+    for (name, hash) in utils::list_refs(repo, "refs/")? {
+        if !patterns.is_empty() && !patterns.iter().any(|p| matches_pattern(&name, p)) {
+            continue;
+        }
+        let (kind, _) = object_store.read_typed(&hash)?;
+        let commit = match kind.as_str() {
+            "commit" => Some(object_store.load_commit(&hash)?),
+            _ => None,
+        };
+        refs.push(RefInfo {
+            name,
+            hash,
+            kind,
+            commit,
+        });
+    }
+
+    for (key, descending) in &sort_keys {
+        refs.sort_by(|a, b| {
+            let order = a.sort_value(key).cmp(&b.sort_value(key));
+            if *descending { order.reverse() } else { order }
+        });
+    }
+
+    let head_ref = utils::head_ref(repo)?;
+    for info in refs.iter().take(count.unwrap_or(usize::MAX)) {
+        println!(
+            "{}",
+            pretty::expand(&format, |name| Ok(info.field(name, &head_ref)))?
+        );
+    }
+    Ok(())
+}
+
+impl RefInfo {
+    fn field(&self, name: &str, head_ref: &str) -> String {
+        match name {
+            "refname" => self.name.clone(),
+            "refname:short" => short_ref_name(&self.name).to_string(),
+            "objectname" => self.hash.clone(),
+            "objectname:short" => self.hash[..self.hash.len().min(7)].to_string(),
+            "objecttype" => self.kind.clone(),
+            "HEAD" => if self.name == head_ref { "*" } else { " " }.to_string(),
+            _ => self
+                .commit
+                .as_ref()
+                .and_then(|commit| pretty::commit_field(commit, name))
+                .unwrap_or_default(),
+        }
+    }
+
+    fn sort_value(&self, key: &str) -> SortValue {
+        match (key, &self.commit) {
+            ("authordate" | "committerdate", Some(commit)) => {
+                SortValue::Time(commit.timestamp.timestamp())
+            }
+            ("authordate" | "committerdate", None) => SortValue::Time(i64::MIN),
+            _ => SortValue::Text(self.field(key, "")),
+        }
+    }
+}
+
+// `main` for refs/heads/main, `origin/main` for refs/remotes/origin/main
+fn short_ref_name(name: &str) -> &str {
+    ["refs/heads/", "refs/tags/", "refs/remotes/", "refs/"]
+        .iter()
+        .find_map(|prefix| name.strip_prefix(prefix))
+        .unwrap_or(name)
+}
+
+// A pattern names a ref or a whole hierarchy: `refs/heads` matches every
+// branch, `refs/heads/main` just that one
+fn matches_pattern(name: &str, pattern: &str) -> bool {
+    let pattern = pattern.trim_end_matches('/');
+    name == pattern
+        || name
+            .strip_prefix(pattern)
+            .is_some_and(|rest| rest.starts_with('/'))
+}
@@ -0,0 +1,83 @@
+use crate::{
+    Repository, Result,
+    fsck::{bad_object, content_matches, walk_repository},
+    graph::ObjectId,
+    object_store::ObjectStore,
+};
+use std::collections::{BTreeSet, HashSet};
+
+// Check the whole repository: everything reachable must be present, readable
+// and hash to its name. Stored objects nothing reaches are read as well and
+// listed as dangling when nothing else refers to them.
+pub fn fsck(repo: &Repository, no_dangling: bool) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let mut walk = walk_repository(repo, &object_store)?;
+    let checked = walk.seen.len();
+
+    // Objects in our own store (not alternates) that nothing reached
+    let stored: BTreeSet<String> = object_store
+        .loose_objects()?
+        .into_iter()
+        .chain(object_store.own_packs().iter().flat_map(|pack| pack.ids()))
+        .collect();
+    let mut unreachable = Vec::new();
+    for hash in stored {
+        if !walk.seen.contains(&ObjectId::from_hex(&hash)?) {
+            unreachable.push(hash);
+        }
+    }
+
+    // Unreachable objects another unreachable object refers to aren't
+    // dangling themselves, as in git
+    let mut referenced = HashSet::new();
+    let mut readable = Vec::new();
+    for hash in unreachable {
+        let kind = match object_store.read_typed(&hash) {
+            Ok((kind, _)) => kind,
+            Err(e) => {
+                walk.problems
+                    .push(bad_object(&hash, "object", e).to_string());
+                continue;
+            }
+        };
+        if !content_matches(&object_store, &hash, &kind)? {
+            walk.problems
+                .push(format!("Object {} does not match its contents", hash));
+        }
+        match kind.as_str() {
+            "commit" => {
+                let commit = object_store.load_commit(&hash)?;
+                referenced.extend(object_store.tree_objects(&commit.tree)?);
+                referenced.extend(commit.parents);
+            }
+            "tree" => {
+                let tree = object_store.load_tree(&hash)?;
+                referenced.extend(object_store.tree_objects(&hash)?.into_iter().skip(1));
+                referenced.extend(tree.entries.into_values().map(|entry| entry.hash));
+            }
+            _ => {}
+        }
+        readable.push((hash, kind));
+    }
+
+    for problem in &walk.problems {
+        println!("{}", problem);
+    }
+    if !no_dangling {
+        for (hash, kind) in &readable {
+            if !referenced.contains(hash) {
+                println!("dangling {} {}", kind, hash);
+            }
+        }
+    }
+
+    if !walk.problems.is_empty() {
+        return Err(format!("fsck found {} problem(s)", walk.problems.len()).into());
+    }
+    println!(
+        "Checked {} reachable and {} unreachable object(s): no problems found",
+        checked,
+        readable.len()
+    );
+    Ok(())
+}
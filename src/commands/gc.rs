@@ -0,0 +1,149 @@
+use crate::{
+    Repository, Result, commands::repack, config::Config, fsck::walk_repository, graph::ObjectId,
+    object_store::ObjectStore, packfile::DeltaWindow,
+};
+use std::time::{Duration, SystemTime};
+
+const DEFAULT_PRUNE_EXPIRE: &str = "2.weeks.ago";
+// git's defaults for `gc --aggressive`
+const DEFAULT_AGGRESSIVE_WINDOW: usize = 250;
+const DEFAULT_AGGRESSIVE_DEPTH: usize = 50;
+
+// `gc --aggressive`, with the delta search given by `--window` and
+// `--depth`, else `gc.aggressiveWindow` and `gc.aggressiveDepth`
+pub struct Aggressive {
+    pub window: Option<usize>,
+    pub depth: Option<usize>,
+}
+
+// Delete loose objects nothing reaches (see fsck::walk_repository) once they
+// are older than the grace period: `prune`, else `gc.pruneExpire`, else two
+// weeks. The grace period protects objects a running command has written but
+// not yet referenced. Unreachable objects inside packs are left alone.
+//
+// `aggressive` then repacks everything left into a single pack of deltas,
+// dropping the packs it replaces: slow, but as small as mini_git gets.
+pub fn gc(
+    repo: &Repository,
+    prune: Option<String>,
+    dry_run: bool,
+    aggressive: Option<Aggressive>,
+) -> Result<()> {
+    let config = Config::load(repo)?;
+    let expire = match prune {
+        Some(prune) => prune,
+        None => config
+            .get("gc.pruneExpire")
+            .unwrap_or(DEFAULT_PRUNE_EXPIRE)
+            .to_string(),
+    };
+    let deltas = aggressive
+        .map(|aggressive| -> Result<DeltaWindow> {
+            Ok(DeltaWindow {
+                window: match aggressive.window {
+                    Some(window) => window,
+                    None => config_usize(&config, "gc.aggressiveWindow")?
+                        .unwrap_or(DEFAULT_AGGRESSIVE_WINDOW),
+                },
+                depth: match aggressive.depth {
+                    Some(depth) => depth,
+                    None => config_usize(&config, "gc.aggressiveDepth")?
+                        .unwrap_or(DEFAULT_AGGRESSIVE_DEPTH),
+                },
+            })
+        })
+        .transpose()?;
+    let cutoff = parse_expiry(&expire)?;
+
+    let object_store = ObjectStore::new(repo);
+    let walk = walk_repository(repo, &object_store)?;
+    if !walk.problems.is_empty() {
+        for problem in &walk.problems {
+            println!("{}", problem);
+        }
+        return Err(format!(
+            "Not pruning anything: the repository has {} problem(s) (see fsck)",
+            walk.problems.len()
+        )
+        .into());
+    }
+
+    let (mut pruned, mut recent) = (0, 0);
+    for hash in object_store.loose_objects()? {
+        if walk.seen.contains(&ObjectId::from_hex(&hash)?) {
+            continue;
+        }
+        let old_enough = cutoff.is_some_and(|cutoff| {
+            object_store
+                .loose_modified(&hash)
+                .is_ok_and(|modified| modified <= cutoff)
+        });
+        if !old_enough {
+            recent += 1;
+            continue;
+        }
+        if dry_run {
+            println!("Would remove {}", hash);
+        } else {
+            object_store.remove_loose(&hash)?;
+        }
+        pruned += 1;
+    }
+
+    println!(
+        "{} {} unreachable object(s); kept {} newer than {}",
+        if dry_run { "Would remove" } else { "Removed" },
+        pruned,
+        recent,
+        expire
+    );
+
+    if let Some(deltas) = deltas {
+        if dry_run {
+            println!(
+                "Would repack everything with --window={} --depth={}",
+                deltas.window, deltas.depth
+            );
+        } else {
+            repack(repo, true, Some(deltas))?;
+        }
+    }
+    Ok(())
+}
+
+fn config_usize(config: &Config, name: &str) -> Result<Option<usize>> {
+    config
+        .get(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| format!("Invalid {} '{}': expected a number", name, value).into())
+        })
+        .transpose()
+}
+
+// `now`, `never` or `<n>.<unit>.ago` (also `<n> <unit> ago`), as git takes
+// them; returns the newest modification time that may be pruned
+fn parse_expiry(value: &str) -> Result<Option<SystemTime>> {
+    let invalid = || format!("Invalid expiry '{}' (try now, never or 2.weeks.ago)", value);
+    match value {
+        "now" => return Ok(Some(SystemTime::now())),
+        "never" => return Ok(None),
+        _ => {}
+    }
+
+    let parts: Vec<&str> = value.split(['.', ' ']).collect();
+    let [count, unit, "ago"] = parts.as_slice() else {
+        return Err(invalid().into());
+    };
+    let count: u64 = count.parse().map_err(|_| invalid())?;
+    let seconds = match unit.trim_end_matches('s') {
+        "second" => 1,
+        "minute" => 60,
+        "hour" => 60 * 60,
+        "day" => 24 * 60 * 60,
+        "week" => 7 * 24 * 60 * 60,
+        _ => return Err(invalid().into()),
+    };
+    Ok(SystemTime::now().checked_sub(Duration::from_secs(count * seconds)))
+}
@@ -0,0 +1,169 @@
+use crate::{
+    Repository, Result, TreeEntry,
+    commands::commit::create_commit,
+    config::Config,
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils, worktree,
+};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// Turn a directory of snapshots (`backup-2023-01-01/`, `site_20230201/`, ...)
+// into a linear history: one commit per subdirectory, oldest first, on top
+// of `branch` (the current branch by default). Each commit is dated by the
+// date (and optional time) in the snapshot's name, or failing that by the
+// newest file in it. Snapshots identical to the one before are skipped.
+pub fn import_snapshots(repo: &Repository, dir: String, branch: Option<String>) -> Result<()> {
+    let source = PathBuf::from(&dir);
+    if !source.is_dir() {
+        return Err(format!("'{}' is not a directory", dir).into());
+    }
+
+    let current = utils::head_branch(repo)?;
+    let branch = match branch.or_else(|| current.clone()) {
+        Some(branch) => branch,
+        None => return Err("HEAD is detached; name a branch with --branch".into()),
+    };
+    utils::validate_ref_name("branch", &branch)?;
+
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(&source)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            let name = path.file_name().unwrap().to_string_lossy().to_string();
+            let timestamp = match date_from_name(&name) {
+                Some(timestamp) => timestamp,
+                None => newest_mtime(&path)?,
+            };
+            snapshots.push((timestamp, name, path));
+        }
+    }
+    if snapshots.is_empty() {
+        return Err(format!("No snapshot directories in '{}'", dir).into());
+    }
+    snapshots.sort();
+
+    let object_store = ObjectStore::new(repo);
+    let author = Config::load(repo)?.author_ident();
+    let old = utils::get_branch_commit(repo, &branch)?;
+    let mut parent = old.clone();
+    let mut previous_tree = match &parent {
+        Some(commit) => Some(worktree::load_commit_tree(&object_store, commit)?.hash),
+        None => None,
+    };
+    let mut imported = 0;
+    for (timestamp, name, path) in &snapshots {
+        let tree = snapshot_tree(&object_store, path)?;
+        if previous_tree.as_deref() == Some(tree.as_str()) {
+            println!("Skipped {}: same content as the snapshot before", name);
+            continue;
+        }
+        let commit = create_commit(
+            &object_store,
+            &tree,
+            parent.into_iter().collect(),
+            author.clone(),
+            format!("Import snapshot {}", name),
+            *timestamp,
+        )?;
+        println!(
+            "{} {} {}",
+            &commit[..8],
+            timestamp.format("%Y-%m-%d %H:%M:%S"),
+            name
+        );
+        parent = Some(commit);
+        previous_tree = Some(tree);
+        imported += 1;
+    }
+
+    let Some(tip) = parent.filter(|tip| Some(tip) != old.as_ref()) else {
+        println!("Nothing to import");
+        return Ok(());
+    };
+    // The checked-out branch brings the working tree along, as a checkout would
+    let checked_out = current.as_deref() == Some(branch.as_str());
+    let tree = worktree::load_commit_tree(&object_store, &tip)?;
+    if checked_out {
+        worktree::check_checkout_safety(repo, &tree)?;
+    }
+    utils::update_branch(repo, &branch, &tip)?;
+    journal::record(
+        repo,
+        "import-snapshots",
+        &format!("{} snapshot(s) from {}", imported, dir),
+        vec![RefUpdate::branch(&branch, old, Some(tip.clone()))],
+    )?;
+    if checked_out {
+        worktree::switch_to_tree(repo, &object_store, &tree)?;
+    }
+    println!("Imported {} snapshot(s) onto {}", imported, branch);
+    Ok(())
+}
+
+// Every file under `dir` as a tree, skipping repository directories
+fn snapshot_tree(object_store: &ObjectStore, dir: &Path) -> Result<String> {
+    let mut entries = HashMap::new();
+    for entry in WalkDir::new(dir)
+        .into_iter()
+        .filter_entry(|e| e.file_name() != ".mini_git" && e.file_name() != ".git")
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry
+            .path()
+            .strip_prefix(dir)?
+            .to_string_lossy()
+            .replace('\\', "/");
+        let hash = object_store.store_blob(&fs::read(entry.path())?)?;
+        entries.insert(
+            path.clone(),
+            TreeEntry {
+                mode: worktree::file_mode(entry.path())?,
+                hash,
+                name: path,
+                is_file: true,
+            },
+        );
+    }
+    Ok(object_store.write_tree(entries)?.hash)
+}
+
+// The first run of 8+ digits in `name` (separators `-_.: T` allowed
+// between them) read as YYYYMMDD, then HHMM or HHMMSS if more follow
+fn date_from_name(name: &str) -> Option<DateTime<Utc>> {
+    let mut runs = vec![String::new()];
+    for c in name.chars() {
+        if c.is_ascii_digit() {
+            runs.last_mut().unwrap().push(c);
+        } else if !"-_.: T".contains(c) {
+            runs.push(String::new());
+        }
+    }
+    let digits = runs.into_iter().find(|run| run.len() >= 8)?;
+    let number = |range: std::ops::Range<usize>| digits.get(range)?.parse::<u32>().ok();
+
+    let date = NaiveDate::from_ymd_opt(number(0..4)? as i32, number(4..6)?, number(6..8)?)?;
+    let time = date.and_hms_opt(
+        number(8..10).unwrap_or(0),
+        number(10..12).unwrap_or(0),
+        number(12..14).unwrap_or(0),
+    )?;
+    Some(Utc.from_utc_datetime(&time))
+}
+
+fn newest_mtime(dir: &Path) -> Result<DateTime<Utc>> {
+    let mut newest = fs::metadata(dir)?.modified()?;
+    for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            newest = newest.max(entry.metadata()?.modified()?);
+        }
+    }
+    Ok(newest.into())
+}
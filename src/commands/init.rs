@@ -1,10 +1,36 @@
-use crate::{Repository, Result};
+use crate::{
+    Index, Repository, Result,
+    commands::{add::add_file_to_index, commit::create_commit},
+    config::Config,
+    hash::HashAlgorithm,
+    journal::{self, RefUpdate},
+    object_store::{ObjectStorage, ObjectStore},
+    templates::{self, Template},
+    utils,
+    worktree::tree_from_index,
+};
+use chrono::Utc;
 use std::fs;
 use std::path::PathBuf;
 
-pub fn init(path: Option<PathBuf>) -> Result<()> {
+pub fn init(
+    path: Option<PathBuf>,
+    object_format: Option<String>,
+    object_storage: Option<String>,
+    template: Option<String>,
+) -> Result<()> {
     let work_dir = path.unwrap_or_else(|| std::env::current_dir().unwrap());
     let git_dir = work_dir.join(".mini_git");
+    let algorithm = match object_format {
+        Some(name) => HashAlgorithm::from_name(&name)?,
+        None => HashAlgorithm::default(),
+    };
+    let storage = match object_storage {
+        Some(name) => ObjectStorage::from_name(&name)?,
+        None => ObjectStorage::default(),
+    };
+    // Load the template first so a bad name leaves nothing behind
+    let template = template.map(|name| templates::load(&name)).transpose()?;
 
     if git_dir.exists() {
         return Err("Repository already exists".into());
@@ -19,15 +45,75 @@ pub fn init(path: Option<PathBuf>) -> Result<()> {
     // Create HEAD file pointing to main branch
     fs::write(git_dir.join("HEAD"), "ref: refs/heads/main")?;
 
-    // Create empty config file
-    fs::write(
-        git_dir.join("config"),
-        "[core]\n\trepositoryformatversion = 0\n",
-    )?;
+    fs::write(git_dir.join("config"), initial_config(algorithm, storage))?;
 
     println!(
         "Initialized empty Mini Git repository in {}",
         git_dir.display()
     );
+
+    if let Some(template) = template {
+        let repo = utils::get_repository(Some(work_dir))?;
+        seed_from_template(&repo, &template)?;
+    }
     Ok(())
 }
+
+// Write the template's files and hooks, then commit the files on main
+fn seed_from_template(repo: &Repository, template: &Template) -> Result<()> {
+    let paths = templates::apply(repo, template)?;
+    if paths.is_empty() {
+        println!("Installed hooks from the {} template", template.name);
+        return Ok(());
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let mut index = Index::default();
+    for path in &paths {
+        add_file_to_index(&mut index, &object_store, &repo.work_dir.join(path), path)?;
+    }
+    utils::save_index(repo, &index)?;
+
+    let tree = tree_from_index(&object_store, &index)?;
+    let message = format!("Initial commit from {} template", template.name);
+    let commit_hash = create_commit(
+        &object_store,
+        &tree.hash,
+        Vec::new(),
+        Config::load(repo)?.author_ident(),
+        message.clone(),
+        Utc::now(),
+    )?;
+    utils::update_branch(repo, "main", &commit_hash)?;
+    journal::record(
+        repo,
+        "commit",
+        &message,
+        vec![RefUpdate::branch("main", None, Some(commit_hash.clone()))],
+    )?;
+
+    println!(
+        "Created commit {} with {} file(s) from the {} template",
+        &commit_hash[..8],
+        paths.len(),
+        template.name
+    );
+    Ok(())
+}
+
+// SHA-1 repositories keep the original format; anything else needs readers
+// that understand `extensions`, as in git. The object storage goes under
+// `core`, which git ignores, so git can still read `git` storage.
+pub(crate) fn initial_config(algorithm: HashAlgorithm, storage: ObjectStorage) -> String {
+    let mut config = match algorithm {
+        HashAlgorithm::Sha1 => "[core]\n\trepositoryformatversion = 0\n".to_string(),
+        _ => "[core]\n\trepositoryformatversion = 1\n".to_string(),
+    };
+    if storage != ObjectStorage::default() {
+        config.push_str(&format!("\tobjectStorage = {}\n", storage));
+    }
+    if algorithm != HashAlgorithm::Sha1 {
+        config.push_str(&format!("[extensions]\n\tobjectFormat = {}\n", algorithm));
+    }
+    config
+}
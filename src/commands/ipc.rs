@@ -0,0 +1,413 @@
+use crate::{
+    Repository, Result,
+    blame::{self, BlameLine},
+    commands::add::{add_file_to_index, stage_files},
+    commands::diff::{DiffType, compute_diff},
+    ignore, index,
+    object_store::ObjectStore,
+    repo_path, utils, worktree,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::BTreeSet;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+// A long-running JSON-RPC 2.0 server for editor plugins, so they can ask
+// about the repository without starting a process per keystroke. Requests
+// and responses are single lines of JSON on a Unix socket
+// (.mini_git/ipc.sock by default). Methods:
+//
+//   status                          branch, HEAD, staged/modified/untracked/unmerged paths
+//   blame      {path, line}         the commit that last changed a working tree line
+//   diff_hunks {path, against?}     changed line ranges of a working file, against
+//                                   "index" (the default) or "head"
+//   stage      {paths}              stage files (or their deletion)
+//   unstage    {paths}              put index entries back to HEAD's version
+//   shutdown                        stop the server
+//
+// Each connection is served on its own thread; requests run one at a time.
+
+#[derive(Deserialize)]
+struct RpcRequest {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const SERVER_ERROR: i64 = -32000;
+
+pub fn ipc(repo: &Repository, socket: Option<String>) -> Result<()> {
+    let socket = socket
+        .map(PathBuf::from)
+        .unwrap_or_else(|| repo.git_dir.join("ipc.sock"));
+    serve(repo, socket)
+}
+
+#[cfg(unix)]
+fn serve(repo: &Repository, socket: PathBuf) -> Result<()> {
+    use std::os::unix::net::{UnixListener, UnixStream};
+
+    // A socket left behind by a server that is gone can be replaced; one
+    // that still answers can't
+    if socket.exists() {
+        if UnixStream::connect(&socket).is_ok() {
+            return Err(
+                format!("An ipc server is already listening on {}", socket.display()).into(),
+            );
+        }
+        fs::remove_file(&socket)?;
+    }
+    let listener = UnixListener::bind(&socket)?;
+    println!("Listening on {}", socket.display());
+
+    let lock = Arc::new(Mutex::new(()));
+    let stop = Arc::new(AtomicBool::new(false));
+    for stream in listener.incoming() {
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+        let Ok(stream) = stream else { continue };
+        let (repo, lock, stop, socket) = (repo.clone(), lock.clone(), stop.clone(), socket.clone());
+        std::thread::spawn(move || {
+            let reader = match stream.try_clone() {
+                Ok(reader) => BufReader::new(reader),
+                Err(_) => return,
+            };
+            let _ = serve_connection(&repo, reader, stream, &lock, &stop);
+            // Wake the accept loop so it sees the stop flag
+            if stop.load(Ordering::SeqCst) {
+                let _ = UnixStream::connect(&socket);
+            }
+        });
+    }
+
+    let _ = fs::remove_file(&socket);
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn serve(_repo: &Repository, _socket: PathBuf) -> Result<()> {
+    Err("ipc needs Unix domain sockets, which this platform doesn't have".into())
+}
+
+fn serve_connection(
+    repo: &Repository,
+    reader: impl BufRead,
+    mut writer: impl Write,
+    lock: &Mutex<()>,
+    stop: &AtomicBool,
+) -> Result<()> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let mut shutdown = false;
+        let response = match serde_json::from_str::<RpcRequest>(&line) {
+            Err(e) => error_response(Value::Null, PARSE_ERROR, &e.to_string()),
+            Ok(request) => {
+                shutdown = request.method == "shutdown";
+                let _guard = lock.lock().unwrap_or_else(|e| e.into_inner());
+                match handle(repo, &request.method, request.params) {
+                    Ok(result) => json!({"jsonrpc": "2.0", "id": request.id, "result": result}),
+                    Err((code, message)) => error_response(request.id, code, &message),
+                }
+            }
+        };
+        writeln!(writer, "{}", response)?;
+        writer.flush()?;
+        if shutdown {
+            stop.store(true, Ordering::SeqCst);
+        }
+        if stop.load(Ordering::SeqCst) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({"jsonrpc": "2.0", "id": id, "error": {"code": code, "message": message}})
+}
+
+type RpcResult = std::result::Result<Value, (i64, String)>;
+
+fn handle(repo: &Repository, method: &str, params: Value) -> RpcResult {
+    fn params_of<T: for<'de> Deserialize<'de>>(
+        params: Value,
+    ) -> std::result::Result<T, (i64, String)> {
+        serde_json::from_value(params).map_err(|e| (INVALID_PARAMS, e.to_string()))
+    }
+    let server_error = |e: Box<dyn std::error::Error>| (SERVER_ERROR, e.to_string());
+
+    match method {
+        "status" => status(repo).map_err(server_error),
+        "blame" => {
+            let params: BlameParams = params_of(params)?;
+            blame_line(repo, &params.path, params.line).map_err(server_error)
+        }
+        "diff_hunks" => {
+            let params: HunkParams = params_of(params)?;
+            diff_hunks(repo, &params.path, params.against.as_deref()).map_err(server_error)
+        }
+        "stage" => {
+            let params: PathsParams = params_of(params)?;
+            stage(repo, params.paths).map_err(server_error)
+        }
+        "unstage" => {
+            let params: PathsParams = params_of(params)?;
+            unstage(repo, params.paths).map_err(server_error)
+        }
+        "shutdown" => Ok(Value::Null),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{}'", method))),
+    }
+}
+
+#[derive(Deserialize)]
+struct BlameParams {
+    path: String,
+    // 1-based, in the working tree file
+    line: usize,
+}
+
+#[derive(Deserialize)]
+struct HunkParams {
+    path: String,
+    against: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct PathsParams {
+    paths: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct Hunk {
+    old_start: usize,
+    old_lines: usize,
+    new_start: usize,
+    new_lines: usize,
+    removed: Vec<String>,
+    added: Vec<String>,
+}
+
+fn status(repo: &Repository) -> Result<Value> {
+    let object_store = ObjectStore::new(repo);
+    let index = utils::load_index(repo)?;
+    let head = utils::get_head_commit(repo)?;
+    let head_tree = match &head {
+        Some(commit) => worktree::load_commit_tree(&object_store, commit)?.entries,
+        None => Default::default(),
+    };
+
+    let paths: BTreeSet<&String> = index.entries.keys().chain(head_tree.keys()).collect();
+    let mut staged = Vec::new();
+    for path in paths {
+        let kind = match (head_tree.get(path), index.entries.get(path)) {
+            (None, Some(_)) => "added",
+            (Some(_), None) => "deleted",
+            (Some(old), Some(new)) if old.hash != new.hash || old.mode != new.mode => "modified",
+            _ => continue,
+        };
+        if !index.unmerged.contains_key(path) {
+            staged.push(json!({"path": path, "kind": kind}));
+        }
+    }
+    let mut unmerged: Vec<&String> = index.unmerged.keys().collect();
+    unmerged.sort();
+
+    Ok(json!({
+        "branch": utils::head_branch(repo)?,
+        "head": head,
+        "staged": staged,
+        "modified": worktree::modified_files(repo, &index)?,
+        "untracked": ignore::untracked_files(repo, &index)?,
+        "unmerged": unmerged,
+    }))
+}
+
+fn working_text(repo: &Repository, path: &str) -> Result<String> {
+    let content = fs::read(repo_path::to_path(&repo.work_dir, path))
+        .map_err(|e| format!("Cannot read '{}': {}", path, e))?;
+    Ok(String::from_utf8_lossy(&content).into_owned())
+}
+
+fn blob_text(object_store: &ObjectStore, hash: &str) -> Result<String> {
+    Ok(String::from_utf8_lossy(&object_store.load_blob(hash)?.content).into_owned())
+}
+
+// The commit that last changed `line` of the working tree file, or
+// `uncommitted` for a line changed since HEAD
+fn blame_line(repo: &Repository, path: &str, line: usize) -> Result<Value> {
+    let object_store = ObjectStore::new(repo);
+    let content = working_text(repo, path)?;
+    if line == 0 || line > content.lines().count() {
+        return Err(format!("'{}' has no line {}", path, line).into());
+    }
+
+    let uncommitted = json!({"path": path, "line": line, "uncommitted": true});
+    let Some(head) = utils::get_head_commit(repo)? else {
+        return Ok(uncommitted);
+    };
+    let Some(entry) = worktree::load_commit_tree(&object_store, &head)?
+        .entries
+        .remove(path)
+    else {
+        return Ok(uncommitted);
+    };
+    let head_content = blob_text(&object_store, &entry.hash)?;
+    let Some(head_line) = blame::line_origins(&head_content, &content)[line - 1] else {
+        return Ok(uncommitted);
+    };
+
+    let BlameLine {
+        commit,
+        line: origin,
+    } = blame::blame_file(&object_store, &head, path)?
+        .into_iter()
+        .nth(head_line)
+        .ok_or("Blame lost track of the line")?;
+    let details = object_store.load_commit(&commit)?;
+    Ok(json!({
+        "path": path,
+        "line": line,
+        "uncommitted": false,
+        "commit": commit,
+        "original_line": origin,
+        "author": details.author,
+        "timestamp": details.timestamp,
+        "summary": details.message.lines().next().unwrap_or(""),
+    }))
+}
+
+// Changed line ranges of the working tree file, with no context, numbered
+// as in a unified diff
+fn diff_hunks(repo: &Repository, path: &str, against: Option<&str>) -> Result<Value> {
+    let object_store = ObjectStore::new(repo);
+    let base_hash = match against.unwrap_or("index") {
+        "index" => index::read_entry(repo, path)?.map(|entry| entry.hash),
+        "head" => match utils::get_head_commit(repo)? {
+            Some(head) => worktree::load_commit_tree(&object_store, &head)?
+                .entries
+                .get(path)
+                .map(|entry| entry.hash.clone()),
+            None => None,
+        },
+        other => {
+            return Err(format!("Unknown comparison '{}' (expected index or head)", other).into());
+        }
+    };
+    let old = match base_hash {
+        Some(hash) => blob_text(&object_store, &hash)?,
+        None => String::new(),
+    };
+    let new = match repo_path::to_path(&repo.work_dir, path).exists() {
+        true => working_text(repo, path)?,
+        false => String::new(),
+    };
+
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut old_index, mut new_index) = (0, 0);
+    let mut in_hunk = false;
+    for change in compute_diff(&old_lines, &new_lines) {
+        if matches!(change, DiffType::Equal) {
+            in_hunk = false;
+            old_index += 1;
+            new_index += 1;
+            continue;
+        }
+        if !in_hunk {
+            hunks.push(Hunk {
+                old_start: old_index + 1,
+                old_lines: 0,
+                new_start: new_index + 1,
+                new_lines: 0,
+                removed: Vec::new(),
+                added: Vec::new(),
+            });
+            in_hunk = true;
+        }
+        let hunk = hunks.last_mut().unwrap();
+        match change {
+            DiffType::Delete => {
+                hunk.removed.push(old_lines[old_index].to_string());
+                hunk.old_lines += 1;
+                old_index += 1;
+            }
+            _ => {
+                hunk.added.push(new_lines[new_index].to_string());
+                hunk.new_lines += 1;
+                new_index += 1;
+            }
+        }
+    }
+    // As in unified diffs, an empty side starts at the line before
+    for hunk in &mut hunks {
+        if hunk.old_lines == 0 {
+            hunk.old_start -= 1;
+        }
+        if hunk.new_lines == 0 {
+            hunk.new_start -= 1;
+        }
+    }
+    Ok(json!({"path": path, "hunks": hunks}))
+}
+
+// Stage each path's working tree content, or its deletion when the file is
+// gone
+fn stage(repo: &Repository, paths: Vec<String>) -> Result<Value> {
+    let object_store = ObjectStore::new(repo);
+    if paths
+        .iter()
+        .all(|path| repo_path::to_path(&repo.work_dir, path).is_file())
+    {
+        stage_files(repo, &object_store, &paths)?;
+        return Ok(json!({"staged": paths}));
+    }
+    let mut index = utils::lock_index(repo)?;
+    for path in &paths {
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if file_path.is_file() {
+            add_file_to_index(&mut index, &object_store, &file_path, path)?;
+        } else if index.entries.remove(path).is_some() {
+            index.unmerged.remove(path);
+        } else {
+            return Err(format!("'{}' is neither a file nor tracked", path).into());
+        }
+    }
+    utils::save_locked_index(repo, index)?;
+    Ok(json!({"staged": paths}))
+}
+
+// Undo staging: each path's index entry goes back to HEAD's version, or is
+// dropped when HEAD doesn't have the path
+fn unstage(repo: &Repository, paths: Vec<String>) -> Result<Value> {
+    let object_store = ObjectStore::new(repo);
+    let mut index = utils::lock_index(repo)?;
+    let mut head_index = match utils::get_head_commit(repo)? {
+        Some(head) => worktree::index_from_tree(&worktree::load_commit_tree(&object_store, &head)?),
+        None => Default::default(),
+    };
+    for path in &paths {
+        if index.unmerged.contains_key(path) {
+            return Err(format!("'{}' is unmerged; resolve the conflict first", path).into());
+        }
+        match head_index.entries.remove(path) {
+            Some(entry) => index.entries.insert(path.clone(), entry),
+            None => index.entries.remove(path),
+        };
+    }
+    utils::save_locked_index(repo, index)?;
+    Ok(json!({"unstaged": paths}))
+}
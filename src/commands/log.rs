@@ -1,35 +1,566 @@
-use crate::{Repository, Result, object_store::ObjectStore, utils};
+use crate::{
+    Commit, Repository, Result, TreeEntry,
+    commands::{diff, stats},
+    graph::{CommitGraph, NodeSet},
+    object_store::ObjectStore,
+    pretty, repo_path,
+    revnum::RevisionMap,
+    utils,
+};
+use chrono::{DateTime, NaiveDate, Utc};
+use regex::Regex;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
-pub fn log(repo: &Repository, max_count: Option<usize>) -> Result<()> {
-    let current_branch = utils::get_current_branch(repo)?;
-    let mut current_commit = utils::get_branch_commit(repo, &current_branch)?;
+#[derive(Default)]
+pub struct LogOptions {
+    pub max_count: Option<usize>,
+    pub show_revnum: bool,
+    pub graph: bool,
+    // Each commit on one line: its abbreviated id and subject
+    pub oneline: bool,
+    // Each commit as this format string expands (see pretty::format_commit)
+    pub format: Option<String>,
+    // Files each commit changed, with the lines added and removed
+    pub stat: bool,
+    // What each commit changed, as a unified diff
+    pub patch: bool,
+    // Only commits whose author contains this
+    pub author: Option<String>,
+    pub since: Option<String>,
+    pub until: Option<String>,
+    // Only commits whose message matches this regex
+    pub grep: Option<String>,
+    // Only commits that changed something under these paths
+    pub paths: Vec<String>,
+}
 
-    if current_commit.is_none() {
-        println!("No commits yet");
-        return Ok(());
-    }
+// History from `revision` (a branch, tag, commit, ...) or HEAD. Commits a
+// tag points at are marked with the tag's name; with `show_revnum`, mainline
+// commits also show their sequential revision number. With `graph`, history
+// is drawn beside the commits, children always above their parents. The
+// filters leave out commits without hiding the history they sit in. What
+// a commit changed is shown against its first parent; merges show none.
+pub fn log(repo: &Repository, revision: Option<String>, options: LogOptions) -> Result<()> {
+    let filter = Filter::new(repo, &options)?;
+    let start = match &revision {
+        Some(revision) => utils::resolve_revision(repo, revision)?,
+        None => {
+            let Some(head) = utils::get_head_commit(repo)? else {
+                println!("No commits yet");
+                return Ok(());
+            };
+            if utils::head_branch(repo)?.is_none() {
+                println!("HEAD detached at {}", &head[..8]);
+                println!();
+            }
+            head
+        }
+    };
 
+    // Every parent of a merge is followed, newest commit first
     let object_store = ObjectStore::new(repo);
-    let mut count = 0;
-    let max = max_count.unwrap_or(usize::MAX);
+    let tags = tags_by_commit(repo, &object_store)?;
+    let revisions = if options.show_revnum {
+        Some(RevisionMap::load(repo)?)
+    } else {
+        None
+    };
+    let revnums = revisions
+        .as_ref()
+        .map(RevisionMap::numbers)
+        .unwrap_or_default();
+    let mut commit_graph = CommitGraph::new(&object_store);
+    let tip = commit_graph.node(&start)?;
+    let max = options.max_count.unwrap_or(usize::MAX);
+    let nodes = if options.graph {
+        commit_graph.topo_walk(tip, &NodeSet::default())?
+    } else {
+        commit_graph.walk(tip, &NodeSet::default())?
+    };
+    let mut rails = options.graph.then(Rails::default);
 
-    while let Some(commit_hash) = current_commit {
-        if count >= max {
+    let mut shown = 0;
+    for node in nodes {
+        if shown == max {
             break;
         }
+        let commit = object_store.load_commit(&commit_graph.hex(node))?;
+        if !filter.matches(&object_store, &commit)? {
+            if let Some(rails) = &mut rails {
+                rails.skip(node, &commit_graph.parents(node)?);
+            }
+            continue;
+        }
+        shown += 1;
 
-        let commit = object_store.load_commit(&commit_hash)?;
-
-        println!("commit {}", commit.hash);
-        println!("Author: {}", commit.author);
-        println!("Date: {}", commit.timestamp.format("%Y-%m-%d %H:%M:%S UTC"));
-        println!();
-        println!("    {}", commit.message);
-        println!();
+        let tags = tags
+            .get(&commit.hash)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let revnum = revnums.get(commit.hash.as_str()).copied();
+        let mut lines = match (&options.format, options.oneline) {
+            (Some(format), _) => pretty::format_commit(format, &commit)?
+                .split('\n')
+                .map(str::to_string)
+                .collect(),
+            (None, true) => vec![oneline(&commit, tags, revnum)],
+            (None, false) => medium(&commit, tags, revnum),
+        };
+        if options.stat || options.patch {
+            let changes = changes(&object_store, &commit)?;
+            if options.stat {
+                lines.extend(stat_lines(&object_store, &changes)?);
+            }
+            if options.patch {
+                lines.extend(patch_lines(&object_store, &changes)?);
+            }
+            if !changes.is_empty() {
+                lines.push(String::new());
+            }
+        }
 
-        current_commit = commit.parent;
-        count += 1;
+        match &mut rails {
+            Some(rails) => {
+                let parents = commit_graph.parents(node)?;
+                rails.draw(node, &parents, &lines);
+            }
+            None => {
+                for line in lines {
+                    println!("{}", line);
+                }
+            }
+        }
     }
 
     Ok(())
 }
+
+fn medium(commit: &Commit, tags: &[String], revnum: Option<usize>) -> Vec<String> {
+    let mut lines = Vec::new();
+    if tags.is_empty() {
+        lines.push(format!("commit {}", commit.hash));
+    } else {
+        lines.push(format!(
+            "commit {} (tag: {})",
+            commit.hash,
+            tags.join(", tag: ")
+        ));
+    }
+    if commit.parents.len() > 1 {
+        let short: Vec<&str> = commit.parents.iter().map(|p| &p[..7]).collect();
+        lines.push(format!("Merge: {}", short.join(" ")));
+    }
+    if let Some(number) = revnum {
+        lines.push(format!("Revision: r{}", number));
+    }
+    lines.push(format!("Author: {}", commit.author));
+    lines.push(format!("Date: {}", pretty::date(&commit.timestamp)));
+    lines.push(String::new());
+    lines.push(format!("    {}", commit.message));
+    lines.push(String::new());
+    lines
+}
+
+fn oneline(commit: &Commit, tags: &[String], revnum: Option<usize>) -> String {
+    let mut line = commit.hash[..7].to_string();
+    if let Some(number) = revnum {
+        line.push_str(&format!(" r{}", number));
+    }
+    if !tags.is_empty() {
+        line.push_str(&format!(" (tag: {})", tags.join(", tag: ")));
+    }
+    let (subject, _) = pretty::subject_and_body(&commit.message);
+    format!("{} {}", line, subject)
+}
+
+struct FileChange {
+    path: String,
+    old: Option<TreeEntry>,
+    new: Option<TreeEntry>,
+}
+
+// The files `commit` changed against its first parent, by path
+fn changes(object_store: &ObjectStore, commit: &Commit) -> Result<Vec<FileChange>> {
+    if commit.parents.len() > 1 {
+        return Ok(Vec::new());
+    }
+    let files = |tree: &str| -> Result<BTreeMap<String, TreeEntry>> {
+        Ok(object_store
+            .load_tree(tree)?
+            .entries
+            .into_iter()
+            .filter(|(_, entry)| entry.is_file)
+            .collect())
+    };
+    let mut old = match commit.parent() {
+        Some(parent) => files(&object_store.load_commit(parent)?.tree)?,
+        None => BTreeMap::new(),
+    };
+    let mut new = files(&commit.tree)?;
+
+    let paths: BTreeSet<String> = old.keys().chain(new.keys()).cloned().collect();
+    let mut changes = Vec::new();
+    for path in paths {
+        let (old, new) = (old.remove(&path), new.remove(&path));
+        if old.as_ref().map(|e| (&e.hash, &e.mode)) != new.as_ref().map(|e| (&e.hash, &e.mode)) {
+            changes.push(FileChange { path, old, new });
+        }
+    }
+    Ok(changes)
+}
+
+// Longest bar of `--stat`; longer ones are scaled down to fit
+const STAT_WIDTH: usize = 40;
+
+fn stat_lines(object_store: &ObjectStore, changes: &[FileChange]) -> Result<Vec<String>> {
+    let mut rows = Vec::new();
+    for change in changes {
+        let (added, removed) = stats::line_changes(
+            object_store,
+            change.old.as_ref().map(|e| e.hash.as_str()),
+            change.new.as_ref().map(|e| e.hash.as_str()),
+        )?;
+        rows.push((&change.path, added, removed));
+    }
+    let Some(most) = rows.iter().map(|(_, added, removed)| added + removed).max() else {
+        return Ok(Vec::new());
+    };
+    let path_width = rows
+        .iter()
+        .map(|(path, _, _)| path.len())
+        .max()
+        .unwrap_or(0);
+    let count_width = most.to_string().len();
+    let scale = |n: usize| {
+        if most > STAT_WIDTH {
+            (n * STAT_WIDTH).div_ceil(most)
+        } else {
+            n
+        }
+    };
+
+    let mut lines = Vec::new();
+    let (mut insertions, mut deletions) = (0, 0);
+    for (path, added, removed) in &rows {
+        lines.push(format!(
+            " {:<path_width$} | {:>count_width$} {}{}",
+            path,
+            added + removed,
+            "+".repeat(scale(*added)),
+            "-".repeat(scale(*removed))
+        ));
+        insertions += added;
+        deletions += removed;
+    }
+    let plural =
+        |n: usize, one: &str, many: &str| format!("{} {}", n, if n == 1 { one } else { many });
+    let mut summary = format!(" {}", plural(rows.len(), "file changed", "files changed"));
+    if insertions > 0 {
+        summary.push_str(&format!(
+            ", {}",
+            plural(insertions, "insertion(+)", "insertions(+)")
+        ));
+    }
+    if deletions > 0 {
+        summary.push_str(&format!(
+            ", {}",
+            plural(deletions, "deletion(-)", "deletions(-)")
+        ));
+    }
+    lines.push(summary);
+    Ok(lines)
+}
+
+fn patch_lines(object_store: &ObjectStore, changes: &[FileChange]) -> Result<Vec<String>> {
+    let mut lines = Vec::new();
+    for change in changes {
+        let old_name = repo_path::quote(&format!("a/{}", change.path));
+        let new_name = repo_path::quote(&format!("b/{}", change.path));
+        lines.push(format!("diff --git {} {}", old_name, new_name));
+        match (&change.old, &change.new) {
+            (None, Some(new)) => lines.push(format!("new file mode {}", new.mode)),
+            (Some(old), None) => lines.push(format!("deleted file mode {}", old.mode)),
+            (Some(old), Some(new)) if old.mode != new.mode => {
+                lines.push(format!("old mode {}", old.mode));
+                lines.push(format!("new mode {}", new.mode));
+            }
+            _ => {}
+        }
+        let short = |entry: &Option<TreeEntry>| {
+            entry
+                .as_ref()
+                .map_or("0000000".to_string(), |e| e.hash[..7].to_string())
+        };
+        lines.push(format!(
+            "index {}..{}",
+            short(&change.old),
+            short(&change.new)
+        ));
+        lines.push(format!(
+            "--- {}",
+            if change.old.is_some() {
+                &old_name
+            } else {
+                "/dev/null"
+            }
+        ));
+        lines.push(format!(
+            "+++ {}",
+            if change.new.is_some() {
+                &new_name
+            } else {
+                "/dev/null"
+            }
+        ));
+
+        let content = |entry: &Option<TreeEntry>| -> Result<String> {
+            Ok(match entry {
+                Some(entry) => {
+                    String::from_utf8_lossy(&object_store.load_blob(&entry.hash)?.content)
+                        .to_string()
+                }
+                None => String::new(),
+            })
+        };
+        lines.extend(diff::unified_diff(
+            &content(&change.old)?,
+            &content(&change.new)?,
+        ));
+    }
+    Ok(lines)
+}
+
+struct Filter {
+    author: Option<String>,
+    since: Option<DateTime<Utc>>,
+    until: Option<DateTime<Utc>>,
+    grep: Option<Regex>,
+    paths: Vec<String>,
+}
+
+impl Filter {
+    fn new(repo: &Repository, options: &LogOptions) -> Result<Self> {
+        let grep = options
+            .grep
+            .as_deref()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| format!("Invalid --grep pattern: {}", e))
+            })
+            .transpose()?;
+        let until = match options.until.as_deref() {
+            // A bare date takes in the whole of that day
+            Some(until) => Some(match NaiveDate::parse_from_str(until, "%Y-%m-%d") {
+                Ok(date) => date.and_hms_opt(23, 59, 59).unwrap().and_utc(),
+                Err(_) => pretty::parse_date("until", until)?,
+            }),
+            None => None,
+        };
+        let paths = repo_path::precompose_args(repo, options.paths.clone())?
+            .into_iter()
+            .map(|path| {
+                path.trim_start_matches("./")
+                    .trim_end_matches('/')
+                    .to_string()
+            })
+            .collect();
+        Ok(Filter {
+            author: options.author.clone(),
+            since: options
+                .since
+                .as_deref()
+                .map(|since| pretty::parse_date("since", since))
+                .transpose()?,
+            until,
+            grep,
+            paths,
+        })
+    }
+
+    fn matches(&self, object_store: &ObjectStore, commit: &Commit) -> Result<bool> {
+        if self
+            .author
+            .as_ref()
+            .is_some_and(|author| !commit.author.contains(author.as_str()))
+            || self.since.is_some_and(|since| commit.timestamp < since)
+            || self.until.is_some_and(|until| commit.timestamp > until)
+            || self
+                .grep
+                .as_ref()
+                .is_some_and(|grep| !grep.is_match(&commit.message))
+        {
+            return Ok(false);
+        }
+        if self.paths.is_empty() {
+            return Ok(true);
+        }
+
+        // Changed unless the paths are the same in some parent, so a merge
+        // only counts when it differs from every side it joined
+        let limited = self.limited(object_store, &commit.tree)?;
+        if commit.parents.is_empty() {
+            return Ok(!limited.is_empty());
+        }
+        for parent in &commit.parents {
+            let parent = object_store.load_commit(parent)?;
+            if self.limited(object_store, &parent.tree)? == limited {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    // The entries of `tree` under the paths, as (hash, mode) by path
+    fn limited(
+        &self,
+        object_store: &ObjectStore,
+        tree: &str,
+    ) -> Result<BTreeMap<String, (String, String)>> {
+        let tree = object_store.load_tree(tree)?;
+        Ok(tree
+            .entries
+            .into_iter()
+            .filter(|(path, entry)| entry.is_file && self.covers(path))
+            .map(|(path, entry)| (path, (entry.hash, entry.mode)))
+            .collect())
+    }
+
+    fn covers(&self, path: &str) -> bool {
+        self.paths.iter().any(|limit| {
+            limit.is_empty()
+                || limit == "."
+                || path == limit
+                || path
+                    .strip_prefix(limit.as_str())
+                    .is_some_and(|rest| rest.starts_with('/'))
+        })
+    }
+}
+
+// The lines of history `log --graph` draws beside the commits: one column
+// per line of descent still open, each waiting for the commit it leads to.
+// A commit is a `*` in its column; a merge opens a column for each parent
+// after the first with `\`, and columns that come to wait for the same
+// commit join up again with `/`.
+#[derive(Default)]
+struct Rails {
+    columns: Vec<u32>,
+}
+
+impl Rails {
+    fn draw(&mut self, node: u32, parents: &[u32], lines: &[String]) {
+        let column = match self.columns.iter().position(|&waiting| waiting == node) {
+            Some(column) => column,
+            None => {
+                self.columns.push(node);
+                self.columns.len() - 1
+            }
+        };
+        let mut row = self.rails();
+        row[2 * column] = '*';
+        let mut lines = lines.iter();
+        if let Some(first) = lines.next() {
+            print_row(&row, first);
+        }
+
+        match parents.split_first() {
+            Some((first, rest)) => {
+                self.columns[column] = *first;
+                for (i, parent) in rest.iter().enumerate() {
+                    self.columns.insert(column + 1 + i, *parent);
+                }
+                // Columns right of the merge move out to make room
+                if !rest.is_empty() {
+                    let mut row = self.rails();
+                    for i in column + 1..self.columns.len() {
+                        row[2 * i] = ' ';
+                        row[2 * i - 1] = '\\';
+                    }
+                    print_row(&row, "");
+                }
+            }
+            None => self.close(column, false),
+        }
+        for line in lines {
+            print_row(&self.rails(), line);
+        }
+        self.join_up();
+    }
+
+    // Follow a commit that isn't shown: its line carries on to its parents
+    // without a row of its own
+    fn skip(&mut self, node: u32, parents: &[u32]) {
+        let Some(column) = self.columns.iter().position(|&waiting| waiting == node) else {
+            return;
+        };
+        match parents.split_first() {
+            Some((first, rest)) => {
+                self.columns[column] = *first;
+                for (i, parent) in rest.iter().enumerate() {
+                    self.columns.insert(column + 1 + i, *parent);
+                }
+            }
+            None => self.close(column, false),
+        }
+        self.join_up();
+    }
+
+    // Columns waiting for the same commit join the leftmost of them
+    fn join_up(&mut self) {
+        while let Some(join) =
+            (1..self.columns.len()).find(|&i| self.columns[..i].contains(&self.columns[i]))
+        {
+            self.close(join, true);
+        }
+    }
+
+    // Drop a column, moving those right of it in to close the gap; with
+    // `join`, its line also bends into the column to its left
+    fn close(&mut self, column: usize, join: bool) {
+        if join || column + 1 < self.columns.len() {
+            let mut row = self.rails();
+            for i in column..self.columns.len() {
+                row[2 * i] = ' ';
+                if i > column || join {
+                    row[2 * i - 1] = '/';
+                }
+            }
+            print_row(&row, "");
+        }
+        self.columns.remove(column);
+    }
+
+    // A `|` for each column, a space between each
+    fn rails(&self) -> Vec<char> {
+        let mut row = vec![' '; (2 * self.columns.len()).saturating_sub(1)];
+        for i in 0..self.columns.len() {
+            row[2 * i] = '|';
+        }
+        row
+    }
+}
+
+fn print_row(row: &[char], text: &str) {
+    let rails: String = row.iter().collect();
+    let line = if rails.is_empty() {
+        text.to_string()
+    } else {
+        format!("{} {}", rails, text)
+    };
+    println!("{}", line.trim_end());
+}
+
+// Tag names by the commit they (after peeling annotated tags) point at
+fn tags_by_commit(
+    repo: &Repository,
+    object_store: &ObjectStore,
+) -> Result<HashMap<String, Vec<String>>> {
+    let mut tags: HashMap<String, Vec<String>> = HashMap::new();
+    for (name, hash) in utils::list_refs(repo, "refs/tags/")? {
+        if let Ok(commit) = object_store.peel(&hash) {
+            tags.entry(commit)
+                .or_default()
+                .push(name["refs/tags/".len()..].to_string());
+        }
+    }
+    Ok(tags)
+}
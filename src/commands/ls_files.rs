@@ -0,0 +1,35 @@
+use crate::{Repository, Result, repo_path, utils};
+
+// Index paths, sorted. `stage` adds each entry's mode, id and stage number
+// (0 once merged; 1, 2 and 3 for the base, our and their versions of a
+// conflicted path); `unmerged` lists only the conflicted stages.
+pub fn ls_files(repo: &Repository, stage: bool, unmerged: bool) -> Result<()> {
+    let index = utils::load_index(repo)?;
+
+    let mut paths: Vec<&String> = index.entries.keys().collect();
+    paths.extend(
+        index
+            .unmerged
+            .keys()
+            .filter(|path| !index.entries.contains_key(*path)),
+    );
+    paths.sort();
+
+    for path in paths {
+        let quoted = repo_path::quote(path);
+        match index.unmerged.get(path) {
+            Some(conflict) if stage || unmerged => {
+                for (number, entry) in conflict.stages() {
+                    println!("{} {} {}\t{}", entry.mode, entry.hash, number, quoted);
+                }
+            }
+            None if unmerged => {}
+            _ if stage => {
+                let entry = &index.entries[path];
+                println!("{} {} 0\t{}", entry.mode, entry.hash, quoted);
+            }
+            _ => println!("{}", quoted),
+        }
+    }
+    Ok(())
+}
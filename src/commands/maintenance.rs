@@ -0,0 +1,51 @@
+use crate::{Repository, Result, commands::fetch, config::Config};
+
+pub fn maintenance(repo: &Repository, task: Option<String>) -> Result<()> {
+    match task.as_deref() {
+        Some("prefetch") => {
+            prefetch(repo)?;
+        }
+        _ => {
+            return Err("Invalid maintenance task. Use: prefetch".into());
+        }
+    }
+
+    Ok(())
+}
+
+// Fetch every branch of every remote into refs/prefetch/remotes/<remote>/,
+// leaving the user-visible remote-tracking branches untouched. Meant to be run
+// quietly from cron or a scheduled task so later pulls find the objects present.
+fn prefetch(repo: &Repository) -> Result<()> {
+    let config = Config::load(repo)?;
+
+    let mut remotes_fetched = 0;
+    let mut refs_updated = 0;
+    let mut failed = 0;
+
+    // One unreachable remote shouldn't keep the others from being prefetched
+    for remote_name in config.subsections("remote") {
+        let Some(url) = config.get(&format!("remote.{}.url", remote_name)) else {
+            continue;
+        };
+        match fetch::prefetch_remote(repo, &remote_name, url) {
+            Ok(updated) => {
+                refs_updated += updated;
+                remotes_fetched += 1;
+            }
+            Err(e) => {
+                println!("{}: failed: {}", remote_name, e);
+                failed += 1;
+            }
+        }
+    }
+
+    println!(
+        "Prefetched {} remote(s), {} ref(s) updated",
+        remotes_fetched, refs_updated
+    );
+    if failed > 0 {
+        return Err(format!("{} remote(s) failed to prefetch", failed).into());
+    }
+    Ok(())
+}
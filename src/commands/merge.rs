@@ -1,10 +1,56 @@
-use crate::{Commit, Repository, Result, Tree, TreeEntry, object_store::ObjectStore, utils};
+use crate::{
+    Repository, Result, StageEntry, Tree, TreeEntry, UnmergedEntry,
+    commands::commit::{self, create_commit},
+    config::Config,
+    graph::{self, CommitGraph},
+    journal::{self, RefUpdate},
+    merge_file::{self, ConflictStyle, Favor, Labels},
+    object_store::ObjectStore,
+    repo_path, utils, worktree,
+};
 use chrono::Utc;
 use std::collections::HashMap;
 use std::fs;
 
-pub fn merge(repo: &Repository, branch_name: String, author: Option<String>) -> Result<()> {
-    let current_branch = utils::get_current_branch(repo)?;
+pub struct MergeOptions {
+    pub no_commit: bool,
+    // Record a merge commit even where a fast-forward would do
+    pub no_ff: bool,
+    // Stage the result for an ordinary commit instead of merging
+    pub squash: bool,
+    // `-X ours` / `-X theirs`
+    pub favor: Option<Favor>,
+}
+
+pub fn merge(
+    repo: &Repository,
+    branch_name: Option<String>,
+    author: Option<String>,
+    options: MergeOptions,
+    continue_merge: bool,
+    abort: bool,
+) -> Result<()> {
+    let MergeOptions {
+        no_commit,
+        no_ff,
+        squash,
+        favor,
+    } = options;
+    if continue_merge {
+        return conclude_merge(repo);
+    }
+    if abort {
+        return abort_merge(repo);
+    }
+    if merge_head(repo)?.is_some() {
+        return Err(
+            "You have not concluded your merge (MERGE_HEAD exists); run `merge --continue` or `merge --abort`".into(),
+        );
+    }
+    let branch_name = branch_name.ok_or("Name the branch to merge")?;
+
+    let current_branch = utils::head_branch(repo)?
+        .ok_or("Cannot merge in detached HEAD state; check out a branch first")?;
     if current_branch == branch_name {
         return Err("Cannot merge branch into itself".into());
     }
@@ -23,131 +69,421 @@ pub fn merge(repo: &Repository, branch_name: String, author: Option<String>) ->
     let object_store = ObjectStore::new(repo);
 
     // Check if it's a fast-forward merge
-    if is_ancestor(&object_store, &current_commit, &merge_commit)? {
+    let fast_forward = graph::is_ancestor(&object_store, &current_commit, &merge_commit)?;
+    if fast_forward && squash {
+        let target_tree = worktree::load_commit_tree(&object_store, &merge_commit)?;
+        worktree::check_checkout_safety(repo, &target_tree)?;
+        worktree::switch_to_tree(repo, &object_store, &target_tree)?;
+        write_squash_msg(repo, &object_store, &current_commit, &merge_commit)?;
+        println!("Squash commit -- not updating HEAD");
+        return Ok(());
+    }
+    if fast_forward && !no_ff {
         // Fast-forward merge
+        let target_tree = worktree::load_commit_tree(&object_store, &merge_commit)?;
+        worktree::check_checkout_safety(repo, &target_tree)?;
         utils::update_branch(repo, &current_branch, &merge_commit)?;
-        crate::commands::checkout(repo, current_branch)?;
+        journal::record(
+            repo,
+            "merge",
+            &format!("fast-forward {} to {}", current_branch, branch_name),
+            vec![RefUpdate::branch(
+                &current_branch,
+                Some(current_commit.clone()),
+                Some(merge_commit.clone()),
+            )],
+        )?;
+        worktree::switch_to_tree(repo, &object_store, &target_tree)?;
         println!("Fast-forward merge completed");
         return Ok(());
     }
 
     // Three-way merge
-    let common_ancestor = find_common_ancestor(&object_store, &current_commit, &merge_commit)?
-        .ok_or("No common ancestor found")?;
+    let mut graph = CommitGraph::new(&object_store);
+    let ours = graph.node(&current_commit)?;
+    let theirs = graph.node(&merge_commit)?;
+    let bases = graph.merge_bases(ours, theirs)?;
+    if bases.is_empty() {
+        return Err("No common ancestor found".into());
+    }
+    let base_names: Vec<String> = bases
+        .iter()
+        .map(|&base| graph.hex(base)[..8].to_string())
+        .collect();
 
     println!("Performing three-way merge...");
-    println!("Base: {}", &common_ancestor[..8]);
+    println!("Base: {}", base_names.join(", "));
+    if bases.len() > 1 {
+        println!("(criss-cross history: merging the bases into a virtual one)");
+    }
     println!("Ours: {}", &current_commit[..8]);
     println!("Theirs: {}", &merge_commit[..8]);
 
-    let merged_tree = perform_three_way_merge(
+    let base_tree = merge_base_tree(&object_store, &mut graph, &bases)?;
+    let (merged_tree, conflicts) = perform_three_way_merge(
         &object_store,
-        &common_ancestor,
+        &base_tree,
         &current_commit,
         &merge_commit,
+        favor,
     )?;
+    // Uncommitted work must survive the merge, so that `--abort` can go back
+    // to HEAD without losing anything
+    worktree::check_checkout_safety(repo, &merged_tree)?;
+    for (path, _) in &conflicts {
+        println!("CONFLICT: Merge conflict in {}", path);
+    }
 
-    // Create merge commit
-    let author = author.unwrap_or_else(|| "Mini Git <minigit@example.com>".to_string());
     let message = format!("Merge branch '{}' into {}", branch_name, current_branch);
-    let commit_content = format!(
-        "{}{}{}{}{}",
-        merged_tree.hash, current_commit, merge_commit, author, message
-    );
-    let commit_hash = ObjectStore::hash_content(commit_content.as_bytes());
-
-    let merge_commit_obj = Commit {
-        hash: commit_hash.clone(),
-        parent: Some(current_commit),
-        tree: merged_tree.hash.clone(),
+
+    // A squash only stages the result, conflicts included, for an ordinary
+    // commit with one parent: no MERGE_HEAD is left behind
+    if squash {
+        worktree::switch_to_tree(repo, &object_store, &merged_tree)?;
+        let conflicted = !conflicts.is_empty();
+        if conflicted {
+            let base_label = match base_names.as_slice() {
+                [base] => base,
+                _ => "merged common ancestors",
+            };
+            write_conflicts(repo, &object_store, conflicts, base_label, &branch_name)?;
+        }
+        write_squash_msg(repo, &object_store, &current_commit, &merge_commit)?;
+        println!("Squash commit -- not updating HEAD");
+        if conflicted {
+            return Err("Automatic merge failed; fix conflicts, `add` them and commit".into());
+        }
+        return Ok(());
+    }
+
+    // Stop with the result staged, and any conflicts left unmerged with
+    // markers in their files; `merge --continue` commits from here
+    if no_commit || !conflicts.is_empty() {
+        worktree::switch_to_tree(repo, &object_store, &merged_tree)?;
+        if conflicts.is_empty() {
+            write_merge_state(repo, "MERGE_HEAD", &merge_commit, &message)?;
+            println!("Automatic merge went well; stopped before committing as requested");
+        } else {
+            let base_label = match base_names.as_slice() {
+                [base] => base,
+                _ => "merged common ancestors",
+            };
+            write_conflicts(repo, &object_store, conflicts, base_label, &branch_name)?;
+            write_merge_state(repo, "MERGE_HEAD", &merge_commit, &message)?;
+            return Err(
+                "Automatic merge failed; fix conflicts, `add` them and run `merge --continue`"
+                    .into(),
+            );
+        }
+        return Ok(());
+    }
+
+    // Create merge commit
+    let author = match author {
+        Some(author) => author,
+        None => Config::load(repo)?.author_ident(),
+    };
+    let commit_hash = create_commit(
+        &object_store,
+        &merged_tree.hash,
+        vec![current_commit.clone(), merge_commit],
         author,
         message,
-        timestamp: Utc::now(),
-    };
-
-    object_store.store_commit(&merge_commit_obj)?;
+        Utc::now(),
+    )?;
     utils::update_branch(repo, &current_branch, &commit_hash)?;
+    journal::record(
+        repo,
+        "merge",
+        &format!("merge {} into {}", branch_name, current_branch),
+        vec![RefUpdate::branch(
+            &current_branch,
+            Some(current_commit),
+            Some(commit_hash.clone()),
+        )],
+    )?;
 
     // Update working directory
-    restore_tree_to_working_dir(repo, &object_store, &merged_tree)?;
+    worktree::switch_to_tree(repo, &object_store, &merged_tree)?;
 
     println!("Merge completed: {}", &commit_hash[..8]);
     Ok(())
 }
 
-fn is_ancestor(object_store: &ObjectStore, ancestor: &str, descendant: &str) -> Result<bool> {
-    let mut current = descendant.to_string();
-
-    while current != ancestor {
-        let commit = object_store.load_commit(&current)?;
-        if let Some(parent) = commit.parent {
-            current = parent;
-        } else {
-            return Ok(false);
-        }
+// Commit a merge that stopped on conflicts or at `--no-commit`, once every
+// conflicted path has been resolved and `add`ed
+fn conclude_merge(repo: &Repository) -> Result<()> {
+    if merge_head(repo)?.is_none() {
+        return Err("There is no merge to continue (MERGE_HEAD missing)".into());
     }
+    commit::commit(repo, None, None, None, false, Vec::new(), false)
+}
 
-    Ok(true)
+// Give up a merge that stopped before its commit: the index and working tree
+// go back to HEAD, which the merge hasn't moved, and MERGE_HEAD is removed
+fn abort_merge(repo: &Repository) -> Result<()> {
+    if merge_head(repo)?.is_none() {
+        return Err("There is no merge to abort (MERGE_HEAD missing)".into());
+    }
+    let object_store = ObjectStore::new(repo);
+    let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+    let tree = worktree::load_commit_tree(&object_store, &head)?;
+    worktree::reset_to_tree(repo, &object_store, &tree)?;
+    clear_merge_state(repo)?;
+    println!("Merge aborted; back at {}", &head[..8]);
+    Ok(())
 }
 
-fn find_common_ancestor(
+// SQUASH_MSG prepares the message for the commit that concludes `merge
+// --squash`, listing the commits it brings in
+fn write_squash_msg(
+    repo: &Repository,
     object_store: &ObjectStore,
-    commit1: &str,
-    commit2: &str,
-) -> Result<Option<String>> {
-    let mut ancestors1 = std::collections::HashSet::new();
-    let mut current = commit1.to_string();
-
-    // Collect all ancestors of commit1
-    loop {
-        ancestors1.insert(current.clone());
-        let commit = object_store.load_commit(&current)?;
-        if let Some(parent) = commit.parent {
-            current = parent;
-        } else {
-            break;
-        }
+    current_commit: &str,
+    merge_commit: &str,
+) -> Result<()> {
+    let mut graph = CommitGraph::new(object_store);
+    let ours = graph.node(current_commit)?;
+    let theirs = graph.node(merge_commit)?;
+    let stop = graph.reachable(ours)?;
+    let mut message = String::from("Squashed commit of the following:\n");
+    for node in graph.walk(theirs, &stop)? {
+        let hash = graph.hex(node);
+        let commit = object_store.load_commit(&hash)?;
+        let subject = commit.message.lines().next().unwrap_or("");
+        message.push_str(&format!("\n{} {}", &hash[..8], subject));
     }
+    fs::write(repo.git_dir.join("SQUASH_MSG"), format!("{}\n", message))?;
+    Ok(())
+}
 
-    // Find first common ancestor in commit2's history
-    current = commit2.to_string();
-    loop {
-        if ancestors1.contains(&current) {
-            return Ok(Some(current));
-        }
+pub(crate) fn squash_msg(repo: &Repository) -> Option<String> {
+    fs::read_to_string(repo.git_dir.join("SQUASH_MSG"))
+        .ok()
+        .map(|message| message.trim_end_matches('\n').to_string())
+}
 
-        let commit = object_store.load_commit(&current)?;
-        if let Some(parent) = commit.parent {
-            current = parent;
-        } else {
-            break;
-        }
+// A merge stopped before its commit (on conflicts or `merge --no-commit`):
+// MERGE_HEAD names the commit being merged and MERGE_MSG holds the message
+// `merge --continue` and `commit` use unless given another
+pub(crate) fn merge_head(repo: &Repository) -> Result<Option<String>> {
+    read_state_file(repo, "MERGE_HEAD")
+}
+
+// The commit id in a state file such as MERGE_HEAD, if it exists
+pub(crate) fn read_state_file(repo: &Repository, name: &str) -> Result<Option<String>> {
+    match fs::read_to_string(repo.git_dir.join(name)) {
+        Ok(hash) => Ok(Some(hash.trim().to_string())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e.into()),
     }
+}
+
+pub(crate) fn merge_msg(repo: &Repository) -> Option<String> {
+    fs::read_to_string(repo.git_dir.join("MERGE_MSG"))
+        .ok()
+        .map(|message| message.trim_end_matches('\n').to_string())
+}
 
-    Ok(None)
+// `state_file` is MERGE_HEAD, or REVERT_HEAD / CHERRY_PICK_HEAD for those
+// commands stopped on conflicts
+pub(crate) fn write_merge_state(
+    repo: &Repository,
+    state_file: &str,
+    commit: &str,
+    message: &str,
+) -> Result<()> {
+    fs::write(repo.git_dir.join("MERGE_MSG"), format!("{}\n", message))?;
+    // Written last: its presence is what marks the operation in progress
+    fs::write(repo.git_dir.join(state_file), format!("{}\n", commit))?;
+    Ok(())
 }
 
+// Also ends a revert or cherry-pick stopped on conflicts, or a squash merge
+pub(crate) fn clear_merge_state(repo: &Repository) -> Result<()> {
+    for name in [
+        "MERGE_HEAD",
+        "MERGE_MSG",
+        "REVERT_HEAD",
+        "CHERRY_PICK_HEAD",
+        "SQUASH_MSG",
+    ] {
+        match fs::remove_file(repo.git_dir.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+// The merged tree, with our version of each path whose changes conflict, and
+// the versions from each side of those paths. Files both sides changed in
+// separate places are merged line by line; with `favor` (`-X ours` or `-X
+// theirs`), overlapping changes within them take that side instead.
 fn perform_three_way_merge(
     object_store: &ObjectStore,
-    base_commit: &str,
+    base_tree: &Tree,
     our_commit: &str,
     their_commit: &str,
+    favor: Option<Favor>,
+) -> Result<(Tree, Vec<(String, UnmergedEntry)>)> {
+    let our_tree = worktree::load_commit_tree(object_store, our_commit)?;
+    let their_tree = worktree::load_commit_tree(object_store, their_commit)?;
+
+    let result = merge_trees(object_store, base_tree, &our_tree, &their_tree)?;
+    let result = merge_clean_contents(
+        object_store,
+        result,
+        base_tree,
+        &our_tree,
+        &their_tree,
+        favor,
+    )?;
+    let conflicts = conflict_stages(&result.conflicts, base_tree, &our_tree, &their_tree);
+    Ok((result.tree, conflicts))
+}
+
+// The tree to merge against, given the best common ancestors of the two
+// sides. Criss-cross history can leave several; like git's recursive
+// strategy, they are then merged one after another into a virtual base, each
+// merge against the (possibly also virtual) base of its own sides. Conflicts
+// there are kept, markers and all: where both sides of the real merge still
+// differ from them, the real merge conflicts too.
+fn merge_base_tree(
+    object_store: &ObjectStore,
+    graph: &mut CommitGraph,
+    bases: &[u32],
 ) -> Result<Tree> {
-    let base_tree = {
-        let commit = object_store.load_commit(base_commit)?;
-        object_store.load_tree(&commit.tree)?
+    let Some((&first, rest)) = bases.split_first() else {
+        return Ok(Tree {
+            hash: String::new(),
+            entries: HashMap::new(),
+        });
     };
+    let mut tree = worktree::load_commit_tree(object_store, &graph.hex(first))?;
+    let mut merged = vec![first];
+    let labels = Labels {
+        ours: "Temporary merge branch 1",
+        base: "",
+        theirs: "Temporary merge branch 2",
+    };
+    for &next in rest {
+        let inner_bases = graph.merge_bases_of(&merged, next)?;
+        let base_tree = merge_base_tree(object_store, graph, &inner_bases)?;
+        let next_tree = worktree::load_commit_tree(object_store, &graph.hex(next))?;
+
+        let result = merge_trees(object_store, &base_tree, &tree, &next_tree)?;
+        let result =
+            merge_clean_contents(object_store, result, &base_tree, &tree, &next_tree, None)?;
+        let mut entries = result.tree.entries;
+        for (path, stages) in conflict_stages(&result.conflicts, &base_tree, &tree, &next_tree) {
+            let content = conflicted_content(object_store, &stages, ConflictStyle::Merge, &labels)?;
+            if let Some(entry) = entries.get_mut(&path) {
+                entry.hash = object_store.store_blob(&content)?;
+            }
+        }
+        tree = object_store.write_tree(entries)?;
+        merged.push(next);
+    }
+    Ok(tree)
+}
+
+// The versions each tree has of the conflicted `paths`
+pub(crate) fn conflict_stages(
+    paths: &[String],
+    base_tree: &Tree,
+    our_tree: &Tree,
+    their_tree: &Tree,
+) -> Vec<(String, UnmergedEntry)> {
+    paths
+        .iter()
+        .map(|path| {
+            let stage = |tree: &Tree| {
+                tree.entries.get(path).map(|entry| StageEntry {
+                    mode: entry.mode.clone(),
+                    hash: entry.hash.clone(),
+                })
+            };
+            let entry = UnmergedEntry {
+                base: stage(base_tree),
+                ours: stage(our_tree),
+                theirs: stage(their_tree),
+            };
+            (path.clone(), entry)
+        })
+        .collect()
+}
 
-    let our_tree = {
-        let commit = object_store.load_commit(our_commit)?;
-        object_store.load_tree(&commit.tree)?
+// Leave each conflicted path unmerged in the index, with conflict markers in
+// its working tree file
+pub(crate) fn write_conflicts(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    conflicts: Vec<(String, UnmergedEntry)>,
+    base_label: &str,
+    their_label: &str,
+) -> Result<()> {
+    let style = ConflictStyle::from_config(&Config::load(repo)?)?;
+    let labels = Labels {
+        ours: "HEAD",
+        base: base_label,
+        theirs: their_label,
     };
 
-    let their_tree = {
-        let commit = object_store.load_commit(their_commit)?;
-        object_store.load_tree(&commit.tree)?
+    let mut index = utils::lock_index(repo)?;
+    for (path, entry) in conflicts {
+        let content = conflicted_content(object_store, &entry, style, &labels)?;
+        fs::write(repo_path::to_path(&repo.work_dir, &path), content)?;
+        index.unmerged.insert(path, entry);
+    }
+    utils::save_locked_index(repo, index)
+}
+
+// A conflicted file as it is left for the user: both sides merged with
+// conflict markers, or whichever side still has the file. Binary files can't
+// hold markers and keep our version.
+pub(crate) fn conflicted_content(
+    object_store: &ObjectStore,
+    entry: &UnmergedEntry,
+    style: ConflictStyle,
+    labels: &Labels,
+) -> Result<Vec<u8>> {
+    let load = |stage: Option<&StageEntry>| -> Result<Option<Vec<u8>>> {
+        stage
+            .map(|stage| object_store.load_blob(&stage.hash).map(|blob| blob.content))
+            .transpose()
+    };
+    let (ours, theirs) = match (load(entry.ours.as_ref())?, load(entry.theirs.as_ref())?) {
+        (Some(ours), Some(theirs)) => (ours, theirs),
+        (ours, theirs) => return Ok(ours.or(theirs).unwrap_or_default()),
     };
+    let base = load(entry.base.as_ref())?.unwrap_or_default();
+
+    match (
+        String::from_utf8(base),
+        std::str::from_utf8(&ours),
+        String::from_utf8(theirs),
+    ) {
+        (Ok(base), Ok(our_text), Ok(theirs)) => {
+            let merged = merge_file::merge(&base, our_text, &theirs, style, labels, None);
+            Ok(merged.content.into_bytes())
+        }
+        _ => Ok(ours),
+    }
+}
+
+pub(crate) struct TreeMergeResult {
+    pub tree: Tree,
+    pub conflicts: Vec<String>,
+}
 
+pub(crate) fn merge_trees(
+    object_store: &ObjectStore,
+    base_tree: &Tree,
+    our_tree: &Tree,
+    their_tree: &Tree,
+) -> Result<TreeMergeResult> {
+    let mut conflicts = Vec::new();
     let mut merged_entries = HashMap::new();
     let mut all_paths = std::collections::HashSet::new();
 
@@ -201,8 +537,12 @@ fn perform_three_way_merge(
             }
             // Conflict: both branches modified the file differently
             (Some(_), Some(our), Some(their)) if our.hash != their.hash => {
-                println!("CONFLICT: Merge conflict in {}", path);
-                println!("Automatic merge failed; using our version");
+                conflicts.push(path.clone());
+                merged_entries.insert(path, our.clone());
+            }
+            // Conflict: both branches added the file with different content
+            (None, Some(our), Some(their)) if our.hash != their.hash => {
+                conflicts.push(path.clone());
                 merged_entries.insert(path, our.clone());
             }
             // Other cases: use default behavior
@@ -215,34 +555,73 @@ fn perform_three_way_merge(
     }
 
     // Create merged tree
-    let tree_content = serde_json::to_vec(&merged_entries)?;
-    let tree_hash = ObjectStore::hash_content(&tree_content);
-    let merged_tree = Tree {
-        hash: tree_hash,
-        entries: merged_entries,
-    };
-
-    object_store.store_tree(&merged_tree)?;
-    Ok(merged_tree)
+    let merged_tree = object_store.write_tree(merged_entries)?;
+    conflicts.sort();
+    Ok(TreeMergeResult {
+        tree: merged_tree,
+        conflicts,
+    })
 }
 
-fn restore_tree_to_working_dir(
-    repo: &Repository,
+// Settle the conflicted paths whose versions merge line by line without
+// overlapping changes, or with them resolved to the `favor`ed side,
+// rewriting the merged tree with the result
+pub(crate) fn merge_clean_contents(
     object_store: &ObjectStore,
-    tree: &Tree,
-) -> Result<()> {
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
+    result: TreeMergeResult,
+    base_tree: &Tree,
+    our_tree: &Tree,
+    their_tree: &Tree,
+    favor: Option<Favor>,
+) -> Result<TreeMergeResult> {
+    let load = |tree: &Tree, path: &str| -> Result<Option<String>> {
+        match tree.entries.get(path) {
+            Some(entry) => Ok(String::from_utf8(object_store.load_blob(&entry.hash)?.content).ok()),
+            None => Ok(Some(String::new())),
+        }
+    };
+    let labels = Labels {
+        ours: "",
+        base: "",
+        theirs: "",
+    };
 
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
+    let mut entries = result.tree.entries;
+    let mut conflicts = Vec::new();
+    for path in result.conflicts {
+        let (Some(our_entry), true) = (
+            our_tree.entries.get(&path),
+            their_tree.entries.contains_key(&path),
+        ) else {
+            conflicts.push(path);
+            continue;
+        };
+        let merged = match (
+            load(base_tree, &path)?,
+            load(our_tree, &path)?,
+            load(their_tree, &path)?,
+        ) {
+            (Some(base), Some(ours), Some(theirs)) => {
+                merge_file::merge(&base, &ours, &theirs, ConflictStyle::Merge, &labels, favor)
             }
-
-            fs::write(file_path, &blob.content)?;
+            _ => {
+                conflicts.push(path);
+                continue;
+            }
+        };
+        if merged.conflicts > 0 {
+            conflicts.push(path);
+            continue;
         }
+        let entry = TreeEntry {
+            hash: object_store.store_blob(merged.content.as_bytes())?,
+            ..our_entry.clone()
+        };
+        entries.insert(path, entry);
     }
 
-    Ok(())
+    Ok(TreeMergeResult {
+        tree: object_store.write_tree(entries)?,
+        conflicts,
+    })
 }
@@ -0,0 +1,76 @@
+use crate::{
+    Repository, Result,
+    config::Config,
+    merge_file::{self, ConflictStyle, Labels},
+};
+use std::fs;
+use std::path::PathBuf;
+
+// Merge the changes from `base` to `other` into `current`, as `git
+// merge-file` does. Conflicts are written into the result with markers in
+// the style of `merge.conflictStyle` unless `style` overrides it. Works
+// outside a repository with the global config.
+pub fn merge_file(
+    repo: Option<&Repository>,
+    files: [PathBuf; 3],
+    labels: Vec<String>,
+    style: Option<ConflictStyle>,
+    stdout: bool,
+) -> Result<()> {
+    let style = match style {
+        Some(style) => style,
+        None => match repo {
+            Some(repo) => ConflictStyle::from_config(&Config::load(repo)?)?,
+            None => ConflictStyle::from_config(&Config::load_global()?)?,
+        },
+    };
+
+    let mut contents = Vec::with_capacity(3);
+    for file in &files {
+        let content =
+            fs::read(file).map_err(|e| format!("Cannot read {}: {}", file.display(), e))?;
+        contents.push(
+            String::from_utf8(content)
+                .map_err(|_| format!("Cannot merge binary file {}", file.display()))?,
+        );
+    }
+
+    // Labels default to the file names, as given
+    let names: Vec<String> = files
+        .iter()
+        .enumerate()
+        .map(|(i, file)| {
+            labels
+                .get(i)
+                .cloned()
+                .unwrap_or_else(|| file.display().to_string())
+        })
+        .collect();
+    let merged = merge_file::merge(
+        &contents[1],
+        &contents[0],
+        &contents[2],
+        style,
+        &Labels {
+            ours: &names[0],
+            base: &names[1],
+            theirs: &names[2],
+        },
+        None,
+    );
+
+    if stdout {
+        print!("{}", merged.content);
+    } else {
+        fs::write(&files[0], &merged.content)?;
+    }
+    if merged.conflicts > 0 {
+        return Err(format!(
+            "{} conflict(s) while merging into {}",
+            merged.conflicts,
+            files[0].display()
+        )
+        .into());
+    }
+    Ok(())
+}
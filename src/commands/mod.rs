@@ -1,29 +1,109 @@
 pub mod add;
+pub mod am;
+pub mod backup;
+pub mod blame;
 pub mod branch;
+pub mod cat_file;
 pub mod checkout;
+pub mod cherry_pick;
 pub mod clone;
 pub mod commit;
+pub mod commit_graph;
+pub mod commit_tree;
+pub mod config;
 pub mod diff;
+pub mod difftool;
+pub mod fetch;
+pub mod for_each_ref;
+pub mod fsck;
+pub mod gc;
+pub mod import_snapshots;
 pub mod init;
+pub mod ipc;
 pub mod log;
+pub mod ls_files;
+pub mod maintenance;
 pub mod merge;
+pub mod merge_file;
+pub mod mv;
 pub mod pull;
 pub mod push;
+pub mod read_tree;
+pub mod rebase;
+pub mod reflog;
 pub mod remote;
+pub mod repack;
+pub mod reset;
+pub mod restore;
+pub mod rev;
+pub mod rev_list;
+pub mod revert;
+pub mod review;
+pub mod rm;
+pub mod serve;
+pub mod snapshot;
 pub mod stash;
+pub mod stats;
 pub mod status;
+pub mod tag;
+pub mod trailers;
+pub mod undo;
+pub mod update_index;
+pub mod var;
+pub mod verify_history;
+pub mod write_tree;
 
 pub use add::*;
+pub use am::*;
+pub use backup::*;
+pub use blame::*;
 pub use branch::*;
+pub use cat_file::*;
 pub use checkout::*;
+pub use cherry_pick::*;
 pub use clone::*;
 pub use commit::*;
+pub use commit_graph::*;
+pub use commit_tree::*;
+pub use config::*;
 pub use diff::*;
+pub use difftool::*;
+pub use fetch::*;
+pub use for_each_ref::*;
+pub use fsck::*;
+pub use gc::*;
+pub use import_snapshots::*;
 pub use init::*;
+pub use ipc::*;
 pub use log::*;
+pub use ls_files::*;
+pub use maintenance::*;
 pub use merge::*;
+pub use merge_file::*;
+pub use mv::*;
 pub use pull::*;
 pub use push::*;
+pub use read_tree::*;
+pub use rebase::*;
+pub use reflog::*;
 pub use remote::*;
+pub use repack::*;
+pub use reset::*;
+pub use restore::*;
+pub use rev::*;
+pub use rev_list::*;
+pub use revert::*;
+pub use review::*;
+pub use rm::*;
+pub use serve::*;
+pub use snapshot::*;
 pub use stash::*;
+pub use stats::*;
 pub use status::*;
+pub use tag::*;
+pub use trailers::*;
+pub use undo::*;
+pub use update_index::*;
+pub use var::*;
+pub use verify_history::*;
+pub use write_tree::*;
@@ -0,0 +1,87 @@
+use crate::{Repository, Result, repo_path, utils};
+use std::fs;
+
+// Move or rename a tracked file or directory, on disk and in the index
+// together, so the next commit records a rename. A destination that is an
+// existing directory receives the source inside it. An existing destination
+// file is only replaced with `force`.
+pub fn mv(repo: &Repository, source: String, destination: String, force: bool) -> Result<()> {
+    let mut args = repo_path::precompose_args(repo, vec![source, destination])?;
+    let destination = args.pop().unwrap().trim_end_matches('/').to_string();
+    let source = args.pop().unwrap().trim_end_matches('/').to_string();
+    let mut index = utils::lock_index(repo)?;
+
+    // Tracked paths being moved, with where each one ends up
+    let moves: Vec<(String, String)> = index
+        .entries
+        .keys()
+        .filter_map(|path| {
+            let rest = if *path == source {
+                ""
+            } else {
+                path.strip_prefix(&source)?.strip_prefix('/')?
+            };
+            Some((path.clone(), rest.to_string()))
+        })
+        .collect();
+    if moves.is_empty() {
+        return Err(format!("not under version control, source={}", source).into());
+    }
+    if let Some((path, _)) = moves
+        .iter()
+        .find(|(path, _)| index.unmerged.contains_key(path))
+    {
+        return Err(format!("'{}' is unmerged; resolve the conflict first", path).into());
+    }
+
+    let source_path = repo_path::to_path(&repo.work_dir, &source);
+    if !source_path.exists() {
+        return Err(format!("bad source, source={}", source).into());
+    }
+    let mut target = destination.clone();
+    let mut target_path = repo_path::to_path(&repo.work_dir, &target);
+    if target_path.is_dir() {
+        let name = source.rsplit('/').next().unwrap_or(&source);
+        target = if target.is_empty() || target == "." {
+            name.to_string()
+        } else {
+            format!("{}/{}", target, name)
+        };
+        target_path = repo_path::to_path(&repo.work_dir, &target);
+    }
+    if target == source || target.starts_with(&format!("{}/", source)) {
+        return Err(format!("can not move '{}' into itself", source).into());
+    }
+    if target_path.exists() && (target_path.is_dir() || !force) {
+        return Err(format!(
+            "destination exists, source={}, destination={}",
+            source, target
+        )
+        .into());
+    }
+    if let Some(parent) = target_path.parent()
+        && !parent.is_dir()
+    {
+        return Err(format!(
+            "destination directory does not exist, source={}, destination={}",
+            source, target
+        )
+        .into());
+    }
+
+    fs::rename(&source_path, &target_path)?;
+    for (path, rest) in moves {
+        let new_path = if rest.is_empty() {
+            target.clone()
+        } else {
+            format!("{}/{}", target, rest)
+        };
+        let mut entry = index.entries.remove(&path).unwrap();
+        entry.path = new_path.clone();
+        index.entries.insert(new_path, entry);
+    }
+    utils::save_locked_index(repo, index)?;
+
+    println!("Renamed {} -> {}", source, target);
+    Ok(())
+}
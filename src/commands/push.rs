@@ -1,24 +1,89 @@
-use crate::{Repository, Result, utils};
+use crate::{
+    Repository, Result,
+    access::{self, Operation},
+    commands::checkout::restore_working_directory,
+    fsck::{self, Side},
+    graph, hash,
+    hooks::{self, Hook, HookContext},
+    mirror,
+    object_store::{ObjectStore, add_alternate, copy_missing_objects, read_alternates},
+    protocol::{self, Connection, Request},
+    push_cert, utils, worktree,
+};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn push(repo: &Repository, remote: Option<String>, branch: Option<String>) -> Result<()> {
+pub fn push(
+    repo: &Repository,
+    remote: Option<String>,
+    branch: Option<String>,
+    no_verify: bool,
+    signed: bool,
+    mirror: bool,
+    force: bool,
+) -> Result<()> {
     let remote_name = remote.unwrap_or_else(|| "origin".to_string());
     let branch_name =
         branch.unwrap_or_else(|| utils::get_current_branch(repo).unwrap_or("main".to_string()));
 
     // Get remote URL from config
-    let remote_url = get_remote_url(repo, &remote_name)?;
+    let remote_url = utils::get_remote_url(repo, &remote_name)?;
 
     println!("Pushing to {} ({})", remote_name, remote_url);
 
+    if mirror {
+        return push_mirror(repo, &remote_name, &remote_url, no_verify);
+    }
+
+    let tag_path = repo.git_dir.join("refs").join("tags").join(&branch_name);
+    if utils::get_branch_commit(repo, &branch_name)?.is_none() && tag_path.is_file() {
+        let id = fs::read_to_string(tag_path)?.trim().to_string();
+        return push_tag(
+            repo,
+            &remote_name,
+            &remote_url,
+            &branch_name,
+            &id,
+            no_verify,
+            signed,
+        );
+    }
+
     // Get current branch commit
     let local_commit = utils::get_branch_commit(repo, &branch_name)?
         .ok_or(format!("Branch '{}' has no commits", branch_name))?;
 
+    run_pre_push(
+        repo,
+        &remote_name,
+        &remote_url,
+        &format!("refs/heads/{}", branch_name),
+        &local_commit,
+        no_verify,
+    )?;
+
     // Only handle local file path remotes
     if PathBuf::from(&remote_url).exists() {
-        push_to_local_remote(repo, &remote_url, &branch_name, &local_commit)?;
+        push_to_local_remote(
+            repo,
+            &remote_url,
+            &branch_name,
+            &local_commit,
+            signed,
+            force,
+        )?;
+    } else if protocol::is_remote_url(&remote_url) {
+        push_over_connection(
+            repo,
+            &remote_url,
+            &remote_name,
+            &branch_name,
+            &local_commit,
+            signed,
+            force,
+        )?;
+    } else if signed {
+        return Err(format!("Cannot sign a push to '{}'", remote_url).into());
     } else {
         println!("Note: Mini Git only supports local repository pushing.");
         println!("Remote URL: {}", remote_url);
@@ -34,14 +99,13 @@ pub fn push(repo: &Repository, remote: Option<String>, branch: Option<String>) -
         );
 
         // Update local remote tracking branch for consistency
-        let remote_branch_path = repo
-            .git_dir
-            .join("refs")
-            .join("remotes")
-            .join(&remote_name)
-            .join(&branch_name);
-        fs::create_dir_all(remote_branch_path.parent().unwrap())?;
-        fs::write(remote_branch_path, &local_commit)?;
+        utils::update_remote_ref(
+            repo,
+            &remote_name,
+            &branch_name,
+            Some(&local_commit),
+            "update by push",
+        )?;
 
         println!(
             "Updated local tracking branch {}/{}",
@@ -57,19 +121,22 @@ fn push_to_local_remote(
     remote_path: &str,
     branch_name: &str,
     commit_hash: &str,
+    signed: bool,
+    force: bool,
 ) -> Result<()> {
     let remote_git_dir = PathBuf::from(remote_path).join(".mini_git");
     if !remote_git_dir.exists() {
         return Err("Remote is not a mini-git repository".into());
     }
+    hash::ensure_same_format(&repo.git_dir, &remote_git_dir, "Remote")?;
 
-    println!("Pushing to local Mini Git repository...");
-
-    // Copy objects that don't exist in remote
-    let local_objects = repo.git_dir.join("objects");
-    let remote_objects = remote_git_dir.join("objects");
+    access::authorize(
+        &remote_git_dir,
+        Operation::Push,
+        &format!("refs/heads/{}", branch_name),
+    )?;
 
-    let copied_count = copy_missing_objects(&local_objects, &remote_objects)?;
+    println!("Pushing to local Mini Git repository...");
 
     // Create remote repository struct
     let remote_repo = Repository {
@@ -77,25 +144,74 @@ fn push_to_local_remote(
         work_dir: PathBuf::from(remote_path).to_path_buf(),
     };
 
-    // Check if remote has uncommitted changes
-    let remote_has_changes = check_for_uncommitted_changes(&remote_repo)?;
-
-    // Update remote branch
-    let remote_branch_path = remote_git_dir.join("refs").join("heads").join(branch_name);
-    fs::create_dir_all(remote_branch_path.parent().unwrap())?;
-
+    let remote_branch_path = utils::served_refs_dir(&remote_git_dir)
+        .join("heads")
+        .join(branch_name);
     let old_commit = if remote_branch_path.exists() {
         Some(fs::read_to_string(&remote_branch_path)?.trim().to_string())
     } else {
         None
     };
 
+    let refname = format!("refs/heads/{}", branch_name);
+    // Our history can only contain the remote's tip if we have it
+    let object_store = ObjectStore::new(repo);
+    if let Some(old) = &old_commit
+        && !force
+        && !(object_store.object_exists(old)
+            && graph::is_ancestor(&object_store, old, commit_hash)?)
+    {
+        return Err(non_fast_forward(&refname));
+    }
+
+    let cert = if signed {
+        Some(push_cert::create(
+            repo,
+            remote_path,
+            &refname,
+            old_commit.as_deref(),
+            commit_hash,
+        )?)
+    } else {
+        None
+    };
+
+    // Copy objects that don't exist in remote
+    let local_objects = repo.git_dir.join("objects");
+    let copied_count = receive_objects(
+        &remote_repo,
+        &[commit_hash.to_string()],
+        |quarantine| copy_missing_objects(&local_objects, quarantine),
+        || {
+            run_pre_receive(
+                &remote_repo,
+                &refname,
+                old_commit.as_deref(),
+                commit_hash,
+                cert.as_deref(),
+            )
+        },
+    )?;
+
+    // Check if remote has uncommitted changes
+    let remote_has_changes = worktree::has_local_changes(
+        &remote_repo,
+        &ObjectStore::new(&remote_repo),
+        &utils::load_index(&remote_repo)?,
+        true,
+    )?;
+
+    // Update remote branch
+    fs::create_dir_all(remote_branch_path.parent().unwrap())?;
     fs::write(remote_branch_path, commit_hash)?;
 
-    // Update remote working directory if safe to do so
-    if !remote_has_changes {
+    // Update remote working directory if safe to do so. Namespaced refs are
+    // never checked out, so leave the remote's files alone.
+    if let Some(namespace) = utils::ref_namespace() {
+        println!("Updated namespace '{}' in remote repository", namespace);
+    } else if !remote_has_changes {
         println!("Updating remote working directory...");
-        update_remote_working_directory(&remote_repo, commit_hash)?;
+        restore_working_directory(&remote_repo, commit_hash)?;
         println!("Remote working directory updated with new files");
     } else {
         println!("Warning: Remote repository has uncommitted changes.");
@@ -106,14 +222,13 @@ fn push_to_local_remote(
     }
 
     // Update local remote tracking branch
-    let local_remote_branch_path = repo
-        .git_dir
-        .join("refs")
-        .join("remotes")
-        .join("origin")
-        .join(branch_name);
-    fs::create_dir_all(local_remote_branch_path.parent().unwrap())?;
-    fs::write(local_remote_branch_path, commit_hash)?;
+    utils::update_remote_ref(
+        repo,
+        "origin",
+        branch_name,
+        Some(commit_hash),
+        "update by push",
+    )?;
 
     println!(
         "Successfully pushed {} to origin/{}",
@@ -122,175 +237,406 @@ fn push_to_local_remote(
     );
     println!("Copied {} objects to remote repository", copied_count);
 
-    if let Some(old) = old_commit {
-        if old != commit_hash {
-            println!(
-                "Updated remote branch from {} to {}",
-                &old[..8],
-                &commit_hash[..8]
-            );
-        }
+    if let Some(old) = old_commit
+        && old != commit_hash
+    {
+        println!(
+            "Updated remote branch from {} to {}",
+            &old[..8],
+            &commit_hash[..8]
+        );
     }
 
     Ok(())
 }
 
-fn check_for_uncommitted_changes(repo: &Repository) -> Result<bool> {
-    let index = utils::load_index(repo)?;
-
-    // Check if working directory matches index
-    for (path, index_entry) in &index.entries {
-        let file_path = repo.work_dir.join(path);
-        if file_path.exists() {
-            let content = fs::read(&file_path)?;
-            let current_hash = crate::object_store::ObjectStore::hash_content(&content);
-            if current_hash != index_entry.hash {
-                return Ok(true); // Modified file
-            }
-        } else {
-            return Ok(true); // Deleted file
-        }
+// Make a local remote's refs identical to ours (see mirror): every ref is
+// written as we have it, and refs we don't have are deleted
+fn push_mirror(
+    repo: &Repository,
+    remote_name: &str,
+    remote_url: &str,
+    no_verify: bool,
+) -> Result<()> {
+    let remote_git_dir = PathBuf::from(remote_url).join(".mini_git");
+    if protocol::is_remote_url(remote_url) || !remote_git_dir.exists() {
+        return Err("push --mirror needs a local mini-git repository as the remote".into());
     }
+    hash::ensure_same_format(&repo.git_dir, &remote_git_dir, "Remote")?;
+    let remote_repo = Repository {
+        git_dir: remote_git_dir.clone(),
+        work_dir: PathBuf::from(remote_url),
+    };
 
-    // Check for untracked files
-    for entry in walkdir::WalkDir::new(&repo.work_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        let path = entry.path();
-        if path.starts_with(&repo.git_dir) {
-            continue;
+    let remote_refs = utils::served_refs_dir(&remote_git_dir);
+    let updates = mirror::plan(&repo.git_dir.join("refs"), &remote_refs)?;
+    if updates.is_empty() {
+        println!("Everything up-to-date");
+        return Ok(());
+    }
+    for update in &updates {
+        access::authorize(&remote_git_dir, Operation::Push, &update.name)?;
+        if let Some(new) = &update.new {
+            run_pre_push(repo, remote_name, remote_url, &update.name, new, no_verify)?;
         }
+    }
+
+    let head_ref = utils::head_ref(&remote_repo)?;
+    let remote_had_changes = worktree::has_local_changes(
+        &remote_repo,
+        &ObjectStore::new(&remote_repo),
+        &utils::load_index(&remote_repo)?,
+        true,
+    )?;
+    let tips: Vec<String> = updates.iter().filter_map(|u| u.new.clone()).collect();
+    let local_objects = repo.git_dir.join("objects");
+    let copied_count = receive_objects(
+        &remote_repo,
+        &tips,
+        |quarantine| copy_missing_objects(&local_objects, quarantine),
+        || {
+            for update in &updates {
+                if let Some(new) = &update.new {
+                    run_pre_receive(&remote_repo, &update.name, update.old.as_deref(), new, None)?;
+                }
+            }
+            Ok(())
+        },
+    )?;
+    mirror::apply(&remote_refs, &updates)?;
 
-        let relative_path = path
-            .strip_prefix(&repo.work_dir)?
-            .to_string_lossy()
-            .replace('\\', "/");
+    for update in &updates {
+        println!("{}", update.summary());
+    }
+    println!("Copied {} objects to remote repository", copied_count);
 
-        if !index.entries.contains_key(&relative_path) {
-            return Ok(true); // Untracked file
+    // Keep the remote's checked-out files in step with its branch, as a
+    // plain push does
+    let head_update = updates
+        .iter()
+        .find(|u| u.name == head_ref)
+        .and_then(|u| u.new.as_deref());
+    if let Some(commit) = head_update
+        && utils::ref_namespace().is_none()
+    {
+        if remote_had_changes {
+            println!(
+                "Warning: Remote repository has uncommitted changes; working directory not updated."
+            );
+        } else {
+            restore_working_directory(&remote_repo, commit)?;
+            println!("Remote working directory updated");
         }
     }
-
-    Ok(false)
+    Ok(())
 }
 
-fn update_remote_working_directory(repo: &Repository, commit_hash: &str) -> Result<()> {
-    let object_store = crate::object_store::ObjectStore::new(repo);
-    let commit = object_store.load_commit(commit_hash)?;
-    let tree = object_store.load_tree(&commit.tree)?;
+fn push_over_connection(
+    repo: &Repository,
+    remote_url: &str,
+    remote_name: &str,
+    branch_name: &str,
+    commit_hash: &str,
+    signed: bool,
+    force: bool,
+) -> Result<()> {
+    let refname = format!("refs/heads/{}", branch_name);
+    let object_store = ObjectStore::new(repo);
+    let mut connection = Connection::open(remote_url, &object_store)?;
+    let old = connection.list_refs()?.get(&refname).cloned();
+
+    let cert = if signed {
+        Some(push_cert::create(
+            repo,
+            remote_url,
+            &refname,
+            old.as_deref(),
+            commit_hash,
+        )?)
+    } else {
+        None
+    };
+    let haves: Vec<String> = old.iter().cloned().collect();
+    let objects = protocol::objects_to_send(&object_store, &[commit_hash.to_string()], &haves)?;
+    let push = Request::Push {
+        refname,
+        old,
+        new: commit_hash.to_string(),
+        cert,
+        force,
+    };
+    let message = connection.push(&object_store, &push, &objects)?;
+    println!("Remote: {}", message);
 
-    // Clear existing files (except .mini_git)
-    for entry in fs::read_dir(&repo.work_dir)? {
-        let entry = entry?;
-        let path = entry.path();
+    utils::update_remote_ref(
+        repo,
+        remote_name,
+        branch_name,
+        Some(commit_hash),
+        "update by push",
+    )?;
 
-        if path.file_name().unwrap() == ".mini_git" {
-            continue;
-        }
+    println!(
+        "Successfully pushed {} to {}/{}",
+        &commit_hash[..8],
+        remote_name,
+        branch_name
+    );
+    println!("Sent {} objects to remote repository", objects.len());
+    Ok(())
+}
 
-        if path.is_file() {
-            let _ = fs::remove_file(&path); // Ignore errors for now
-        } else if path.is_dir() {
-            let _ = fs::remove_dir_all(&path); // Ignore errors for now
+// Push a tag (lightweight or annotated) as refs/tags/<name>. A tag the
+// remote already has is never moved.
+fn push_tag(
+    repo: &Repository,
+    remote_name: &str,
+    remote_url: &str,
+    name: &str,
+    id: &str,
+    no_verify: bool,
+    signed: bool,
+) -> Result<()> {
+    let refname = format!("refs/tags/{}", name);
+    run_pre_push(repo, remote_name, remote_url, &refname, id, no_verify)?;
+    let object_store = ObjectStore::new(repo);
+
+    let (sent, transferred) = if PathBuf::from(remote_url).exists() {
+        let remote_git_dir = PathBuf::from(remote_url).join(".mini_git");
+        if !remote_git_dir.exists() {
+            return Err("Remote is not a mini-git repository".into());
         }
-    }
-
-    // Restore files from tree
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
+        hash::ensure_same_format(&repo.git_dir, &remote_git_dir, "Remote")?;
+        access::authorize(&remote_git_dir, Operation::Push, &refname)?;
+        let remote_repo = Repository {
+            git_dir: remote_git_dir.clone(),
+            work_dir: PathBuf::from(remote_url),
+        };
+
+        let tag_path = utils::served_refs_dir(&remote_git_dir)
+            .join("tags")
+            .join(name);
+        let old = fs::read_to_string(&tag_path)
+            .ok()
+            .map(|c| c.trim().to_string());
+        if check_remote_tag(name, old.as_deref(), id)? {
+            return Ok(());
+        }
+        let cert = if signed {
+            Some(push_cert::create(repo, remote_url, &refname, None, id)?)
+        } else {
+            None
+        };
+
+        let local_objects = repo.git_dir.join("objects");
+        let copied = receive_objects(
+            &remote_repo,
+            &[id.to_string()],
+            |quarantine| copy_missing_objects(&local_objects, quarantine),
+            || run_pre_receive(&remote_repo, &refname, None, id, cert.as_deref()),
+        )?;
+        fs::create_dir_all(tag_path.parent().unwrap())?;
+        fs::write(tag_path, id)?;
+        (copied, "Copied")
+    } else if protocol::is_remote_url(remote_url) {
+        let mut connection = Connection::open(remote_url, &object_store)?;
+        let remote_refs = connection.list_refs()?;
+        if check_remote_tag(name, remote_refs.get(&refname).map(String::as_str), id)? {
+            return Ok(());
+        }
+        let cert = if signed {
+            Some(push_cert::create(repo, remote_url, &refname, None, id)?)
+        } else {
+            None
+        };
+
+        // Whatever the remote's branches already hold needn't be sent
+        let haves: Vec<String> = remote_refs
+            .into_values()
+            .filter(|hash| object_store.object_exists(hash))
+            .collect();
+        let objects = protocol::objects_to_send(&object_store, &[id.to_string()], &haves)?;
+        let push = Request::Push {
+            refname: refname.clone(),
+            old: None,
+            new: id.to_string(),
+            cert,
+            force: false,
+        };
+        let message = connection.push(&object_store, &push, &objects)?;
+        println!("Remote: {}", message);
+        (objects.len(), "Sent")
+    } else {
+        return Err(format!("Cannot push tags to '{}'", remote_url).into());
+    };
 
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
+    println!(
+        "Successfully pushed tag {} ({}) to {}",
+        name,
+        &id[..8],
+        remote_name
+    );
+    println!("{} {} objects to remote repository", transferred, sent);
+    Ok(())
+}
 
-            fs::write(file_path, &blob.content)?;
+// Whether the remote already has the tag as it is here; a different tag of
+// the same name is an error
+fn check_remote_tag(name: &str, remote: Option<&str>, id: &str) -> Result<bool> {
+    match remote {
+        Some(remote) if remote == id => {
+            println!("Everything up-to-date");
+            Ok(true)
         }
+        Some(_) => Err(format!("tag '{}' already exists in the remote", name).into()),
+        None => Ok(false),
     }
+}
 
-    // Update index to match
-    let mut new_index = crate::Index {
-        entries: std::collections::HashMap::new(),
+// The pre-push hook sees what the remote-tracking branch last recorded as
+// the remote's side (nothing, for tags). A local remote's own
+// `hooks.enforce` applies as well.
+fn run_pre_push(
+    repo: &Repository,
+    remote_name: &str,
+    remote_url: &str,
+    refname: &str,
+    local_commit: &str,
+    no_verify: bool,
+) -> Result<()> {
+    let null_id = hash::HashAlgorithm::of_repo(repo)
+        .unwrap_or_default()
+        .null_id();
+    let (branch_name, tracking) = match refname.strip_prefix("refs/heads/") {
+        Some(branch) => (
+            branch,
+            utils::resolve_revision(repo, &format!("{}/{}", remote_name, branch))
+                .unwrap_or(null_id),
+        ),
+        None => (
+            refname.strip_prefix("refs/tags/").unwrap_or(refname),
+            null_id,
+        ),
     };
 
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            new_index.entries.insert(
-                path.clone(),
-                crate::IndexEntry {
-                    hash: tree_entry.hash.clone(),
-                    mode: tree_entry.mode.clone(),
-                    path: path.clone(),
-                },
-            );
-        }
-    }
+    let remote_git_dir = PathBuf::from(remote_url).join(".mini_git");
+    let remote_repo = Repository {
+        git_dir: remote_git_dir.clone(),
+        work_dir: PathBuf::from(remote_url),
+    };
 
-    utils::save_index(repo, &new_index)?;
-    Ok(())
+    let mut context = HookContext::new(Hook::PrePush, branch_name);
+    context.args = vec![remote_name.to_string(), remote_url.to_string()];
+    context.stdin = Some(format!(
+        "{} {} {} {}\n",
+        refname, local_commit, refname, tracking
+    ));
+    if remote_git_dir.is_dir() {
+        context.enforced_by.push(&remote_repo);
+    }
+    hooks::run(repo, &context, no_verify)
 }
 
-fn copy_missing_objects(src_objects: &PathBuf, dst_objects: &PathBuf) -> Result<usize> {
-    if !src_objects.exists() {
-        return Ok(0);
+// Run the receiving repository's pre-receive hook for an update that is
+// about to happen. A push certificate is checked, stored as a blob and
+// described to the hook in MINI_GIT_PUSH_CERT* variables (see push_cert).
+pub(crate) fn run_pre_receive(
+    remote_repo: &Repository,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    cert: Option<&str>,
+) -> Result<()> {
+    let null_id = hash::HashAlgorithm::of_repo(remote_repo)?.null_id();
+    let branch = refname.strip_prefix("refs/heads/").unwrap_or(refname);
+    let mut context = HookContext::new(Hook::PreReceive, branch);
+    context.stdin = Some(format!("{} {} {}\n", old.unwrap_or(&null_id), new, refname));
+
+    if let Some(cert) = cert {
+        if !push_cert::covers(remote_repo, cert, refname, old, new) {
+            return Err(
+                format!("Push certificate does not match the update of {}", refname).into(),
+            );
+        }
+        let verification = push_cert::verify(remote_repo, cert)?;
+        let blob = ObjectStore::new(remote_repo).store_blob(cert.as_bytes())?;
+        context.env.push(("MINI_GIT_PUSH_CERT".to_string(), blob));
+        context.env.push((
+            "MINI_GIT_PUSH_CERT_STATUS".to_string(),
+            verification.status.letter().to_string(),
+        ));
+        if let Some(signer) = verification.signer {
+            context
+                .env
+                .push(("MINI_GIT_PUSH_CERT_SIGNER".to_string(), signer));
+        }
     }
 
-    fs::create_dir_all(dst_objects)?;
-    let mut copied_count = 0;
+    hooks::run(remote_repo, &context, false)
+}
 
-    for entry in fs::read_dir(src_objects)? {
-        let entry = entry?;
-        let src_dir = entry.path();
+// Objects land in a quarantine directory first and only move into the remote's
+// object store once the push has been accepted (by the fsck checks, then
+// `accept`), so a rejected push leaves no unreachable objects behind
+// Refusal of a push that would drop commits from `refname`
+pub(crate) fn non_fast_forward(refname: &str) -> Box<dyn std::error::Error> {
+    format!(
+        "Rejected {}: not a fast-forward; fetch and merge first, or push with --force",
+        refname
+    )
+    .into()
+}
 
-        if src_dir.is_dir() {
-            let dir_name = entry.file_name();
-            let dst_dir = dst_objects.join(&dir_name);
-            fs::create_dir_all(&dst_dir)?;
+pub(crate) fn receive_objects(
+    remote_repo: &Repository,
+    tips: &[String],
+    fill: impl FnOnce(&Path) -> Result<usize>,
+    accept: impl FnOnce() -> Result<()>,
+) -> Result<usize> {
+    let remote_objects = remote_repo.git_dir.join("objects");
+    let quarantine = remote_repo
+        .git_dir
+        .join(format!("quarantine-{}", std::process::id()));
 
-            for obj_entry in fs::read_dir(&src_dir)? {
-                let obj_entry = obj_entry?;
-                let src_obj = obj_entry.path();
-                let dst_obj = dst_dir.join(obj_entry.file_name());
+    // Let the quarantine see the remote's existing objects through alternates
+    for alternate in std::iter::once(remote_objects.clone()).chain(read_alternates(&remote_objects))
+    {
+        add_alternate(&quarantine, &fs::canonicalize(alternate)?)?;
+    }
 
-                if !dst_obj.exists() {
-                    fs::copy(&src_obj, &dst_obj)?;
-                    copied_count += 1;
-                }
-            }
+    let result = fill(&quarantine).and_then(|count| {
+        // Refuse to point a ref at anything corrupt or incomplete
+        if fsck::transfer_checks_enabled(remote_repo, Side::Receive)? {
+            fsck::check_connectivity(&ObjectStore::at(quarantine.clone()), tips)
+                .map_err(|e| format!("Remote rejected push: {}", e))?;
         }
-    }
+        accept()?;
+        migrate_objects(&quarantine, &remote_objects)?;
+        Ok(count)
+    });
 
-    Ok(copied_count)
+    fs::remove_dir_all(&quarantine)?;
+    result
 }
 
-fn get_remote_url(repo: &Repository, remote_name: &str) -> Result<String> {
-    let config_path = repo.git_dir.join("config");
-    let config_content = fs::read_to_string(config_path)?;
-
-    let lines: Vec<&str> = config_content.lines().collect();
-    let mut in_remote_section = false;
-    let remote_header = format!("[remote \"{}\"]", remote_name);
-
-    for line in lines {
-        let line = line.trim();
-        if line == remote_header {
-            in_remote_section = true;
+fn migrate_objects(quarantine: &Path, objects_dir: &Path) -> Result<()> {
+    for entry in fs::read_dir(quarantine)? {
+        let entry = entry?;
+        if !entry.path().is_dir() || entry.file_name() == "info" {
             continue;
         }
 
-        if in_remote_section {
-            if line.starts_with('[') && line.ends_with(']') {
-                break;
-            }
-
-            if line.starts_with("url = ") {
-                return Ok(line.replace("url = ", ""));
+        let dst_dir = objects_dir.join(entry.file_name());
+        fs::create_dir_all(&dst_dir)?;
+        let mut obj_entries = fs::read_dir(entry.path())?.collect::<std::io::Result<Vec<_>>>()?;
+        // A pack has to be in place before its index makes it visible
+        obj_entries.sort_by_key(|e| e.path().extension().is_some_and(|ext| ext == "idx"));
+        for obj_entry in obj_entries {
+            let dst_obj = dst_dir.join(obj_entry.file_name());
+            if !dst_obj.exists() {
+                fs::rename(obj_entry.path(), dst_obj)?;
             }
         }
     }
-
-    Err(format!("Remote '{}' not found", remote_name).into())
+    Ok(())
 }
@@ -0,0 +1,161 @@
+use crate::{
+    Index, IndexEntry, Repository, Result, Tree, TreeEntry, object_store::ObjectStore, utils,
+};
+use std::collections::{BTreeSet, HashMap};
+
+pub fn read_tree(repo: &Repository, trees: Vec<String>, merge: bool) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let mut current = utils::lock_index(repo)?;
+
+    let trees = trees
+        .iter()
+        .map(|name| resolve_tree_ish(repo, &object_store, name))
+        .collect::<Result<Vec<Tree>>>()?;
+
+    let entries = match (merge, trees.as_slice()) {
+        (_, []) => return Err("read-tree requires at least one tree".into()),
+        (_, [tree]) => tree.entries.clone(),
+        (false, _) => return Err("Merging several trees requires -m".into()),
+        (true, [old, new]) => two_way_merge(&current, old, new)?,
+        (true, [base, ours, theirs]) => three_way_merge(base, ours, theirs)?,
+        (true, _) => return Err("read-tree -m accepts at most three trees".into()),
+    };
+
+    // Carry stat data over for entries whose content is unchanged so that the
+    // next status doesn't have to re-hash them
+    let mut index = Index {
+        untracked_cache: current.untracked_cache.take(),
+        ..Default::default()
+    };
+    for (path, tree_entry) in entries {
+        let entry = match current.entries.get(&path) {
+            Some(existing) if merge && existing.hash == tree_entry.hash => existing.clone(),
+            _ => IndexEntry {
+                hash: tree_entry.hash,
+                mode: tree_entry.mode,
+                path: path.clone(),
+                ..Default::default()
+            },
+        };
+        index.entries.insert(path, entry);
+    }
+
+    *current = index;
+    utils::save_locked_index(repo, current)
+}
+
+// Accept a tree hash or anything that resolves to a commit
+pub(crate) fn resolve_tree_ish(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    name: &str,
+) -> Result<Tree> {
+    let hash = utils::resolve_revision(repo, name)
+        .map_err(|_| format!("Not a valid object name {}", name))?;
+
+    if let Ok(commit) = object_store.load_commit(&hash) {
+        return object_store.load_tree(&commit.tree);
+    }
+    object_store
+        .load_tree(&hash)
+        .map_err(|_| format!("{} is not a tree-ish", name).into())
+}
+
+// Move the index from `old` to `new`, keeping staged changes to paths that
+// are the same in both trees
+fn two_way_merge(current: &Index, old: &Tree, new: &Tree) -> Result<HashMap<String, TreeEntry>> {
+    let mut result = HashMap::new();
+    let mut conflicts = Vec::new();
+
+    let paths: BTreeSet<&String> = current
+        .entries
+        .keys()
+        .chain(old.entries.keys())
+        .chain(new.entries.keys())
+        .collect();
+
+    for path in paths {
+        let staged = current.entries.get(path).map(|entry| entry.hash.as_str());
+        let old_hash = old.entries.get(path).map(|entry| entry.hash.as_str());
+        let new_entry = new.entries.get(path);
+        let new_hash = new_entry.map(|entry| entry.hash.as_str());
+
+        if staged == old_hash || staged == new_hash {
+            // Index is clean for this path (or already matches the target)
+            if let Some(entry) = new_entry {
+                result.insert(path.clone(), entry.clone());
+            }
+        } else if old_hash == new_hash {
+            // Path doesn't change between the trees; keep what's staged
+            if let Some(entry) = current.entries.get(path) {
+                result.insert(path.clone(), index_tree_entry(entry));
+            }
+        } else {
+            conflicts.push(path.clone());
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(format!(
+            "Entry '{}' would be overwritten by merge. Cannot merge.",
+            conflicts.join("', '")
+        )
+        .into());
+    }
+
+    Ok(result)
+}
+
+// Trivial index-level merge: a path resolves when only one side changed it
+// or both sides agree. Anything else needs a file-level merge.
+fn three_way_merge(base: &Tree, ours: &Tree, theirs: &Tree) -> Result<HashMap<String, TreeEntry>> {
+    let mut result = HashMap::new();
+    let mut conflicts = Vec::new();
+
+    let paths: BTreeSet<&String> = base
+        .entries
+        .keys()
+        .chain(ours.entries.keys())
+        .chain(theirs.entries.keys())
+        .collect();
+
+    for path in paths {
+        let base_entry = base.entries.get(path);
+        let our_entry = ours.entries.get(path);
+        let their_entry = theirs.entries.get(path);
+        let hash = |entry: Option<&TreeEntry>| entry.map(|entry| entry.hash.clone());
+
+        let resolved =
+            if hash(our_entry) == hash(their_entry) || hash(base_entry) == hash(their_entry) {
+                our_entry
+            } else if hash(base_entry) == hash(our_entry) {
+                their_entry
+            } else {
+                conflicts.push(path.clone());
+                continue;
+            };
+
+        if let Some(entry) = resolved {
+            result.insert(path.clone(), entry.clone());
+        }
+    }
+
+    if !conflicts.is_empty() {
+        return Err(format!(
+            "Merge requires file-level merging for: {}",
+            conflicts.join(", ")
+        )
+        .into());
+    }
+
+    Ok(result)
+}
+
+fn index_tree_entry(entry: &IndexEntry) -> TreeEntry {
+    TreeEntry {
+        mode: entry.mode.clone(),
+        hash: entry.hash.clone(),
+        name: entry.path.clone(),
+        is_file: !entry.is_dir(),
+    }
+}
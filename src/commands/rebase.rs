@@ -0,0 +1,537 @@
+use crate::{
+    Commit, Repository, Result, Tree,
+    commands::checkout::restore_working_directory,
+    commands::commit::create_commit,
+    commands::merge::{self, merge_clean_contents, merge_trees, write_conflicts},
+    graph::{self, CommitGraph},
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    sequencer::{self, Action, Step},
+    utils,
+    worktree::{self, load_commit_tree},
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+
+// A rebase in progress keeps its state in .mini_git/rebase-merge/:
+//
+//   head-name    the branch being rebased, e.g. refs/heads/topic
+//   orig-head    its tip before the rebase
+//   onto         the new base
+//   current      the last commit replayed so far (onto before the first)
+//   todo         the steps still to run, as `<action> <id> <subject>` lines
+//                (see sequencer)
+//   stopped-sha  the commit a conflict stopped at, if any, and
+//   stopped-action  what was being done with it
+//   squash-pending  present while a run of squashes awaits its combined
+//                message
+//
+// The branch only moves when every commit has been replayed. While stopped,
+// HEAD is detached at `current` and the working tree holds the conflicted
+// merge of the stopped commit.
+
+const STATE_DIR: &str = "rebase-merge";
+
+// What to do with a rebase that stopped on a conflict
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RebaseControl {
+    // Commit the resolved conflict and replay the remaining commits
+    Continue,
+    // Drop the stopped commit and replay the remaining commits
+    Skip,
+    // Put the branch, HEAD and working tree back as they were
+    Abort,
+}
+
+pub struct RebaseOptions {
+    // Replay onto this base instead of upstream
+    pub onto: Option<String>,
+    pub allow_protected: bool,
+    // Let the user edit the todo list before it runs
+    pub interactive: bool,
+    // Fold `fixup!`/`squash!` commits into the commits they name
+    pub autosquash: bool,
+}
+
+pub fn rebase(
+    repo: &Repository,
+    upstream: Option<String>,
+    branch: Option<String>,
+    options: RebaseOptions,
+    control: Option<RebaseControl>,
+) -> Result<()> {
+    let state_dir = repo.git_dir.join(STATE_DIR);
+    if let Some(control) = control {
+        if !state_dir.exists() {
+            return Err("No rebase in progress".into());
+        }
+        let object_store = ObjectStore::new(repo);
+        return match control {
+            RebaseControl::Continue => continue_rebase(repo, &object_store),
+            RebaseControl::Skip => skip_commit(repo, &object_store),
+            RebaseControl::Abort => abort_rebase(repo, &object_store),
+        };
+    }
+    if state_dir.exists() {
+        return Err(
+            "A rebase is in progress; use --continue, --skip or --abort to finish it".into(),
+        );
+    }
+    let upstream = upstream.ok_or("Name the upstream to rebase onto")?;
+
+    let branch_name = match branch {
+        Some(name) => name,
+        None => utils::head_branch(repo)?.ok_or("Cannot rebase in detached HEAD state")?,
+    };
+
+    utils::check_branch_protection(
+        repo,
+        &branch_name,
+        "rewrite its history",
+        options.allow_protected,
+    )?;
+
+    let branch_tip = utils::get_branch_commit(repo, &branch_name)?
+        .ok_or(format!("Branch '{}' has no commits", branch_name))?;
+
+    let object_store = ObjectStore::new(repo);
+    if worktree::has_local_changes(repo, &object_store, &utils::load_index(repo)?, false)? {
+        return Err(
+            "Cannot rebase: you have uncommitted changes. Commit or stash them first.".into(),
+        );
+    }
+
+    let upstream_hash = utils::resolve_revision(repo, &upstream)?;
+    let onto_hash = match &options.onto {
+        Some(onto) => utils::resolve_revision(repo, onto)?,
+        None => upstream_hash.clone(),
+    };
+
+    let commits = commits_to_replay(&object_store, &upstream_hash, &branch_tip)?;
+
+    // Nothing to do when the branch already sits directly on the new base,
+    // unless its commits are to be edited
+    let current_base = match commits.first() {
+        Some(first) => object_store.load_commit(first)?.parent().cloned(),
+        None => Some(branch_tip.clone()),
+    };
+    let up_to_date = current_base.as_deref() == Some(onto_hash.as_str())
+        || (commits.is_empty() && graph::is_ancestor(&object_store, &onto_hash, &branch_tip)?);
+    let edited = options.interactive || options.autosquash;
+    if up_to_date && (!edited || commits.is_empty()) {
+        println!("Current branch {} is up to date.", branch_name);
+        return Ok(());
+    }
+
+    let mut todo = Vec::new();
+    for hash in &commits {
+        todo.push(Step::pick(hash, &object_store.load_commit(hash)?.message));
+    }
+    if options.autosquash {
+        todo = sequencer::autosquash(todo);
+    }
+    let state = RebaseState {
+        dir: state_dir,
+        branch: branch_name,
+        orig_head: branch_tip,
+        onto: onto_hash,
+    };
+    fs::create_dir_all(&state.dir)?;
+    if options.interactive {
+        let edited = sequencer::edit_todo(repo, &state.dir.join("todo"), &todo, &state.onto);
+        match edited {
+            Ok(steps) if !steps.is_empty() => todo = steps,
+            Ok(_) => {
+                fs::remove_dir_all(&state.dir)?;
+                return Err("Nothing to do; the rebase was not started".into());
+            }
+            Err(e) => {
+                fs::remove_dir_all(&state.dir)?;
+                return Err(e);
+            }
+        }
+    }
+
+    println!(
+        "Rebasing {} commit(s) of '{}' onto {}",
+        todo.len(),
+        state.branch,
+        &state.onto[..8]
+    );
+    state.write("head-name", &format!("refs/heads/{}", state.branch))?;
+    state.write("orig-head", &state.orig_head)?;
+    state.write("onto", &state.onto)?;
+    state.write("current", &state.onto)?;
+    state.write_todo(&todo)?;
+    replay(repo, &object_store, &state)
+}
+
+struct RebaseState {
+    dir: PathBuf,
+    branch: String,
+    orig_head: String,
+    onto: String,
+}
+
+impl RebaseState {
+    fn load(repo: &Repository) -> Result<Self> {
+        let dir = repo.git_dir.join(STATE_DIR);
+        let read = |name: &str| -> Result<String> {
+            fs::read_to_string(dir.join(name))
+                .map(|value| value.trim().to_string())
+                .map_err(|e| format!("Rebase state is damaged ({}: {})", name, e).into())
+        };
+        let head_name = read("head-name")?;
+        Ok(RebaseState {
+            branch: head_name
+                .strip_prefix("refs/heads/")
+                .unwrap_or(&head_name)
+                .to_string(),
+            orig_head: read("orig-head")?,
+            onto: read("onto")?,
+            dir,
+        })
+    }
+
+    fn read(&self, name: &str) -> Result<Option<String>> {
+        match fs::read_to_string(self.dir.join(name)) {
+            Ok(value) => Ok(Some(value.trim().to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn write(&self, name: &str, value: &str) -> Result<()> {
+        Ok(fs::write(self.dir.join(name), format!("{}\n", value))?)
+    }
+
+    fn todo(&self, repo: &Repository) -> Result<Vec<Step>> {
+        match self.read("todo")? {
+            Some(todo) => sequencer::parse_todo(repo, &todo),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn write_todo(&self, todo: &[Step]) -> Result<()> {
+        Ok(fs::write(
+            self.dir.join("todo"),
+            sequencer::format_todo(todo),
+        )?)
+    }
+
+    fn current(&self) -> Result<String> {
+        Ok(self.read("current")?.unwrap_or_else(|| self.onto.clone()))
+    }
+}
+
+// Run the steps left in the todo list on top of `current`, stopping at the
+// first conflict
+fn replay(repo: &Repository, object_store: &ObjectStore, state: &RebaseState) -> Result<()> {
+    let mut new_head = state.current()?;
+    let mut todo = state.todo(repo)?;
+    while !todo.is_empty() {
+        let step = todo.remove(0);
+        let commit_hash = step.commit;
+        let commit = object_store.load_commit(&commit_hash)?;
+        let subject = commit.message.lines().next().unwrap_or("");
+        if step.action == Action::Drop {
+            println!("Dropped {}: {}", &commit_hash[..8], subject);
+            state.write_todo(&todo)?;
+            continue;
+        }
+
+        let base_tree = match commit.parent() {
+            Some(parent) => load_commit_tree(object_store, parent)?,
+            None => empty_tree(),
+        };
+        let our_tree = load_commit_tree(object_store, &new_head)?;
+        let their_tree = object_store.load_tree(&commit.tree)?;
+
+        let result = merge_trees(object_store, &base_tree, &our_tree, &their_tree)?;
+        let result = merge_clean_contents(
+            object_store,
+            result,
+            &base_tree,
+            &our_tree,
+            &their_tree,
+            None,
+        )?;
+        if !result.conflicts.is_empty() {
+            // Stop with HEAD detached at the commits replayed so far and the
+            // conflicted merge in the working tree
+            fs::write(repo.git_dir.join("HEAD"), &new_head)?;
+            worktree::switch_to_tree(repo, object_store, &result.tree)?;
+            let conflicts =
+                merge::conflict_stages(&result.conflicts, &base_tree, &our_tree, &their_tree);
+            for (path, _) in &conflicts {
+                println!("CONFLICT: Merge conflict in {}", path);
+            }
+            let base_label = format!("parent of {}", &commit_hash[..8]);
+            let their_label = format!("{} ({})", &commit_hash[..8], subject);
+            write_conflicts(repo, object_store, conflicts, &base_label, &their_label)?;
+            state.write("stopped-sha", &commit_hash)?;
+            state.write("stopped-action", step.action.name())?;
+            state.write_todo(&todo)?;
+            println!("Could not apply {}... {}", &commit_hash[..8], subject);
+            println!("Resolve the conflicts, `add` them and run `rebase --continue`");
+            println!(
+                "(or `rebase --skip` to drop this commit, `rebase --abort` to go back to where you started)"
+            );
+            return Ok(());
+        }
+
+        new_head = finish_step(
+            repo,
+            object_store,
+            state,
+            step.action,
+            &commit,
+            &result.tree,
+            &new_head,
+        )?;
+        if !todo.first().is_some_and(|step| step.action.folds()) {
+            new_head = finish_squashes(repo, object_store, state, &new_head)?;
+        }
+        state.write("current", &new_head)?;
+        state.write_todo(&todo)?;
+    }
+
+    utils::update_branch(repo, &state.branch, &new_head)?;
+    journal::record(
+        repo,
+        "rebase",
+        &format!("rebase {} onto {}", state.branch, &state.onto[..8]),
+        vec![RefUpdate::branch(
+            &state.branch,
+            Some(state.orig_head.clone()),
+            Some(new_head.clone()),
+        )],
+    )?;
+    utils::update_head(repo, &state.branch)?;
+    restore_working_directory(repo, &new_head)?;
+    fs::remove_dir_all(&state.dir)?;
+
+    println!(
+        "Successfully rebased and updated refs/heads/{}.",
+        state.branch
+    );
+    Ok(())
+}
+
+// Commit the resolution of the stopped commit, keeping its author, date and
+// message, then carry on. A resolution that leaves nothing to commit drops
+// the commit.
+fn continue_rebase(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let state = RebaseState::load(repo)?;
+    let index = utils::load_index(repo)?;
+    if !index.unmerged.is_empty() {
+        let mut paths: Vec<&str> = index.unmerged.keys().map(String::as_str).collect();
+        paths.sort();
+        return Err(format!(
+            "Unmerged paths ({}); resolve them and `add` them first",
+            paths.join(", ")
+        )
+        .into());
+    }
+
+    if let Some(stopped) = state.read("stopped-sha")? {
+        // HEAD, detached at the stop, has moved if the user committed
+        let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+        let commit = object_store.load_commit(&stopped)?;
+        let tree = worktree::tree_from_index(object_store, &index)?;
+        let action = match state.read("stopped-action")? {
+            Some(name) => sequencer::parse_todo(repo, &format!("{} {}", name, stopped))?[0].action,
+            None => Action::Pick,
+        };
+        let mut new_head = finish_step(repo, object_store, &state, action, &commit, &tree, &head)?;
+        if !state
+            .todo(repo)?
+            .first()
+            .is_some_and(|step| step.action.folds())
+        {
+            new_head = finish_squashes(repo, object_store, &state, &new_head)?;
+        }
+        state.write("current", &new_head)?;
+        clear_stop(&state)?;
+    }
+    replay(repo, object_store, &state)
+}
+
+// Throw away the stopped commit's changes and carry on
+fn skip_commit(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let state = RebaseState::load(repo)?;
+    let Some(stopped) = state.read("stopped-sha")? else {
+        return Err("The rebase is not stopped at a commit; use --continue".into());
+    };
+    let head = utils::get_head_commit(repo)?.ok_or("HEAD has no commits")?;
+    worktree::reset_to_tree(repo, object_store, &load_commit_tree(object_store, &head)?)?;
+    state.write("current", &head)?;
+    clear_stop(&state)?;
+    println!("Skipped {}", &stopped[..8]);
+    replay(repo, object_store, &state)
+}
+
+fn abort_rebase(repo: &Repository, object_store: &ObjectStore) -> Result<()> {
+    let state = RebaseState::load(repo)?;
+    // HEAD is detached at the stop; record it going back to the branch
+    let head_ref = utils::head_ref(repo)?;
+    let current = utils::get_head_commit(repo)?;
+    let tree = load_commit_tree(object_store, &state.orig_head)?;
+    worktree::reset_to_tree(repo, object_store, &tree)?;
+    utils::update_head(repo, &state.branch)?;
+    journal::record(
+        repo,
+        "rebase",
+        "abort",
+        vec![RefUpdate {
+            name: head_ref,
+            old: current,
+            new: Some(state.orig_head.clone()),
+        }],
+    )?;
+    merge::clear_merge_state(repo)?;
+    fs::remove_dir_all(&state.dir)?;
+    println!(
+        "Rebase aborted; {} is back at {}",
+        state.branch,
+        &state.orig_head[..8]
+    );
+    Ok(())
+}
+
+// A rebase stopped part way, with the branch being rebased
+pub(crate) fn rebase_in_progress(repo: &Repository) -> Result<Option<String>> {
+    if !repo.git_dir.join(STATE_DIR).exists() {
+        return Ok(None);
+    }
+    Ok(Some(RebaseState::load(repo)?.branch))
+}
+
+// Commits reachable from `tip` but not from `upstream`, oldest first. As in
+// git, merge commits are dropped and the history they joined is flattened.
+fn commits_to_replay(object_store: &ObjectStore, upstream: &str, tip: &str) -> Result<Vec<String>> {
+    let mut graph = CommitGraph::new(object_store);
+    let upstream = graph.node(upstream)?;
+    let excluded = graph.reachable(upstream)?;
+    let tip = graph.node(tip)?;
+
+    let mut commits = Vec::new();
+    for node in graph.walk(tip, &excluded)? {
+        if graph.parents(node)?.len() <= 1 {
+            commits.push(graph.hex(node));
+        }
+    }
+
+    commits.reverse();
+    Ok(commits)
+}
+
+// Commit `tree`, the merge of `commit` onto `head`, as `action` says: a new
+// commit on `head` for a pick or reword, a replacement for `head` for a
+// squash or fixup. Returns the new head.
+fn finish_step(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    state: &RebaseState,
+    action: Action,
+    commit: &Commit,
+    tree: &Tree,
+    head: &str,
+) -> Result<String> {
+    let subject = commit.message.lines().next().unwrap_or("");
+    let short = &commit.hash[..8];
+
+    if !action.folds() {
+        if tree.hash == load_commit_tree(object_store, head)?.hash {
+            println!("Skipping {} (nothing to apply): {}", short, subject);
+            return Ok(head.to_string());
+        }
+        let message = if action == Action::Reword {
+            let note = format!("Rewording {}", short);
+            sequencer::edit_message(repo, &state.dir.join("message"), &commit.message, &note)?
+        } else {
+            commit.message.clone()
+        };
+        let new_head = create_commit(
+            object_store,
+            &tree.hash,
+            vec![head.to_string()],
+            commit.author.clone(),
+            message,
+            commit.timestamp,
+        )?;
+        println!("Applied {} -> {}: {}", short, &new_head[..8], subject);
+        return Ok(new_head);
+    }
+
+    // Folding replaces `head` with a commit of the combined changes, keeping
+    // its author and date
+    let target = object_store.load_commit(head)?;
+    let mut message = target.message.clone();
+    if action == Action::Squash {
+        message = format!("{}\n\n{}", message.trim_end(), commit.message.trim_end());
+        fs::write(state.dir.join("squash-pending"), "")?;
+    }
+    let new_head = create_commit(
+        object_store,
+        &tree.hash,
+        target.parents.clone(),
+        target.author.clone(),
+        message,
+        target.timestamp,
+    )?;
+    println!(
+        "{} {} into {}: {}",
+        if action == Action::Squash {
+            "Squashed"
+        } else {
+            "Fixed up"
+        },
+        short,
+        &new_head[..8],
+        subject
+    );
+    Ok(new_head)
+}
+
+// At the end of a run of squashes, let the user edit the combined message
+// of `head`, the commit they were folded into
+fn finish_squashes(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    state: &RebaseState,
+    head: &str,
+) -> Result<String> {
+    let pending = state.dir.join("squash-pending");
+    if !pending.exists() {
+        return Ok(head.to_string());
+    }
+    let commit = object_store.load_commit(head)?;
+    let note = "This is a combination of several commits";
+    let message = sequencer::edit_message(repo, &state.dir.join("message"), &commit.message, note)?;
+    fs::remove_file(&pending)?;
+    create_commit(
+        object_store,
+        &commit.tree,
+        commit.parents,
+        commit.author,
+        message,
+        commit.timestamp,
+    )
+}
+
+fn clear_stop(state: &RebaseState) -> Result<()> {
+    fs::remove_file(state.dir.join("stopped-sha"))?;
+    match fs::remove_file(state.dir.join("stopped-action")) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+        _ => Ok(()),
+    }
+}
+
+fn empty_tree() -> Tree {
+    Tree {
+        hash: String::new(),
+        entries: HashMap::new(),
+    }
+}
@@ -0,0 +1,23 @@
+use crate::{Repository, Result, reflog};
+
+// Newest first, as `<id> <ref>@{n}: <message>`
+pub fn reflog(repo: &Repository, name: Option<String>) -> Result<()> {
+    let name = name.unwrap_or_else(|| "HEAD".to_string());
+    let refname = reflog::resolve_refname(repo, &name)?;
+    let entries = reflog::read(repo, &refname)?;
+    if entries.is_empty() {
+        println!("No reflog entries for {}", name);
+        return Ok(());
+    }
+
+    for (n, entry) in entries.iter().rev().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            &entry.new[..entry.new.len().min(8)],
+            name,
+            n,
+            entry.message
+        );
+    }
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use crate::{Repository, Result};
+use crate::{Repository, Result, protocol, transport::Transport, utils};
 use std::fs;
 use std::path::PathBuf;
 
@@ -25,7 +25,7 @@ pub fn remote(
         }
         Some("get-url") => {
             let name = name.ok_or("Remote name required")?;
-            get_remote_url(repo, name)?;
+            println!("{}", utils::get_remote_url(repo, &name)?);
         }
         Some("-v") | Some("--verbose") | None => {
             list_remotes(repo, action.is_some())?;
@@ -39,8 +39,14 @@ pub fn remote(
 }
 
 fn add_remote(repo: &Repository, name: String, url: String) -> Result<()> {
+    utils::validate_ref_name("remote", &name)?;
+
     // Validate URL for local-only approach
-    if !is_local_path(&url) {
+    if protocol::is_remote_url(&url) {
+        println!("Adding remote '{}' -> {}", name, url);
+    } else if let Transport::Bundle(_) = Transport::for_url(&url) {
+        println!("Adding bundle remote '{}' -> {}", name, url);
+    } else if !is_local_path(&url) {
         println!("Note: Mini Git only supports local repository remotes.");
         println!("Adding remote '{}' -> {} (for reference only)", name, url);
         println!("Push/pull operations will only work with local file paths.");
@@ -128,6 +134,15 @@ fn remove_remote(repo: &Repository, name: String) -> Result<()> {
     if remote_refs_dir.exists() {
         fs::remove_dir_all(remote_refs_dir)?;
     }
+    let remote_logs_dir = repo
+        .git_dir
+        .join("logs")
+        .join("refs")
+        .join("remotes")
+        .join(&name);
+    if remote_logs_dir.exists() {
+        fs::remove_dir_all(remote_logs_dir)?;
+    }
 
     println!("Removed remote '{}'", name);
     Ok(())
@@ -135,7 +150,11 @@ fn remove_remote(repo: &Repository, name: String) -> Result<()> {
 
 fn set_remote_url(repo: &Repository, name: String, new_url: String) -> Result<()> {
     // Validate URL for local-only approach
-    if !is_local_path(&new_url) {
+    if protocol::is_remote_url(&new_url) {
+        // Reached through a command; nothing to check until it runs
+    } else if let Transport::Bundle(_) = Transport::for_url(&new_url) {
+        // Read when fetched from
+    } else if !is_local_path(&new_url) {
         println!("Note: Mini Git only supports local repository remotes.");
         println!(
             "Setting remote '{}' URL to: {} (for reference only)",
@@ -200,38 +219,6 @@ fn set_remote_url(repo: &Repository, name: String, new_url: String) -> Result<()
     Ok(())
 }
 
-fn get_remote_url(repo: &Repository, name: String) -> Result<()> {
-    let config_path = repo.git_dir.join("config");
-    let config_content = fs::read_to_string(&config_path)?;
-
-    let lines: Vec<&str> = config_content.lines().collect();
-    let mut in_remote_section = false;
-    let remote_header = format!("[remote \"{}\"]", name);
-
-    for line in lines {
-        let line_trimmed = line.trim();
-
-        if line_trimmed == remote_header {
-            in_remote_section = true;
-            continue;
-        }
-
-        if in_remote_section {
-            if line_trimmed.starts_with('[') && line_trimmed.ends_with(']') {
-                break;
-            }
-
-            if line_trimmed.starts_with("url = ") {
-                let url = line_trimmed.replace("url = ", "");
-                println!("{}", url);
-                return Ok(());
-            }
-        }
-    }
-
-    Err(format!("Remote '{}' not found", name).into())
-}
-
 fn list_remotes(repo: &Repository, verbose: bool) -> Result<()> {
     let config_path = repo.git_dir.join("config");
     if !config_path.exists() {
@@ -268,7 +255,11 @@ fn list_remotes(repo: &Repository, verbose: bool) -> Result<()> {
 
     for (name, url) in remotes {
         if verbose {
-            let status = if is_local_path(&url) {
+            let status = if protocol::is_remote_url(&url) {
+                "(command)"
+            } else if let Transport::Bundle(_) = Transport::for_url(&url) {
+                "(bundle)"
+            } else if is_local_path(&url) {
                 if PathBuf::from(&url).exists() {
                     "(local, available)"
                 } else {
@@ -0,0 +1,75 @@
+use crate::{Repository, Result, bitmap, object_store::ObjectStore, packfile::DeltaWindow, utils};
+use std::fs;
+
+// Move every loose object into a new pack. With `all`, the objects of
+// existing packs go into it too and the old packs are removed, leaving one,
+// with reachability bitmaps for the history it holds.
+// With `deltas`, objects are stored as deltas against similar ones where
+// that's smaller, even if a single pack already holds them all.
+pub fn repack(repo: &Repository, all: bool, deltas: Option<DeltaWindow>) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let loose = object_store.loose_objects()?;
+
+    let mut hashes = loose.clone();
+    let mut old_packs = Vec::new();
+    if all {
+        for pack in object_store.own_packs() {
+            hashes.extend(pack.ids());
+            old_packs.push(pack.pack_path.clone());
+        }
+        hashes.sort();
+        hashes.dedup();
+    }
+
+    if hashes.is_empty() || (loose.is_empty() && old_packs.len() < 2 && deltas.is_none()) {
+        println!("Nothing to pack");
+        return Ok(());
+    }
+
+    let pack_path = object_store.write_pack(&hashes, deltas)?;
+
+    // Only remove what the new pack is now known to hold
+    let packed = ObjectStore::new(repo);
+    for hash in &loose {
+        if !packed.object_exists(hash) {
+            return Err(format!("Object {} is missing from the new pack", hash).into());
+        }
+    }
+    for hash in &loose {
+        object_store.remove_loose(hash)?;
+    }
+    let mut removed_packs = 0;
+    for old in old_packs.iter().filter(|old| **old != pack_path) {
+        fs::remove_file(old.with_extension("idx"))?;
+        fs::remove_file(old)?;
+        match fs::remove_file(bitmap::path(old)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+            _ => {}
+        }
+        removed_packs += 1;
+    }
+    let bitmaps = if all {
+        let pack = packed
+            .own_packs()
+            .into_iter()
+            .find(|pack| pack.pack_path == pack_path)
+            .ok_or("The new pack can't be read back")?;
+        Some(bitmap::write(&packed, pack, &utils::tip_commits(repo)?)?)
+    } else {
+        None
+    };
+
+    println!(
+        "Packed {} objects into {}",
+        hashes.len(),
+        pack_path.file_name().unwrap_or_default().to_string_lossy()
+    );
+    println!("Removed {} loose objects", loose.len());
+    if removed_packs > 0 {
+        println!("Removed {} old packs", removed_packs);
+    }
+    if let Some(bitmaps) = bitmaps {
+        println!("Wrote {} reachability bitmaps", bitmaps);
+    }
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use crate::{
+    Repository, Result,
+    commands::merge,
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils, worktree,
+};
+
+// How much of the repository `reset` brings back to the target commit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetMode {
+    // Only the branch (or detached HEAD) moves
+    Soft,
+    // The index too; working tree edits are kept, unstaged
+    Mixed,
+    // The index and the working tree, discarding local edits
+    Hard,
+}
+
+// Point the current branch at `target` (HEAD when None). Recorded in the
+// journal, so `undo` can put the branch back.
+pub fn reset(
+    repo: &Repository,
+    target: Option<String>,
+    mode: ResetMode,
+    allow_protected: bool,
+) -> Result<()> {
+    let target = target.unwrap_or_else(|| "HEAD".to_string());
+    let object_store = ObjectStore::new(repo);
+    let commit = utils::resolve_revision(repo, &target)?;
+    let tree = worktree::load_commit_tree(&object_store, &commit)
+        .map_err(|_| format!("'{}' is not a commit", target))?;
+
+    let old = utils::get_head_commit(repo)?;
+    let mut index = utils::load_index(repo)?;
+    if mode == ResetMode::Soft && (!index.unmerged.is_empty() || merge::merge_head(repo)?.is_some())
+    {
+        return Err("Cannot do a soft reset in the middle of a merge".into());
+    }
+    if old.as_deref() != Some(commit.as_str())
+        && let Some(branch) = utils::head_branch(repo)?
+    {
+        utils::check_branch_protection(repo, &branch, "reset it", allow_protected)?;
+    }
+
+    match mode {
+        ResetMode::Soft => {}
+        ResetMode::Mixed => {
+            // Entries that don't change keep what the index knew about
+            // them (file stat data, flags)
+            let mut locked = utils::lock_index(repo)?;
+            let mut reset_index = worktree::index_from_tree(&tree);
+            for (path, entry) in reset_index.entries.iter_mut() {
+                if let Some(current) = locked.entries.get(path)
+                    && current.hash == entry.hash
+                    && current.mode == entry.mode
+                    && !locked.unmerged.contains_key(path)
+                {
+                    *entry = current.clone();
+                }
+            }
+            reset_index.untracked_cache = locked.untracked_cache.take();
+            index = reset_index.clone();
+            *locked = reset_index;
+            utils::save_locked_index(repo, locked)?;
+        }
+        ResetMode::Hard => worktree::reset_to_tree(repo, &object_store, &tree)?,
+    }
+    if mode != ResetMode::Soft {
+        merge::clear_merge_state(repo)?;
+    }
+
+    utils::update_head_commit(repo, &commit)?;
+    journal::record(
+        repo,
+        "reset",
+        &format!("moving to {}", target),
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old,
+            new: Some(commit.clone()),
+        }],
+    )?;
+
+    match mode {
+        ResetMode::Hard => {
+            let subject = object_store.load_commit(&commit)?.message;
+            println!(
+                "HEAD is now at {} {}",
+                &commit[..8],
+                subject.lines().next().unwrap_or("")
+            );
+        }
+        ResetMode::Mixed => {
+            let modified = worktree::modified_files(repo, &index)?;
+            if !modified.is_empty() {
+                println!("Unstaged changes after reset:");
+                for path in modified {
+                    println!("M\t{}", path);
+                }
+            }
+        }
+        ResetMode::Soft => println!("HEAD is now at {}", &commit[..8]),
+    }
+    Ok(())
+}
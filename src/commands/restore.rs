@@ -0,0 +1,100 @@
+use crate::{
+    Repository, Result,
+    commands::merge::{self, conflicted_content},
+    config::Config,
+    merge_file::{ConflictStyle, Labels},
+    object_store::ObjectStore,
+    repo_path, utils, worktree,
+};
+use std::fs;
+
+// Which version of a conflicted path `--ours` / `--theirs` takes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConflictSide {
+    Ours,
+    Theirs,
+}
+
+// Overwrite working tree files with their staged version. Paths a merge
+// left conflicted have none; they take one side (`side`), or get their
+// conflict markers back (`merge`). Either way they stay unmerged until
+// they are added.
+pub fn restore(
+    repo: &Repository,
+    paths: Vec<String>,
+    merge: bool,
+    side: Option<ConflictSide>,
+) -> Result<()> {
+    let index = utils::load_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+    let style = ConflictStyle::from_config(&Config::load(repo)?)?;
+    // Markers name the sides as the merge did: HEAD, and the branch being
+    // merged when one still points at MERGE_HEAD
+    let merging = match merge::merge_head(repo)? {
+        Some(commit) => utils::list_refs(repo, "refs/heads/")?
+            .into_iter()
+            .find(|(_, tip)| *tip == commit)
+            .map(|(name, _)| name["refs/heads/".len()..].to_string())
+            .unwrap_or_else(|| commit[..8].to_string()),
+        None => "theirs".to_string(),
+    };
+    let labels = Labels {
+        ours: "HEAD",
+        base: "base",
+        theirs: &merging,
+    };
+
+    let mut restored = 0;
+    for pathspec in repo_path::precompose_args(repo, paths)? {
+        let matched = utils::matching_paths(&index, &pathspec);
+        if matched.is_empty() {
+            return Err(format!(
+                "pathspec '{}' did not match any file(s) known to mini_git",
+                pathspec
+            )
+            .into());
+        }
+
+        for path in matched {
+            match (index.unmerged.get(&path), side) {
+                (Some(entry), _) if merge => {
+                    let content = conflicted_content(&object_store, entry, style, &labels)?;
+                    fs::write(repo_path::to_path(&repo.work_dir, &path), content)?;
+                }
+                (Some(entry), Some(side)) => {
+                    let (stage, name) = match side {
+                        ConflictSide::Ours => (&entry.ours, "our"),
+                        ConflictSide::Theirs => (&entry.theirs, "their"),
+                    };
+                    let stage = stage
+                        .as_ref()
+                        .ok_or(format!("path '{}' does not have {} version", path, name))?;
+                    worktree::write_blob(repo, &object_store, &path, &stage.hash, &stage.mode)?;
+                }
+                (Some(_), None) => {
+                    return Err(format!(
+                        "path '{}' is unmerged; use --ours, --theirs or --merge",
+                        path
+                    )
+                    .into());
+                }
+                (None, _) => {
+                    let entry = &index.entries[&path];
+                    if entry.is_dir() {
+                        fs::create_dir_all(repo_path::to_path(&repo.work_dir, &path))?;
+                    } else {
+                        worktree::write_blob(repo, &object_store, &path, &entry.hash, &entry.mode)?;
+                    }
+                }
+            }
+            restored += 1;
+        }
+    }
+
+    if merge {
+        println!("Recreated conflicts in {} path(s)", restored);
+    } else {
+        println!("Updated {} path(s)", restored);
+    }
+    Ok(())
+}
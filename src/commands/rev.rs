@@ -0,0 +1,16 @@
+use crate::{Repository, Result, revnum::RevisionMap};
+
+// Print the commit with sequential revision number `number`
+pub fn rev(repo: &Repository, number: usize) -> Result<()> {
+    let map = RevisionMap::load(repo)?;
+    match map.commit(number) {
+        Some(commit) => {
+            println!("{}", commit);
+            Ok(())
+        }
+        None if map.is_empty() => {
+            Err("No revisions yet: the mainline branch has no commits".into())
+        }
+        None => Err(format!("No revision r{} (the latest is r{})", number, map.len()).into()),
+    }
+}
@@ -0,0 +1,32 @@
+use crate::{
+    Repository, Result,
+    bitmap::Reachable,
+    graph::{CommitGraph, NodeSet},
+    object_store::ObjectStore,
+    utils,
+};
+
+// List the commits reachable from `revision` (HEAD by default), newest
+// first, or with `count` just how many there are. Counting takes most of
+// the history from reachability bitmaps when `repack -a` has written them.
+pub fn rev_list(repo: &Repository, revision: Option<String>, count: bool) -> Result<()> {
+    let start = match revision {
+        Some(revision) => utils::resolve_revision(repo, &revision)?,
+        None => utils::get_head_commit(repo)?.ok_or("No commits yet")?,
+    };
+    let object_store = ObjectStore::new(repo);
+
+    if count {
+        let mut reachable = Reachable::new(&object_store);
+        reachable.add_history(&start, false)?;
+        println!("{}", reachable.count_commits());
+        return Ok(());
+    }
+
+    let mut graph = CommitGraph::new(&object_store);
+    let tip = graph.node(&start)?;
+    for node in graph.walk(tip, &NodeSet::default())? {
+        println!("{}", graph.hex(node));
+    }
+    Ok(())
+}
@@ -0,0 +1,133 @@
+use crate::{
+    Repository, Result, Tree,
+    commands::cherry_pick,
+    commands::commit::create_commit,
+    commands::merge::{self, merge_clean_contents, merge_trees, write_conflicts},
+    config::Config,
+    journal::{self, RefUpdate},
+    object_store::ObjectStore,
+    utils, worktree,
+};
+use chrono::Utc;
+use std::collections::HashMap;
+
+// Undo the changes `target` made with a new commit on top of HEAD. This is a
+// three-way merge of HEAD and the target's parent, with the target itself as
+// the base. When it conflicts, the result is left staged with conflict
+// markers and REVERT_HEAD set; `commit` finishes it with the prepared message.
+pub fn revert(repo: &Repository, target: String) -> Result<()> {
+    if merge::merge_head(repo)?.is_some()
+        || revert_head(repo)?.is_some()
+        || cherry_pick::cherry_pick_head(repo)?.is_some()
+    {
+        return Err(
+            "A merge, revert or cherry-pick is in progress; commit or reset it first".into(),
+        );
+    }
+    if !utils::load_index(repo)?.unmerged.is_empty() {
+        return Err("Cannot revert with unmerged paths; resolve them first".into());
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let head = utils::get_head_commit(repo)?.ok_or("Cannot revert: HEAD has no commits")?;
+    let commit_hash = utils::resolve_revision(repo, &target)?;
+    let commit = object_store
+        .load_commit(&commit_hash)
+        .map_err(|_| format!("'{}' is not a commit", target))?;
+    if commit.parents.len() > 1 {
+        return Err(format!(
+            "Commit {} is a merge; reverting merges is not supported",
+            target
+        )
+        .into());
+    }
+    let subject = commit.message.lines().next().unwrap_or("");
+
+    let base_tree = object_store.load_tree(&commit.tree)?;
+    let our_tree = worktree::load_commit_tree(&object_store, &head)?;
+    let their_tree = match commit.parent() {
+        Some(parent) => worktree::load_commit_tree(&object_store, parent)?,
+        None => Tree {
+            hash: String::new(),
+            entries: HashMap::new(),
+        },
+    };
+    let result = merge_trees(&object_store, &base_tree, &our_tree, &their_tree)?;
+    let result = merge_clean_contents(
+        &object_store,
+        result,
+        &base_tree,
+        &our_tree,
+        &their_tree,
+        None,
+    )?;
+    if result.conflicts.is_empty() && result.tree.hash == our_tree.hash {
+        return Err(format!(
+            "Nothing to revert: the changes of {} are already undone",
+            target
+        )
+        .into());
+    }
+    worktree::check_checkout_safety(repo, &result.tree)?;
+
+    let message = format!(
+        "Revert \"{}\"\n\nThis reverts commit {}.",
+        subject, commit_hash
+    );
+    worktree::switch_to_tree(repo, &object_store, &result.tree)?;
+
+    if !result.conflicts.is_empty() {
+        let conflicts =
+            merge::conflict_stages(&result.conflicts, &base_tree, &our_tree, &their_tree);
+        for (path, _) in &conflicts {
+            println!("CONFLICT: Revert conflict in {}", path);
+        }
+        let parent_label = format!("parent of {}", &commit_hash[..8]);
+        write_conflicts(
+            repo,
+            &object_store,
+            conflicts,
+            &commit_hash[..8],
+            &parent_label,
+        )?;
+        merge::write_merge_state(repo, "REVERT_HEAD", &commit_hash, &message)?;
+        println!(
+            "Could not revert {}... {}\nFix the conflicts, `add` them and then commit the result",
+            &commit_hash[..8],
+            subject
+        );
+        return Ok(());
+    }
+
+    let new_commit = create_commit(
+        &object_store,
+        &result.tree.hash,
+        vec![head.clone()],
+        Config::load(repo)?.author_ident(),
+        message,
+        Utc::now(),
+    )?;
+    utils::update_head_commit(repo, &new_commit)?;
+    journal::record(
+        repo,
+        "revert",
+        &format!("revert {}", &commit_hash[..8]),
+        vec![RefUpdate {
+            name: utils::head_ref(repo)?,
+            old: Some(head),
+            new: Some(new_commit.clone()),
+        }],
+    )?;
+
+    println!(
+        "Created commit {}: Revert \"{}\"",
+        &new_commit[..8],
+        subject
+    );
+    Ok(())
+}
+
+// A revert stopped on conflicts: REVERT_HEAD names the commit being reverted
+pub(crate) fn revert_head(repo: &Repository) -> Result<Option<String>> {
+    merge::read_state_file(repo, "REVERT_HEAD")
+}
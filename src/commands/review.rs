@@ -0,0 +1,228 @@
+use crate::{
+    Repository, Result, Tree,
+    commands::diff::{DiffType, compute_diff},
+    graph,
+    object_store::ObjectStore,
+    utils, worktree,
+};
+use std::collections::BTreeSet;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+
+// Serve a read-only page with the branch's changes since its merge base
+// with HEAD, until interrupted
+pub fn review(repo: &Repository, branch: String, port: u16) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let head = utils::resolve_revision(repo, "HEAD")?;
+    let tip = utils::resolve_revision(repo, &branch)?;
+    let base = graph::merge_base(&object_store, &head, &tip)?
+        .ok_or(format!("'{}' has no common history with HEAD", branch))?;
+
+    let page = render_page(
+        &object_store,
+        &branch,
+        &base,
+        &worktree::load_commit_tree(&object_store, &base)?,
+        &worktree::load_commit_tree(&object_store, &tip)?,
+    )?;
+
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    println!(
+        "Reviewing {} against {} at http://{}/",
+        branch,
+        &base[..8],
+        listener.local_addr()?
+    );
+    println!("Press Ctrl-C to stop");
+
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(e) = respond(stream, &page) {
+                    eprintln!("review: {}", e);
+                }
+            }
+            Err(e) => eprintln!("review: {}", e),
+        }
+    }
+
+    Ok(())
+}
+
+fn respond(mut stream: TcpStream, page: &str) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        header.clear();
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    let (status, body) = if path == "/" || path == "/index.html" {
+        ("200 OK", page)
+    } else {
+        ("404 Not Found", "Not found")
+    };
+
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+fn render_page(
+    object_store: &ObjectStore,
+    branch: &str,
+    base: &str,
+    base_tree: &Tree,
+    branch_tree: &Tree,
+) -> Result<String> {
+    let mut html = String::new();
+    html.push_str("<!DOCTYPE html><html><head><meta charset=\"utf-8\">");
+    html.push_str(&format!("<title>Review: {}</title>", escape(branch)));
+    html.push_str(STYLE);
+    html.push_str("</head><body>");
+    html.push_str(&format!(
+        "<h1>{}</h1><p>Changes since merge base <code>{}</code></p>",
+        escape(branch),
+        &base[..8]
+    ));
+
+    let paths: BTreeSet<&String> = base_tree
+        .entries
+        .keys()
+        .chain(branch_tree.entries.keys())
+        .collect();
+
+    let mut changed = 0;
+    for path in paths {
+        // Tracked empty directories have no content to show
+        let old_hash = base_tree
+            .entries
+            .get(path)
+            .filter(|e| e.is_file)
+            .map(|e| e.hash.as_str());
+        let new_hash = branch_tree
+            .entries
+            .get(path)
+            .filter(|e| e.is_file)
+            .map(|e| e.hash.as_str());
+        if old_hash == new_hash {
+            continue;
+        }
+        changed += 1;
+
+        let old_content = load_text(object_store, old_hash)?;
+        let new_content = load_text(object_store, new_hash)?;
+        html.push_str(&render_file(path, &old_content, &new_content));
+    }
+
+    if changed == 0 {
+        html.push_str("<p>No changes.</p>");
+    }
+    html.push_str("</body></html>");
+    Ok(html)
+}
+
+fn load_text(object_store: &ObjectStore, hash: Option<&str>) -> Result<String> {
+    match hash {
+        Some(hash) => {
+            Ok(String::from_utf8_lossy(&object_store.load_blob(hash)?.content).to_string())
+        }
+        None => Ok(String::new()),
+    }
+}
+
+// One collapsible side-by-side table per file. Runs of deletions and
+// insertions are paired up row by row.
+fn render_file(path: &str, old_content: &str, new_content: &str) -> String {
+    let old_lines: Vec<&str> = old_content.lines().collect();
+    let new_lines: Vec<&str> = new_content.lines().collect();
+    let diff = compute_diff(&old_lines, &new_lines);
+
+    let added = diff.iter().filter(|&&d| d == DiffType::Insert).count();
+    let removed = diff.iter().filter(|&&d| d == DiffType::Delete).count();
+
+    let mut rows = String::new();
+    let (mut old_num, mut new_num) = (0, 0);
+    let mut i = 0;
+    while i < diff.len() {
+        if diff[i] == DiffType::Equal {
+            rows.push_str(&row(
+                Some((old_num + 1, old_lines[old_num])),
+                Some((new_num + 1, new_lines[new_num])),
+                "",
+            ));
+            old_num += 1;
+            new_num += 1;
+            i += 1;
+            continue;
+        }
+
+        let mut deleted = Vec::new();
+        let mut inserted = Vec::new();
+        while i < diff.len() && diff[i] != DiffType::Equal {
+            if diff[i] == DiffType::Delete {
+                deleted.push((old_num + 1, old_lines[old_num]));
+                old_num += 1;
+            } else {
+                inserted.push((new_num + 1, new_lines[new_num]));
+                new_num += 1;
+            }
+            i += 1;
+        }
+        for k in 0..deleted.len().max(inserted.len()) {
+            rows.push_str(&row(
+                deleted.get(k).copied(),
+                inserted.get(k).copied(),
+                "change",
+            ));
+        }
+    }
+
+    format!(
+        "<details open><summary><code>{}</code> <span class=\"add\">+{}</span> <span class=\"del\">-{}</span></summary><table>{}</table></details>",
+        escape(path),
+        added,
+        removed,
+        rows
+    )
+}
+
+fn row(old: Option<(usize, &str)>, new: Option<(usize, &str)>, class: &str) -> String {
+    let cell = |side: Option<(usize, &str)>, kind: &str| match side {
+        Some((num, line)) => format!(
+            "<td class=\"num\">{}</td><td class=\"{}\">{}</td>",
+            num,
+            format!("{} {}", class, kind).trim(),
+            escape(line)
+        ),
+        None => "<td class=\"num\"></td><td class=\"empty\"></td>".to_string(),
+    };
+    format!("<tr>{}{}</tr>", cell(old, "old"), cell(new, "new"))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+const STYLE: &str = "<style>\
+body{font-family:sans-serif;margin:2em}\
+details{margin-bottom:1em;border:1px solid #ccc}\
+summary{padding:.4em;background:#f4f4f4;cursor:pointer}\
+table{border-collapse:collapse;width:100%;font-family:monospace;font-size:13px}\
+td{padding:0 .4em;white-space:pre;vertical-align:top;width:50%}\
+td.num{width:3em;color:#999;text-align:right}\
+td.change.old{background:#fdd}td.change.new{background:#dfd}\
+td.empty{background:#eee}.add{color:#080}.del{color:#c00}\
+</style>";
@@ -0,0 +1,85 @@
+use crate::{Repository, Result, object_store::ObjectStore, repo_path, utils, worktree};
+use std::collections::HashMap;
+
+// Stop tracking paths: drop them from the index, so the next commit records
+// their deletion, and delete them from the working tree unless `cached`.
+// Directories need `recursive`. As in git, a path whose content would be lost
+// (staged changes, or local edits when the file is deleted too) is refused
+// unless `force`.
+pub fn rm(
+    repo: &Repository,
+    paths: Vec<String>,
+    cached: bool,
+    force: bool,
+    recursive: bool,
+) -> Result<()> {
+    let mut index = utils::lock_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+    let head_tree = match utils::get_head_commit(repo)? {
+        Some(commit) => worktree::load_commit_tree(&object_store, &commit)?.entries,
+        None => HashMap::new(),
+    };
+
+    let mut removing = Vec::new();
+    for pathspec in repo_path::precompose_args(repo, paths)? {
+        let pathspec = pathspec.trim_end_matches('/').to_string();
+        let matched = utils::matching_paths(&index, &pathspec);
+        if matched.is_empty() {
+            return Err(format!(
+                "pathspec '{}' did not match any files known to mini_git",
+                pathspec
+            )
+            .into());
+        }
+        if !recursive && matched.iter().any(|path| *path != pathspec) {
+            return Err(format!("not removing '{}' recursively without -r", pathspec).into());
+        }
+        removing.extend(matched);
+    }
+    removing.sort();
+    removing.dedup();
+
+    if !force {
+        for path in &removing {
+            // Conflicted paths are being resolved by their removal
+            if index.unmerged.contains_key(path) {
+                continue;
+            }
+            let entry = &index.entries[path];
+            let staged = head_tree
+                .get(path)
+                .is_none_or(|head| head.hash != entry.hash || head.mode != entry.mode);
+            let modified = worktree::is_modified(repo, &object_store, entry)?
+                && repo_path::to_path(&repo.work_dir, path).exists();
+            let problem = if staged && modified {
+                Some("has staged content different from both the file and the HEAD")
+            } else if cached {
+                None
+            } else if staged {
+                Some(
+                    "has changes staged in the index (use --cached to keep the file, or -f to force removal)",
+                )
+            } else if modified {
+                Some(
+                    "has local modifications (use --cached to keep the file, or -f to force removal)",
+                )
+            } else {
+                None
+            };
+            if let Some(problem) = problem {
+                return Err(format!("'{}' {}", path, problem).into());
+            }
+        }
+    }
+
+    for path in &removing {
+        index.entries.remove(path);
+        index.unmerged.remove(path);
+        if !cached {
+            worktree::remove_working_file(repo, path)?;
+        }
+        println!("rm '{}'", path);
+    }
+    utils::save_locked_index(repo, index)?;
+    Ok(())
+}
@@ -0,0 +1,313 @@
+use crate::{
+    Repository, Result,
+    access::{self, Operation},
+    commands::push::{non_fast_forward, receive_objects, run_pre_receive},
+    graph,
+    hash::HashAlgorithm,
+    object_store::{ObjectStorage, ObjectStore},
+    protocol::{self, Request, Response, Session},
+    utils,
+};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, BufRead, Write};
+use walkdir::WalkDir;
+
+// Serve the repository over stdin/stdout, for sshd, inetd or tests. Nothing
+// else may be written to stdout while serving.
+pub fn serve(repo: &Repository, stdio: bool) -> Result<()> {
+    if !stdio {
+        return Err("Only --stdio is supported".into());
+    }
+
+    let mut reader = io::stdin().lock();
+    let mut writer = io::stdout().lock();
+    // Clients that skip `hello` get no optional features
+    let mut session = Session::default();
+    let format = HashAlgorithm::of_repo(repo)?;
+    let storage = ObjectStorage::of_git_dir(&repo.git_dir)?;
+
+    while let Some(message) = protocol::receive::<serde_json::Value>(&mut reader)? {
+        let result = serde_json::from_value::<Request>(message)
+            .map_err(|e| format!("Invalid request: {}", e).into())
+            .and_then(|request| match request {
+                Request::Hello {
+                    version,
+                    capabilities,
+                } => {
+                    // Announce our capabilities even when the object formats
+                    // differ, so the client can report the mismatch itself
+                    let negotiated = Session::negotiate(version, &capabilities, format, storage);
+                    protocol::send(
+                        &mut writer,
+                        &Response::Capabilities {
+                            version: protocol::PROTOCOL_VERSION,
+                            capabilities: protocol::capabilities(format, storage),
+                        },
+                    )?;
+                    session = negotiated?;
+                    Ok(())
+                }
+                request => handle_request(repo, &session, request, &mut reader, &mut writer),
+            });
+        if let Err(e) = result {
+            protocol::send(
+                &mut writer,
+                &Response::Error {
+                    message: e.to_string(),
+                },
+            )?;
+        }
+    }
+    Ok(())
+}
+
+fn handle_request(
+    repo: &Repository,
+    session: &Session,
+    request: Request,
+    reader: &mut impl BufRead,
+    writer: &mut impl Write,
+) -> Result<()> {
+    let refs_dir = utils::served_refs_dir(&repo.git_dir);
+
+    match request {
+        Request::ListRefs => {
+            let refs = served_refs(repo)?;
+            for name in refs.keys() {
+                access::authorize(&repo.git_dir, Operation::Fetch, name)?;
+            }
+            protocol::send(writer, &Response::Refs { refs })
+        }
+        Request::Fetch { wants, haves } => {
+            let object_store = ObjectStore::new(repo);
+            let refs = served_refs(repo)?;
+            for want in &wants {
+                check_want(repo, &object_store, &refs, want)?;
+            }
+            let objects = protocol::objects_to_send(&object_store, &wants, &haves)?;
+            for object in &objects {
+                protocol::send_object(writer, session, &object_store, object)?;
+            }
+            protocol::send(
+                writer,
+                &Response::Done {
+                    count: objects.len(),
+                },
+            )
+        }
+        Request::Hello { .. } => Err("Unexpected hello".into()),
+        Request::Push {
+            refname,
+            old,
+            new,
+            cert,
+            force,
+        } => {
+            // Read the objects first so the stream stays in sync even when
+            // the push is rejected
+            let mut objects = Vec::new();
+            loop {
+                match protocol::receive::<Response>(reader)? {
+                    Some(Response::Object { hash, payload }) => objects.push((hash, payload)),
+                    Some(Response::Done { .. }) => break,
+                    _ => return Err("Malformed push".into()),
+                }
+            }
+
+            let (kind, name) = if let Some(branch) = refname.strip_prefix("refs/heads/") {
+                ("branch", branch)
+            } else if let Some(tag) = refname.strip_prefix("refs/tags/") {
+                ("tag", tag)
+            } else {
+                return Err(format!("Cannot push to '{}'", refname).into());
+            };
+            utils::validate_ref_name(kind, name)?;
+            let hex_len = HashAlgorithm::of_repo(repo)?.hex_len();
+            if new.len() != hex_len || !new.bytes().all(|b| b.is_ascii_hexdigit()) {
+                return Err(format!("Invalid object id '{}'", new).into());
+            }
+            access::authorize(&repo.git_dir, Operation::Push, &refname)?;
+
+            let ref_path = refs_dir.join(&refname["refs/".len()..]);
+            let current = fs::read_to_string(&ref_path)
+                .ok()
+                .map(|c| c.trim().to_string());
+            // Tags don't move once published
+            if kind == "tag" && current.is_some() {
+                return Err(format!("tag '{}' already exists", name).into());
+            }
+            if current != old {
+                return Err(format!("{} changed during the push; fetch and retry", refname).into());
+            }
+
+            receive_objects(
+                repo,
+                std::slice::from_ref(&new),
+                |quarantine| {
+                    // Deltas resolve against the repository through the
+                    // quarantine's alternates
+                    let store = ObjectStore::at(quarantine.to_path_buf());
+                    let count = objects.len();
+                    for (hash, payload) in objects {
+                        let content = protocol::decode_object(payload, &store)?;
+                        store.store_object(&hash, &content)?;
+                    }
+                    if let Some(current) = &current
+                        && !force
+                        && !graph::is_ancestor(&store, current, &new)?
+                    {
+                        return Err(non_fast_forward(&refname));
+                    }
+                    Ok(count)
+                },
+                || run_pre_receive(repo, &refname, old.as_deref(), &new, cert.as_deref()),
+            )?;
+
+            fs::create_dir_all(ref_path.parent().unwrap())?;
+            fs::write(&ref_path, &new)?;
+            protocol::send(
+                writer,
+                &Response::Ok {
+                    message: format!("updated {} to {}", refname, &new[..8]),
+                },
+            )
+        }
+    }
+}
+
+// Branches and tags the repository serves, by full ref name
+fn served_refs(repo: &Repository) -> Result<BTreeMap<String, String>> {
+    let refs_dir = utils::served_refs_dir(&repo.git_dir);
+    let mut refs = BTreeMap::new();
+
+    for kind in ["heads", "tags"] {
+        let dir = refs_dir.join(kind);
+        if !dir.is_dir() {
+            continue;
+        }
+        for entry in WalkDir::new(&dir).into_iter().filter_map(|e| e.ok()) {
+            if entry.file_type().is_file() {
+                let name = entry.path().strip_prefix(&refs_dir)?.to_string_lossy();
+                let target = fs::read_to_string(entry.path())?.trim().to_string();
+                refs.insert(format!("refs/{}", name.replace('\\', "/")), target);
+            }
+        }
+    }
+    Ok(refs)
+}
+
+// Only objects the served refs lead to may be fetched, so a client can't
+// ask by hash for what another namespace, or nothing at all, points to. One
+// of those refs must also be one the client may fetch.
+fn check_want(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    refs: &BTreeMap<String, String>,
+    want: &str,
+) -> Result<()> {
+    let mut result = Err(format!("not our ref {}", want).into());
+    for name in advertising_refs(object_store, refs, want)? {
+        result = access::authorize(&repo.git_dir, Operation::Fetch, name);
+        if result.is_ok() {
+            break;
+        }
+    }
+    result
+}
+
+// The served refs pointing at `want`, or at a commit it is reachable from
+fn advertising_refs<'a>(
+    object_store: &ObjectStore,
+    refs: &'a BTreeMap<String, String>,
+    want: &str,
+) -> Result<Vec<&'a str>> {
+    let is_commit =
+        object_store.object_exists(want) && object_store.read_typed(want)?.0 == "commit";
+    let mut names = Vec::new();
+    for (name, tip) in refs {
+        if tip == want
+            || is_commit && graph::is_ancestor(object_store, want, &object_store.peel(tip)?)?
+        {
+            names.push(name.as_str());
+        }
+    }
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands;
+
+    fn repo(name: &str) -> Repository {
+        let dir =
+            std::env::temp_dir().join(format!("mini_git-serve-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        commands::init(Some(dir.clone()), None, None, None).unwrap();
+        utils::get_repository(Some(dir)).unwrap()
+    }
+
+    fn commit_file(repo: &Repository, content: &str) -> String {
+        fs::write(repo.work_dir.join("a.txt"), content).unwrap();
+        commands::add(repo, vec!["a.txt".to_string()], false).unwrap();
+        commands::commit(
+            repo,
+            Some(content.to_string()),
+            None,
+            None,
+            false,
+            Vec::new(),
+            false,
+        )
+        .unwrap();
+        utils::get_head_commit(repo).unwrap().unwrap()
+    }
+
+    #[test]
+    fn wants_must_be_reachable_from_served_refs() {
+        let repo = repo("namespaces");
+        let first = commit_file(&repo, "one\n");
+        let second = commit_file(&repo, "two\n");
+        // Only another namespace's branch points past `first`
+        let other = repo.git_dir.join("refs/namespaces/other/refs/heads");
+        fs::create_dir_all(&other).unwrap();
+        fs::write(other.join("main"), format!("{}\n", second)).unwrap();
+        let branch = utils::get_current_branch(&repo).unwrap();
+        utils::update_branch(&repo, &branch, &first).unwrap();
+
+        let object_store = ObjectStore::new(&repo);
+        let refs = served_refs(&repo).unwrap();
+        assert!(check_want(&repo, &object_store, &refs, &first).is_ok());
+        let error = check_want(&repo, &object_store, &refs, &second).unwrap_err();
+        assert!(error.to_string().contains("not our ref"), "{}", error);
+        fs::remove_dir_all(&repo.work_dir).unwrap();
+    }
+
+    #[test]
+    fn wants_only_denied_refs_lead_to_are_refused() {
+        let repo = repo("access");
+        let first = commit_file(&repo, "one\n");
+        let plan = commit_file(&repo, "plan\n");
+        let tagged = commit_file(&repo, "tagged\n");
+        let branch = utils::get_current_branch(&repo).unwrap();
+        utils::update_branch(&repo, &branch, &first).unwrap();
+        utils::update_branch(&repo, "secret", &plan).unwrap();
+        fs::create_dir_all(repo.git_dir.join("refs/tags")).unwrap();
+        fs::write(repo.git_dir.join("refs/tags/v1"), format!("{}\n", tagged)).unwrap();
+        let mut config = fs::read_to_string(repo.git_dir.join("config")).unwrap();
+        config.push_str("[access \"refs/heads/secret\"]\n\tfetch = letmein\n");
+        config.push_str("[access \"refs/tags/*\"]\n\tfetch = letmein\n");
+        fs::write(repo.git_dir.join("config"), config).unwrap();
+
+        let object_store = ObjectStore::new(&repo);
+        let refs = served_refs(&repo).unwrap();
+        assert!(check_want(&repo, &object_store, &refs, &first).is_ok());
+        for want in [&plan, &tagged] {
+            let error = check_want(&repo, &object_store, &refs, want).unwrap_err();
+            assert!(error.to_string().contains("Access denied"), "{}", error);
+        }
+        fs::remove_dir_all(&repo.work_dir).unwrap();
+    }
+}
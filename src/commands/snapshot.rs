@@ -0,0 +1,136 @@
+use crate::{
+    Repository, Result, commands::commit::create_commit, object_store::ObjectStore, utils, worktree,
+};
+use chrono::Utc;
+use std::fs;
+use std::path::PathBuf;
+
+// Snapshots are ordinary commits of the whole working tree, kept under
+// refs/snapshots/<branch>/<timestamp> so they never move a branch or touch
+// the index
+pub fn snapshot(
+    repo: &Repository,
+    action: Option<String>,
+    target: Option<String>,
+    message: Option<String>,
+) -> Result<()> {
+    match action.as_deref() {
+        Some("save") | None => snapshot_save(repo, message),
+        Some("list") => snapshot_list(repo, target),
+        Some("restore") => {
+            let target = target.ok_or("Snapshot to restore required")?;
+            snapshot_restore(repo, &target)
+        }
+        _ => Err("Invalid snapshot action. Use: save, list, restore".into()),
+    }
+}
+
+fn snapshot_save(repo: &Repository, message: Option<String>) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let branch = utils::get_current_branch(repo)?;
+    let now = Utc::now();
+
+    let tree = worktree::tree_from_working_dir(repo, &object_store)?;
+    let message = message.unwrap_or_else(|| format!("snapshot of {}", branch));
+    let commit = create_commit(
+        &object_store,
+        &tree.hash,
+        utils::get_head_commit(repo)?.into_iter().collect(),
+        "Mini Git Snapshot <snapshot@minigit.local>".to_string(),
+        message,
+        now,
+    )?;
+
+    let name = now.format("%Y%m%dT%H%M%S%.3fZ").to_string();
+    let ref_path = snapshots_dir(repo).join(&branch).join(&name);
+    fs::create_dir_all(ref_path.parent().unwrap())?;
+    fs::write(ref_path, &commit)?;
+
+    println!("Saved snapshot {}/{} ({})", branch, name, &commit[..8]);
+    Ok(())
+}
+
+fn snapshot_list(repo: &Repository, branch: Option<String>) -> Result<()> {
+    let branch = match branch {
+        Some(branch) => branch,
+        None => utils::get_current_branch(repo)?,
+    };
+    let object_store = ObjectStore::new(repo);
+
+    let snapshots = list_snapshots(repo, &branch)?;
+    if snapshots.is_empty() {
+        println!("No snapshots for {}", branch);
+        return Ok(());
+    }
+
+    for (name, commit_hash) in snapshots.iter().rev() {
+        let commit = object_store.load_commit(commit_hash)?;
+        println!(
+            "{}/{} {} {}",
+            branch,
+            name,
+            &commit_hash[..8],
+            commit.message.lines().next().unwrap_or("")
+        );
+    }
+    Ok(())
+}
+
+// Write the snapshot's files back into the working directory. The index and
+// branch are left alone, so the result shows up as ordinary local changes.
+fn snapshot_restore(repo: &Repository, target: &str) -> Result<()> {
+    let commit_hash = resolve_snapshot(repo, target)?;
+    let object_store = ObjectStore::new(repo);
+    let tree = worktree::load_commit_tree(&object_store, &commit_hash)?;
+
+    worktree::write_tree_files(repo, &object_store, &tree)?;
+    println!(
+        "Restored {} file(s) from snapshot {}",
+        tree.entries.len(),
+        &commit_hash[..8]
+    );
+    Ok(())
+}
+
+// Accepts `<branch>/<timestamp>`, a timestamp on the current branch, or
+// `latest`
+fn resolve_snapshot(repo: &Repository, target: &str) -> Result<String> {
+    let branch = utils::get_current_branch(repo)?;
+    if target == "latest" {
+        return list_snapshots(repo, &branch)?
+            .pop()
+            .map(|(_, commit)| commit)
+            .ok_or(format!("No snapshots for {}", branch).into());
+    }
+
+    let dir = snapshots_dir(repo);
+    for candidate in [dir.join(target), dir.join(&branch).join(target)] {
+        if candidate.is_file() {
+            return Ok(fs::read_to_string(candidate)?.trim().to_string());
+        }
+    }
+    Err(format!("Snapshot '{}' not found", target).into())
+}
+
+// Snapshots for `branch`, oldest first
+fn list_snapshots(repo: &Repository, branch: &str) -> Result<Vec<(String, String)>> {
+    let dir = snapshots_dir(repo).join(branch);
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut snapshots = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        if entry.path().is_file() {
+            let commit = fs::read_to_string(entry.path())?.trim().to_string();
+            snapshots.push((entry.file_name().to_string_lossy().to_string(), commit));
+        }
+    }
+    snapshots.sort();
+    Ok(snapshots)
+}
+
+fn snapshots_dir(repo: &Repository) -> PathBuf {
+    repo.git_dir.join("refs").join("snapshots")
+}
@@ -1,6 +1,9 @@
 use crate::{
-    Commit, Index, IndexEntry, Repository, Result, Tree, TreeEntry, object_store::ObjectStore,
+    Repository, Result, Tree,
+    commands::commit::create_commit,
+    object_store::ObjectStore,
     utils,
+    worktree::{self, tree_from_index},
 };
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
@@ -55,7 +58,7 @@ fn stash_push(repo: &Repository, message: Option<String>) -> Result<()> {
     let object_store = ObjectStore::new(repo);
 
     // Check if there are any changes to stash
-    if index.entries.is_empty() && !has_unstaged_changes(repo)? {
+    if !worktree::has_local_changes(repo, &object_store, &index, true)? {
         println!("No local changes to save");
         return Ok(());
     }
@@ -64,38 +67,29 @@ fn stash_push(repo: &Repository, message: Option<String>) -> Result<()> {
     let message = message.unwrap_or_else(|| {
         format!(
             "WIP on {}: {}",
-            utils::get_current_branch(repo).unwrap_or("HEAD".to_string()),
+            utils::head_branch(repo)
+                .ok()
+                .flatten()
+                .unwrap_or("(no branch)".to_string()),
             get_last_commit_subject(repo).unwrap_or("unknown".to_string())
         )
     });
 
     // Save current state
-    let index_tree = create_tree_from_index(repo, &object_store, &index)?;
-    let working_tree = create_tree_from_working_dir(repo, &object_store)?;
+    let index_tree = tree_from_index(&object_store, &index)?;
+    let working_tree = worktree::tree_from_working_dir(repo, &object_store)?;
 
-    let current_branch = utils::get_current_branch(repo)?;
-    let parent_commit = utils::get_branch_commit(repo, &current_branch)?;
+    let parent_commit = utils::get_head_commit(repo)?;
 
     // Create stash commit
-    let stash_content = format!(
-        "{}{}{}{}",
-        working_tree.hash,
-        parent_commit.as_ref().unwrap_or(&String::new()),
-        "Mini Git Stash <stash@minigit.local>",
-        message
-    );
-    let stash_hash = ObjectStore::hash_content(stash_content.as_bytes());
-
-    let stash_commit = Commit {
-        hash: stash_hash.clone(),
-        parent: parent_commit.clone(),
-        tree: working_tree.hash.clone(),
-        author: "Mini Git Stash <stash@minigit.local>".to_string(),
-        message: message.clone(),
-        timestamp: Utc::now(),
-    };
-
-    object_store.store_commit(&stash_commit)?;
+    let stash_hash = create_commit(
+        &object_store,
+        &working_tree.hash,
+        parent_commit.clone().into_iter().collect(),
+        "Mini Git Stash <stash@minigit.local>".to_string(),
+        message.clone(),
+        Utc::now(),
+    )?;
 
     // Save stash entry
     let stash_entry = Stash {
@@ -109,12 +103,15 @@ fn stash_push(repo: &Repository, message: Option<String>) -> Result<()> {
 
     save_stash_entry(repo, &stash_entry)?;
 
-    // Clean working directory and index
-    clear_working_directory(repo)?;
-    let empty_index = Index {
-        entries: HashMap::new(),
-    };
-    utils::save_index(repo, &empty_index)?;
+    // Reset tracked files to HEAD, then remove the untracked files that went
+    // into the stash
+    let head_tree = head_tree(repo, &object_store)?;
+    worktree::reset_to_tree(repo, &object_store, &head_tree)?;
+    for path in working_tree.entries.keys() {
+        if !head_tree.entries.contains_key(path) {
+            worktree::remove_working_file(repo, path)?;
+        }
+    }
 
     println!("Saved working directory and index state");
     Ok(())
@@ -133,11 +130,11 @@ fn stash_pop(repo: &Repository, index: Option<usize>) -> Result<()> {
 
     // Restore working directory from stash
     let working_tree = object_store.load_tree(&stash_entry.working_tree)?;
-    restore_tree_to_working_dir(repo, &object_store, &working_tree)?;
+    worktree::write_tree_files(repo, &object_store, &working_tree)?;
 
     // Restore index from stash
     let index_tree = object_store.load_tree(&stash_entry.index_tree)?;
-    let restored_index = create_index_from_tree(&index_tree);
+    let restored_index = worktree::index_from_tree(&index_tree);
     utils::save_index(repo, &restored_index)?;
 
     // Remove stash entry
@@ -188,7 +185,7 @@ fn stash_show(repo: &Repository, index: Option<usize>) -> Result<()> {
     let working_tree = object_store.load_tree(&stash_entry.working_tree)?;
 
     println!("\nFiles in stash:");
-    for (path, _) in &working_tree.entries {
+    for path in working_tree.entries.keys() {
         println!("  {}", path);
     }
 
@@ -223,30 +220,18 @@ fn stash_clear(repo: &Repository) -> Result<()> {
     Ok(())
 }
 
-fn has_unstaged_changes(repo: &Repository) -> Result<bool> {
-    let index = utils::load_index(repo)?;
-
-    // Check if any tracked files have been modified
-    for (path, index_entry) in &index.entries {
-        let file_path = repo.work_dir.join(path);
-        if file_path.exists() {
-            let content = fs::read(&file_path)?;
-            let current_hash = ObjectStore::hash_content(&content);
-            if current_hash != index_entry.hash {
-                return Ok(true);
-            }
-        } else {
-            // File was deleted
-            return Ok(true);
-        }
+fn head_tree(repo: &Repository, object_store: &ObjectStore) -> Result<Tree> {
+    match utils::get_head_commit(repo)? {
+        Some(head) => worktree::load_commit_tree(object_store, &head),
+        None => Ok(Tree {
+            hash: String::new(),
+            entries: HashMap::new(),
+        }),
     }
-
-    Ok(false)
 }
 
 fn get_last_commit_subject(repo: &Repository) -> Result<String> {
-    let current_branch = utils::get_current_branch(repo)?;
-    if let Some(commit_hash) = utils::get_branch_commit(repo, &current_branch)? {
+    if let Some(commit_hash) = utils::get_head_commit(repo)? {
         let object_store = ObjectStore::new(repo);
         let commit = object_store.load_commit(&commit_hash)?;
 
@@ -258,136 +243,12 @@ fn get_last_commit_subject(repo: &Repository) -> Result<String> {
     }
 }
 
-fn create_tree_from_index(
-    repo: &Repository,
-    object_store: &ObjectStore,
-    index: &Index,
-) -> Result<Tree> {
-    let mut tree_entries = HashMap::new();
-
-    for (path, index_entry) in &index.entries {
-        tree_entries.insert(
-            path.clone(),
-            TreeEntry {
-                mode: index_entry.mode.clone(),
-                hash: index_entry.hash.clone(),
-                name: path.clone(),
-                is_file: true,
-            },
-        );
-    }
-
-    let tree_content = serde_json::to_vec(&tree_entries)?;
-    let tree_hash = ObjectStore::hash_content(&tree_content);
-    let tree = Tree {
-        hash: tree_hash,
-        entries: tree_entries,
-    };
-
-    object_store.store_tree(&tree)?;
-    Ok(tree)
-}
-
-fn create_tree_from_working_dir(repo: &Repository, object_store: &ObjectStore) -> Result<Tree> {
-    let mut tree_entries = HashMap::new();
-
-    for entry in walkdir::WalkDir::new(&repo.work_dir)
+// The commits and index trees stashes keep alive
+pub(crate) fn stash_objects(repo: &Repository) -> Result<(Vec<String>, Vec<String>)> {
+    Ok(load_stash_entries(repo)?
         .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        let path = entry.path();
-        if path.starts_with(&repo.git_dir) {
-            continue;
-        }
-
-        let relative_path = path
-            .strip_prefix(&repo.work_dir)?
-            .to_string_lossy()
-            .replace('\\', "/");
-
-        let content = fs::read(path)?;
-        let hash = object_store.store_blob(&content)?;
-
-        tree_entries.insert(
-            relative_path.clone(),
-            TreeEntry {
-                mode: "100644".to_string(),
-                hash,
-                name: relative_path,
-                is_file: true,
-            },
-        );
-    }
-
-    let tree_content = serde_json::to_vec(&tree_entries)?;
-    let tree_hash = ObjectStore::hash_content(&tree_content);
-    let tree = Tree {
-        hash: tree_hash,
-        entries: tree_entries,
-    };
-
-    object_store.store_tree(&tree)?;
-    Ok(tree)
-}
-
-fn create_index_from_tree(tree: &Tree) -> Index {
-    let mut entries = HashMap::new();
-
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            entries.insert(
-                path.clone(),
-                IndexEntry {
-                    hash: tree_entry.hash.clone(),
-                    mode: tree_entry.mode.clone(),
-                    path: path.clone(),
-                },
-            );
-        }
-    }
-
-    Index { entries }
-}
-
-fn restore_tree_to_working_dir(
-    repo: &Repository,
-    object_store: &ObjectStore,
-    tree: &Tree,
-) -> Result<()> {
-    for (path, tree_entry) in &tree.entries {
-        if tree_entry.is_file {
-            let blob = object_store.load_blob(&tree_entry.hash)?;
-            let file_path = repo.work_dir.join(path);
-
-            if let Some(parent) = file_path.parent() {
-                fs::create_dir_all(parent)?;
-            }
-
-            fs::write(file_path, &blob.content)?;
-        }
-    }
-
-    Ok(())
-}
-
-fn clear_working_directory(repo: &Repository) -> Result<()> {
-    for entry in fs::read_dir(&repo.work_dir)? {
-        let entry = entry?;
-        let path = entry.path();
-
-        if path.file_name().unwrap() == ".mini_git" {
-            continue;
-        }
-
-        if path.is_file() {
-            fs::remove_file(&path)?;
-        } else if path.is_dir() {
-            fs::remove_dir_all(&path)?;
-        }
-    }
-
-    Ok(())
+        .map(|entry| (entry.commit_hash, entry.index_tree))
+        .unzip())
 }
 
 fn load_stash_entries(repo: &Repository) -> Result<Vec<Stash>> {
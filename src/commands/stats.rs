@@ -0,0 +1,175 @@
+use crate::{
+    Repository, Result, Tree,
+    commands::diff::{DiffType, compute_diff},
+    object_store::ObjectStore,
+    pretty, utils, worktree,
+};
+use chrono::{DateTime, Utc};
+use std::collections::{BTreeSet, HashMap};
+
+#[derive(Default)]
+struct Churn {
+    commits: usize,
+    added: usize,
+    removed: usize,
+}
+
+pub fn stats(repo: &Repository, report: Option<String>, since: Option<String>) -> Result<()> {
+    let since = since
+        .as_deref()
+        .map(|since| pretty::parse_date("since", since))
+        .transpose()?;
+
+    match report.as_deref() {
+        Some("churn") | None => churn(repo, since),
+        Some("contributors") => contributors(repo, since),
+        _ => Err("Invalid stats report. Use: churn, contributors".into()),
+    }
+}
+
+struct CommitChanges {
+    author: String,
+    // (path, lines added, lines removed)
+    files: Vec<(String, usize, usize)>,
+}
+
+// Per-file and per-author change frequency and line churn
+fn churn(repo: &Repository, since: Option<DateTime<Utc>>) -> Result<()> {
+    let mut files: HashMap<String, Churn> = HashMap::new();
+    let mut authors: HashMap<String, Churn> = HashMap::new();
+
+    for commit in walk_history(repo, since)? {
+        let author = authors.entry(commit.author).or_default();
+        author.commits += 1;
+        for (path, added, removed) in commit.files {
+            author.added += added;
+            author.removed += removed;
+
+            let file = files.entry(path).or_default();
+            file.commits += 1;
+            file.added += added;
+            file.removed += removed;
+        }
+    }
+
+    println!("Files by change frequency:");
+    print_table(files);
+    println!();
+    println!("Authors:");
+    print_table(authors);
+    Ok(())
+}
+
+fn contributors(repo: &Repository, since: Option<DateTime<Utc>>) -> Result<()> {
+    let mut authors: HashMap<String, Churn> = HashMap::new();
+
+    for commit in walk_history(repo, since)? {
+        let author = authors.entry(commit.author).or_default();
+        author.commits += 1;
+        for (_, added, removed) in commit.files {
+            author.added += added;
+            author.removed += removed;
+        }
+    }
+
+    print_table(authors);
+    Ok(())
+}
+
+// Diff every commit reachable from HEAD (newer than `since`) against its parent
+fn walk_history(repo: &Repository, since: Option<DateTime<Utc>>) -> Result<Vec<CommitChanges>> {
+    let object_store = ObjectStore::new(repo);
+    let mut current = utils::get_head_commit(repo)?;
+    let mut history = Vec::new();
+
+    while let Some(hash) = current {
+        let commit = object_store.load_commit(&hash)?;
+        if since.is_some_and(|since| commit.timestamp < since) {
+            break;
+        }
+
+        let tree = object_store.load_tree(&commit.tree)?;
+        let parent_tree = match commit.parent() {
+            Some(parent) => worktree::load_commit_tree(&object_store, parent)?,
+            None => Tree {
+                hash: String::new(),
+                entries: HashMap::new(),
+            },
+        };
+
+        let paths: BTreeSet<&String> = tree
+            .entries
+            .keys()
+            .chain(parent_tree.entries.keys())
+            .collect();
+        let mut files = Vec::new();
+        for path in paths {
+            // Tracked empty directories have no lines
+            let old = parent_tree
+                .entries
+                .get(path)
+                .filter(|e| e.is_file)
+                .map(|e| e.hash.as_str());
+            let new = tree
+                .entries
+                .get(path)
+                .filter(|e| e.is_file)
+                .map(|e| e.hash.as_str());
+            if old != new {
+                let (added, removed) = line_changes(&object_store, old, new)?;
+                files.push((path.clone(), added, removed));
+            }
+        }
+
+        current = commit.parent().cloned();
+        history.push(CommitChanges {
+            author: commit.author,
+            files,
+        });
+    }
+    Ok(history)
+}
+
+// Lines added and removed going from blob `old` to `new`, either of which
+// may be missing
+pub(crate) fn line_changes(
+    object_store: &ObjectStore,
+    old: Option<&str>,
+    new: Option<&str>,
+) -> Result<(usize, usize)> {
+    let load = |hash: Option<&str>| -> Result<String> {
+        match hash {
+            Some(hash) => {
+                Ok(String::from_utf8_lossy(&object_store.load_blob(hash)?.content).to_string())
+            }
+            None => Ok(String::new()),
+        }
+    };
+    let old = load(old)?;
+    let new = load(new)?;
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+
+    let diff = compute_diff(&old_lines, &new_lines);
+    let added = diff.iter().filter(|&&d| d == DiffType::Insert).count();
+    let removed = diff.iter().filter(|&&d| d == DiffType::Delete).count();
+    Ok((added, removed))
+}
+
+fn print_table(rows: HashMap<String, Churn>) {
+    let mut rows: Vec<(String, Churn)> = rows.into_iter().collect();
+    rows.sort_by(|a, b| {
+        b.1.commits
+            .cmp(&a.1.commits)
+            .then((b.1.added + b.1.removed).cmp(&(a.1.added + a.1.removed)))
+            .then(a.0.cmp(&b.0))
+    });
+
+    println!("{:>8} {:>8} {:>8}  name", "commits", "added", "removed");
+    for (name, churn) in rows {
+        println!(
+            "{:>8} {:>8} {:>8}  {}",
+            churn.commits, churn.added, churn.removed, name
+        );
+    }
+}
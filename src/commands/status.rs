@@ -1,78 +1,440 @@
-use crate::{Repository, Result, utils};
-use std::collections::HashSet;
+use crate::{
+    Repository, Result, UnmergedEntry,
+    commands::{cherry_pick, merge, rebase, revert},
+    config::Config,
+    ignore::IgnoreRules,
+    object_store::ObjectStore,
+    renames::{self, Candidate, RenameOptions},
+    repo_path, utils, worktree,
+};
+use serde::Serialize;
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::fs;
-use walkdir::WalkDir;
 
-pub fn status(repo: &Repository) -> Result<()> {
-    let current_branch = utils::get_current_branch(repo)?;
-    println!("On branch {}", current_branch);
+// How `--ignored` reports ignored paths: `traditional` collapses a directory
+// holding nothing but ignored files into `dir/`, `matching` only does so for
+// directories an ignore pattern names
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum IgnoredMode {
+    No,
+    Traditional,
+    Matching,
+}
 
-    let index = utils::load_index(repo)?;
+#[derive(Serialize)]
+struct StatusReport {
+    // `HEAD` when detached, with the commit in `detached_at`
+    branch: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    detached_at: Option<String>,
+    staged: Vec<String>,
+    // Staged deletions: in HEAD but no longer in the index
+    deleted: Vec<String>,
+    renamed: Vec<RenameReport>,
+    modified: Vec<String>,
+    untracked: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    ignored: Option<Vec<String>>,
+    unmerged: Vec<UnmergedReport>,
+    counts: Counts,
+}
 
-    // Get all files in working directory
-    let mut working_files = HashSet::new();
-    for entry in WalkDir::new(&repo.work_dir)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| e.file_type().is_file())
-    {
-        let path = entry.path();
-        if path.starts_with(&repo.git_dir) {
-            continue;
+// A path HEAD has that is staged, with the same or similar content, under a
+// new name
+#[derive(Serialize)]
+struct RenameReport {
+    from: String,
+    to: String,
+}
+
+// A conflicted path and the index stages it has (1 base, 2 ours, 3 theirs)
+#[derive(Serialize)]
+struct UnmergedReport {
+    path: String,
+    conflict: &'static str,
+    stages: Vec<StageReport>,
+}
+
+#[derive(Serialize)]
+struct StageReport {
+    stage: u8,
+    mode: String,
+    hash: String,
+}
+
+#[derive(Serialize)]
+struct Counts {
+    staged: usize,
+    deleted: usize,
+    renamed: usize,
+    modified: usize,
+    untracked: usize,
+    // Ignored untracked files, reported even without --ignored
+    ignored: usize,
+    unmerged: usize,
+}
+
+// `no_refresh` leaves the index untouched, so status works on a repository
+// that can't be written to. `find_renames` sets how similar a staged file
+// must be to a deleted one to be reported as a rename of it.
+pub fn status(
+    repo: &Repository,
+    ignored: Option<String>,
+    json: bool,
+    no_refresh: bool,
+    find_renames: Option<String>,
+) -> Result<()> {
+    let mode = match ignored.as_deref() {
+        None | Some("no") => IgnoredMode::No,
+        Some("traditional") => IgnoredMode::Traditional,
+        Some("matching") => IgnoredMode::Matching,
+        Some(other) => {
+            return Err(format!(
+                "Invalid ignored mode '{}' (expected traditional, matching or no)",
+                other
+            )
+            .into());
         }
+    };
 
-        let relative_path = path
-            .strip_prefix(&repo.work_dir)?
-            .to_string_lossy()
-            .replace('\\', "/");
-        working_files.insert(relative_path);
-    }
+    let current_branch = utils::head_branch(repo)?;
+    let detached_at = match current_branch {
+        Some(_) => None,
+        None => utils::get_head_commit(repo)?,
+    };
+
+    let mut index = utils::load_index(repo)?;
+
+    // Get all files in working directory, reusing cached directory listings
+    // when core.untrackedCache is enabled
+    let config = Config::load(repo)?;
+    let rename_options =
+        RenameOptions::new(&config, "status.renameLimit", find_renames.as_deref())?;
+    let use_cache = config.get_bool("core.untrackedCache").unwrap_or(false);
+    let mut working_files: HashSet<String> = if use_cache {
+        let mut cache = index.untracked_cache.take().unwrap_or_default();
+        let files = utils::list_working_files(repo, Some(&mut cache))?;
+        index.untracked_cache = Some(cache);
+        if !no_refresh {
+            utils::refresh_index(repo, &index)?;
+        }
+        files.into_iter().collect()
+    } else {
+        if index.untracked_cache.take().is_some() && !no_refresh {
+            utils::refresh_index(repo, &index)?;
+        }
+        utils::list_working_files(repo, None)?.into_iter().collect()
+    };
 
     // Check staged files
-    let mut staged_files = Vec::new();
+    let object_store = ObjectStore::new(repo);
+    let mut tracked_files = Vec::new();
     let mut modified_files = Vec::new();
+    let mut intent_files = HashSet::new();
 
     for (path, entry) in &index.entries {
-        staged_files.push(path.clone());
+        working_files.remove(path);
+        if index.unmerged.contains_key(path) {
+            continue;
+        }
+
+        // `add -N` paths have nothing staged; their content is all unstaged
+        if entry.intent_to_add {
+            intent_files.insert(path.clone());
+            modified_files.push(path.clone());
+            continue;
+        }
+        tracked_files.push(path.clone());
 
         // Check if file is modified
-        let file_path = repo.work_dir.join(path);
-        if file_path.exists() {
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if !entry.ignores_worktree()
+            && !entry.is_dir()
+            && file_path.exists()
+            && !utils::stat_matches(entry, &file_path)
+        {
             let content = fs::read(&file_path)?;
-            let current_hash = crate::object_store::ObjectStore::hash_content(&content);
+            let current_hash = object_store.hash_content(&content);
             if current_hash != entry.hash {
                 modified_files.push(path.clone());
             }
         }
+    }
+    tracked_files.sort();
+    modified_files.sort();
 
-        working_files.remove(path);
+    let head_tree = match utils::get_head_commit(repo)? {
+        Some(commit) => worktree::load_commit_tree(&object_store, &commit)?.entries,
+        None => HashMap::new(),
+    };
+    // Staged changes are index entries HEAD doesn't have, or has with other
+    // content or mode
+    let staged_files: Vec<String> = tracked_files
+        .iter()
+        .filter(|path| {
+            let entry = &index.entries[*path];
+            head_tree
+                .get(*path)
+                .is_none_or(|head| head.hash != entry.hash || head.mode != entry.mode)
+        })
+        .cloned()
+        .collect();
+    let mut deleted_files: Vec<String> = head_tree
+        .keys()
+        .filter(|path| !index.entries.contains_key(*path))
+        .cloned()
+        .collect();
+    deleted_files.sort();
+
+    // A deleted path whose content is staged, as it is or much the same,
+    // under a path HEAD doesn't have was renamed
+    let deleted: Vec<Candidate> = deleted_files
+        .iter()
+        .map(|path| Candidate {
+            path: path.clone(),
+            hash: head_tree[path].hash.clone(),
+        })
+        .collect();
+    let added: Vec<Candidate> = staged_files
+        .iter()
+        .filter(|path| !head_tree.contains_key(*path) && !index.entries[*path].is_dir())
+        .map(|path| Candidate {
+            path: path.clone(),
+            hash: index.entries[path].hash.clone(),
+        })
+        .collect();
+    let mut renamed_files: Vec<RenameReport> =
+        renames::detect(&deleted, &added, &rename_options, |candidate| {
+            Ok(object_store.load_blob(&candidate.hash)?.content)
+        })?
+        .into_iter()
+        .map(|rename| RenameReport {
+            from: rename.from,
+            to: rename.to,
+        })
+        .collect();
+    renamed_files.sort_by(|a, b| a.from.cmp(&b.from));
+    let renamed_from: HashSet<&str> = renamed_files.iter().map(|r| r.from.as_str()).collect();
+    deleted_files.retain(|path| !renamed_from.contains(path.as_str()));
+    let renamed_to: HashSet<&str> = renamed_files.iter().map(|r| r.to.as_str()).collect();
+
+    let rules = IgnoreRules::load(repo)?;
+    let (ignored_files, mut untracked_files): (Vec<String>, Vec<String>) = working_files
+        .into_iter()
+        .partition(|path| rules.is_ignored(path));
+    untracked_files.sort();
+
+    let ignored_report = (mode != IgnoredMode::No).then(|| {
+        report_ignored(
+            &rules,
+            mode,
+            &ignored_files,
+            &tracked_files,
+            &untracked_files,
+        )
+    });
+
+    let mut unmerged: Vec<(&String, &UnmergedEntry)> = index.unmerged.iter().collect();
+    unmerged.sort_by_key(|(path, _)| *path);
+
+    if json {
+        let unmerged = unmerged
+            .iter()
+            .map(|(path, entry)| UnmergedReport {
+                path: path.to_string(),
+                conflict: conflict_kind(entry),
+                stages: entry
+                    .stages()
+                    .map(|(stage, entry)| StageReport {
+                        stage,
+                        mode: entry.mode.clone(),
+                        hash: entry.hash.clone(),
+                    })
+                    .collect(),
+            })
+            .collect::<Vec<_>>();
+        let report = StatusReport {
+            branch: current_branch.unwrap_or("HEAD".to_string()),
+            detached_at,
+            counts: Counts {
+                staged: staged_files.len(),
+                deleted: deleted_files.len(),
+                renamed: renamed_files.len(),
+                modified: modified_files.len(),
+                untracked: untracked_files.len(),
+                ignored: ignored_files.len(),
+                unmerged: unmerged.len(),
+            },
+            staged: staged_files,
+            deleted: deleted_files,
+            renamed: renamed_files,
+            modified: modified_files,
+            untracked: untracked_files,
+            ignored: ignored_report,
+            unmerged,
+        };
+        println!("{}", serde_json::to_string_pretty(&report)?);
+        return Ok(());
+    }
+
+    match (&current_branch, &detached_at) {
+        (Some(branch), _) => println!("On branch {}", branch),
+        (None, Some(commit)) => println!("HEAD detached at {}", &commit[..8]),
+        (None, None) => println!("HEAD detached"),
+    }
+    // The command that finishes whatever stopped on conflicts
+    let rebasing = rebase::rebase_in_progress(repo)?;
+    let picking = cherry_pick::cherry_pick_head(repo)?;
+    let conclude = if rebasing.is_some() {
+        "mini_git rebase --continue"
+    } else if picking.is_some() {
+        "mini_git cherry-pick --continue"
+    } else if merge::merge_head(repo)?.is_some() {
+        "mini_git merge --continue"
+    } else {
+        "mini_git commit"
+    };
+    if let Some(branch) = &rebasing {
+        println!("You are currently rebasing branch '{}'.", branch);
+    }
+    let merging = merge::merge_head(repo)?.is_some();
+    if !unmerged.is_empty() {
+        println!("You have unmerged paths.");
+        println!("  (fix conflicts and run \"{}\")", conclude);
+        if merging {
+            println!("  (use \"mini_git merge --abort\" to abort the merge)");
+        }
+    } else if merging {
+        println!("All conflicts fixed but you are still merging.");
+        println!("  (use \"{}\" to conclude merge)", conclude);
+        println!("  (use \"mini_git merge --abort\" to abort the merge)");
+    } else if let Some(reverting) = revert::revert_head(repo)? {
+        println!("You are currently reverting commit {}.", &reverting[..8]);
+        println!("  (all conflicts fixed: run \"mini_git commit\")");
+    } else if let Some(picking) = picking {
+        println!("You are currently cherry-picking commit {}.", &picking[..8]);
+        println!("  (all conflicts fixed: run \"{}\")", conclude);
+    } else if rebasing.is_some() {
+        println!("  (all conflicts fixed: run \"{}\")", conclude);
     }
 
     // Print status
-    if !staged_files.is_empty() {
+    if !staged_files.is_empty() || !deleted_files.is_empty() {
         println!("\nChanges to be committed:");
-        for file in &staged_files {
-            println!("  new file:   {}", file);
+        for file in staged_files
+            .iter()
+            .filter(|f| !renamed_to.contains(f.as_str()))
+        {
+            if head_tree.contains_key(file) {
+                println!("  modified:   {}", repo_path::quote(file));
+            } else {
+                println!("  new file:   {}", repo_path::quote(file));
+            }
+        }
+        for rename in &renamed_files {
+            println!(
+                "  renamed:    {} -> {}",
+                repo_path::quote(&rename.from),
+                repo_path::quote(&rename.to)
+            );
+        }
+        for file in &deleted_files {
+            println!("  deleted:    {}", repo_path::quote(file));
+        }
+    }
+
+    if !unmerged.is_empty() {
+        println!("\nUnmerged paths:");
+        println!("  (use \"mini_git add <file>...\" to mark resolution)");
+        for (path, entry) in &unmerged {
+            println!(
+                "  {:<17}{}",
+                format!("{}:", conflict_kind(entry)),
+                repo_path::quote(path)
+            );
         }
     }
 
     if !modified_files.is_empty() {
         println!("\nChanges not staged for commit:");
         for file in &modified_files {
-            println!("  modified:   {}", file);
+            if intent_files.contains(file) {
+                println!("  new file:   {}", repo_path::quote(file));
+            } else {
+                println!("  modified:   {}", repo_path::quote(file));
+            }
         }
     }
 
-    if !working_files.is_empty() {
+    if !untracked_files.is_empty() {
         println!("\nUntracked files:");
-        for file in &working_files {
-            println!("  {}", file);
+        for file in &untracked_files {
+            println!("  {}", repo_path::quote(file));
         }
     }
 
-    if staged_files.is_empty() && modified_files.is_empty() && working_files.is_empty() {
+    if let Some(ignored) = ignored_report.filter(|ignored| !ignored.is_empty()) {
+        println!("\nIgnored files:");
+        for path in &ignored {
+            println!("  {}", repo_path::quote(path));
+        }
+    }
+
+    if staged_files.is_empty()
+        && deleted_files.is_empty()
+        && modified_files.is_empty()
+        && untracked_files.is_empty()
+        && unmerged.is_empty()
+    {
         println!("nothing to commit, working tree clean");
     }
 
     Ok(())
 }
+
+fn conflict_kind(entry: &UnmergedEntry) -> &'static str {
+    match (&entry.base, &entry.ours, &entry.theirs) {
+        (None, Some(_), Some(_)) => "both added",
+        (_, Some(_), Some(_)) => "both modified",
+        (_, Some(_), None) => "deleted by them",
+        (_, None, Some(_)) => "deleted by us",
+        _ => "both deleted",
+    }
+}
+
+// The paths `--ignored` lists for `ignored` untracked files, directories
+// with a trailing `/`
+fn report_ignored(
+    rules: &IgnoreRules,
+    mode: IgnoredMode,
+    ignored: &[String],
+    tracked: &[String],
+    untracked: &[String],
+) -> Vec<String> {
+    // A directory can only be reported as a whole if nothing inside it is
+    // tracked (or, traditionally, shown as untracked)
+    let mut occupied: HashSet<&str> = tracked.iter().flat_map(|p| parent_dirs(p)).collect();
+    if mode == IgnoredMode::Traditional {
+        occupied.extend(untracked.iter().flat_map(|p| parent_dirs(p)));
+    }
+
+    let mut report = BTreeSet::new();
+    for path in ignored {
+        let dir = match mode {
+            IgnoredMode::Traditional => parent_dirs(path).find(|dir| !occupied.contains(dir)),
+            _ => rules
+                .ignored_parent(path)
+                .filter(|dir| !occupied.contains(dir)),
+        };
+        report.insert(match dir {
+            Some(dir) => format!("{}/", dir),
+            None => path.clone(),
+        });
+    }
+    report.into_iter().collect()
+}
+
+// `a/b/c` -> `a`, `a/b`
+fn parent_dirs(path: &str) -> impl Iterator<Item = &str> {
+    path.match_indices('/').map(move |(i, _)| &path[..i])
+}
@@ -0,0 +1,106 @@
+use crate::{Repository, Result, Tag, config::Config, object_store::ObjectStore, utils};
+use chrono::Utc;
+use std::fs;
+
+pub struct TagOptions {
+    pub annotate: bool,
+    pub message: Option<String>,
+    pub delete: bool,
+    pub force: bool,
+    // List with the first line of each message
+    pub lines: bool,
+}
+
+// Tags live in refs/tags/<name>. A lightweight tag holds a commit id; an
+// annotated one (`-a`, or any tag given `-m`) holds the id of a tag object
+// recording who tagged what, when and why.
+pub fn tag(
+    repo: &Repository,
+    name: Option<String>,
+    target: Option<String>,
+    options: TagOptions,
+) -> Result<()> {
+    let Some(name) = name else {
+        return list_tags(repo, options.lines);
+    };
+    utils::validate_ref_name("tag", &name)?;
+    let tag_path = repo.git_dir.join("refs").join("tags").join(&name);
+
+    if options.delete {
+        if !tag_path.is_file() {
+            return Err(format!("tag '{}' not found", name).into());
+        }
+        let old = fs::read_to_string(&tag_path)?.trim().to_string();
+        fs::remove_file(&tag_path)?;
+        println!(
+            "Deleted tag '{}' (was {})",
+            name,
+            old.get(..8).unwrap_or(&old)
+        );
+        return Ok(());
+    }
+
+    if tag_path.exists() && !options.force {
+        return Err(format!("tag '{}' already exists", name).into());
+    }
+
+    let object_store = ObjectStore::new(repo);
+    let target = target.unwrap_or_else(|| "HEAD".to_string());
+    let commit = utils::resolve_revision(repo, &target)?;
+    object_store
+        .load_commit(&commit)
+        .map_err(|_| format!("'{}' is not a commit", target))?;
+
+    let id = if options.annotate || options.message.is_some() {
+        let message = options
+            .message
+            .ok_or("Annotated tags need a message (-m)")?;
+        let mut tag = Tag {
+            hash: String::new(),
+            object: commit.clone(),
+            object_type: "commit".to_string(),
+            name: name.clone(),
+            tagger: Config::load(repo)?.author_ident(),
+            message: format!("{}\n", message.trim_end()),
+            timestamp: Utc::now(),
+        };
+        object_store.store_tag(&mut tag)?
+    } else {
+        commit.clone()
+    };
+
+    fs::create_dir_all(tag_path.parent().unwrap())?;
+    fs::write(&tag_path, &id)?;
+    if id == commit {
+        println!("Created tag '{}' at {}", name, &commit[..8]);
+    } else {
+        println!(
+            "Created annotated tag '{}' ({}) at {}",
+            name,
+            &id[..8],
+            &commit[..8]
+        );
+    }
+    Ok(())
+}
+
+// Tag names in order; `lines` adds the first line of each annotation, or of
+// the tagged commit's message for lightweight tags
+fn list_tags(repo: &Repository, lines: bool) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    for (name, hash) in utils::list_refs(repo, "refs/tags/")? {
+        let name = &name["refs/tags/".len()..];
+        if !lines {
+            println!("{}", name);
+            continue;
+        }
+
+        let message = match object_store.read_typed(&hash)?.0.as_str() {
+            "tag" => object_store.load_tag(&hash)?.message,
+            "commit" => object_store.load_commit(&hash)?.message,
+            _ => String::new(),
+        };
+        println!("{:<15} {}", name, message.lines().next().unwrap_or(""));
+    }
+    Ok(())
+}
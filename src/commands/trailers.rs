@@ -0,0 +1,89 @@
+use crate::{Repository, Result, object_store::ObjectStore, utils};
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct CommitTrailers {
+    commit: String,
+    subject: String,
+    trailers: Vec<Trailer>,
+}
+
+#[derive(Serialize)]
+struct Trailer {
+    key: String,
+    value: String,
+}
+
+// Extract trailers from every commit in `range`, optionally only those named
+// `key`. With `require`, commits lacking a matching trailer are an error.
+pub fn trailers(
+    repo: &Repository,
+    range: Option<String>,
+    key: Option<String>,
+    json: bool,
+    require: bool,
+) -> Result<()> {
+    let range = range.unwrap_or_else(|| "HEAD".to_string());
+    let object_store = ObjectStore::new(repo);
+
+    let mut results = Vec::new();
+    for hash in utils::commit_range(repo, &range)? {
+        let commit = object_store.load_commit(&hash)?;
+        let trailers: Vec<Trailer> = utils::parse_trailers(&commit.message)
+            .into_iter()
+            .filter(|(k, _)| key.as_ref().is_none_or(|key| k.eq_ignore_ascii_case(key)))
+            .map(|(key, value)| Trailer { key, value })
+            .collect();
+
+        results.push(CommitTrailers {
+            subject: commit.message.lines().next().unwrap_or("").to_string(),
+            commit: hash,
+            trailers,
+        });
+    }
+
+    let missing: Vec<&CommitTrailers> = results.iter().filter(|c| c.trailers.is_empty()).collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    } else {
+        for commit in &results {
+            for trailer in &commit.trailers {
+                println!("{} {}: {}", &commit.commit[..8], trailer.key, trailer.value);
+            }
+        }
+
+        // Totals per trailer value, e.g. how many commits each reviewer signed off
+        let mut totals: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+        for trailer in results.iter().flat_map(|c| &c.trailers) {
+            *totals
+                .entry((trailer.key.as_str(), trailer.value.as_str()))
+                .or_default() += 1;
+        }
+        if !totals.is_empty() {
+            println!();
+            for ((key, value), count) in totals {
+                println!("{:>6}  {}: {}", count, key, value);
+            }
+        }
+    }
+
+    if require && !missing.is_empty() {
+        for commit in &missing {
+            eprintln!(
+                "{} {}: no matching trailer",
+                &commit.commit[..8],
+                commit.subject
+            );
+        }
+        return Err(format!(
+            "{} of {} commit(s) lack a {} trailer",
+            missing.len(),
+            results.len(),
+            key.as_deref().unwrap_or("matching")
+        )
+        .into());
+    }
+    Ok(())
+}
@@ -0,0 +1,119 @@
+use crate::{Repository, Result, journal, object_store::ObjectStore, utils, worktree};
+use std::fs;
+
+pub fn undo(repo: &Repository, list: bool) -> Result<()> {
+    if list {
+        return list_operations(repo);
+    }
+
+    let entries = journal::entries(repo)?;
+    let entry = entries.last().ok_or("Nothing to undo")?;
+
+    // Refuse if anything moved since the operation was recorded
+    for update in &entry.updates {
+        let current = read_ref(repo, &update.name)?;
+        if current != update.new {
+            return Err(format!(
+                "Cannot undo {}: {} has changed since ({} now)",
+                entry.operation,
+                update.name,
+                short(current.as_deref())
+            )
+            .into());
+        }
+    }
+
+    let head_ref = utils::head_ref(repo)?;
+    let head_update = entry.updates.iter().find(|u| u.name == head_ref);
+
+    // Undoing a commit keeps its changes staged; anything else that moved the
+    // checked-out branch also restores the working tree
+    let object_store = ObjectStore::new(repo);
+    let restore_tree = match head_update {
+        Some(update) if entry.operation != "commit" => match &update.old {
+            Some(old) => {
+                let tree = worktree::load_commit_tree(&object_store, old)?;
+                worktree::check_checkout_safety(repo, &tree)?;
+                Some(tree)
+            }
+            None => None,
+        },
+        _ => None,
+    };
+
+    println!("Undoing {}: {}", entry.operation, entry.description);
+    for update in &entry.updates {
+        match &update.old {
+            Some(old) => {
+                let path = repo.git_dir.join(&update.name);
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(path, old)?;
+            }
+            None => {
+                let path = repo.git_dir.join(&update.name);
+                if path.exists() {
+                    fs::remove_file(path)?;
+                }
+            }
+        }
+        let reverted = journal::RefUpdate {
+            name: update.name.clone(),
+            old: update.new.clone(),
+            new: update.old.clone(),
+        };
+        journal::log_update(
+            repo,
+            &reverted,
+            &format!("undo: {}: {}", entry.operation, entry.description),
+        )?;
+        println!(
+            "  {}: {} -> {}",
+            update.name,
+            short(update.new.as_deref()),
+            short(update.old.as_deref())
+        );
+    }
+
+    if let Some(tree) = restore_tree {
+        worktree::switch_to_tree(repo, &object_store, &tree)?;
+        println!("Working tree restored");
+    }
+
+    journal::pop(repo)?;
+    Ok(())
+}
+
+fn list_operations(repo: &Repository) -> Result<()> {
+    let entries = journal::entries(repo)?;
+    if entries.is_empty() {
+        println!("No undoable operations");
+        return Ok(());
+    }
+
+    for (i, entry) in entries.iter().rev().enumerate().take(20) {
+        println!(
+            "{}: {} {}: {}",
+            i,
+            entry.timestamp.format("%Y-%m-%d %H:%M:%S"),
+            entry.operation,
+            entry.description
+        );
+    }
+    Ok(())
+}
+
+fn read_ref(repo: &Repository, name: &str) -> Result<Option<String>> {
+    let path = repo.git_dir.join(name);
+    if path.is_file() {
+        Ok(Some(fs::read_to_string(path)?.trim().to_string()))
+    } else {
+        Ok(None)
+    }
+}
+
+fn short(hash: Option<&str>) -> &str {
+    match hash {
+        Some(hash) => &hash[..hash.len().min(8)],
+        None => "(none)",
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{
+    IndexEntry, Repository, Result, commands::add::add_file_to_index, object_store::ObjectStore,
+    repo_path, utils,
+};
+use std::fs;
+
+pub struct UpdateIndexOptions {
+    pub add: bool,
+    pub remove: bool,
+    pub force_remove: bool,
+    pub refresh: bool,
+    pub cacheinfo: Option<Vec<String>>,
+    // Some(true) sets the flag on `files`, Some(false) clears it
+    pub assume_unchanged: Option<bool>,
+    pub skip_worktree: Option<bool>,
+}
+
+pub fn update_index(
+    repo: &Repository,
+    files: Vec<String>,
+    options: UpdateIndexOptions,
+) -> Result<()> {
+    let files = repo_path::precompose_args(repo, files)?;
+    let mut index = utils::lock_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+
+    if let Some(cacheinfo) = options.cacheinfo {
+        let [mode, hash, path] = <[String; 3]>::try_from(cacheinfo)
+            .map_err(|_| "--cacheinfo requires <mode> <hash> <path>")?;
+
+        if u32::from_str_radix(&mode, 8).is_err() {
+            return Err(format!("Invalid file mode '{}'", mode).into());
+        }
+        if !object_store.object_exists(&hash) {
+            return Err(format!("Object {} does not exist", hash).into());
+        }
+        if !options.add && !index.entries.contains_key(&path) {
+            return Err(
+                format!("{}: cannot add to the index - missing --add option?", path).into(),
+            );
+        }
+
+        index.entries.insert(
+            path.clone(),
+            IndexEntry {
+                hash,
+                mode,
+                path,
+                ..Default::default()
+            },
+        );
+    }
+
+    // Flag changes only touch the entries, never their content
+    if options.assume_unchanged.is_some() || options.skip_worktree.is_some() {
+        for path in &files {
+            let entry = index
+                .entries
+                .get_mut(path)
+                .ok_or(format!("{}: not in the index", path))?;
+            if let Some(value) = options.assume_unchanged {
+                entry.assume_unchanged = value;
+            }
+            if let Some(value) = options.skip_worktree {
+                entry.skip_worktree = value;
+            }
+        }
+        return utils::save_locked_index(repo, index);
+    }
+
+    for path in files {
+        let file_path = repo_path::to_path(&repo.work_dir, &path);
+
+        if options.force_remove {
+            index.entries.remove(&path);
+            continue;
+        }
+
+        if !file_path.is_file() {
+            if options.remove {
+                index.entries.remove(&path);
+                continue;
+            }
+            return Err(format!("{}: does not exist and --remove not passed", path).into());
+        }
+
+        if !options.add && !index.entries.contains_key(&path) {
+            return Err(
+                format!("{}: cannot add to the index - missing --add option?", path).into(),
+            );
+        }
+
+        add_file_to_index(&mut index, &object_store, &file_path, &path)?;
+    }
+
+    let mut needs_update = Vec::new();
+    if options.refresh {
+        needs_update = refresh_index(repo, &mut index)?;
+    }
+
+    utils::save_locked_index(repo, index)?;
+
+    if !needs_update.is_empty() {
+        for path in &needs_update {
+            println!("{}: needs update", path);
+        }
+        return Err(format!("{} file(s) need updating", needs_update.len()).into());
+    }
+
+    Ok(())
+}
+
+// Re-record stat data for entries whose content still matches the index,
+// returning the paths whose content has actually changed
+fn refresh_index(repo: &Repository, index: &mut crate::Index) -> Result<Vec<String>> {
+    let object_store = ObjectStore::new(repo);
+    let mut needs_update = Vec::new();
+
+    for (path, entry) in index.entries.iter_mut() {
+        if entry.ignores_worktree() {
+            continue;
+        }
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if !file_path.is_file() {
+            needs_update.push(path.clone());
+            continue;
+        }
+        if utils::stat_matches(entry, &file_path) {
+            continue;
+        }
+
+        let content = fs::read(&file_path)?;
+        if object_store.hash_content(&content) == entry.hash {
+            utils::record_stat(entry, &file_path)?;
+        } else {
+            needs_update.push(path.clone());
+        }
+    }
+
+    needs_update.sort();
+    Ok(needs_update)
+}
@@ -0,0 +1,52 @@
+use crate::{Repository, Result, config::Config};
+
+const VARIABLES: &[&str] = &[
+    "MINI_GIT_AUTHOR_IDENT",
+    "MINI_GIT_COMMITTER_IDENT",
+    "MINI_GIT_EDITOR",
+    "MINI_GIT_PAGER",
+];
+
+// Print the value mini_git itself would use for `name` after resolving the
+// environment and config, or every variable with `list`. Outside a
+// repository only the global config applies.
+pub fn var(repo: Option<&Repository>, name: Option<String>, list: bool) -> Result<()> {
+    let config = match repo {
+        Some(repo) => Config::load(repo)?,
+        None => Config::load_global()?,
+    };
+
+    if list {
+        for name in VARIABLES {
+            println!("{}={}", name, value(&config, name).unwrap_or_default());
+        }
+        return Ok(());
+    }
+
+    let name = name.ok_or("Specify a variable, or use -l to list them all")?;
+    let value = value(&config, &name).ok_or(format!(
+        "Unknown variable '{}' (expected one of {})",
+        name,
+        VARIABLES.join(", ")
+    ))?;
+    println!("{}", value);
+    Ok(())
+}
+
+fn value(config: &Config, name: &str) -> Option<String> {
+    match name {
+        // Identities carry the current time, in the same form as the HEAD log
+        "MINI_GIT_AUTHOR_IDENT" | "MINI_GIT_COMMITTER_IDENT" => {
+            let now = chrono::Local::now();
+            Some(format!(
+                "{} {} {}",
+                config.author_ident(),
+                now.timestamp(),
+                now.format("%z")
+            ))
+        }
+        "MINI_GIT_EDITOR" => Some(config.editor()),
+        "MINI_GIT_PAGER" => Some(config.pager()),
+        _ => None,
+    }
+}
@@ -0,0 +1,139 @@
+use crate::{
+    Commit, Repository, Result, Tree, config::Config, object_store::ObjectStore, utils, worktree,
+};
+use std::collections::{BTreeSet, HashMap};
+
+pub struct VerifyHistoryOptions {
+    pub no_merges: bool,
+    pub signed_off: bool,
+    pub conventional: bool,
+    pub forbidden_paths: Vec<String>,
+}
+
+const CONVENTIONAL_TYPES: &[&str] = &[
+    "build", "chore", "ci", "docs", "feat", "fix", "perf", "refactor", "revert", "style", "test",
+];
+
+// Check every commit in `range` against the selected policies. Policies not
+// given on the command line fall back to the `history.*` config keys, so a
+// hook can simply run `mini_git verify-history <range>`.
+pub fn verify_history(
+    repo: &Repository,
+    range: Option<String>,
+    options: VerifyHistoryOptions,
+) -> Result<()> {
+    let config = Config::load(repo)?;
+    let no_merges = options.no_merges || config.get_bool("history.noMerges").unwrap_or(false);
+    let signed_off =
+        options.signed_off || config.get_bool("history.requireSignoff").unwrap_or(false);
+    let conventional =
+        options.conventional || config.get_bool("history.conventional").unwrap_or(false);
+    let mut forbidden_paths = options.forbidden_paths;
+    forbidden_paths.extend(
+        config
+            .get_all("history.forbiddenPath")
+            .into_iter()
+            .map(String::from),
+    );
+
+    if !no_merges && !signed_off && !conventional && forbidden_paths.is_empty() {
+        return Err("No history checks selected".into());
+    }
+
+    let range = range.unwrap_or_else(|| "HEAD".to_string());
+    let object_store = ObjectStore::new(repo);
+    let commits = utils::commit_range(repo, &range)?;
+
+    let mut violations = 0;
+    for hash in &commits {
+        let commit = object_store.load_commit(hash)?;
+        let subject = commit.message.lines().next().unwrap_or("");
+        let mut problems = Vec::new();
+
+        // Merges made before commits kept every parent only show by subject
+        if no_merges && (commit.parents.len() > 1 || subject.starts_with("Merge ")) {
+            problems.push("merge commit".to_string());
+        }
+        if signed_off
+            && !utils::parse_trailers(&commit.message)
+                .iter()
+                .any(|(key, _)| key.eq_ignore_ascii_case("Signed-off-by"))
+        {
+            problems.push("missing Signed-off-by trailer".to_string());
+        }
+        if conventional && !is_conventional(subject) {
+            problems.push("subject is not a conventional commit".to_string());
+        }
+        if !forbidden_paths.is_empty() {
+            for path in changed_paths(&object_store, &commit)? {
+                if forbidden_paths.iter().any(|p| path_matches(p, &path)) {
+                    problems.push(format!("touches forbidden path {}", path));
+                }
+            }
+        }
+
+        for problem in &problems {
+            println!("{} {}: {}", &hash[..8], subject, problem);
+        }
+        violations += problems.len();
+    }
+
+    if violations > 0 {
+        return Err(format!(
+            "{} policy violation(s) in {} commit(s)",
+            violations,
+            commits.len()
+        )
+        .into());
+    }
+    println!("{} commit(s) in {} pass all checks", commits.len(), range);
+    Ok(())
+}
+
+// `type(scope)!: description`
+fn is_conventional(subject: &str) -> bool {
+    let Some((prefix, description)) = subject.split_once(": ") else {
+        return false;
+    };
+    let prefix = prefix.strip_suffix('!').unwrap_or(prefix);
+    let kind = match prefix.split_once('(') {
+        Some((kind, scope)) => match scope.strip_suffix(')') {
+            Some(scope) if !scope.is_empty() => kind,
+            _ => return false,
+        },
+        None => prefix,
+    };
+    CONVENTIONAL_TYPES.contains(&kind) && !description.trim().is_empty()
+}
+
+fn changed_paths(object_store: &ObjectStore, commit: &Commit) -> Result<Vec<String>> {
+    let tree = object_store.load_tree(&commit.tree)?;
+    let parent_tree = match commit.parent() {
+        Some(parent) => worktree::load_commit_tree(object_store, parent)?,
+        None => Tree {
+            hash: String::new(),
+            entries: HashMap::new(),
+        },
+    };
+
+    let paths: BTreeSet<&String> = tree
+        .entries
+        .keys()
+        .chain(parent_tree.entries.keys())
+        .collect();
+    Ok(paths
+        .into_iter()
+        .filter(|path| {
+            tree.entries.get(*path).map(|e| &e.hash)
+                != parent_tree.entries.get(*path).map(|e| &e.hash)
+        })
+        .cloned()
+        .collect())
+}
+
+// A glob, or a directory prefix such as `vendor/`
+fn path_matches(pattern: &str, path: &str) -> bool {
+    let dir = pattern.trim_end_matches('/');
+    path.starts_with(&format!("{}/", dir))
+        || crate::config::glob_match(pattern.as_bytes(), path.as_bytes())
+}
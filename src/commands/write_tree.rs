@@ -0,0 +1,16 @@
+use crate::{Repository, Result, object_store::ObjectStore, utils, worktree::tree_from_index};
+
+pub fn write_tree(repo: &Repository) -> Result<()> {
+    let index = utils::load_index(repo)?;
+    let object_store = ObjectStore::new(repo);
+
+    for (path, entry) in &index.entries {
+        if !object_store.object_exists(&entry.hash) {
+            return Err(format!("Invalid object {} for '{}'", entry.hash, path).into());
+        }
+    }
+
+    let tree = tree_from_index(&object_store, &index)?;
+    println!("{}", tree.hash);
+    Ok(())
+}
@@ -0,0 +1,322 @@
+use crate::{Result, hash::HashAlgorithm, index::hex_to_bytes, packfile::read_u32};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// A cache of the commit history in objects/info/commit-graph, so walks can
+// follow parents without loading and decoding every commit object. It uses
+// git's version 1 layout, which git reads too:
+//
+//   CGPH <version: u8 = 1> <hash version: u8> <chunk count: u8> <base graphs: u8 = 0>
+//   <chunk table: (id: 4 bytes, offset: u64) per chunk, then (0, end)>
+//   OIDF  fanout: 256 x u32, commits whose id starts with a byte <= i
+//   OIDL  raw ids, sorted
+//   CDAT  per commit: <root tree id> <parent 1: u32> <parent 2: u32>
+//         <generation: 30 bits, commit time: 34 bits>
+//   EDGE  parents after the first of each octopus merge
+//   <checksum of everything above>
+//
+// Parents are positions in OIDL. A missing parent is NO_PARENT; a second
+// parent with the EDGE_LIST bit set is where that commit's list starts in
+// EDGE instead, its last entry flagged with LAST_EDGE. The generation is the
+// length of the longest line of history down to a root commit, roots being 1.
+
+const SIGNATURE: &[u8] = b"CGPH";
+const VERSION: u8 = 1;
+const OIDF: &[u8; 4] = b"OIDF";
+const OIDL: &[u8; 4] = b"OIDL";
+const CDAT: &[u8; 4] = b"CDAT";
+const EDGE: &[u8; 4] = b"EDGE";
+const NO_PARENT: u32 = 0x7000_0000;
+const EDGE_LIST: u32 = 0x8000_0000;
+const LAST_EDGE: u32 = 0x8000_0000;
+const MAX_GENERATION: u32 = 0x3fff_ffff;
+const MAX_TIME: i64 = (1 << 34) - 1;
+
+pub fn path(objects_dir: &Path) -> PathBuf {
+    objects_dir.join("info").join("commit-graph")
+}
+
+fn hash_version(algorithm: HashAlgorithm) -> u8 {
+    match algorithm {
+        HashAlgorithm::Sha1 => 1,
+        HashAlgorithm::Sha256 => 2,
+    }
+}
+
+// A commit as the graph records it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GraphCommit {
+    pub hash: String,
+    pub tree: String,
+    pub parents: Vec<String>,
+    pub time: i64,
+}
+
+pub struct CommitGraphFile {
+    raw_len: usize,
+    // Sorted raw ids, `raw_len` bytes each
+    ids: Vec<u8>,
+    commit_data: Vec<u8>,
+    edges: Vec<u8>,
+}
+
+impl CommitGraphFile {
+    // Structural problems are errors; whether the data matches the commits
+    // it describes is for `commit-graph verify` to check
+    pub fn open(path: &Path, algorithm: HashAlgorithm) -> Result<Self> {
+        let data = fs::read(path)?;
+        let invalid = |reason: &str| format!("Invalid commit-graph {}: {}", path.display(), reason);
+        let raw_len = algorithm.raw_len();
+
+        if data.len() < 8 + raw_len || &data[..4] != SIGNATURE {
+            return Err(invalid("bad signature").into());
+        }
+        if data[4] != VERSION {
+            return Err(invalid("unsupported version").into());
+        }
+        if data[5] != hash_version(algorithm) {
+            return Err(invalid("written for another object format").into());
+        }
+        let (body, checksum) = data.split_at(data.len() - raw_len);
+        if hex_to_bytes(&algorithm.hash(body))? != checksum {
+            return Err(invalid("checksum mismatch").into());
+        }
+
+        let chunk_count = data[6] as usize;
+        let mut chunks: HashMap<[u8; 4], (usize, usize)> = HashMap::new();
+        for i in 0..chunk_count {
+            let at = 8 + i * 12;
+            let entry = body
+                .get(at..at + 24)
+                .ok_or_else(|| invalid("truncated chunk table"))?;
+            let start = u64::from_be_bytes(entry[4..12].try_into().unwrap()) as usize;
+            let end = u64::from_be_bytes(entry[16..24].try_into().unwrap()) as usize;
+            if start > end || end > body.len() {
+                return Err(invalid("chunk out of range").into());
+            }
+            chunks.insert(entry[..4].try_into().unwrap(), (start, end));
+        }
+        let chunk = |id: &[u8; 4]| chunks.get(id).map(|&(start, end)| &body[start..end]);
+
+        let fanout = chunk(OIDF).ok_or_else(|| invalid("no fanout chunk"))?;
+        if fanout.len() != 256 * 4 {
+            return Err(invalid("bad fanout chunk").into());
+        }
+        let count = u32::from_be_bytes(fanout[255 * 4..].try_into().unwrap()) as usize;
+        let ids = chunk(OIDL).ok_or_else(|| invalid("no id chunk"))?;
+        let commit_data = chunk(CDAT).ok_or_else(|| invalid("no commit data chunk"))?;
+        if ids.len() != count * raw_len || commit_data.len() != count * (raw_len + 16) {
+            return Err(invalid("chunk sizes don't match the commit count").into());
+        }
+
+        Ok(Self {
+            raw_len,
+            ids: ids.to_vec(),
+            commit_data: commit_data.to_vec(),
+            edges: chunk(EDGE).unwrap_or_default().to_vec(),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len() / self.raw_len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn id(&self, position: usize) -> &[u8] {
+        &self.ids[position * self.raw_len..(position + 1) * self.raw_len]
+    }
+
+    // Position of the commit with raw id `id`
+    pub fn find(&self, id: &[u8]) -> Option<usize> {
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.id(mid).cmp(id) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    fn data(&self, position: usize) -> &[u8] {
+        let size = self.raw_len + 16;
+        &self.commit_data[position * size..(position + 1) * size]
+    }
+
+    pub fn tree(&self, position: usize) -> &[u8] {
+        &self.data(position)[..self.raw_len]
+    }
+
+    // Positions of the parents of the commit at `position`, first parent
+    // first; None if they point outside the graph
+    pub fn parents(&self, position: usize) -> Option<Vec<usize>> {
+        let data = &self.data(position)[self.raw_len..];
+        let first = read_u32(data, 0);
+        let second = read_u32(data, 4);
+        let mut parents = Vec::new();
+        if first != NO_PARENT {
+            parents.push(first as usize);
+        }
+        if second & EDGE_LIST != 0 {
+            let mut at = (second & !EDGE_LIST) as usize;
+            loop {
+                let edge = u32::from_be_bytes(self.edges.get(at * 4..at * 4 + 4)?.try_into().ok()?);
+                parents.push((edge & !LAST_EDGE) as usize);
+                if edge & LAST_EDGE != 0 {
+                    break;
+                }
+                at += 1;
+            }
+        } else if second != NO_PARENT {
+            parents.push(second as usize);
+        }
+        parents
+            .iter()
+            .all(|&parent| parent < self.len())
+            .then_some(parents)
+    }
+
+    pub fn generation(&self, position: usize) -> u32 {
+        read_u32(&self.data(position)[self.raw_len..], 8) >> 2
+    }
+
+    pub fn time(&self, position: usize) -> i64 {
+        let data = &self.data(position)[self.raw_len..];
+        (((read_u32(data, 8) & 0x3) as i64) << 32) | read_u32(data, 12) as i64
+    }
+}
+
+// Write the graph of `commits` to `objects_dir`/info/commit-graph, replacing
+// any earlier one. Every parent must be among `commits`.
+pub fn write(objects_dir: &Path, algorithm: HashAlgorithm, commits: &[GraphCommit]) -> Result<()> {
+    let mut sorted: Vec<&GraphCommit> = commits.iter().collect();
+    sorted.sort_by(|a, b| a.hash.cmp(&b.hash));
+    sorted.dedup_by(|a, b| a.hash == b.hash);
+    let positions: HashMap<&str, u32> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, commit)| (commit.hash.as_str(), i as u32))
+        .collect();
+    let position = |hash: &str| {
+        positions
+            .get(hash)
+            .copied()
+            .ok_or(format!("Parent {} is not in the commit-graph", hash))
+    };
+    let generations = generations(&sorted, &positions)?;
+
+    let mut fanout = Vec::with_capacity(256 * 4);
+    let mut counts = [0u32; 256];
+    let mut ids = Vec::new();
+    for commit in &sorted {
+        let raw = hex_to_bytes(&commit.hash)?;
+        counts[raw[0] as usize] += 1;
+        ids.extend(raw);
+    }
+    let mut total = 0;
+    for count in counts {
+        total += count;
+        fanout.extend_from_slice(&total.to_be_bytes());
+    }
+
+    let mut commit_data = Vec::new();
+    let mut edges: Vec<u8> = Vec::new();
+    for (commit, generation) in sorted.iter().zip(&generations) {
+        commit_data.extend(hex_to_bytes(&commit.tree)?);
+        let first = match commit.parents.first() {
+            Some(parent) => position(parent)?,
+            None => NO_PARENT,
+        };
+        let second = match &commit.parents[..] {
+            [] | [_] => NO_PARENT,
+            [_, parent] => position(parent)?,
+            [_, rest @ ..] => {
+                let start = (edges.len() / 4) as u32 | EDGE_LIST;
+                for (i, parent) in rest.iter().enumerate() {
+                    let mut edge = position(parent)?;
+                    if i == rest.len() - 1 {
+                        edge |= LAST_EDGE;
+                    }
+                    edges.extend_from_slice(&edge.to_be_bytes());
+                }
+                start
+            }
+        };
+        commit_data.extend_from_slice(&first.to_be_bytes());
+        commit_data.extend_from_slice(&second.to_be_bytes());
+        let time = commit.time.clamp(0, MAX_TIME);
+        let high = (generation << 2) | (time >> 32) as u32;
+        commit_data.extend_from_slice(&high.to_be_bytes());
+        commit_data.extend_from_slice(&(time as u32).to_be_bytes());
+    }
+
+    let mut chunks: Vec<(&[u8; 4], Vec<u8>)> =
+        vec![(OIDF, fanout), (OIDL, ids), (CDAT, commit_data)];
+    if !edges.is_empty() {
+        chunks.push((EDGE, edges));
+    }
+
+    let mut graph = SIGNATURE.to_vec();
+    graph.extend_from_slice(&[VERSION, hash_version(algorithm), chunks.len() as u8, 0]);
+    let mut offset = (graph.len() + (chunks.len() + 1) * 12) as u64;
+    for (id, content) in &chunks {
+        graph.extend_from_slice(*id);
+        graph.extend_from_slice(&offset.to_be_bytes());
+        offset += content.len() as u64;
+    }
+    graph.extend_from_slice(&[0; 4]);
+    graph.extend_from_slice(&offset.to_be_bytes());
+    for (_, content) in &chunks {
+        graph.extend_from_slice(content);
+    }
+    let checksum = hex_to_bytes(&algorithm.hash(&graph))?;
+    graph.extend(checksum);
+
+    let path = path(objects_dir);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, &graph)?;
+    fs::rename(&tmp, &path)?;
+    Ok(())
+}
+
+// The generation of each of the `sorted` commits, worked out parents first
+// without recursing, since histories can be deep
+fn generations(sorted: &[&GraphCommit], positions: &HashMap<&str, u32>) -> Result<Vec<u32>> {
+    let mut generations = vec![0u32; sorted.len()];
+    for start in 0..sorted.len() {
+        let mut pending = vec![start];
+        while let Some(&current) = pending.last() {
+            if generations[current] != 0 {
+                pending.pop();
+                continue;
+            }
+            let mut highest = 0;
+            let mut waiting = false;
+            for parent in &sorted[current].parents {
+                let parent = *positions
+                    .get(parent.as_str())
+                    .ok_or(format!("Parent {} is not in the commit-graph", parent))?
+                    as usize;
+                match generations[parent] {
+                    0 => {
+                        pending.push(parent);
+                        waiting = true;
+                    }
+                    generation => highest = highest.max(generation),
+                }
+            }
+            if !waiting {
+                generations[current] = (highest + 1).min(MAX_GENERATION);
+                pending.pop();
+            }
+        }
+    }
+    Ok(generations)
+}
@@ -0,0 +1,370 @@
+use crate::{Repository, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAX_INCLUDE_DEPTH: usize = 10;
+
+#[derive(Debug, Clone)]
+pub struct ConfigEntry {
+    pub section: String,
+    pub subsection: Option<String>,
+    pub key: String,
+    pub value: String,
+    // File the entry was read from, if any
+    pub origin: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub entries: Vec<ConfigEntry>,
+}
+
+impl Config {
+    // Effective configuration: the global file first, then the repository's
+    // own config, so repository values win
+    pub fn load(repo: &Repository) -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = global_config_path() {
+            config.read_file(&path, Some(&repo.git_dir), 0)?;
+        }
+        config.read_file(&repo.git_dir.join("config"), Some(&repo.git_dir), 0)?;
+        Ok(config)
+    }
+
+    pub fn load_global() -> Result<Config> {
+        let mut config = Config::default();
+        if let Some(path) = global_config_path() {
+            config.read_file(&path, None, 0)?;
+        }
+        Ok(config)
+    }
+
+    // Read a config file, splicing in `[include]` files and any
+    // `[includeIf "gitdir:..."]` files whose condition matches `git_dir`
+    fn read_file(&mut self, path: &Path, git_dir: Option<&Path>, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(format!(
+                "Exceeded maximum include depth ({}) while including {}",
+                MAX_INCLUDE_DEPTH,
+                path.display()
+            )
+            .into());
+        }
+        if !path.is_file() {
+            return Ok(());
+        }
+
+        let content = fs::read_to_string(path)?;
+        for mut entry in Config::parse(&content).entries {
+            entry.origin = Some(path.to_path_buf());
+
+            let included = entry.key == "path"
+                && match (entry.section.as_str(), entry.subsection.as_deref()) {
+                    ("include", None) => true,
+                    ("includeif", Some(condition)) => condition_matches(condition, path, git_dir),
+                    _ => false,
+                };
+            let target = included.then(|| resolve_include_path(&entry.value, path));
+
+            self.entries.push(entry);
+            if let Some(target) = target {
+                self.read_file(&target, git_dir, depth + 1)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn parse(content: &str) -> Config {
+        let mut entries = Vec::new();
+        let mut section = String::new();
+        let mut subsection = None;
+
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+
+            if let Some((name, sub)) = parse_header(line) {
+                section = name;
+                subsection = sub;
+                continue;
+            }
+
+            // A bare key is shorthand for `key = true`
+            let (key, value) = match line.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim().trim_matches('"')),
+                None => (line, "true"),
+            };
+
+            entries.push(ConfigEntry {
+                section: section.clone(),
+                subsection: subsection.clone(),
+                key: key.to_lowercase(),
+                value: value.to_string(),
+                origin: None,
+            });
+        }
+
+        Config { entries }
+    }
+
+    // Keys use git's dotted form: `core.whitespace`, `remote.origin.url`
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.get_all(name).last().copied()
+    }
+
+    pub fn get_all(&self, name: &str) -> Vec<&str> {
+        let Some((section, subsection, key)) = split_key(name) else {
+            return Vec::new();
+        };
+
+        self.entries
+            .iter()
+            .filter(|e| {
+                e.section == section && e.subsection.as_deref() == subsection && e.key == key
+            })
+            .map(|e| e.value.as_str())
+            .collect()
+    }
+
+    pub fn get_bool(&self, name: &str) -> Option<bool> {
+        self.get(name)
+            .map(|value| matches!(value.to_lowercase().as_str(), "true" | "yes" | "on" | "1"))
+    }
+
+    // The (key, value) pairs of a section without a subsection, in file
+    // order, so later ones override earlier ones
+    pub fn section(&self, section: &str) -> Vec<(&str, &str)> {
+        let section = section.to_lowercase();
+        self.entries
+            .iter()
+            .filter(|e| e.section == section && e.subsection.is_none())
+            .map(|e| (e.key.as_str(), e.value.as_str()))
+            .collect()
+    }
+
+    pub fn subsections(&self, section: &str) -> Vec<String> {
+        let section = section.to_lowercase();
+        let mut names: Vec<String> = Vec::new();
+
+        for entry in &self.entries {
+            if entry.section == section
+                && let Some(sub) = &entry.subsection
+                && !names.contains(sub)
+            {
+                names.push(sub.clone());
+            }
+        }
+
+        names
+    }
+
+    // `Name <email>` that commits are attributed to when none is given:
+    // $MINI_GIT_AUTHOR_NAME and $MINI_GIT_AUTHOR_EMAIL, then user.name and
+    // user.email
+    pub fn author_ident(&self) -> String {
+        let name = env_value("MINI_GIT_AUTHOR_NAME")
+            .or_else(|| self.get("user.name").map(str::to_string))
+            .unwrap_or_else(|| "Unknown".to_string());
+        let email = env_value("MINI_GIT_AUTHOR_EMAIL")
+            .or_else(|| self.get("user.email").map(str::to_string))
+            .unwrap_or_else(|| "unknown@example.com".to_string());
+        format!("{} <{}>", name, email)
+    }
+
+    // $MINI_GIT_EDITOR, core.editor, $VISUAL, $EDITOR, then vi
+    pub fn editor(&self) -> String {
+        env_value("MINI_GIT_EDITOR")
+            .or_else(|| self.get("core.editor").map(str::to_string))
+            .or_else(|| env_value("VISUAL"))
+            .or_else(|| env_value("EDITOR"))
+            .unwrap_or_else(|| "vi".to_string())
+    }
+
+    // $MINI_GIT_PAGER, core.pager, $PAGER, then less
+    pub fn pager(&self) -> String {
+        env_value("MINI_GIT_PAGER")
+            .or_else(|| self.get("core.pager").map(str::to_string))
+            .or_else(|| env_value("PAGER"))
+            .unwrap_or_else(|| "less".to_string())
+    }
+}
+
+// An environment variable, treating an empty value as unset
+fn env_value(name: &str) -> Option<String> {
+    std::env::var(name).ok().filter(|value| !value.is_empty())
+}
+
+impl ConfigEntry {
+    pub fn name(&self) -> String {
+        match &self.subsection {
+            Some(sub) => format!("{}.{}.{}", self.section, sub, self.key),
+            None => format!("{}.{}", self.section, self.key),
+        }
+    }
+}
+
+// `$MINI_GIT_CONFIG_GLOBAL`, or `~/.mini_gitconfig`
+pub fn global_config_path() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("MINI_GIT_CONFIG_GLOBAL") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mini_gitconfig"))
+}
+
+// Set `name` (dotted form) to `value` in the file at `path`, replacing the
+// last existing assignment or adding the key to its section
+pub fn set_value(path: &Path, name: &str, value: &str) -> Result<()> {
+    let (section, subsection, key) =
+        split_key(name).ok_or(format!("Invalid key '{}': expected section.key", name))?;
+
+    let content = fs::read_to_string(path).unwrap_or_default();
+    let mut lines: Vec<String> = content.lines().map(str::to_string).collect();
+
+    let mut in_section = false;
+    let mut section_end = None;
+    let mut existing = None;
+    for (i, line) in lines.iter().enumerate() {
+        let trimmed = line.trim();
+        if let Some((name, sub)) = parse_header(trimmed) {
+            in_section = name == section && sub.as_deref() == subsection;
+            if in_section {
+                section_end = Some(i);
+            }
+            continue;
+        }
+
+        if in_section {
+            section_end = Some(i);
+            let line_key = trimmed
+                .split('=')
+                .next()
+                .unwrap_or("")
+                .trim()
+                .to_lowercase();
+            if line_key == key {
+                existing = Some(i);
+            }
+        }
+    }
+
+    let assignment = format!("\t{} = {}", key, value);
+    match (existing, section_end) {
+        (Some(i), _) => lines[i] = assignment,
+        (None, Some(i)) => lines.insert(i + 1, assignment),
+        (None, None) => {
+            match subsection {
+                Some(sub) => lines.push(format!("[{} \"{}\"]", section, sub)),
+                None => lines.push(format!("[{}]", section)),
+            }
+            lines.push(assignment);
+        }
+    }
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(path, lines.join("\n") + "\n")?;
+    Ok(())
+}
+
+// Include paths may start with `~/`; relative paths are taken from the
+// directory of the file containing the directive
+fn resolve_include_path(value: &str, including_file: &Path) -> PathBuf {
+    if let Some(rest) = value.strip_prefix("~/")
+        && let Some(home) = std::env::var_os("HOME")
+    {
+        return PathBuf::from(home).join(rest);
+    }
+
+    let path = PathBuf::from(value);
+    if path.is_absolute() {
+        path
+    } else {
+        including_file.parent().unwrap_or(Path::new(".")).join(path)
+    }
+}
+
+// `gitdir:<pattern>` (or `gitdir/i:` for case-insensitive matching) against
+// the repository directory. As in git, a trailing `/` matches everything
+// below it and a pattern that isn't anchored may match at any depth.
+fn condition_matches(condition: &str, including_file: &Path, git_dir: Option<&Path>) -> bool {
+    let Some(git_dir) = git_dir else {
+        return false;
+    };
+    let (pattern, ignore_case) = if let Some(pattern) = condition.strip_prefix("gitdir:") {
+        (pattern, false)
+    } else if let Some(pattern) = condition.strip_prefix("gitdir/i:") {
+        (pattern, true)
+    } else {
+        return false;
+    };
+
+    let mut pattern = if let Some(rest) = pattern.strip_prefix("./") {
+        resolve_include_path(rest, including_file)
+            .to_string_lossy()
+            .to_string()
+    } else if pattern.starts_with("~/") {
+        resolve_include_path(pattern, including_file)
+            .to_string_lossy()
+            .to_string()
+    } else if pattern.starts_with('/') {
+        pattern.to_string()
+    } else {
+        format!("**/{}", pattern)
+    };
+    if pattern.ends_with('/') {
+        pattern.push_str("**");
+    }
+
+    let git_dir = fs::canonicalize(git_dir).unwrap_or_else(|_| git_dir.to_path_buf());
+    let mut target = git_dir.to_string_lossy().replace('\\', "/");
+    if ignore_case {
+        pattern = pattern.to_lowercase();
+        target = target.to_lowercase();
+    }
+    glob_match(pattern.as_bytes(), target.as_bytes())
+}
+
+// `*` and `?` stay within one path component; `**` crosses components
+pub(crate) fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', rest @ ..] => match rest.strip_prefix(b"/") {
+            // `**/` matches zero or more whole directories
+            Some(rest) => (0..=text.len())
+                .filter(|&i| i == 0 || text[i - 1] == b'/')
+                .any(|i| glob_match(rest, &text[i..])),
+            None => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        },
+        [b'*', rest @ ..] => {
+            let limit = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=limit).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => !text.is_empty() && text[0] != b'/' && glob_match(rest, &text[1..]),
+        [c, rest @ ..] => text.first() == Some(c) && glob_match(rest, &text[1..]),
+    }
+}
+
+// `[section]` or `[section "subsection"]`
+fn parse_header(line: &str) -> Option<(String, Option<String>)> {
+    let header = line.strip_prefix('[')?.strip_suffix(']')?;
+    Some(match header.split_once(' ') {
+        Some((name, sub)) => (
+            name.trim().to_lowercase(),
+            Some(sub.trim().trim_matches('"').to_string()),
+        ),
+        None => (header.trim().to_lowercase(), None),
+    })
+}
+
+fn split_key(name: &str) -> Option<(String, Option<&str>, String)> {
+    let (section, rest) = name.split_once('.')?;
+    match rest.rsplit_once('.') {
+        Some((subsection, key)) => {
+            Some((section.to_lowercase(), Some(subsection), key.to_lowercase()))
+        }
+        None => Some((section.to_lowercase(), None, rest.to_lowercase())),
+    }
+}
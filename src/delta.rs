@@ -0,0 +1,217 @@
+use crate::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+// Binary deltas: a target is rebuilt from a base it shares content with by
+// copying ranges of the base and inserting literal bytes. Matches are found
+// on BLOCK-sized windows of the base, then extended as far as they go.
+
+const BLOCK: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+pub enum DeltaOp {
+    Copy { offset: usize, len: usize },
+    // Hex-encoded literal bytes
+    Insert { data: String },
+}
+
+pub fn compute(base: &[u8], target: &[u8]) -> Vec<DeltaOp> {
+    let mut blocks: HashMap<&[u8], usize> = HashMap::new();
+    for offset in (0..base.len().saturating_sub(BLOCK - 1)).step_by(BLOCK) {
+        blocks
+            .entry(&base[offset..offset + BLOCK])
+            .or_insert(offset);
+    }
+
+    let mut ops = Vec::new();
+    let mut literal: Vec<u8> = Vec::new();
+    let mut pos = 0;
+
+    while pos < target.len() {
+        let found = (pos + BLOCK <= target.len())
+            .then(|| blocks.get(&target[pos..pos + BLOCK]))
+            .flatten();
+
+        match found {
+            Some(&offset) => {
+                let len = BLOCK
+                    + base[offset + BLOCK..]
+                        .iter()
+                        .zip(&target[pos + BLOCK..])
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                if !literal.is_empty() {
+                    ops.push(insert(&literal));
+                    literal.clear();
+                }
+                ops.push(DeltaOp::Copy { offset, len });
+                pos += len;
+            }
+            None => {
+                literal.push(target[pos]);
+                pos += 1;
+            }
+        }
+    }
+    if !literal.is_empty() {
+        ops.push(insert(&literal));
+    }
+    ops
+}
+
+pub fn apply(base: &[u8], ops: &[DeltaOp]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let range = base
+                    .get(*offset..offset + len)
+                    .ok_or("Delta copies past the end of its base")?;
+                out.extend_from_slice(range);
+            }
+            DeltaOp::Insert { data } => out.extend(crate::index::hex_to_bytes(data)?),
+        }
+    }
+    Ok(out)
+}
+
+fn insert(bytes: &[u8]) -> DeltaOp {
+    DeltaOp::Insert {
+        data: crate::index::bytes_to_hex(bytes),
+    }
+}
+
+// Pack entries hold deltas in git's binary form: the base and result sizes
+// as little-endian varints, then instructions. A copy is 0x80 with flag bits
+// saying which offset (0x01-0x08) and size (0x10-0x40) bytes follow, a size
+// of 0 meaning 0x10000; an insert is its length (1-127) and the bytes.
+const MAX_COPY: usize = 0xff_ffff;
+const MAX_INSERT: usize = 0x7f;
+
+pub fn encode(base_len: usize, target_len: usize, ops: &[DeltaOp]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    push_varint(&mut out, base_len);
+    push_varint(&mut out, target_len);
+    for op in ops {
+        match op {
+            DeltaOp::Copy { offset, len } => {
+                let (mut offset, mut len) = (*offset, *len);
+                while len > 0 {
+                    let size = len.min(MAX_COPY);
+                    let start = u32::try_from(offset)
+                        .map_err(|_| "Delta copies from beyond 4 GiB into its base")?;
+                    let mut cmd = 0x80u8;
+                    let mut args = Vec::new();
+                    for (i, byte) in start.to_le_bytes().into_iter().enumerate() {
+                        if byte != 0 {
+                            cmd |= 1 << i;
+                            args.push(byte);
+                        }
+                    }
+                    for (i, byte) in (size as u32).to_le_bytes()[..3].iter().enumerate() {
+                        if *byte != 0 {
+                            cmd |= 0x10 << i;
+                            args.push(*byte);
+                        }
+                    }
+                    out.push(cmd);
+                    out.extend(args);
+                    offset += size;
+                    len -= size;
+                }
+            }
+            DeltaOp::Insert { data } => {
+                for chunk in crate::index::hex_to_bytes(data)?.chunks(MAX_INSERT) {
+                    out.push(chunk.len() as u8);
+                    out.extend_from_slice(chunk);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+pub fn apply_encoded(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut pos = 0;
+    let base_len = read_varint(delta, &mut pos)?;
+    let target_len = read_varint(delta, &mut pos)?;
+    if base_len != base.len() {
+        return Err(format!(
+            "Delta expects a base of {} bytes, found {}",
+            base_len,
+            base.len()
+        )
+        .into());
+    }
+
+    let truncated = "Delta instructions are truncated";
+    let mut out = Vec::with_capacity(target_len);
+    while pos < delta.len() {
+        let cmd = delta[pos];
+        pos += 1;
+        if cmd & 0x80 != 0 {
+            let mut arg = |bits: u8, first: u8| -> Result<usize> {
+                let mut value = 0;
+                for i in 0..bits {
+                    if cmd & (first << i) != 0 {
+                        let byte = *delta.get(pos).ok_or(truncated)?;
+                        value |= (byte as usize) << (8 * i);
+                        pos += 1;
+                    }
+                }
+                Ok(value)
+            };
+            let offset = arg(4, 0x01)?;
+            let len = match arg(3, 0x10)? {
+                0 => 0x10000,
+                len => len,
+            };
+            let range = base
+                .get(offset..offset + len)
+                .ok_or("Delta copies past the end of its base")?;
+            out.extend_from_slice(range);
+        } else if cmd != 0 {
+            let len = cmd as usize;
+            out.extend_from_slice(delta.get(pos..pos + len).ok_or(truncated)?);
+            pos += len;
+        } else {
+            return Err("Delta has a reserved instruction 0".into());
+        }
+    }
+    if out.len() != target_len {
+        return Err(format!(
+            "Delta produced {} bytes, expected {}",
+            out.len(),
+            target_len
+        )
+        .into());
+    }
+    Ok(out)
+}
+
+// Little-endian base-128, the low seven bits first
+pub(crate) fn push_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+pub(crate) fn read_varint(data: &[u8], pos: &mut usize) -> Result<usize> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or("Delta header is truncated")?;
+        *pos += 1;
+        if shift > 57 {
+            return Err("Delta size is too large".into());
+        }
+        value |= ((byte & 0x7f) as usize) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
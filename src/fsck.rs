@@ -0,0 +1,314 @@
+use crate::{
+    Repository, Result,
+    commands::{cherry_pick, merge, revert, stash},
+    config::Config,
+    git_object,
+    graph::ObjectId,
+    hash::HashAlgorithm,
+    journal,
+    object_store::{CorruptObject, ObjectStorage, ObjectStore},
+    utils,
+};
+use std::collections::HashSet;
+use std::fs;
+
+// Object checks, for `fsck` and while transferring objects between
+// repositories. With `transfer.fsckObjects` (or the per-side
+// `fetch.fsckObjects` / `receive.fsckObjects`) enabled, everything reachable
+// from the new ref tips must be present, parse as the expected type and hash
+// to its name before any ref is updated.
+
+pub enum Side {
+    Fetch,
+    Receive,
+}
+
+pub fn transfer_checks_enabled(repo: &Repository, side: Side) -> Result<bool> {
+    let config = Config::load(repo)?;
+    let key = match side {
+        Side::Fetch => "fetch.fsckObjects",
+        Side::Receive => "receive.fsckObjects",
+    };
+    Ok(config
+        .get_bool(key)
+        .or_else(|| config.get_bool("transfer.fsckObjects"))
+        .unwrap_or(false))
+}
+
+// Verify every object reachable from `tips`; returns how many were checked
+pub fn check_connectivity(object_store: &ObjectStore, tips: &[String]) -> Result<usize> {
+    let mut walk = Walk::new(object_store);
+    for tip in tips {
+        walk.tip(tip)?;
+    }
+    match walk.problems.into_iter().next() {
+        Some(problem) => Err(problem.into()),
+        None => Ok(walk.seen.len()),
+    }
+}
+
+// Walk everything the repository keeps alive: the refs, a detached HEAD, an
+// unfinished merge, stashes, the HEAD log, the undo journal and the index
+pub fn walk_repository<'a>(repo: &Repository, object_store: &'a ObjectStore) -> Result<Walk<'a>> {
+    let mut walk = Walk::new(object_store);
+
+    let mut missing = HashSet::new();
+    for (name, hash) in root_commits(repo)? {
+        if object_store.object_exists(&hash) {
+            walk.tip(&hash)?;
+        } else if missing.insert(hash.clone()) {
+            walk.problems
+                .push(format!("{} points to missing commit {}", name, hash));
+        }
+    }
+    let (_, stashed_trees) = stash::stash_objects(repo)?;
+    for tree in stashed_trees {
+        walk.tree(&tree)?;
+    }
+    for entry in utils::load_index(repo)?.entries.values() {
+        if entry.is_dir() {
+            walk.tree(&entry.hash)?;
+        } else {
+            walk.blob(&entry.hash, true)?;
+        }
+    }
+    Ok(walk)
+}
+
+// Commits that must stay intact, with where each was found
+fn root_commits(repo: &Repository) -> Result<Vec<(String, String)>> {
+    let mut roots = utils::list_refs(repo, "refs/")?;
+
+    // HEAD names a commit directly when detached
+    let head = fs::read_to_string(repo.git_dir.join("HEAD"))?;
+    if !head.trim().starts_with("ref:") {
+        roots.push(("HEAD".to_string(), head.trim().to_string()));
+    }
+    if let Some(merge_head) = merge::merge_head(repo)? {
+        roots.push(("MERGE_HEAD".to_string(), merge_head));
+    }
+    if let Some(revert_head) = revert::revert_head(repo)? {
+        roots.push(("REVERT_HEAD".to_string(), revert_head));
+    }
+    if let Some(picking) = cherry_pick::cherry_pick_head(repo)? {
+        roots.push(("CHERRY_PICK_HEAD".to_string(), picking));
+    }
+    let (stashed_commits, _) = stash::stash_objects(repo)?;
+    for (i, commit) in stashed_commits.into_iter().enumerate() {
+        roots.push((format!("stash@{{{}}}", i), commit));
+    }
+
+    // Ref logs under .mini_git/logs/ start each line with the old and new
+    // ids; the journal kept there too is read below
+    let logs_dir = repo.git_dir.join("logs");
+    let null_id = HashAlgorithm::of_repo(repo)?.null_id();
+    let is_id = |hash: &str| {
+        hash.len() == null_id.len()
+            && hash != null_id
+            && hash.bytes().all(|b| b.is_ascii_hexdigit())
+    };
+    for entry in walkdir::WalkDir::new(&logs_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let name = entry
+            .path()
+            .strip_prefix(&logs_dir)?
+            .to_string_lossy()
+            .to_string();
+        for line in fs::read_to_string(entry.path())?.lines() {
+            for hash in line.split(' ').take(2) {
+                if is_id(hash) {
+                    roots.push((format!("log entry for {}", name), hash.to_string()));
+                }
+            }
+        }
+    }
+    // `undo` can move refs back to anything the journal recorded
+    for entry in journal::entries(repo)? {
+        for update in entry.updates {
+            for hash in update.old.into_iter().chain(update.new) {
+                roots.push((format!("journal entry for {}", update.name), hash));
+            }
+        }
+    }
+    Ok(roots)
+}
+
+// A walk over reachable objects that notes what is missing or corrupt and
+// carries on, so one pass can report every problem
+pub struct Walk<'a> {
+    object_store: &'a ObjectStore,
+    pub seen: HashSet<ObjectId>,
+    pub problems: Vec<String>,
+}
+
+impl<'a> Walk<'a> {
+    pub fn new(object_store: &'a ObjectStore) -> Self {
+        Walk {
+            object_store,
+            seen: HashSet::new(),
+            problems: Vec::new(),
+        }
+    }
+
+    // Visit what a ref points at: a commit, or an annotated tag and
+    // everything behind it
+    pub fn tip(&mut self, hash: &str) -> Result<()> {
+        if !matches!(self.object_store.read_typed(hash), Ok((kind, _)) if kind == "tag") {
+            return self.commit(hash);
+        }
+        if !self.seen.insert(ObjectId::from_hex(hash)?) {
+            return Ok(());
+        }
+        let tag = match self.object_store.load_tag(hash) {
+            Ok(tag) => tag,
+            Err(e) => {
+                self.problems.push(bad_object(hash, "tag", e).to_string());
+                return Ok(());
+            }
+        };
+        if tag.hash != hash || !content_matches(self.object_store, hash, "tag")? {
+            self.problems
+                .push(format!("Tag {} does not match its contents", hash));
+        }
+        match tag.object_type.as_str() {
+            "tree" => self.tree(&tag.object),
+            "blob" => self.blob(&tag.object, true),
+            _ => self.tip(&tag.object),
+        }
+    }
+
+    // Visit the commit `hash` and everything reachable from it
+    pub fn commit(&mut self, hash: &str) -> Result<()> {
+        let mut commits = vec![hash.to_string()];
+        while let Some(hash) = commits.pop() {
+            if !self.seen.insert(ObjectId::from_hex(&hash)?) {
+                continue;
+            }
+            let commit = match self.object_store.load_commit(&hash) {
+                Ok(commit) => commit,
+                Err(e) => {
+                    self.problems
+                        .push(bad_object(&hash, "commit", e).to_string());
+                    continue;
+                }
+            };
+            // Commit ids aren't derived from a canonical encoding with JSON
+            // storage, so there only the recorded id can be checked
+            if commit.hash != hash || !content_matches(self.object_store, &hash, "commit")? {
+                self.problems
+                    .push(format!("Commit {} does not match its contents", hash));
+            }
+
+            self.tree(&commit.tree)?;
+            commits.extend(commit.parents);
+        }
+        Ok(())
+    }
+
+    // Visit the tree `hash`, its subtrees and its blobs
+    pub fn tree(&mut self, hash: &str) -> Result<()> {
+        if !self.seen.insert(ObjectId::from_hex(hash)?) {
+            return Ok(());
+        }
+        let tree = match self.object_store.load_tree(hash) {
+            Ok(tree) => tree,
+            Err(e) => {
+                self.problems.push(bad_object(hash, "tree", e).to_string());
+                return Ok(());
+            }
+        };
+        if tree.hash != hash || self.object_store.tree_hash(&tree.entries)? != hash {
+            self.problems
+                .push(format!("Tree {} does not match its contents", hash));
+        }
+        // With git storage, subdirectories are trees of their own
+        for subtree in self.object_store.tree_objects(hash)?.into_iter().skip(1) {
+            self.seen.insert(ObjectId::from_hex(&subtree)?);
+        }
+
+        for entry in tree.entries.values() {
+            if !self.seen.insert(ObjectId::from_hex(&entry.hash)?) {
+                continue;
+            }
+            // Empty directories point at the empty tree
+            if !entry.is_file {
+                match self.object_store.load_tree(&entry.hash) {
+                    Ok(dir) => {
+                        if !dir.entries.is_empty()
+                            || self.object_store.tree_hash(&dir.entries)? != entry.hash
+                        {
+                            self.problems
+                                .push(format!("Tree {} is not an empty directory", entry.hash));
+                        }
+                    }
+                    Err(e) => self
+                        .problems
+                        .push(bad_object(&entry.hash, "tree", e).to_string()),
+                }
+                continue;
+            }
+            self.blob(&entry.hash, false)?;
+        }
+        Ok(())
+    }
+
+    // Check the blob `hash`; `visit` marks it seen first
+    pub fn blob(&mut self, hash: &str, visit: bool) -> Result<()> {
+        if visit && !self.seen.insert(ObjectId::from_hex(hash)?) {
+            return Ok(());
+        }
+        match self.object_store.load_blob(hash) {
+            Ok(blob) => {
+                if blob.hash != hash || self.object_store.hash_content(&blob.content) != hash {
+                    self.problems
+                        .push(format!("Blob {} does not match its contents", hash));
+                }
+            }
+            Err(e) => self.problems.push(bad_object(hash, "blob", e).to_string()),
+        }
+        Ok(())
+    }
+}
+
+// Whether an object hashes to its id. Git storage hashes the whole stored
+// object; JSON storage can only say so for blobs, trees and tags.
+pub fn content_matches(object_store: &ObjectStore, hash: &str, kind: &str) -> Result<bool> {
+    match (object_store.storage(), kind) {
+        (ObjectStorage::Git, _) => Ok(object_store
+            .algorithm()
+            .hash(&object_store.load_object(hash)?)
+            == hash),
+        (ObjectStorage::Json, "blob") => {
+            Ok(object_store.hash_content(&object_store.load_blob(hash)?.content) == hash)
+        }
+        (ObjectStorage::Json, "tree") => {
+            Ok(object_store.tree_hash(&object_store.load_tree(hash)?.entries)? == hash)
+        }
+        (ObjectStorage::Json, "tag") => {
+            let tag = object_store.load_tag(hash)?;
+            Ok(object_store
+                .algorithm()
+                .hash(&git_object::wrap("tag", &git_object::encode_tag(&tag)))
+                == hash)
+        }
+        (ObjectStorage::Json, _) => Ok(true),
+    }
+}
+
+// Corrupt objects already name their file and the failing byte
+pub fn bad_object(
+    hash: &str,
+    kind: &str,
+    error: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error> {
+    if error.is::<CorruptObject>() {
+        error
+    } else if error.to_string().contains("not found") {
+        format!("Missing {} {}", kind, hash).into()
+    } else {
+        format!("Object {} is not a valid {}: {}", hash, kind, error).into()
+    }
+}
@@ -0,0 +1,250 @@
+use crate::{
+    Commit, Tag,
+    index::{bytes_to_hex, hex_to_bytes},
+    repo_path,
+    worktree::DIR_MODE,
+};
+use chrono::{DateTime, Utc};
+
+// Git's loose object encoding, used by `core.objectStorage = git`. An object
+// is `<kind> <payload length>\0<payload>`, and its id is the hash of all of
+// it. Trees are nested, one per directory:
+//
+//   <mode> <name>\0<raw id>   for each entry, sorted by name, directories
+//                             compared as if their name ended in `/`
+//
+// and commits are text:
+//
+//   tree <id>
+//   parent <id>               (none for a root commit)
+//   author <name> <email> <seconds> +0000
+//   committer <name> <email> <seconds> +0000
+//
+//   <message>
+//
+// as are annotated tags:
+//
+//   object <id>
+//   type <kind of the tagged object>
+//   tag <name>
+//   tagger <name> <email> <seconds> +0000
+//
+//   <message>
+//
+// Decoding failures carry the byte offset in the payload they were found at.
+
+pub type DecodeError = (usize, String);
+
+pub fn wrap(kind: &str, payload: &[u8]) -> Vec<u8> {
+    let mut out = format!("{} {}\0", kind, payload.len()).into_bytes();
+    out.extend_from_slice(payload);
+    out
+}
+
+// Split an object into its kind and payload. Offsets in later errors are
+// relative to the payload, which starts at the returned offset.
+pub fn unwrap(raw: &[u8]) -> Result<(&str, &[u8], usize), DecodeError> {
+    let end = raw
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or((raw.len(), "missing object header".to_string()))?;
+    let header =
+        std::str::from_utf8(&raw[..end]).map_err(|_| (0, "invalid object header".to_string()))?;
+    let (kind, size) = header
+        .split_once(' ')
+        .ok_or((0, format!("invalid object header '{}'", header)))?;
+    let size: usize = size
+        .parse()
+        .map_err(|_| (kind.len() + 1, format!("invalid object size '{}'", size)))?;
+    let payload = &raw[end + 1..];
+    if payload.len() != size {
+        return Err((
+            raw.len(),
+            format!("object is {} bytes, header says {}", payload.len(), size),
+        ));
+    }
+    Ok((kind, payload, end + 1))
+}
+
+pub struct TreeItem {
+    pub mode: String,
+    // A single path component, in repository form (see repo_path)
+    pub name: String,
+    pub hash: String,
+}
+
+impl TreeItem {
+    pub fn is_dir(&self) -> bool {
+        self.mode == DIR_MODE
+    }
+
+    fn sort_key(&self) -> Vec<u8> {
+        let mut key = repo_path::decode(&self.name);
+        if self.is_dir() {
+            key.push(b'/');
+        }
+        key
+    }
+}
+
+pub fn encode_tree(mut items: Vec<TreeItem>) -> Result<Vec<u8>, String> {
+    items.sort_by_cached_key(TreeItem::sort_key);
+    let mut out = Vec::new();
+    for item in items {
+        out.extend_from_slice(item.mode.as_bytes());
+        out.push(b' ');
+        out.extend_from_slice(&repo_path::decode(&item.name));
+        out.push(0);
+        out.extend_from_slice(
+            &hex_to_bytes(&item.hash)
+                .map_err(|_| format!("Invalid object id '{}' for {}", item.hash, item.name))?,
+        );
+    }
+    Ok(out)
+}
+
+pub fn decode_tree(payload: &[u8], hash_len: usize) -> Result<Vec<TreeItem>, DecodeError> {
+    let mut items = Vec::new();
+    let mut pos = 0;
+    while pos < payload.len() {
+        let space = find(payload, pos, b' ').ok_or((pos, "truncated tree entry".to_string()))?;
+        let mode = std::str::from_utf8(&payload[pos..space])
+            .ok()
+            .filter(|mode| !mode.is_empty() && mode.bytes().all(|b| (b'0'..=b'7').contains(&b)))
+            .ok_or((pos, "invalid mode in tree entry".to_string()))?;
+        let nul = find(payload, space + 1, 0).ok_or((space, "truncated tree entry".to_string()))?;
+        let name = &payload[space + 1..nul];
+        if name.is_empty() || name.contains(&b'/') || name == b"." || name == b".." {
+            return Err((space + 1, "invalid name in tree entry".to_string()));
+        }
+        let id = payload
+            .get(nul + 1..nul + 1 + hash_len)
+            .ok_or((nul + 1, "truncated object id in tree entry".to_string()))?;
+        items.push(TreeItem {
+            mode: mode.to_string(),
+            name: repo_path::from_bytes(name),
+            hash: bytes_to_hex(id),
+        });
+        pos = nul + 1 + hash_len;
+    }
+    Ok(items)
+}
+
+fn find(bytes: &[u8], from: usize, byte: u8) -> Option<usize> {
+    bytes[from..]
+        .iter()
+        .position(|&b| b == byte)
+        .map(|i| from + i)
+}
+
+pub fn encode_commit(commit: &Commit) -> Vec<u8> {
+    let mut out = format!("tree {}\n", commit.tree);
+    for parent in &commit.parents {
+        out.push_str(&format!("parent {}\n", parent));
+    }
+    let signature = format!("{} {} +0000", commit.author, commit.timestamp.timestamp());
+    out.push_str(&format!("author {}\n", signature));
+    out.push_str(&format!("committer {}\n", signature));
+    out.push('\n');
+    out.push_str(&commit.message);
+    out.into_bytes()
+}
+
+pub fn decode_commit(hash: &str, payload: &[u8]) -> Result<Commit, DecodeError> {
+    let text = std::str::from_utf8(payload)
+        .map_err(|e| (e.valid_up_to(), "commit is not valid UTF-8".to_string()))?;
+    let (headers, message) = text.split_once("\n\n").ok_or((
+        text.len(),
+        "missing blank line after commit headers".to_string(),
+    ))?;
+
+    let mut tree = None;
+    let mut parents = Vec::new();
+    let mut author = None;
+    let mut offset = 0;
+    for line in headers.lines() {
+        match line.split_once(' ') {
+            Some(("tree", id)) => tree = Some(id.to_string()),
+            Some(("parent", id)) => parents.push(id.to_string()),
+            Some(("author", signature)) => {
+                author = Some(
+                    parse_signature(signature)
+                        .ok_or((offset, "invalid author line".to_string()))?,
+                )
+            }
+            _ => {}
+        }
+        offset += line.len() + 1;
+    }
+
+    let tree = tree.ok_or((0, "commit has no tree".to_string()))?;
+    let (author, timestamp) = author.ok_or((0, "commit has no author".to_string()))?;
+    Ok(Commit {
+        hash: hash.to_string(),
+        parents,
+        tree,
+        author,
+        message: message.to_string(),
+        timestamp,
+    })
+}
+
+pub fn encode_tag(tag: &Tag) -> Vec<u8> {
+    format!(
+        "object {}\ntype {}\ntag {}\ntagger {} {} +0000\n\n{}",
+        tag.object,
+        tag.object_type,
+        tag.name,
+        tag.tagger,
+        tag.timestamp.timestamp(),
+        tag.message
+    )
+    .into_bytes()
+}
+
+pub fn decode_tag(hash: &str, payload: &[u8]) -> Result<Tag, DecodeError> {
+    let text = std::str::from_utf8(payload)
+        .map_err(|e| (e.valid_up_to(), "tag is not valid UTF-8".to_string()))?;
+    let (headers, message) = text.split_once("\n\n").ok_or((
+        text.len(),
+        "missing blank line after tag headers".to_string(),
+    ))?;
+
+    let (mut object, mut object_type, mut name, mut tagger) = (None, None, None, None);
+    let mut offset = 0;
+    for line in headers.lines() {
+        match line.split_once(' ') {
+            Some(("object", id)) => object = Some(id.to_string()),
+            Some(("type", kind)) => object_type = Some(kind.to_string()),
+            Some(("tag", tag)) => name = Some(tag.to_string()),
+            Some(("tagger", signature)) => {
+                tagger = Some(
+                    parse_signature(signature)
+                        .ok_or((offset, "invalid tagger line".to_string()))?,
+                )
+            }
+            _ => {}
+        }
+        offset += line.len() + 1;
+    }
+
+    let missing = |field: &str| (0, format!("tag has no {}", field));
+    let (tagger, timestamp) = tagger.ok_or(missing("tagger"))?;
+    Ok(Tag {
+        hash: hash.to_string(),
+        object: object.ok_or(missing("object"))?,
+        object_type: object_type.ok_or(missing("type"))?,
+        name: name.ok_or(missing("name"))?,
+        tagger,
+        message: message.to_string(),
+        timestamp,
+    })
+}
+
+// `Name <email> <seconds> <zone>`
+fn parse_signature(signature: &str) -> Option<(String, DateTime<Utc>)> {
+    let (rest, _zone) = signature.rsplit_once(' ')?;
+    let (name, seconds) = rest.rsplit_once(' ')?;
+    let timestamp = DateTime::from_timestamp(seconds.parse().ok()?, 0)?;
+    Some((name.to_string(), timestamp))
+}
@@ -0,0 +1,362 @@
+use crate::{Result, object_store::ObjectStore};
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+use std::fmt;
+
+// Commit history as a compact in-memory graph. Walks over large histories
+// used to allocate a String per visited hash; here every commit is interned
+// once as a fixed-size ObjectId and referred to by a u32 node number, parent
+// links live in one flat Vec, and visited sets are bitsets over node numbers.
+// Commits are loaded lazily, the first time a walk needs their parents, from
+// the commit-graph cache when it has them (see commit_graph).
+
+const MAX_HASH_LEN: usize = 32;
+
+// A binary object id, long enough for either object format
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ObjectId {
+    len: u8,
+    bytes: [u8; MAX_HASH_LEN],
+}
+
+impl ObjectId {
+    pub fn from_hex(hex: &str) -> Result<Self> {
+        let raw =
+            crate::index::hex_to_bytes(hex).map_err(|_| format!("Invalid object id '{}'", hex))?;
+        if raw.is_empty() || raw.len() > MAX_HASH_LEN {
+            return Err(format!("Invalid object id '{}'", hex).into());
+        }
+        let mut bytes = [0; MAX_HASH_LEN];
+        bytes[..raw.len()].copy_from_slice(&raw);
+        Ok(ObjectId {
+            len: raw.len() as u8,
+            bytes,
+        })
+    }
+
+    fn from_bytes(raw: &[u8]) -> Self {
+        let mut bytes = [0; MAX_HASH_LEN];
+        bytes[..raw.len()].copy_from_slice(raw);
+        ObjectId {
+            len: raw.len() as u8,
+            bytes,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    pub fn to_hex(&self) -> String {
+        crate::index::bytes_to_hex(self.as_bytes())
+    }
+}
+
+impl fmt::Display for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.to_hex())
+    }
+}
+
+impl fmt::Debug for ObjectId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "ObjectId({})", self)
+    }
+}
+
+// Parent slots that don't name another node
+const UNLOADED: u32 = u32::MAX;
+const ROOT: u32 = u32::MAX - 1;
+
+pub struct CommitGraph<'a> {
+    object_store: &'a ObjectStore,
+    ids: Vec<ObjectId>,
+    nodes: HashMap<ObjectId, u32>,
+    // First parent of each node
+    parents: Vec<u32>,
+    // Parents after the first, for the few nodes that are merges
+    merge_parents: HashMap<u32, Vec<u32>>,
+    // Commit time of each loaded node, for walking newest first
+    times: Vec<i64>,
+}
+
+impl<'a> CommitGraph<'a> {
+    pub fn new(object_store: &'a ObjectStore) -> Self {
+        CommitGraph {
+            object_store,
+            ids: Vec::new(),
+            nodes: HashMap::new(),
+            parents: Vec::new(),
+            merge_parents: HashMap::new(),
+            times: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    // The node for a commit, adding it to the graph on first sight
+    pub fn node(&mut self, hash: &str) -> Result<u32> {
+        Ok(self.node_for(ObjectId::from_hex(hash)?))
+    }
+
+    fn node_for(&mut self, id: ObjectId) -> u32 {
+        if let Some(&node) = self.nodes.get(&id) {
+            return node;
+        }
+        let node = self.ids.len() as u32;
+        self.ids.push(id);
+        self.parents.push(UNLOADED);
+        self.times.push(0);
+        self.nodes.insert(id, node);
+        node
+    }
+
+    pub fn id(&self, node: u32) -> ObjectId {
+        self.ids[node as usize]
+    }
+
+    pub fn hex(&self, node: u32) -> String {
+        self.id(node).to_hex()
+    }
+
+    fn load(&mut self, node: u32) -> Result<()> {
+        if self.parents[node as usize] != UNLOADED {
+            return Ok(());
+        }
+        let object_store = self.object_store;
+        let cached = object_store.commit_graph().and_then(|file| {
+            let position = file.find(self.id(node).as_bytes())?;
+            let parents = file.parents(position)?;
+            Some((file, parents, file.time(position)))
+        });
+        let (mut parents, time) = match cached {
+            Some((file, positions, time)) => {
+                let parents = positions
+                    .into_iter()
+                    .map(|position| self.node_for(ObjectId::from_bytes(file.id(position))))
+                    .collect::<Vec<_>>();
+                (parents, time)
+            }
+            None => {
+                let commit = object_store.load_commit(&self.hex(node))?;
+                let mut parents = Vec::with_capacity(commit.parents.len());
+                for parent in &commit.parents {
+                    parents.push(self.node(parent)?);
+                }
+                (parents, commit.timestamp.timestamp())
+            }
+        };
+        self.times[node as usize] = time;
+        self.parents[node as usize] = parents.first().copied().unwrap_or(ROOT);
+        if parents.len() > 1 {
+            self.merge_parents.insert(node, parents.split_off(1));
+        }
+        Ok(())
+    }
+
+    // All parents of `node`, first parent first
+    pub fn parents(&mut self, node: u32) -> Result<Vec<u32>> {
+        self.load(node)?;
+        let mut parents = match self.parents[node as usize] {
+            ROOT => Vec::new(),
+            parent => vec![parent],
+        };
+        if let Some(more) = self.merge_parents.get(&node) {
+            parents.extend(more);
+        }
+        Ok(parents)
+    }
+
+    pub fn time(&mut self, node: u32) -> Result<i64> {
+        self.load(node)?;
+        Ok(self.times[node as usize])
+    }
+
+    // `node` followed by its ancestors through every parent, newest commit
+    // first, leaving out any node in `stop`. Commits with the same time keep
+    // the order they were found in, so a child still precedes its parent.
+    pub fn walk(&mut self, node: u32, stop: &NodeSet) -> Result<Vec<u32>> {
+        let mut nodes = Vec::new();
+        let mut queued = NodeSet::default();
+        let mut queue = BinaryHeap::new();
+        if !stop.contains(node) {
+            queued.insert(node);
+            queue.push((self.time(node)?, Reverse(node)));
+        }
+        while let Some((_, Reverse(node))) = queue.pop() {
+            nodes.push(node);
+            for parent in self.parents(node)? {
+                if !stop.contains(parent) && queued.insert(parent) {
+                    queue.push((self.time(parent)?, Reverse(parent)));
+                }
+            }
+        }
+        Ok(nodes)
+    }
+
+    // The nodes of `walk`, reordered so that no commit comes before one of
+    // its children even when commit times say otherwise, newest first among
+    // those free to go next. Drawing history as a graph needs this.
+    pub fn topo_walk(&mut self, node: u32, stop: &NodeSet) -> Result<Vec<u32>> {
+        let nodes = self.walk(node, stop)?;
+        let mut included = NodeSet::default();
+        for &node in &nodes {
+            included.insert(node);
+        }
+        let mut children: HashMap<u32, usize> = HashMap::new();
+        for &node in &nodes {
+            for parent in self.parents(node)? {
+                if included.contains(parent) {
+                    *children.entry(parent).or_default() += 1;
+                }
+            }
+        }
+
+        let mut ready = BinaryHeap::new();
+        for &node in &nodes {
+            if !children.contains_key(&node) {
+                ready.push((self.time(node)?, Reverse(node)));
+            }
+        }
+        let mut ordered = Vec::with_capacity(nodes.len());
+        while let Some((_, Reverse(node))) = ready.pop() {
+            ordered.push(node);
+            for parent in self.parents(node)? {
+                let Some(count) = children.get_mut(&parent) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count == 0 {
+                    ready.push((self.time(parent)?, Reverse(parent)));
+                }
+            }
+        }
+        Ok(ordered)
+    }
+
+    // Every node reachable from `node`, including itself
+    pub fn reachable(&mut self, node: u32) -> Result<NodeSet> {
+        let mut set = NodeSet::default();
+        let mut pending = vec![node];
+        while let Some(node) = pending.pop() {
+            if set.insert(node) {
+                pending.extend(self.parents(node)?);
+            }
+        }
+        Ok(set)
+    }
+
+    pub fn is_ancestor(&mut self, ancestor: u32, descendant: u32) -> Result<bool> {
+        let mut seen = NodeSet::default();
+        let mut pending = vec![descendant];
+        while let Some(node) = pending.pop() {
+            if node == ancestor {
+                return Ok(true);
+            }
+            if seen.insert(node) {
+                pending.extend(self.parents(node)?);
+            }
+        }
+        Ok(false)
+    }
+
+    // The best common ancestors of `a` and `b`: commits reachable from both
+    // that aren't an ancestor of another such commit. Criss-cross merges
+    // can leave more than one.
+    pub fn merge_bases(&mut self, a: u32, b: u32) -> Result<Vec<u32>> {
+        self.merge_bases_of(&[a], b)
+    }
+
+    // The best common ancestors of `b` and the commits `a` taken together, as
+    // if they were the parents of one merge. Merging several merge bases into
+    // a virtual one needs this for each base after the first.
+    pub fn merge_bases_of(&mut self, a: &[u32], b: u32) -> Result<Vec<u32>> {
+        let mut from_a = NodeSet::default();
+        let mut pending = a.to_vec();
+        while let Some(node) = pending.pop() {
+            if from_a.insert(node) {
+                pending.extend(self.parents(node)?);
+            }
+        }
+
+        // The first common commits on each line of history back from `b`
+        let mut candidates = Vec::new();
+        let mut seen = NodeSet::default();
+        let mut pending = VecDeque::from([b]);
+        while let Some(node) = pending.pop_front() {
+            if !seen.insert(node) {
+                continue;
+            }
+            if from_a.contains(node) {
+                candidates.push(node);
+            } else {
+                pending.extend(self.parents(node)?);
+            }
+        }
+
+        let mut bases = Vec::new();
+        for &candidate in &candidates {
+            let mut redundant = false;
+            for &other in &candidates {
+                if other != candidate && self.is_ancestor(candidate, other)? {
+                    redundant = true;
+                    break;
+                }
+            }
+            if !redundant {
+                bases.push(candidate);
+            }
+        }
+        Ok(bases)
+    }
+
+    pub fn merge_base(&mut self, a: u32, b: u32) -> Result<Option<u32>> {
+        Ok(self.merge_bases(a, b)?.first().copied())
+    }
+}
+
+// A set of graph nodes, one bit each
+#[derive(Debug, Clone, Default)]
+pub struct NodeSet {
+    words: Vec<u64>,
+}
+
+impl NodeSet {
+    // Returns false if the node was already present
+    pub fn insert(&mut self, node: u32) -> bool {
+        let (word, bit) = (node as usize / 64, node % 64);
+        if word >= self.words.len() {
+            self.words.resize(word + 1, 0);
+        }
+        let present = self.words[word] & (1 << bit) != 0;
+        self.words[word] |= 1 << bit;
+        !present
+    }
+
+    pub fn contains(&self, node: u32) -> bool {
+        let (word, bit) = (node as usize / 64, node % 64);
+        self.words
+            .get(word)
+            .is_some_and(|bits| bits & (1 << bit) != 0)
+    }
+}
+
+pub fn is_ancestor(object_store: &ObjectStore, ancestor: &str, descendant: &str) -> Result<bool> {
+    let mut graph = CommitGraph::new(object_store);
+    let ancestor = graph.node(ancestor)?;
+    let descendant = graph.node(descendant)?;
+    graph.is_ancestor(ancestor, descendant)
+}
+
+pub fn merge_base(object_store: &ObjectStore, a: &str, b: &str) -> Result<Option<String>> {
+    let mut graph = CommitGraph::new(object_store);
+    let a = graph.node(a)?;
+    let b = graph.node(b)?;
+    Ok(graph.merge_base(a, b)?.map(|node| graph.hex(node)))
+}
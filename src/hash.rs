@@ -0,0 +1,114 @@
+use crate::{
+    Repository, Result,
+    config::Config,
+    object_store::ObjectStorage,
+    profile::{self, Phase},
+};
+use sha1::Sha1;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// The hash function naming a repository's objects. It is chosen at `init`
+// time and recorded as `extensions.objectFormat` in the repository's own
+// config; repositories without it use SHA-1. Objects from repositories with
+// different formats can never be mixed, since every id would differ.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+}
+
+impl HashAlgorithm {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "sha1" => Ok(HashAlgorithm::Sha1),
+            "sha256" => Ok(HashAlgorithm::Sha256),
+            _ => Err(format!("Unsupported object format '{}'", name).into()),
+        }
+    }
+
+    // Read the format of the repository at `git_dir`. Only the repository's
+    // config counts: a global setting must not change how existing objects
+    // are named.
+    pub fn of_git_dir(git_dir: &Path) -> Result<Self> {
+        let config = match fs::read_to_string(git_dir.join("config")) {
+            Ok(content) => Config::parse(&content),
+            Err(_) => return Ok(HashAlgorithm::default()),
+        };
+        match config.get("extensions.objectFormat") {
+            Some(name) => Self::from_name(name),
+            None => Ok(HashAlgorithm::default()),
+        }
+    }
+
+    pub fn of_repo(repo: &Repository) -> Result<Self> {
+        Self::of_git_dir(&repo.git_dir)
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashAlgorithm::Sha1 => "sha1",
+            HashAlgorithm::Sha256 => "sha256",
+        }
+    }
+
+    // Length of a digest in bytes; ids are twice as long in hex
+    pub fn raw_len(&self) -> usize {
+        match self {
+            HashAlgorithm::Sha1 => 20,
+            HashAlgorithm::Sha256 => 32,
+        }
+    }
+
+    pub fn hex_len(&self) -> usize {
+        self.raw_len() * 2
+    }
+
+    pub fn hash(&self, content: &[u8]) -> String {
+        let _span = profile::span(Phase::Hashing);
+        match self {
+            HashAlgorithm::Sha1 => format!("{:x}", Sha1::digest(content)),
+            HashAlgorithm::Sha256 => format!("{:x}", Sha256::digest(content)),
+        }
+    }
+
+    // An id of all zeroes, for refs that don't exist yet
+    pub fn null_id(&self) -> String {
+        "0".repeat(self.hex_len())
+    }
+}
+
+impl fmt::Display for HashAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+// Fail unless the repository at `theirs` (described as `what` in the error)
+// names its objects the same way as the one at `ours`
+pub fn ensure_same_format(ours: &Path, theirs: &Path, what: &str) -> Result<()> {
+    let local = HashAlgorithm::of_git_dir(ours)?;
+    let remote = HashAlgorithm::of_git_dir(theirs)?;
+    if local != remote {
+        return Err(format!(
+            "{} uses object format {}, this repository uses {}",
+            what, remote, local
+        )
+        .into());
+    }
+
+    let local = ObjectStorage::of_git_dir(ours)?;
+    let remote = ObjectStorage::of_git_dir(theirs)?;
+    if local != remote {
+        return Err(format!(
+            "{} uses object storage {}, this repository uses {}",
+            what, remote, local
+        )
+        .into());
+    }
+    Ok(())
+}
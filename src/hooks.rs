@@ -0,0 +1,151 @@
+use crate::{Repository, Result, config::Config, utils};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+// Hooks: executables in .mini_git/hooks/ named after the hook, run from the
+// top of the working tree. A hook that exits non-zero stops the command. `--no-verify` skips them, unless a repository involved sets
+// `hooks.enforce = true` and the branch being updated is protected there
+// (`branch.<name>.protect`), so CI and shared remotes can insist on them.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Hook {
+    // Before a commit is created; no arguments
+    PreCommit,
+    // With the path of a file holding the message, which the hook may edit
+    CommitMsg,
+    // With the remote name and URL, and a `<local ref> <local id> <remote
+    // ref> <remote id>` line on stdin for the ref being pushed
+    PrePush,
+    // In the repository receiving a push, before its ref moves, with an
+    // `<old id> <new id> <ref>` line on stdin; not skipped by --no-verify
+    PreReceive,
+}
+
+impl Hook {
+    pub fn name(self) -> &'static str {
+        match self {
+            Hook::PreCommit => "pre-commit",
+            Hook::CommitMsg => "commit-msg",
+            Hook::PrePush => "pre-push",
+            Hook::PreReceive => "pre-receive",
+        }
+    }
+}
+
+// What running (or skipping) a hook guards
+pub struct HookContext<'a> {
+    pub hook: Hook,
+    // Branch the command is about to update; for a push, the remote's
+    pub branch: &'a str,
+    pub args: Vec<String>,
+    pub stdin: Option<String>,
+    pub env: Vec<(String, String)>,
+    // Repositories whose `hooks.enforce` applies besides `repo` itself, such
+    // as the remote a push goes to
+    pub enforced_by: Vec<&'a Repository>,
+}
+
+impl<'a> HookContext<'a> {
+    pub fn new(hook: Hook, branch: &'a str) -> Self {
+        Self {
+            hook,
+            branch,
+            args: Vec::new(),
+            stdin: None,
+            env: Vec::new(),
+            enforced_by: Vec::new(),
+        }
+    }
+}
+
+pub fn run(repo: &Repository, context: &HookContext, no_verify: bool) -> Result<()> {
+    if no_verify {
+        return check_bypass(repo, context);
+    }
+
+    let path = hook_path(repo, context.hook);
+    if !utils::is_executable(&path) {
+        return Ok(());
+    }
+
+    let mut child = Command::new(&path)
+        .args(&context.args)
+        .envs(context.env.iter().map(|(key, value)| (key, value)))
+        .current_dir(&repo.work_dir)
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {} hook: {}", context.hook.name(), e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        // A hook that doesn't read its input may already have exited
+        let _ = stdin.write_all(context.stdin.as_deref().unwrap_or("").as_bytes());
+    }
+    let status = child.wait()?;
+    if !status.success() {
+        let (action, bypass) = match context.hook {
+            Hook::PrePush => ("push", "; use --no-verify to bypass"),
+            Hook::PreReceive => ("push", ""),
+            _ => ("commit", "; use --no-verify to bypass"),
+        };
+        return Err(format!(
+            "{} hook rejected the {} to '{}' ({}){}",
+            context.hook.name(),
+            action,
+            context.branch,
+            status,
+            bypass
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Run commit-msg on `message` via .mini_git/COMMIT_EDITMSG and return the
+// message as the hook left it
+pub fn commit_msg(
+    repo: &Repository,
+    branch: &str,
+    message: String,
+    no_verify: bool,
+) -> Result<String> {
+    let mut context = HookContext::new(Hook::CommitMsg, branch);
+    if no_verify || !utils::is_executable(&hook_path(repo, Hook::CommitMsg)) {
+        run(repo, &context, no_verify)?;
+        return Ok(message);
+    }
+
+    // Newline-terminated like any text file, so a hook can append lines
+    let path = repo.git_dir.join("COMMIT_EDITMSG");
+    fs::write(&path, format!("{}\n", message.trim_end_matches('\n')))?;
+    context.args.push(path.to_string_lossy().to_string());
+    run(repo, &context, false)?;
+    Ok(fs::read_to_string(&path)?
+        .trim_end_matches('\n')
+        .to_string())
+}
+
+// Refuse `--no-verify` where a repository enforces hooks on the branch
+fn check_bypass(repo: &Repository, context: &HookContext) -> Result<()> {
+    for enforcing in std::iter::once(repo).chain(context.enforced_by.iter().copied()) {
+        let config = Config::load(enforcing)?;
+        let enforced = config.get_bool("hooks.enforce").unwrap_or(false);
+        let protected = config
+            .get_bool(&format!("branch.{}.protect", context.branch))
+            .unwrap_or(false);
+        if enforced && protected {
+            return Err(format!(
+                "Cannot skip the {} hook: branch '{}' is protected and {} sets hooks.enforce",
+                context.hook.name(),
+                context.branch,
+                enforcing.work_dir.display()
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+fn hook_path(repo: &Repository, hook: Hook) -> PathBuf {
+    repo.git_dir.join("hooks").join(hook.name())
+}
@@ -0,0 +1,203 @@
+use crate::{Index, Repository, Result, config::Config, config::glob_match, utils};
+use std::fs;
+use std::path::PathBuf;
+
+// Ignore rules, gitignore-style, one pattern per line:
+//
+//   *.log        a file or directory with this name anywhere
+//   build/       only directories (and so everything under them)
+//   docs/*.html  patterns containing `/` are relative to the ignore file
+//   !keep.log    re-include something an earlier pattern ignored
+//   # comment    blank lines and comments are skipped (`\#` and `\!` escape)
+//
+// They come from, lowest precedence first: the global excludes file
+// (`core.excludesFile`, else `$XDG_CONFIG_HOME/mini_git/ignore` or
+// `~/.config/mini_git/ignore`), `.mini_git/info/exclude`, and every
+// `.mini_gitignore` in the working tree, a directory's file taking
+// precedence over its parents'. The last pattern matching a path decides.
+// As in git, nothing inside an ignored directory can be re-included.
+//
+// Tracked files are never ignored; the rules only decide which untracked
+// paths are worth mentioning, adding or stashing.
+
+pub const IGNORE_FILE: &str = ".mini_gitignore";
+
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    // Trailing `/`: matches directories only
+    dir_only: bool,
+    // Contains a `/`: matched against the whole path, not just the name
+    anchored: bool,
+    // Leading `!`: un-ignores what it matches
+    negated: bool,
+    // Directory of the file the pattern came from; it only applies below it
+    base: String,
+}
+
+impl Pattern {
+    fn matches(&self, path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let relative = if self.base.is_empty() {
+            path
+        } else {
+            match path
+                .strip_prefix(self.base.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            {
+                Some(rest) => rest,
+                None => return false,
+            }
+        };
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), relative.as_bytes())
+        } else {
+            let name = relative.rsplit('/').next().unwrap_or(relative);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    patterns: Vec<Pattern>,
+}
+
+impl IgnoreRules {
+    pub fn load(repo: &Repository) -> Result<Self> {
+        let mut rules = IgnoreRules::default();
+        if let Some(path) = global_excludes_file(repo)?
+            && path.is_file()
+        {
+            rules.add(&fs::read_to_string(path)?, "");
+        }
+        let exclude = repo.git_dir.join("info").join("exclude");
+        if exclude.is_file() {
+            rules.add(&fs::read_to_string(exclude)?, "");
+        }
+        rules.add_tree_files(repo, "")?;
+        Ok(rules)
+    }
+
+    pub fn parse(content: &str) -> Self {
+        let mut rules = IgnoreRules::default();
+        rules.add(content, "");
+        rules
+    }
+
+    // Patterns from an ignore file in the directory `base`
+    fn add(&mut self, content: &str, base: &str) {
+        for line in content.lines().map(str::trim_end) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (line, negated) = match line.strip_prefix('!') {
+                Some(line) => (line, true),
+                None => (line.strip_prefix('\\').unwrap_or(line), false),
+            };
+            let (line, dir_only) = match line.strip_suffix('/') {
+                Some(line) => (line, true),
+                None => (line, false),
+            };
+            self.patterns.push(Pattern {
+                glob: line.trim_start_matches('/').to_string(),
+                dir_only,
+                anchored: line.contains('/'),
+                negated,
+                base: base.to_string(),
+            });
+        }
+    }
+
+    // Read `dir`'s ignore file, then its subdirectories' (skipping ignored
+    // ones, whose files can't un-ignore anything)
+    fn add_tree_files(&mut self, repo: &Repository, dir: &str) -> Result<()> {
+        let dir_path = if dir.is_empty() {
+            repo.work_dir.clone()
+        } else {
+            repo.work_dir.join(dir)
+        };
+        let ignore_file = dir_path.join(IGNORE_FILE);
+        if ignore_file.is_file() {
+            self.add(&fs::read_to_string(ignore_file)?, dir);
+        }
+
+        let mut subdirs = Vec::new();
+        for entry in fs::read_dir(&dir_path)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() && entry.path() != repo.git_dir {
+                let name = entry.file_name().to_string_lossy().to_string();
+                subdirs.push(if dir.is_empty() {
+                    name
+                } else {
+                    format!("{}/{}", dir, name)
+                });
+            }
+        }
+        subdirs.sort();
+        for subdir in subdirs {
+            if !self.matches(&subdir, true) {
+                self.add_tree_files(repo, &subdir)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Whether `path` (relative, `/`-separated) is ignored by a pattern
+    // itself, without looking at its parent directories
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|pattern| pattern.matches(path, is_dir))
+            .is_some_and(|pattern| !pattern.negated)
+    }
+
+    // The outermost ignored directory of `path`, if any
+    pub fn ignored_parent<'a>(&self, path: &'a str) -> Option<&'a str> {
+        path.match_indices('/')
+            .map(|(i, _)| &path[..i])
+            .find(|dir| self.matches(dir, true))
+    }
+
+    // Whether `path` is ignored, either itself or through a parent directory
+    pub fn is_ignored(&self, path: &str) -> bool {
+        self.ignored_parent(path).is_some() || self.matches(path, false)
+    }
+}
+
+// `core.excludesFile`, or `$XDG_CONFIG_HOME/mini_git/ignore`, or
+// `~/.config/mini_git/ignore`
+fn global_excludes_file(repo: &Repository) -> Result<Option<PathBuf>> {
+    if let Some(path) = Config::load(repo)?.get("core.excludesFile") {
+        return Ok(Some(match path.strip_prefix("~/") {
+            Some(rest) => match std::env::var_os("HOME") {
+                Some(home) => PathBuf::from(home).join(rest),
+                None => PathBuf::from(path),
+            },
+            None => PathBuf::from(path),
+        }));
+    }
+    if let Some(config_home) = std::env::var_os("XDG_CONFIG_HOME") {
+        return Ok(Some(
+            PathBuf::from(config_home).join("mini_git").join("ignore"),
+        ));
+    }
+    Ok(std::env::var_os("HOME").map(|home| {
+        PathBuf::from(home)
+            .join(".config")
+            .join("mini_git")
+            .join("ignore")
+    }))
+}
+
+// Working tree files that are neither tracked nor ignored
+pub fn untracked_files(repo: &Repository, index: &Index) -> Result<Vec<String>> {
+    let rules = IgnoreRules::load(repo)?;
+    Ok(utils::list_working_files(repo, None)?
+        .into_iter()
+        .filter(|path| !index.entries.contains_key(path) && !rules.is_ignored(path))
+        .collect())
+}
@@ -0,0 +1,883 @@
+use crate::{
+    CachedDirectory, Index, IndexEntry, Repository, Result, StageEntry, UnmergedEntry,
+    UntrackedCache, config::Config, hash::HashAlgorithm,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Write;
+use std::ops::{Deref, DerefMut};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// On-disk layout (all integers big-endian):
+//
+//   "MIDX" | version u32 | hash length u8 | entry count u32
+//   entries, sorted by path:
+//     mode u32 | mtime secs u64 | mtime nanos u32 | size u64 | flags u16
+//     | raw hash | path length u16 | path bytes
+//   extensions: signature [u8; 4] | payload length u32 | payload
+//     UNMG, conflicted paths: count u32, then per path its path and three
+//     stages (base, ours, theirs) of mode u32 | raw hash, mode 0 and no hash
+//     for a missing stage
+//   SHA-1 of everything above
+//
+// Entry hashes are as long as the repository's object format needs; the
+// trailing checksum only guards the file itself and is always SHA-1.
+//
+// Older repositories wrote the index as JSON, and version 1 had no flags;
+// those files are still read and get rewritten in this format on the next
+// save.
+//
+// Writers take .mini_git/index.lock, write the new index into it and rename it
+// over the old one, so readers never wait and never see a half-written file.
+// Writers wait for each other, but `add` of individual files holds the lock
+// only while splicing its entries into the existing file (see stage_entries),
+// and status drops its cache refresh rather than wait. A command that changes
+// the index reads it under the lock too (see lock_index), so nothing another
+// writer saves in between is overwritten.
+const SIGNATURE: &[u8; 4] = b"MIDX";
+const VERSION: u32 = 2;
+const FLAG_ASSUME_UNCHANGED: u16 = 0x8000;
+const FLAG_SKIP_WORKTREE: u16 = 0x4000;
+const FLAG_INTENT_TO_ADD: u16 = 0x2000;
+const CHECKSUM_LEN: usize = 20;
+const EXT_LINK: &[u8; 4] = b"LINK";
+const EXT_UNTRACKED: &[u8; 4] = b"UNTR";
+const EXT_UNMERGED: &[u8; 4] = b"UNMG";
+// mode, mtime secs, mtime nanos, size and flags, before an entry's hash
+const ENTRY_STAT_LEN: usize = 26;
+const LOCK_TIMEOUT: Duration = Duration::from_secs(1);
+
+// With core.splitIndex enabled, most entries live in a shared base file
+// (.mini_git/sharedindex.<hash>) that is rarely rewritten, and the index file
+// itself only records what changed since that base.
+#[derive(Debug, Default, serde::Deserialize)]
+struct IndexFile {
+    entries: HashMap<String, IndexEntry>,
+    #[serde(default)]
+    removed: Vec<String>,
+    #[serde(default)]
+    shared_index: Option<String>,
+    #[serde(default)]
+    untracked_cache: Option<UntrackedCache>,
+    #[serde(default)]
+    unmerged: HashMap<String, UnmergedEntry>,
+}
+
+// Rewrite the shared base once the delta exceeds this share of it
+const DEFAULT_MAX_PERCENT_CHANGE: usize = 20;
+
+pub fn read_index(repo: &Repository) -> Result<Index> {
+    let index_path = repo.git_dir.join("index");
+    if !index_path.exists() {
+        return Ok(Index::default());
+    }
+
+    let file = decode_index_file(&fs::read(index_path)?)?;
+
+    let mut entries = match &file.shared_index {
+        Some(hash) => read_shared_index(repo, hash)?,
+        None => HashMap::new(),
+    };
+    for path in &file.removed {
+        entries.remove(path);
+    }
+    entries.extend(file.entries);
+
+    Ok(Index {
+        entries,
+        untracked_cache: file.untracked_cache,
+        unmerged: file.unmerged,
+    })
+}
+
+pub fn write_index(repo: &Repository, index: &Index) -> Result<()> {
+    let lock = IndexLock::acquire(repo)?;
+    write_locked(repo, index, lock)
+}
+
+// The index, read under its lock to be changed and saved. Dropping it unsaved
+// releases the lock and leaves the index as it was.
+pub struct LockedIndex {
+    index: Index,
+    lock: IndexLock,
+}
+
+pub fn lock_index(repo: &Repository) -> Result<LockedIndex> {
+    let lock = IndexLock::acquire(repo)?;
+    Ok(LockedIndex {
+        index: read_index(repo)?,
+        lock,
+    })
+}
+
+impl LockedIndex {
+    pub fn save(self, repo: &Repository) -> Result<()> {
+        write_locked(repo, &self.index, self.lock)
+    }
+}
+
+impl Deref for LockedIndex {
+    type Target = Index;
+
+    fn deref(&self) -> &Index {
+        &self.index
+    }
+}
+
+impl DerefMut for LockedIndex {
+    fn deref_mut(&mut self) -> &mut Index {
+        &mut self.index
+    }
+}
+
+// Write an index whose only changes are cached data, unless another writer
+// holds the lock or has replaced the index since `index` was read: the cache
+// isn't worth waiting for, or undoing someone's update for.
+pub fn write_index_if_idle(repo: &Repository, index: &Index) -> Result<()> {
+    let Some(lock) = IndexLock::try_acquire(repo)? else {
+        return Ok(());
+    };
+    if !same_entries(&read_index(repo)?, index) {
+        return Ok(());
+    }
+    write_locked(repo, index, lock)
+}
+
+fn same_entries(a: &Index, b: &Index) -> bool {
+    a.entries.len() == b.entries.len()
+        && a.unmerged.len() == b.unmerged.len()
+        && a.entries.iter().all(|(path, entry)| {
+            b.entries.get(path).is_some_and(|other| {
+                other.hash == entry.hash
+                    && other.mode == entry.mode
+                    && other.assume_unchanged == entry.assume_unchanged
+                    && other.skip_worktree == entry.skip_worktree
+                    && other.intent_to_add == entry.intent_to_add
+            })
+        })
+        && a.unmerged.keys().all(|path| b.unmerged.contains_key(path))
+}
+
+fn write_locked(repo: &Repository, index: &Index, lock: IndexLock) -> Result<()> {
+    let config = Config::load(repo)?;
+    let algorithm = HashAlgorithm::of_repo(repo)?;
+    let file = if split_index_enabled(&config) {
+        let max_percent = config
+            .get("splitIndex.maxPercentChange")
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_MAX_PERCENT_CHANGE);
+        split_index(repo, algorithm, index, max_percent)?
+    } else {
+        remove_shared_indexes(repo, None)?;
+        IndexFile {
+            entries: index.entries.clone(),
+            untracked_cache: index.untracked_cache.clone(),
+            unmerged: index.unmerged.clone(),
+            ..Default::default()
+        }
+    };
+
+    lock.commit(&encode_index_file(&file, algorithm)?)
+}
+
+fn split_index_enabled(config: &Config) -> bool {
+    config.get_bool("core.splitIndex").unwrap_or(false)
+}
+
+// Stage `entries` the way `add` does: each replaces the entry for its path,
+// marks a conflict there resolved and stops tracking empty directories above
+// it. When the index is a plain binary file, the new entries are spliced into
+// it and everything else is copied over without being decoded, all under the
+// lock, so updates from concurrent writers aren't lost.
+pub fn stage_entries(repo: &Repository, mut entries: Vec<IndexEntry>) -> Result<()> {
+    let lock = IndexLock::acquire(repo)?;
+    entries.sort_by(|a, b| a.path.cmp(&b.path));
+    let algorithm = HashAlgorithm::of_repo(repo)?;
+    if !split_index_enabled(&Config::load(repo)?)
+        && let Some(view) = LazyIndex::open(repo)?
+        && view.hash_len == algorithm.raw_len()
+        && let Some(content) = view.splice(&entries)?
+    {
+        return lock.commit(&content);
+    }
+
+    let mut index = read_index(repo)?;
+    for entry in entries {
+        stage_entry(&mut index, entry);
+    }
+    write_locked(repo, &index, lock)
+}
+
+pub(crate) fn stage_entry(index: &mut Index, entry: IndexEntry) {
+    let path = entry.path.clone();
+    index.unmerged.remove(&path);
+    forget_parent_dirs(index, &path);
+    index.entries.insert(path, entry);
+}
+
+// A directory holding a file is no longer empty, so stop tracking it as such
+pub(crate) fn forget_parent_dirs(index: &mut Index, relative_path: &str) {
+    for (i, _) in relative_path.match_indices('/') {
+        if index
+            .entries
+            .get(&relative_path[..i])
+            .is_some_and(|entry| entry.is_dir())
+        {
+            index.entries.remove(&relative_path[..i]);
+        }
+    }
+}
+
+// The index entry for `path`, decoding only that entry when possible
+pub fn read_entry(repo: &Repository, path: &str) -> Result<Option<IndexEntry>> {
+    match LazyIndex::open(repo)? {
+        Some(view) => view.get(path),
+        None => Ok(read_index(repo)?.entries.remove(path)),
+    }
+}
+
+// Exclusive right to replace the index, held as .mini_git/index.lock. The
+// file is removed when the lock is dropped without being committed.
+struct IndexLock {
+    path: PathBuf,
+    file: Option<fs::File>,
+}
+
+impl IndexLock {
+    // Wait a little for another writer to finish
+    fn acquire(repo: &Repository) -> Result<Self> {
+        let start = Instant::now();
+        loop {
+            if let Some(lock) = Self::try_acquire(repo)? {
+                return Ok(lock);
+            }
+            if start.elapsed() >= LOCK_TIMEOUT {
+                return Err(format!(
+                    "Unable to create '{}': another mini_git process seems to be \
+                     writing the index (if it has died, remove the file and try again)",
+                    lock_path(repo).display()
+                )
+                .into());
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    fn try_acquire(repo: &Repository) -> Result<Option<Self>> {
+        let path = lock_path(repo);
+        match fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&path)
+        {
+            Ok(file) => Ok(Some(IndexLock {
+                path,
+                file: Some(file),
+            })),
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Make `content` the index and release the lock
+    fn commit(mut self, content: &[u8]) -> Result<()> {
+        let mut file = self.file.take().ok_or("Index lock already released")?;
+        file.write_all(content)?;
+        file.sync_all()?;
+        drop(file);
+        fs::rename(&self.path, self.path.with_file_name("index"))?;
+        Ok(())
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+fn lock_path(repo: &Repository) -> PathBuf {
+    repo.git_dir.join("index.lock")
+}
+
+fn split_index(
+    repo: &Repository,
+    algorithm: HashAlgorithm,
+    index: &Index,
+    max_percent: usize,
+) -> Result<IndexFile> {
+    let current_base = current_shared_index(repo)?;
+    let base_entries = match &current_base {
+        Some(hash) => read_shared_index(repo, hash)?,
+        None => HashMap::new(),
+    };
+
+    let changed: HashMap<String, IndexEntry> = index
+        .entries
+        .iter()
+        .filter(|(path, entry)| {
+            base_entries.get(*path).is_none_or(|base| {
+                base.hash != entry.hash
+                    || base.mode != entry.mode
+                    || base.size != entry.size
+                    || base.mtime_secs != entry.mtime_secs
+                    || base.mtime_nanos != entry.mtime_nanos
+                    || base.assume_unchanged != entry.assume_unchanged
+                    || base.skip_worktree != entry.skip_worktree
+                    || base.intent_to_add != entry.intent_to_add
+            })
+        })
+        .map(|(path, entry)| (path.clone(), entry.clone()))
+        .collect();
+    let mut removed: Vec<String> = base_entries
+        .keys()
+        .filter(|path| !index.entries.contains_key(*path))
+        .cloned()
+        .collect();
+    removed.sort();
+
+    let delta_size = changed.len() + removed.len();
+    if current_base.is_some() && delta_size * 100 <= base_entries.len() * max_percent {
+        return Ok(IndexFile {
+            entries: changed,
+            removed,
+            shared_index: current_base,
+            untracked_cache: index.untracked_cache.clone(),
+            unmerged: index.unmerged.clone(),
+        });
+    }
+
+    // The delta has grown too large (or there is no base yet): fold
+    // everything into a fresh shared base and start with an empty delta
+    let content = encode_index_file(
+        &IndexFile {
+            entries: index.entries.clone(),
+            ..Default::default()
+        },
+        algorithm,
+    )?;
+    let hash = algorithm.hash(&content);
+    let temp = repo.git_dir.join(format!("sharedindex.{}.tmp", hash));
+    fs::write(&temp, content)?;
+    fs::rename(temp, shared_index_path(repo, &hash))?;
+    remove_shared_indexes(repo, Some(&hash))?;
+
+    Ok(IndexFile {
+        entries: HashMap::new(),
+        removed: Vec::new(),
+        shared_index: Some(hash),
+        untracked_cache: index.untracked_cache.clone(),
+        unmerged: index.unmerged.clone(),
+    })
+}
+
+fn current_shared_index(repo: &Repository) -> Result<Option<String>> {
+    let index_path = repo.git_dir.join("index");
+    if !index_path.exists() {
+        return Ok(None);
+    }
+
+    let file = decode_index_file(&fs::read(index_path)?)?;
+    Ok(file
+        .shared_index
+        .filter(|hash| shared_index_path(repo, hash).exists()))
+}
+
+fn read_shared_index(repo: &Repository, hash: &str) -> Result<HashMap<String, IndexEntry>> {
+    let path = shared_index_path(repo, hash);
+    let content = fs::read(&path)
+        .map_err(|e| format!("Cannot read shared index {}: {}", path.display(), e))?;
+
+    if content.starts_with(SIGNATURE) {
+        Ok(decode_index_file(&content)?.entries)
+    } else {
+        Ok(serde_json::from_slice(&content)?)
+    }
+}
+
+fn shared_index_path(repo: &Repository, hash: &str) -> std::path::PathBuf {
+    repo.git_dir.join(format!("sharedindex.{}", hash))
+}
+
+fn remove_shared_indexes(repo: &Repository, keep: Option<&str>) -> Result<()> {
+    for entry in fs::read_dir(&repo.git_dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(hash) = name.strip_prefix("sharedindex.")
+            && Some(hash) != keep
+        {
+            fs::remove_file(entry.path())?;
+        }
+    }
+    Ok(())
+}
+
+fn encode_index_file(file: &IndexFile, algorithm: HashAlgorithm) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    out.extend_from_slice(SIGNATURE);
+    out.extend_from_slice(&VERSION.to_be_bytes());
+    out.push(algorithm.raw_len() as u8);
+    out.extend_from_slice(&(file.entries.len() as u32).to_be_bytes());
+
+    let mut paths: Vec<&String> = file.entries.keys().collect();
+    paths.sort();
+    for path in paths {
+        encode_entry(&mut out, path, &file.entries[path])?;
+    }
+
+    if let Some(shared) = &file.shared_index {
+        let mut payload = hex_to_bytes(shared)?;
+        payload.extend_from_slice(&(file.removed.len() as u32).to_be_bytes());
+        for path in &file.removed {
+            write_str(&mut payload, path)?;
+        }
+        write_extension(&mut out, EXT_LINK, &payload);
+    }
+
+    if let Some(cache) = &file.untracked_cache {
+        let mut payload = Vec::new();
+        let mut dirs: Vec<&String> = cache.directories.keys().collect();
+        dirs.sort();
+        payload.extend_from_slice(&(dirs.len() as u32).to_be_bytes());
+        for dir in dirs {
+            let listing = &cache.directories[dir];
+            write_str(&mut payload, dir)?;
+            payload.extend_from_slice(&listing.mtime_secs.to_be_bytes());
+            payload.extend_from_slice(&listing.mtime_nanos.to_be_bytes());
+            write_str_list(&mut payload, &listing.files)?;
+            write_str_list(&mut payload, &listing.subdirs)?;
+        }
+        write_extension(&mut out, EXT_UNTRACKED, &payload);
+    }
+
+    if !file.unmerged.is_empty() {
+        let mut payload = Vec::new();
+        let mut paths: Vec<&String> = file.unmerged.keys().collect();
+        paths.sort();
+        payload.extend_from_slice(&(paths.len() as u32).to_be_bytes());
+        for path in paths {
+            write_str(&mut payload, path)?;
+            for stage in 1..=3 {
+                match file.unmerged[path].stage(stage) {
+                    Some(entry) => {
+                        let mode = u32::from_str_radix(&entry.mode, 8)
+                            .map_err(|_| format!("Invalid mode '{}' for {}", entry.mode, path))?;
+                        payload.extend_from_slice(&mode.to_be_bytes());
+                        payload.extend_from_slice(&hex_to_bytes(&entry.hash)?);
+                    }
+                    None => payload.extend_from_slice(&0u32.to_be_bytes()),
+                }
+            }
+        }
+        write_extension(&mut out, EXT_UNMERGED, &payload);
+    }
+
+    let checksum = HashAlgorithm::Sha1.hash(&out);
+    out.extend_from_slice(&hex_to_bytes(&checksum)?);
+    Ok(out)
+}
+
+fn encode_entry(out: &mut Vec<u8>, path: &str, entry: &IndexEntry) -> Result<()> {
+    let mode = u32::from_str_radix(&entry.mode, 8)
+        .map_err(|_| format!("Invalid mode '{}' for {}", entry.mode, path))?;
+    out.extend_from_slice(&mode.to_be_bytes());
+    out.extend_from_slice(&entry.mtime_secs.to_be_bytes());
+    out.extend_from_slice(&entry.mtime_nanos.to_be_bytes());
+    out.extend_from_slice(&entry.size.to_be_bytes());
+    let mut flags = 0;
+    if entry.assume_unchanged {
+        flags |= FLAG_ASSUME_UNCHANGED;
+    }
+    if entry.skip_worktree {
+        flags |= FLAG_SKIP_WORKTREE;
+    }
+    if entry.intent_to_add {
+        flags |= FLAG_INTENT_TO_ADD;
+    }
+    out.extend_from_slice(&flags.to_be_bytes());
+    out.extend_from_slice(&hex_to_bytes(&entry.hash)?);
+    write_str(out, path)
+}
+
+// Check the trailing checksum and return what it covers
+fn checked_body(data: &[u8]) -> Result<&[u8]> {
+    if data.len() < SIGNATURE.len() + CHECKSUM_LEN {
+        return Err("Corrupt index: file is truncated".into());
+    }
+    let (body, trailer) = data.split_at(data.len() - CHECKSUM_LEN);
+    if hex_to_bytes(&HashAlgorithm::Sha1.hash(body))? != trailer {
+        return Err("Corrupt index: checksum mismatch".into());
+    }
+    Ok(body)
+}
+
+fn decode_index_file(data: &[u8]) -> Result<IndexFile> {
+    if !data.starts_with(SIGNATURE) {
+        // Pre-binary index, kept readable so existing repositories migrate
+        return Ok(serde_json::from_slice(data)?);
+    }
+
+    let body = checked_body(data)?;
+    let mut reader = Reader { data: body, pos: 4 };
+    let version = reader.u32()?;
+    if version != 1 && version != VERSION {
+        return Err(format!("Unsupported index version {}", version).into());
+    }
+    let hash_len = reader.u8()? as usize;
+
+    let mut file = IndexFile::default();
+    let count = reader.u32()?;
+    for _ in 0..count {
+        let entry = decode_entry(&mut reader, version, hash_len)?;
+        file.entries.insert(entry.path.clone(), entry);
+    }
+
+    while reader.pos < body.len() {
+        let signature: [u8; 4] = reader.bytes(4)?.try_into()?;
+        let length = reader.u32()? as usize;
+        let mut ext = Reader {
+            data: reader.bytes(length)?,
+            pos: 0,
+        };
+
+        match &signature {
+            EXT_LINK => {
+                file.shared_index = Some(bytes_to_hex(ext.bytes(hash_len)?));
+                let removed = ext.u32()?;
+                for _ in 0..removed {
+                    file.removed.push(ext.string()?);
+                }
+            }
+            EXT_UNTRACKED => {
+                let mut cache = UntrackedCache::default();
+                let dirs = ext.u32()?;
+                for _ in 0..dirs {
+                    let dir = ext.string()?;
+                    let mtime_secs = ext.u64()?;
+                    let mtime_nanos = ext.u32()?;
+                    let files = ext.string_list()?;
+                    let subdirs = ext.string_list()?;
+                    cache.directories.insert(
+                        dir,
+                        CachedDirectory {
+                            mtime_secs,
+                            mtime_nanos,
+                            files,
+                            subdirs,
+                        },
+                    );
+                }
+                file.untracked_cache = Some(cache);
+            }
+            EXT_UNMERGED => {
+                let count = ext.u32()?;
+                for _ in 0..count {
+                    let path = ext.string()?;
+                    let mut stages = Vec::with_capacity(3);
+                    for _ in 0..3 {
+                        let mode = ext.u32()?;
+                        stages.push(if mode == 0 {
+                            None
+                        } else {
+                            Some(StageEntry {
+                                mode: format!("{:o}", mode),
+                                hash: bytes_to_hex(ext.bytes(hash_len)?),
+                            })
+                        });
+                    }
+                    let theirs = stages.pop().flatten();
+                    let ours = stages.pop().flatten();
+                    let base = stages.pop().flatten();
+                    file.unmerged
+                        .insert(path, UnmergedEntry { base, ours, theirs });
+                }
+            }
+            // Unknown extensions are optional caches; skip them
+            _ => {}
+        }
+    }
+
+    Ok(file)
+}
+
+fn decode_entry(reader: &mut Reader, version: u32, hash_len: usize) -> Result<IndexEntry> {
+    let mode = reader.u32()?;
+    let mtime_secs = reader.u64()?;
+    let mtime_nanos = reader.u32()?;
+    let size = reader.u64()?;
+    let flags = if version >= 2 { reader.u16()? } else { 0 };
+    let hash = bytes_to_hex(reader.bytes(hash_len)?);
+    let path = reader.string()?;
+    Ok(IndexEntry {
+        hash,
+        mode: format!("{:o}", mode),
+        path,
+        mtime_secs,
+        mtime_nanos,
+        size,
+        assume_unchanged: flags & FLAG_ASSUME_UNCHANGED != 0,
+        skip_worktree: flags & FLAG_SKIP_WORKTREE != 0,
+        intent_to_add: flags & FLAG_INTENT_TO_ADD != 0,
+    })
+}
+
+// A binary index whose entries are only located when it is opened, and
+// decoded one at a time as they are looked up: commands that need a few
+// paths don't pay for decoding a large index.
+pub struct LazyIndex {
+    body: Vec<u8>,
+    hash_len: usize,
+    // Where each entry starts, in path order
+    offsets: Vec<usize>,
+    // Where the extensions start
+    entries_end: usize,
+    // Paths the UNMG extension holds
+    unmerged: Vec<String>,
+}
+
+impl LazyIndex {
+    // None when there is no index, or it is in an older format or split, so
+    // callers have to read it whole
+    pub fn open(repo: &Repository) -> Result<Option<Self>> {
+        let mut data = match fs::read(repo.git_dir.join("index")) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        if !data.starts_with(SIGNATURE) {
+            return Ok(None);
+        }
+        let body_len = checked_body(&data)?.len();
+        data.truncate(body_len);
+
+        let mut reader = Reader {
+            data: &data,
+            pos: 4,
+        };
+        if reader.u32()? != VERSION {
+            return Ok(None);
+        }
+        let hash_len = reader.u8()? as usize;
+        let count = reader.u32()? as usize;
+        let mut offsets = Vec::with_capacity(count);
+        for _ in 0..count {
+            offsets.push(reader.pos);
+            reader.bytes(ENTRY_STAT_LEN + hash_len)?;
+            let len = reader.u16()? as usize;
+            reader.bytes(len)?;
+        }
+        let entries_end = reader.pos;
+
+        let (mut unmerged, mut split) = (Vec::new(), false);
+        while reader.pos < data.len() {
+            let signature: [u8; 4] = reader.bytes(4)?.try_into()?;
+            let length = reader.u32()? as usize;
+            let mut ext = Reader {
+                data: reader.bytes(length)?,
+                pos: 0,
+            };
+            match &signature {
+                EXT_LINK => split = true,
+                EXT_UNMERGED => {
+                    for _ in 0..ext.u32()? {
+                        unmerged.push(ext.string()?);
+                        for _ in 0..3 {
+                            if ext.u32()? != 0 {
+                                ext.bytes(hash_len)?;
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        if split {
+            return Ok(None);
+        }
+
+        Ok(Some(LazyIndex {
+            body: data,
+            hash_len,
+            offsets,
+            entries_end,
+            unmerged,
+        }))
+    }
+
+    fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn get(&self, path: &str) -> Result<Option<IndexEntry>> {
+        match self.find(path) {
+            Ok(i) => self.entry(i).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    fn find(&self, path: &str) -> std::result::Result<usize, usize> {
+        self.offsets
+            .binary_search_by(|&offset| self.path_at(offset).cmp(path.as_bytes()))
+    }
+
+    fn path_at(&self, offset: usize) -> &[u8] {
+        let start = offset + ENTRY_STAT_LEN + self.hash_len;
+        let len = u16::from_be_bytes([self.body[start], self.body[start + 1]]) as usize;
+        &self.body[start + 2..start + 2 + len]
+    }
+
+    fn entry(&self, i: usize) -> Result<IndexEntry> {
+        let mut reader = Reader {
+            data: &self.body,
+            pos: self.offsets[i],
+        };
+        decode_entry(&mut reader, VERSION, self.hash_len)
+    }
+
+    fn entry_end(&self, i: usize) -> usize {
+        self.offsets.get(i + 1).copied().unwrap_or(self.entries_end)
+    }
+
+    // The index file with `entries` (sorted by path) staged, as in
+    // stage_entry. None when a conflict would have to be resolved, which
+    // means rewriting the UNMG extension.
+    fn splice(&self, entries: &[IndexEntry]) -> Result<Option<Vec<u8>>> {
+        if entries
+            .iter()
+            .any(|entry| self.unmerged.contains(&entry.path))
+        {
+            return Ok(None);
+        }
+
+        // Tracked empty directories that now hold a file
+        let mut dropped = vec![false; self.len()];
+        for entry in entries {
+            for (i, _) in entry.path.match_indices('/') {
+                if let Ok(parent) = self.find(&entry.path[..i])
+                    && self.entry(parent)?.is_dir()
+                {
+                    dropped[parent] = true;
+                }
+            }
+        }
+
+        // The header up to the entry count, which is filled in at the end
+        let count_at = SIGNATURE.len() + 5;
+        let mut out = Vec::with_capacity(self.body.len() + CHECKSUM_LEN);
+        out.extend_from_slice(&self.body[..count_at]);
+        out.extend_from_slice(&[0; 4]);
+        let mut count: u32 = 0;
+        let mut next = 0;
+        for entry in entries {
+            let position = self.find(&entry.path);
+            let (Ok(end) | Err(end)) = position;
+            for i in (next..end).filter(|&i| !dropped[i]) {
+                out.extend_from_slice(&self.body[self.offsets[i]..self.entry_end(i)]);
+                count += 1;
+            }
+            encode_entry(&mut out, &entry.path, entry)?;
+            count += 1;
+            next = if position.is_ok() { end + 1 } else { end };
+        }
+        for i in (next..self.len()).filter(|&i| !dropped[i]) {
+            out.extend_from_slice(&self.body[self.offsets[i]..self.entry_end(i)]);
+            count += 1;
+        }
+        out[count_at..count_at + 4].copy_from_slice(&count.to_be_bytes());
+        out.extend_from_slice(&self.body[self.entries_end..]);
+
+        let checksum = HashAlgorithm::Sha1.hash(&out);
+        out.extend_from_slice(&hex_to_bytes(&checksum)?);
+        Ok(Some(out))
+    }
+}
+
+fn write_extension(out: &mut Vec<u8>, signature: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(signature);
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    out.extend_from_slice(payload);
+}
+
+fn write_str(out: &mut Vec<u8>, value: &str) -> Result<()> {
+    let len: u16 = value
+        .len()
+        .try_into()
+        .map_err(|_| format!("Path too long for index: {}", value))?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(value.as_bytes());
+    Ok(())
+}
+
+fn write_str_list(out: &mut Vec<u8>, values: &[String]) -> Result<()> {
+    out.extend_from_slice(&(values.len() as u32).to_be_bytes());
+    for value in values {
+        write_str(out, value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn hex_to_bytes(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(format!("Invalid object id '{}'", hex).into());
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            u8::from_str_radix(&hex[i..i + 2], 16)
+                .map_err(|_| format!("Invalid object id '{}'", hex).into())
+        })
+        .collect()
+}
+
+pub(crate) fn bytes_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self.pos + len;
+        if end > self.data.len() {
+            return Err(
+                format!("Corrupt index: unexpected end of data at byte {}", self.pos).into(),
+            );
+        }
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_be_bytes(self.bytes(8)?.try_into()?))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+
+    fn string_list(&mut self) -> Result<Vec<String>> {
+        let count = self.u32()?;
+        (0..count).map(|_| self.string()).collect()
+    }
+}
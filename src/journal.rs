@@ -0,0 +1,111 @@
+use crate::{Repository, Result, reflog};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+// Operation journal backing `undo`: one JSON line per ref-changing command
+// (commit, merge, pull, rebase, reset, revert, cherry-pick, branch changes)
+// recording what each ref pointed at before and after. Each update is also
+// appended to the ref's reflog.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RefUpdate {
+    pub name: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    pub operation: String,
+    pub description: String,
+    pub updates: Vec<RefUpdate>,
+    pub timestamp: DateTime<Utc>,
+}
+
+impl RefUpdate {
+    pub fn branch(branch: &str, old: Option<String>, new: Option<String>) -> Self {
+        RefUpdate {
+            name: format!("refs/heads/{}", branch),
+            old,
+            new,
+        }
+    }
+}
+
+pub fn record(
+    repo: &Repository,
+    operation: &str,
+    description: &str,
+    updates: Vec<RefUpdate>,
+) -> Result<()> {
+    let updates: Vec<RefUpdate> = updates.into_iter().filter(|u| u.old != u.new).collect();
+    if updates.is_empty() {
+        return Ok(());
+    }
+
+    let message = format!("{}: {}", operation, description);
+    for update in &updates {
+        log_update(repo, update, &message)?;
+    }
+
+    let entry = JournalEntry {
+        operation: operation.to_string(),
+        description: description.to_string(),
+        updates,
+        timestamp: Utc::now(),
+    };
+
+    let path = journal_path(repo);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    writeln!(file, "{}", serde_json::to_string(&entry)?)?;
+    Ok(())
+}
+
+// Oldest first
+pub fn entries(repo: &Repository) -> Result<Vec<JournalEntry>> {
+    let path = journal_path(repo);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut entries = Vec::new();
+    for line in fs::read_to_string(path)?.lines() {
+        if !line.trim().is_empty() {
+            entries.push(serde_json::from_str(line)?);
+        }
+    }
+    Ok(entries)
+}
+
+// Remove and return the most recent entry
+pub fn pop(repo: &Repository) -> Result<Option<JournalEntry>> {
+    let mut entries = entries(repo)?;
+    let last = entries.pop();
+
+    let mut content = String::new();
+    for entry in &entries {
+        content.push_str(&serde_json::to_string(entry)?);
+        content.push('\n');
+    }
+    fs::write(journal_path(repo), content)?;
+    Ok(last)
+}
+
+// Append a ref movement to its reflog; a deleted ref loses its log
+pub fn log_update(repo: &Repository, update: &RefUpdate, message: &str) -> Result<()> {
+    match &update.new {
+        Some(new) => reflog::append_branch(repo, &update.name, update.old.as_deref(), new, message),
+        None => reflog::remove(repo, &update.name),
+    }
+}
+
+fn journal_path(repo: &Repository) -> PathBuf {
+    repo.git_dir.join("logs").join("journal")
+}
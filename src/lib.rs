@@ -1,6 +1,37 @@
+pub mod access;
+pub mod bitmap;
+pub mod blame;
+pub mod bundle;
 pub mod commands;
+pub mod commit_graph;
+pub mod config;
+pub mod delta;
+pub mod fsck;
+pub mod git_object;
+pub mod graph;
+pub mod hash;
+pub mod hooks;
+pub mod ignore;
+pub mod index;
+pub mod journal;
+pub mod merge_file;
+pub mod mirror;
 pub mod object_store;
+pub mod packfile;
+pub mod plugin;
+pub mod pretty;
+pub mod profile;
+pub mod protocol;
+pub mod push_cert;
+pub mod reflog;
+pub mod renames;
+pub mod repo_path;
+pub mod revnum;
+pub mod sequencer;
+pub mod templates;
+pub mod transport;
 pub mod utils;
+pub mod worktree;
 
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
@@ -8,15 +39,88 @@ use std::collections::HashMap;
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "StoredCommit", into = "StoredCommit")]
 pub struct Commit {
     pub hash: String,
-    pub parent: Option<String>,
+    // First parent first; a merge has the merged tips after it
+    pub parents: Vec<String>,
     pub tree: String,
     pub author: String,
     pub message: String,
     pub timestamp: DateTime<Utc>,
 }
 
+impl Commit {
+    pub fn parent(&self) -> Option<&String> {
+        self.parents.first()
+    }
+}
+
+// A commit as JSON storage writes it. `parent` is the first parent, as it was
+// before merges kept the others; `parents` lists all of them, and only for
+// merges, so single-parent commits are stored exactly as they always were.
+#[derive(Serialize, Deserialize)]
+struct StoredCommit {
+    hash: String,
+    parent: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    parents: Vec<String>,
+    tree: String,
+    author: String,
+    message: String,
+    timestamp: DateTime<Utc>,
+}
+
+impl From<StoredCommit> for Commit {
+    fn from(stored: StoredCommit) -> Self {
+        let parents = if stored.parents.is_empty() {
+            stored.parent.into_iter().collect()
+        } else {
+            stored.parents
+        };
+        Commit {
+            hash: stored.hash,
+            parents,
+            tree: stored.tree,
+            author: stored.author,
+            message: stored.message,
+            timestamp: stored.timestamp,
+        }
+    }
+}
+
+impl From<Commit> for StoredCommit {
+    fn from(commit: Commit) -> Self {
+        let parents = if commit.parents.len() > 1 {
+            commit.parents.clone()
+        } else {
+            Vec::new()
+        };
+        StoredCommit {
+            hash: commit.hash,
+            parent: commit.parents.into_iter().next(),
+            parents,
+            tree: commit.tree,
+            author: commit.author,
+            message: commit.message,
+            timestamp: commit.timestamp,
+        }
+    }
+}
+
+// An annotated tag: a name for another object (normally a commit) with a
+// tagger, date and message of its own
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tag {
+    pub hash: String,
+    pub object: String,
+    pub object_type: String,
+    pub name: String,
+    pub tagger: String,
+    pub message: String,
+    pub timestamp: DateTime<Utc>,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tree {
     pub hash: String,
@@ -43,16 +147,99 @@ pub struct Repository {
     pub work_dir: PathBuf,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Index {
     pub entries: HashMap<String, IndexEntry>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub untracked_cache: Option<UntrackedCache>,
+    // Paths a merge left conflicted. Until one is resolved (by `add`), its
+    // entry above holds our version.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub unmerged: HashMap<String, UnmergedEntry>,
+}
+
+// The three versions of a conflicted path: stage 1 (the merge base), 2 (ours)
+// and 3 (theirs). A side that doesn't have the file has no stage.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UnmergedEntry {
+    pub base: Option<StageEntry>,
+    pub ours: Option<StageEntry>,
+    pub theirs: Option<StageEntry>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StageEntry {
+    pub mode: String,
+    pub hash: String,
+}
+
+impl UnmergedEntry {
+    pub fn stage(&self, stage: u8) -> Option<&StageEntry> {
+        match stage {
+            1 => self.base.as_ref(),
+            2 => self.ours.as_ref(),
+            3 => self.theirs.as_ref(),
+            _ => None,
+        }
+    }
+
+    // The stages present, as (stage number, entry)
+    pub fn stages(&self) -> impl Iterator<Item = (u8, &StageEntry)> {
+        (1..=3).filter_map(|stage| self.stage(stage).map(|entry| (stage, entry)))
+    }
+}
+
+// Directory listings keyed by path relative to the work tree ("" is the root).
+// A directory whose mtime is unchanged still has the same direct children, so
+// status can reuse the listing instead of reading the directory again.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct UntrackedCache {
+    pub directories: HashMap<String, CachedDirectory>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CachedDirectory {
+    pub mtime_secs: u64,
+    pub mtime_nanos: u32,
+    pub files: Vec<String>,
+    pub subdirs: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct IndexEntry {
     pub hash: String,
     pub mode: String,
     pub path: String,
+    // Stat data from when the entry was staged; zero when unknown
+    #[serde(default)]
+    pub mtime_secs: u64,
+    #[serde(default)]
+    pub mtime_nanos: u32,
+    #[serde(default)]
+    pub size: u64,
+    // Trust that the working copy still matches the index without checking
+    #[serde(default)]
+    pub assume_unchanged: bool,
+    // Leave the working copy alone, e.g. for local config overrides
+    #[serde(default)]
+    pub skip_worktree: bool,
+    // Recorded by `add -N`: the path is known but none of its content is
+    // staged yet, so commits leave it out
+    #[serde(default)]
+    pub intent_to_add: bool,
+}
+
+impl IndexEntry {
+    // Status, diff and add leave flagged entries alone until the flag is
+    // cleared with `update-index --no-assume-unchanged` / `--no-skip-worktree`
+    pub fn ignores_worktree(&self) -> bool {
+        self.assume_unchanged || self.skip_worktree
+    }
+
+    // A tracked empty directory rather than a file
+    pub fn is_dir(&self) -> bool {
+        self.mode == worktree::DIR_MODE
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -1,6 +1,14 @@
-use clap::{Parser, Subcommand};
-use mini_git::{Result, commands, utils};
+use clap::{ArgMatches, CommandFactory, FromArgMatches, Parser, Subcommand, parser::ValueSource};
+use mini_git::{
+    Result,
+    commands::{self, ConflictSide, RebaseControl, ResetMode},
+    config::Config,
+    merge_file::{ConflictStyle, Favor},
+    plugin, profile, utils,
+};
+use std::ffi::OsString;
 use std::path::PathBuf;
+use std::time::Instant;
 
 #[derive(Parser)]
 #[command(name = "mini_git")]
@@ -8,6 +16,12 @@ use std::path::PathBuf;
 struct Cli {
     #[command(subcommand)]
     command: Commands,
+    #[arg(
+        long,
+        global = true,
+        help = "Report time spent hashing, in object IO, walking trees and diffing"
+    )]
+    profile: bool,
 }
 
 #[derive(Subcommand)]
@@ -15,53 +29,348 @@ enum Commands {
     Init {
         #[arg(help = "Directory to initialize")]
         path: Option<PathBuf>,
+        #[arg(long, help = "Hash algorithm for objects (sha1 or sha256)")]
+        object_format: Option<String>,
+        #[arg(
+            long,
+            help = "How objects are stored: json, or git for git-readable loose objects"
+        )]
+        object_storage: Option<String>,
+        #[arg(
+            long = "with",
+            value_name = "TEMPLATE",
+            help = "Seed files, hooks and a first commit from a template (e.g. rust, python)"
+        )]
+        template: Option<String>,
     },
     Add {
         #[arg(help = "Files to add")]
         files: Vec<String>,
+        #[arg(
+            short = 'N',
+            long,
+            help = "Record new paths without staging their content"
+        )]
+        intent_to_add: bool,
     },
     Commit {
-        #[arg(short, long, help = "Commit message")]
-        message: String,
+        #[arg(
+            short,
+            long,
+            help = "Commit message (optional when concluding a merge)"
+        )]
+        message: Option<String>,
         #[arg(short, long, help = "Author")]
         author: Option<String>,
+        #[arg(long, help = "Create a 'fixup!' commit for the given revision")]
+        fixup: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "paths",
+            help = "Stage changes to all tracked files first"
+        )]
+        all: bool,
+        #[arg(
+            long,
+            requires = "paths",
+            help = "Commit only the given paths (the default when paths are given)"
+        )]
+        only: bool,
+        #[arg(help = "Commit the working tree state of only these paths")]
+        paths: Vec<String>,
+        #[arg(short = 'n', long, help = "Skip the pre-commit and commit-msg hooks")]
+        no_verify: bool,
+    },
+    Status {
+        #[arg(
+            long,
+            num_args = 0..=1,
+            default_missing_value = "traditional",
+            value_name = "MODE",
+            help = "Also list ignored files (traditional, matching or no)"
+        )]
+        ignored: Option<String>,
+        #[arg(long, help = "Output JSON")]
+        json: bool,
+        #[arg(long, help = "Don't write refreshed cache data back to the index")]
+        no_refresh: bool,
+        #[arg(
+            short = 'M',
+            long,
+            value_name = "N%",
+            num_args = 0..=1,
+            default_missing_value = "50%",
+            help = "Similarity a staged file needs to a deleted one to be a rename (default 50%)"
+        )]
+        find_renames: Option<String>,
     },
-    Status,
     Log {
+        #[arg(help = "Branch, tag or commit to start from (defaults to HEAD)")]
+        revision: Option<String>,
         #[arg(short, long, help = "Maximum number of commits to show")]
         max_count: Option<usize>,
+        #[arg(long, help = "Show each mainline commit's sequential revision number")]
+        show_revnum: bool,
+        #[arg(
+            long,
+            help = "Draw the history of branches and merges beside the commits"
+        )]
+        graph: bool,
+        #[arg(
+            long,
+            help = "Show each commit on one line: abbreviated id and subject"
+        )]
+        oneline: bool,
+        #[arg(
+            long,
+            conflicts_with = "oneline",
+            help = "Show each commit as a format string (%H %h %T %t %P %p %an %ae %ad %s %b %n)"
+        )]
+        format: Option<String>,
+        #[arg(
+            long,
+            help = "Show the files each commit changed, with lines added and removed"
+        )]
+        stat: bool,
+        #[arg(short, long, help = "Show what each commit changed as a unified diff")]
+        patch: bool,
+        #[arg(long, help = "Only commits whose author contains this")]
+        author: Option<String>,
+        #[arg(long, help = "Only commits made on or after this date (YYYY-MM-DD)")]
+        since: Option<String>,
+        #[arg(long, help = "Only commits made on or before this date (YYYY-MM-DD)")]
+        until: Option<String>,
+        #[arg(long, help = "Only commits whose message matches this regex")]
+        grep: Option<String>,
+        #[arg(last = true, help = "Only commits that changed these paths")]
+        paths: Vec<String>,
+    },
+    Blame {
+        #[arg(help = "File to annotate")]
+        file: String,
+        #[arg(long, help = "Commit to blame the file as of (defaults to HEAD)")]
+        rev: Option<String>,
     },
     Branch {
         #[arg(help = "Branch name")]
         name: Option<String>,
-        #[arg(short, long, help = "Delete branch")]
+        #[arg(short, long, help = "Delete branch (must be merged)")]
+        delete: bool,
+        #[arg(short = 'D', help = "Delete branch even if unmerged")]
+        force_delete: bool,
+        #[arg(short, long, help = "Move an existing branch to the current commit")]
+        force: bool,
+        #[arg(long, help = "Allow changing a protected branch")]
+        allow_protected: bool,
+    },
+    Tag {
+        #[arg(help = "Tag name (lists tags when omitted)")]
+        name: Option<String>,
+        #[arg(help = "Commit to tag (defaults to HEAD)")]
+        commit: Option<String>,
+        #[arg(short, long, help = "Create an annotated tag object")]
+        annotate: bool,
+        #[arg(short, long, help = "Tag message (implies --annotate)")]
+        message: Option<String>,
+        #[arg(short, long, help = "Delete the tag")]
         delete: bool,
+        #[arg(short, long, help = "Replace an existing tag")]
+        force: bool,
+        #[arg(short = 'n', help = "List tags with the first line of their message")]
+        lines: bool,
     },
     Checkout {
-        #[arg(help = "Branch or commit to checkout")]
+        #[arg(help = "Branch, tag, remote-tracking branch or commit to checkout")]
+        target: String,
+        #[arg(help = "More paths, with --ours or --theirs")]
+        paths: Vec<String>,
+        #[arg(long, help = "Check out our version of conflicted paths")]
+        ours: bool,
+        #[arg(
+            long,
+            conflicts_with = "ours",
+            help = "Check out their version of conflicted paths"
+        )]
+        theirs: bool,
+    },
+    Switch {
+        #[arg(help = "Branch to switch to")]
         target: String,
+        #[arg(long, help = "Detach HEAD at a tag, remote-tracking branch or commit")]
+        detach: bool,
+    },
+    Restore {
+        #[arg(
+            required = true,
+            help = "Files or directories to restore from the index"
+        )]
+        paths: Vec<String>,
+        #[arg(short, long, help = "Recreate conflict markers in conflicted paths")]
+        merge: bool,
+        #[arg(
+            long,
+            conflicts_with = "merge",
+            help = "Take our version of conflicted paths"
+        )]
+        ours: bool,
+        #[arg(long, conflicts_with_all = ["merge", "ours"], help = "Take their version of conflicted paths")]
+        theirs: bool,
+    },
+    Rm {
+        #[arg(required = true, help = "Files or directories to stop tracking")]
+        paths: Vec<String>,
+        #[arg(long, help = "Only remove from the index, keeping the working files")]
+        cached: bool,
+        #[arg(short, long, help = "Remove even if the files have changes")]
+        force: bool,
+        #[arg(short, help = "Remove directories recursively")]
+        recursive: bool,
+    },
+    Mv {
+        #[arg(help = "Tracked file or directory to move")]
+        source: String,
+        #[arg(help = "New path, or an existing directory to move it into")]
+        destination: String,
+        #[arg(short, long, help = "Overwrite an existing destination file")]
+        force: bool,
     },
     Clone {
-        #[arg(help = "Repository URL to clone")]
+        #[arg(help = "Repository to clone: a local path, a bundle or a .tar[.gz|.zst] archive")]
         url: String,
         #[arg(help = "Directory name")]
         directory: Option<String>,
+        #[arg(long, help = "Borrow objects from a local reference repository")]
+        reference: Option<String>,
+        #[arg(long, help = "Copy object files instead of hardlinking them")]
+        no_hardlinks: bool,
+        #[arg(long, help = "Copy every ref verbatim, without checking anything out")]
+        mirror: bool,
     },
     Diff {
         #[arg(help = "Files to diff")]
         files: Vec<String>,
+        #[arg(
+            long,
+            visible_alias = "staged",
+            help = "Show staged changes: the index against HEAD (or a given commit)"
+        )]
+        cached: bool,
+        #[arg(long, help = "Warn about whitespace errors and exit non-zero")]
+        check: bool,
+        #[arg(
+            short = 'M',
+            long,
+            value_name = "N%",
+            num_args = 0..=1,
+            default_missing_value = "50%",
+            help = "Similarity a deleted and a new file need to be shown as a rename (default 50%)"
+        )]
+        find_renames: Option<String>,
+    },
+    Difftool {
+        #[arg(
+            help = "Revisions to compare (none: index vs working tree; one: it vs working tree)"
+        )]
+        revisions: Vec<String>,
+        #[arg(
+            short,
+            long,
+            help = "Hand the tool two directories instead of one file at a time"
+        )]
+        dir_diff: bool,
+        #[arg(
+            short,
+            long,
+            help = "Tool command to run (defaults to diff.tool; none prints a summary)"
+        )]
+        tool: Option<String>,
     },
     Merge {
-        #[arg(help = "Branch to merge")]
-        branch: String,
+        #[arg(
+            required_unless_present_any = ["continue_merge", "abort"],
+            help = "Branch to merge"
+        )]
+        branch: Option<String>,
         #[arg(short, long, help = "Author")]
         author: Option<String>,
+        #[arg(long, help = "Stage the merge result but stop before committing")]
+        no_commit: bool,
+        #[arg(
+            long,
+            help = "Create a merge commit even when a fast-forward is possible"
+        )]
+        no_ff: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["no_ff", "no_commit"],
+            help = "Stage the merged changes for a single-parent commit, without committing"
+        )]
+        squash: bool,
+        #[arg(
+            short = 'X',
+            long = "strategy-option",
+            value_name = "ours|theirs",
+            help = "Resolve overlapping changes in favor of our or their side"
+        )]
+        strategy_option: Option<String>,
+        #[arg(
+            long = "continue",
+            conflicts_with_all = ["branch", "no_commit", "no_ff", "squash", "strategy_option", "abort"],
+            help = "Commit a merge stopped on conflicts once they are resolved and added"
+        )]
+        continue_merge: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["branch", "no_commit", "no_ff", "squash", "strategy_option"],
+            help = "Give up a stopped merge, putting the index and working tree back to HEAD"
+        )]
+        abort: bool,
     },
     Push {
         #[arg(help = "Remote name")]
         remote: Option<String>,
         #[arg(help = "Branch name")]
         branch: Option<String>,
+        #[arg(long, help = "Skip the pre-push hook")]
+        no_verify: bool,
+        #[arg(long, help = "Send a push certificate signed with user.signingKey")]
+        signed: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["branch", "signed"],
+            help = "Make every ref of the remote match ours, deleting the ones we lack"
+        )]
+        mirror: bool,
+        #[arg(
+            short,
+            long,
+            conflicts_with = "mirror",
+            help = "Update the remote branch even when it isn't a fast-forward"
+        )]
+        force: bool,
+    },
+    Fetch {
+        #[arg(help = "Remote name")]
+        remote: Option<String>,
+        #[arg(help = "Branch name")]
+        branch: Option<String>,
+        #[arg(long, help = "Fetch every branch of every remote")]
+        all: bool,
+        #[arg(
+            short,
+            long,
+            default_value_t = 1,
+            help = "Number of remotes to fetch in parallel"
+        )]
+        jobs: usize,
+        #[arg(long, help = "Show what would change without writing objects or refs")]
+        dry_run: bool,
+        #[arg(
+            short,
+            long,
+            help = "Remove remote-tracking branches the remote no longer has"
+        )]
+        prune: bool,
     },
     Pull {
         #[arg(help = "Remote name")]
@@ -85,48 +394,754 @@ enum Commands {
         #[arg(short, long, help = "Stash index")]
         index: Option<usize>,
     },
+    Snapshot {
+        #[arg(help = "Action: save, list, restore")]
+        action: Option<String>,
+        #[arg(help = "Snapshot to restore, or branch to list")]
+        target: Option<String>,
+        #[arg(short, long, help = "Snapshot message")]
+        message: Option<String>,
+    },
+    Rebase {
+        #[arg(
+            required_unless_present_any = ["continue_rebase", "skip", "abort"],
+            help = "Upstream to rebase onto"
+        )]
+        upstream: Option<String>,
+        #[arg(help = "Branch to rebase (defaults to the current branch)")]
+        branch: Option<String>,
+        #[arg(long, help = "Replay commits onto this base instead of upstream")]
+        onto: Option<String>,
+        #[arg(long, help = "Allow rewriting a protected branch")]
+        allow_protected: bool,
+        #[arg(
+            short,
+            long,
+            help = "Edit the list of commits to replay (pick, reword, squash, fixup, drop)"
+        )]
+        interactive: bool,
+        #[arg(
+            long,
+            help = "Move fixup!/squash! commits under the commits they name and fold them in"
+        )]
+        autosquash: bool,
+        #[arg(
+            long = "continue",
+            conflicts_with_all = ["upstream", "skip", "abort"],
+            help = "Commit the resolved conflict and replay the remaining commits"
+        )]
+        continue_rebase: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["upstream", "abort"],
+            help = "Drop the commit the rebase stopped at and carry on"
+        )]
+        skip: bool,
+        #[arg(
+            long,
+            conflicts_with = "upstream",
+            help = "Return the branch and working tree to where the rebase started"
+        )]
+        abort: bool,
+    },
+    Am {
+        #[arg(help = "Mailboxes of patches from format-patch (defaults to stdin)")]
+        mailboxes: Vec<String>,
+        #[arg(
+            short = '3',
+            long = "3way",
+            help = "Fall back to a three-way merge with the blobs the patch names"
+        )]
+        three_way: bool,
+        #[arg(
+            long = "continue",
+            conflicts_with_all = ["mailboxes", "skip", "abort"],
+            help = "Commit the fixed-up patch and apply the remaining ones"
+        )]
+        continue_am: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["mailboxes", "abort"],
+            help = "Drop the patch that failed and apply the remaining ones"
+        )]
+        skip: bool,
+        #[arg(
+            long,
+            conflicts_with = "mailboxes",
+            help = "Give up and return to where the session started"
+        )]
+        abort: bool,
+    },
+    Reset {
+        #[arg(help = "Commit to reset to (defaults to HEAD)")]
+        commit: Option<String>,
+        #[arg(
+            long,
+            help = "Only move the branch, keeping the index and working tree"
+        )]
+        soft: bool,
+        #[arg(
+            long,
+            conflicts_with = "soft",
+            help = "Also reset the index, keeping working tree changes (the default)"
+        )]
+        mixed: bool,
+        #[arg(
+            long,
+            conflicts_with_all = ["soft", "mixed"],
+            help = "Also reset the index and working tree, discarding changes"
+        )]
+        hard: bool,
+        #[arg(long, help = "Allow moving a protected branch")]
+        allow_protected: bool,
+    },
+    Revert {
+        #[arg(help = "Commit whose changes to undo")]
+        commit: String,
+    },
+    Rev {
+        #[arg(help = "Sequential revision number (r1 is the first mainline commit)")]
+        number: usize,
+    },
+    RevList {
+        #[arg(help = "Commit to list the history of (defaults to HEAD)")]
+        revision: Option<String>,
+        #[arg(
+            long,
+            help = "Print how many commits there are instead of listing them"
+        )]
+        count: bool,
+    },
+    CherryPick {
+        #[arg(help = "Commits or a..b ranges to apply, oldest first")]
+        commits: Vec<String>,
+        #[arg(
+            long = "continue",
+            conflicts_with_all = ["commits", "abort"],
+            help = "Commit the resolved conflict and pick the remaining commits"
+        )]
+        continue_pick: bool,
+        #[arg(
+            long,
+            conflicts_with = "commits",
+            help = "Give up and return to where the cherry-pick started"
+        )]
+        abort: bool,
+    },
+    Reflog {
+        #[arg(help = "Ref whose log to show (defaults to HEAD)")]
+        name: Option<String>,
+    },
+    UpdateIndex {
+        #[arg(help = "Files to update in the index")]
+        files: Vec<String>,
+        #[arg(long, help = "Allow adding files not yet in the index")]
+        add: bool,
+        #[arg(long, help = "Remove files missing from the working tree")]
+        remove: bool,
+        #[arg(long, help = "Remove files from the index even if present on disk")]
+        force_remove: bool,
+        #[arg(long, help = "Refresh stat information of index entries")]
+        refresh: bool,
+        #[arg(
+            long,
+            num_args = 3,
+            value_names = ["MODE", "HASH", "PATH"],
+            help = "Insert an entry for an existing object directly"
+        )]
+        cacheinfo: Option<Vec<String>>,
+        #[arg(long, help = "Treat the files as unchanged without checking them")]
+        assume_unchanged: bool,
+        #[arg(
+            long,
+            conflicts_with = "assume_unchanged",
+            help = "Clear --assume-unchanged"
+        )]
+        no_assume_unchanged: bool,
+        #[arg(long, help = "Leave the working copies of the files alone")]
+        skip_worktree: bool,
+        #[arg(long, conflicts_with = "skip_worktree", help = "Clear --skip-worktree")]
+        no_skip_worktree: bool,
+    },
+    WriteTree,
+    #[command(group(
+        clap::ArgGroup::new("mode")
+            .required(true)
+            .args(["show_type", "size", "pretty", "batch", "batch_check"])
+    ))]
+    CatFile {
+        #[arg(
+            help = "Object to show",
+            required_unless_present_any = ["batch", "batch_check"],
+            conflicts_with_all = ["batch", "batch_check"]
+        )]
+        object: Option<String>,
+        #[arg(short = 't', help = "Show the object's type")]
+        show_type: bool,
+        #[arg(short = 's', help = "Show the object's size")]
+        size: bool,
+        #[arg(short = 'p', help = "Pretty-print the object's content")]
+        pretty: bool,
+        #[arg(
+            long,
+            help = "Print type, size and content of each object named on stdin"
+        )]
+        batch: bool,
+        #[arg(long, help = "Print type and size of each object named on stdin")]
+        batch_check: bool,
+    },
+    CommitTree {
+        #[arg(help = "Tree object to commit")]
+        tree: String,
+        #[arg(short, long, help = "Parent commit (repeat for a merge commit)")]
+        parent: Vec<String>,
+        #[arg(short, long, help = "Commit message (read from stdin if omitted)")]
+        message: Option<String>,
+        #[arg(short, long, help = "Author")]
+        author: Option<String>,
+    },
+    ReadTree {
+        #[arg(help = "Trees (or commits) to read; two or three with -m")]
+        trees: Vec<String>,
+        #[arg(short, long, help = "Merge the trees into the index")]
+        merge: bool,
+    },
+    Config {
+        #[arg(help = "Key in section.key form")]
+        name: Option<String>,
+        #[arg(help = "Value to set")]
+        value: Option<String>,
+        #[arg(short, long, help = "List all effective settings")]
+        list: bool,
+        #[arg(long, help = "Show the file each value comes from")]
+        show_origin: bool,
+        #[arg(short, long, help = "Open the config file in an editor")]
+        edit: bool,
+        #[arg(long, help = "Use the global config file")]
+        global: bool,
+    },
+    MergeFile {
+        #[arg(help = "File to merge into (the result replaces it)")]
+        current: PathBuf,
+        #[arg(help = "Common ancestor of the two versions")]
+        base: PathBuf,
+        #[arg(help = "File with the changes to merge in")]
+        other: PathBuf,
+        #[arg(
+            short = 'L',
+            action = clap::ArgAction::Append,
+            help = "Labels for the current, base and other versions, in that order"
+        )]
+        label: Vec<String>,
+        #[arg(
+            short = 'p',
+            long,
+            help = "Print the result instead of overwriting <current>"
+        )]
+        stdout: bool,
+        #[arg(long, help = "Show the base version in conflicts")]
+        diff3: bool,
+        #[arg(
+            long,
+            conflicts_with = "diff3",
+            help = "Like --diff3, with lines both sides agree on outside the conflict"
+        )]
+        zdiff3: bool,
+    },
+    Var {
+        #[arg(
+            help = "Variable to print, e.g. MINI_GIT_AUTHOR_IDENT",
+            required_unless_present = "list"
+        )]
+        name: Option<String>,
+        #[arg(short, long, help = "List every variable")]
+        list: bool,
+    },
+    Serve {
+        #[arg(long, help = "Speak the transfer protocol on stdin/stdout")]
+        stdio: bool,
+        #[arg(help = "Repository to serve (defaults to the current directory)")]
+        directory: Option<PathBuf>,
+    },
+    Review {
+        #[arg(help = "Branch to review against its merge base with HEAD")]
+        branch: String,
+        #[arg(
+            short,
+            long,
+            default_value_t = 0,
+            help = "Port to listen on (0 picks a free one)"
+        )]
+        port: u16,
+    },
+    Stats {
+        #[arg(help = "Report: churn, contributors")]
+        report: Option<String>,
+        #[arg(long, help = "Only count commits since this date (YYYY-MM-DD)")]
+        since: Option<String>,
+    },
+    VerifyHistory {
+        #[arg(help = "Commit range to check, e.g. origin/main..HEAD")]
+        range: Option<String>,
+        #[arg(long, help = "Reject merge commits")]
+        no_merges: bool,
+        #[arg(long, help = "Require a Signed-off-by trailer on every commit")]
+        signed_off: bool,
+        #[arg(long, help = "Require conventional commit subjects")]
+        conventional: bool,
+        #[arg(long = "forbid", help = "Reject commits touching this path or glob")]
+        forbidden_paths: Vec<String>,
+    },
+    Trailers {
+        #[arg(help = "Commit range, e.g. v1.0..HEAD")]
+        range: Option<String>,
+        #[arg(short, long, help = "Only show trailers with this key")]
+        key: Option<String>,
+        #[arg(long, help = "Output JSON")]
+        json: bool,
+        #[arg(long, help = "Fail if any commit lacks a matching trailer")]
+        require: bool,
+    },
+    Undo {
+        #[arg(short, long, help = "List recent undoable operations")]
+        list: bool,
+    },
+    Backup {
+        #[arg(help = "Action: create, verify, restore")]
+        action: String,
+        #[arg(help = "Backup archive")]
+        file: String,
+        #[arg(help = "Directory to restore into (defaults to the current one)")]
+        directory: Option<String>,
+    },
+    Maintenance {
+        #[arg(help = "Task: prefetch")]
+        task: Option<String>,
+    },
+    ForEachRef {
+        #[arg(help = "Only list refs under these names, e.g. refs/heads")]
+        patterns: Vec<String>,
+        #[arg(long, help = "Output format, e.g. '%(refname:short) %(subject)'")]
+        format: Option<String>,
+        #[arg(
+            long,
+            help = "Sort by this field, '-' prefix for descending (repeatable)"
+        )]
+        sort: Vec<String>,
+        #[arg(long, help = "Stop after this many refs")]
+        count: Option<usize>,
+    },
+    LsFiles {
+        #[arg(short, long, help = "Show mode, object id and stage number")]
+        stage: bool,
+        #[arg(short, long, help = "Only show the stages of conflicted paths")]
+        unmerged: bool,
+    },
+    Repack {
+        #[arg(short, long, help = "Also fold existing packs into the new one")]
+        all: bool,
+    },
+    Fsck {
+        #[arg(long, help = "Don't list unreachable objects nothing refers to")]
+        no_dangling: bool,
+    },
+    Gc {
+        #[arg(
+            long,
+            value_name = "WHEN",
+            help = "Prune unreachable objects older than this (now, never, 2.weeks.ago)"
+        )]
+        prune: Option<String>,
+        #[arg(
+            short = 'n',
+            long,
+            help = "List what would be removed without removing it"
+        )]
+        dry_run: bool,
+        #[arg(
+            long,
+            help = "Repack everything into one pack of deltas, trading time for size"
+        )]
+        aggressive: bool,
+        #[arg(
+            long,
+            requires = "aggressive",
+            help = "Objects to try as delta bases for each one (defaults to gc.aggressiveWindow, 250)"
+        )]
+        window: Option<usize>,
+        #[arg(
+            long,
+            requires = "aggressive",
+            help = "Longest chain of deltas on deltas (defaults to gc.aggressiveDepth, 50)"
+        )]
+        depth: Option<usize>,
+    },
+    CommitGraph {
+        #[arg(help = "Action: write, verify")]
+        action: String,
+    },
+    Ipc {
+        #[arg(long, help = "Socket to listen on (defaults to .mini_git/ipc.sock)")]
+        socket: Option<String>,
+    },
+    ImportSnapshots {
+        #[arg(help = "Directory holding one subdirectory per snapshot")]
+        dir: String,
+        #[arg(long, help = "Branch to import onto (defaults to the current one)")]
+        branch: Option<String>,
+    },
+    // Any other command runs a `mini_git-<command>` extension from PATH
+    #[command(external_subcommand)]
+    External(Vec<String>),
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
+    let matches = with_command_defaults(Cli::command().get_matches())?;
+    let cli = Cli::from_arg_matches(&matches)?;
+    if cli.profile {
+        profile::enable();
+    }
+
+    let started = Instant::now();
+    let result = run(cli.command);
+    profile::report(
+        matches.subcommand_name().unwrap_or_default(),
+        started.elapsed(),
+    );
+    result
+}
 
-    match cli.command {
-        Commands::Init { path } => {
-            commands::init(path)?;
+// A `[<command>]` config section gives that command default options:
+// `[log] show-revnum = true` acts as if every `log` had `--show-revnum`, and
+// `[difftool] tool = meld` as if it had `--tool=meld`. Keys match long option
+// names, ignoring dashes and case. Options on the command line win.
+fn with_command_defaults(matches: ArgMatches) -> Result<ArgMatches> {
+    let Some((name, sub_matches)) = matches.subcommand() else {
+        return Ok(matches);
+    };
+    let config = match utils::get_repository(None) {
+        Ok(repo) => Config::load(&repo)?,
+        Err(_) => Config::load_global()?,
+    };
+    let defaults = config.section(name);
+    let command = Cli::command();
+    let Some(subcommand) = command
+        .find_subcommand(name)
+        .filter(|_| !defaults.is_empty())
+    else {
+        return Ok(matches);
+    };
+
+    let normalize = |name: &str| name.replace('-', "").to_lowercase();
+    let mut extra = Vec::new();
+    for arg in subcommand.get_arguments() {
+        let Some(long) = arg.get_long() else {
+            continue;
+        };
+        let Some((_, value)) = defaults
+            .iter()
+            .rev()
+            .find(|(key, _)| normalize(key) == normalize(long))
+        else {
+            continue;
+        };
+        if sub_matches.value_source(arg.get_id().as_str()) == Some(ValueSource::CommandLine) {
+            continue;
+        }
+        if arg.get_action().takes_values() {
+            extra.push(OsString::from(format!("--{}={}", long, value)));
+        } else if matches!(value.to_lowercase().as_str(), "true" | "yes" | "on" | "1") {
+            extra.push(OsString::from(format!("--{}", long)));
+        }
+    }
+    if extra.is_empty() {
+        return Ok(matches);
+    }
+
+    // Right after the command name, so they can't end up after a `--`
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let at = args
+        .iter()
+        .position(|arg| arg.as_os_str() == name)
+        .map_or(args.len(), |i| i + 1);
+    args.splice(at..at, extra);
+    Ok(Cli::command().get_matches_from(args))
+}
+
+fn run(command: Commands) -> Result<()> {
+    match command {
+        Commands::Init {
+            path,
+            object_format,
+            object_storage,
+            template,
+        } => {
+            commands::init(path, object_format, object_storage, template)?;
+        }
+        Commands::Clone {
+            url,
+            directory,
+            reference,
+            no_hardlinks,
+            mirror,
+        } => {
+            commands::clone(url, directory, reference, no_hardlinks, mirror)?;
         }
-        Commands::Clone { url, directory } => {
-            commands::clone(url, directory)?;
+        Commands::Config {
+            name,
+            value,
+            list,
+            show_origin,
+            edit,
+            global,
+        } => {
+            // Works outside a repository with --global
+            let repo = utils::get_repository(None).ok();
+            let options = commands::ConfigOptions {
+                name,
+                value,
+                list,
+                show_origin,
+                edit,
+                global,
+            };
+            commands::config(repo.as_ref(), options)?;
+        }
+        Commands::MergeFile {
+            current,
+            base,
+            other,
+            label,
+            stdout,
+            diff3,
+            zdiff3,
+        } => {
+            // A plain file operation; a repository only supplies config
+            let repo = utils::get_repository(None).ok();
+            let style = if zdiff3 {
+                Some(ConflictStyle::Zdiff3)
+            } else if diff3 {
+                Some(ConflictStyle::Diff3)
+            } else {
+                None
+            };
+            commands::merge_file(repo.as_ref(), [current, base, other], label, style, stdout)?;
+        }
+        Commands::Backup {
+            action,
+            file,
+            directory,
+        } => {
+            // Restoring happens where there is no repository yet
+            let repo = utils::get_repository(None).ok();
+            commands::backup(repo.as_ref(), &action, file, directory)?;
+        }
+        Commands::Var { name, list } => {
+            // Like config, works outside a repository
+            let repo = utils::get_repository(None).ok();
+            commands::var(repo.as_ref(), name, list)?;
+        }
+        Commands::Serve { stdio, directory } => {
+            let repo = utils::get_repository(directory)?;
+            commands::serve(&repo, stdio)?;
+        }
+        Commands::External(args) => {
+            // Extensions may work outside a repository too
+            plugin::run(args)?;
         }
         _ => {
             let repo = utils::get_repository(None)?;
 
-            match cli.command {
-                Commands::Add { files } => {
-                    commands::add(&repo, files)?;
+            match command {
+                Commands::Add {
+                    files,
+                    intent_to_add,
+                } => {
+                    commands::add(&repo, files, intent_to_add)?;
+                }
+                Commands::Commit {
+                    message,
+                    author,
+                    fixup,
+                    all,
+                    only: _,
+                    paths,
+                    no_verify,
+                } => {
+                    commands::commit(&repo, message, author, fixup, all, paths, no_verify)?;
+                }
+                Commands::Status {
+                    ignored,
+                    json,
+                    no_refresh,
+                    find_renames,
+                } => {
+                    commands::status(&repo, ignored, json, no_refresh, find_renames)?;
                 }
-                Commands::Commit { message, author } => {
-                    commands::commit(&repo, message, author)?;
+                Commands::Log {
+                    revision,
+                    max_count,
+                    show_revnum,
+                    graph,
+                    oneline,
+                    format,
+                    stat,
+                    patch,
+                    author,
+                    since,
+                    until,
+                    grep,
+                    paths,
+                } => {
+                    let options = commands::LogOptions {
+                        max_count,
+                        show_revnum,
+                        graph,
+                        oneline,
+                        format,
+                        stat,
+                        patch,
+                        author,
+                        since,
+                        until,
+                        grep,
+                        paths,
+                    };
+                    commands::log(&repo, revision, options)?;
                 }
-                Commands::Status => {
-                    commands::status(&repo)?;
+                Commands::Blame { file, rev } => {
+                    commands::blame(&repo, file, rev)?;
                 }
-                Commands::Log { max_count } => {
-                    commands::log(&repo, max_count)?;
+                Commands::Branch {
+                    name,
+                    delete,
+                    force_delete,
+                    force,
+                    allow_protected,
+                } => {
+                    commands::branch(&repo, name, delete, force_delete, force, allow_protected)?;
+                }
+                Commands::Tag {
+                    name,
+                    commit,
+                    annotate,
+                    message,
+                    delete,
+                    force,
+                    lines,
+                } => {
+                    let options = commands::TagOptions {
+                        annotate,
+                        message,
+                        delete,
+                        force,
+                        lines,
+                    };
+                    commands::tag(&repo, name, commit, options)?;
                 }
-                Commands::Branch { name, delete } => {
-                    commands::branch(&repo, name, delete)?;
+                Commands::Checkout {
+                    target,
+                    paths,
+                    ours,
+                    theirs,
+                } => {
+                    if ours || theirs {
+                        let paths = std::iter::once(target).chain(paths).collect();
+                        commands::restore(&repo, paths, false, Some(conflict_side(ours)))?;
+                    } else if !paths.is_empty() {
+                        return Err(
+                            "Checking out paths needs --ours or --theirs; see `restore`".into()
+                        );
+                    } else {
+                        commands::checkout(&repo, target)?;
+                    }
                 }
-                Commands::Checkout { target } => {
-                    commands::checkout(&repo, target)?;
+                Commands::Switch { target, detach } => {
+                    commands::switch(&repo, target, detach)?;
                 }
-                Commands::Diff { files } => {
-                    commands::diff(&repo, files)?;
+                Commands::Restore {
+                    paths,
+                    merge,
+                    ours,
+                    theirs,
+                } => {
+                    let side = (ours || theirs).then(|| conflict_side(ours));
+                    commands::restore(&repo, paths, merge, side)?;
                 }
-                Commands::Merge { branch, author } => {
-                    commands::merge(&repo, branch, author)?;
+                Commands::Rm {
+                    paths,
+                    cached,
+                    force,
+                    recursive,
+                } => {
+                    commands::rm(&repo, paths, cached, force, recursive)?;
                 }
-                Commands::Push { remote, branch } => {
-                    commands::push(&repo, remote, branch)?;
+                Commands::Mv {
+                    source,
+                    destination,
+                    force,
+                } => {
+                    commands::mv(&repo, source, destination, force)?;
+                }
+                Commands::Diff {
+                    files,
+                    cached,
+                    check,
+                    find_renames,
+                } => {
+                    commands::diff(&repo, files, cached, check, find_renames)?;
+                }
+                Commands::Difftool {
+                    revisions,
+                    dir_diff,
+                    tool,
+                } => {
+                    commands::difftool(&repo, revisions, dir_diff, tool)?;
+                }
+                Commands::Merge {
+                    branch,
+                    author,
+                    no_commit,
+                    no_ff,
+                    squash,
+                    strategy_option,
+                    continue_merge,
+                    abort,
+                } => {
+                    let options = commands::MergeOptions {
+                        no_commit,
+                        no_ff,
+                        squash,
+                        favor: strategy_option
+                            .as_deref()
+                            .map(Favor::from_name)
+                            .transpose()?,
+                    };
+                    commands::merge(&repo, branch, author, options, continue_merge, abort)?;
+                }
+                Commands::Push {
+                    remote,
+                    branch,
+                    no_verify,
+                    signed,
+                    mirror,
+                    force,
+                } => {
+                    commands::push(&repo, remote, branch, no_verify, signed, mirror, force)?;
+                }
+                Commands::Fetch {
+                    remote,
+                    branch,
+                    all,
+                    jobs,
+                    dry_run,
+                    prune,
+                } => {
+                    if all {
+                        commands::fetch_all(&repo, jobs, dry_run, prune)?;
+                    } else {
+                        commands::fetch(&repo, remote, branch, dry_run, prune)?;
+                    }
                 }
                 Commands::Pull { remote, branch } => {
                     commands::pull(&repo, remote, branch)?;
@@ -141,10 +1156,236 @@ fn main() -> Result<()> {
                 } => {
                     commands::stash(&repo, action, message, index)?;
                 }
-                Commands::Init { .. } | Commands::Clone { .. } => unreachable!(),
+                Commands::Snapshot {
+                    action,
+                    target,
+                    message,
+                } => {
+                    commands::snapshot(&repo, action, target, message)?;
+                }
+                Commands::Rebase {
+                    upstream,
+                    branch,
+                    onto,
+                    allow_protected,
+                    interactive,
+                    autosquash,
+                    continue_rebase,
+                    skip,
+                    abort,
+                } => {
+                    let control = if continue_rebase {
+                        Some(RebaseControl::Continue)
+                    } else if skip {
+                        Some(RebaseControl::Skip)
+                    } else if abort {
+                        Some(RebaseControl::Abort)
+                    } else {
+                        None
+                    };
+                    let options = commands::RebaseOptions {
+                        onto,
+                        allow_protected,
+                        interactive,
+                        autosquash,
+                    };
+                    commands::rebase(&repo, upstream, branch, options, control)?;
+                }
+                Commands::Am {
+                    mailboxes,
+                    three_way,
+                    continue_am,
+                    skip,
+                    abort,
+                } => {
+                    commands::am(&repo, mailboxes, three_way, continue_am, skip, abort)?;
+                }
+                Commands::UpdateIndex {
+                    files,
+                    add,
+                    remove,
+                    force_remove,
+                    refresh,
+                    cacheinfo,
+                    assume_unchanged,
+                    no_assume_unchanged,
+                    skip_worktree,
+                    no_skip_worktree,
+                } => {
+                    let flag = |set: bool, clear: bool| (set || clear).then_some(set);
+                    let options = commands::UpdateIndexOptions {
+                        add,
+                        remove,
+                        force_remove,
+                        refresh,
+                        cacheinfo,
+                        assume_unchanged: flag(assume_unchanged, no_assume_unchanged),
+                        skip_worktree: flag(skip_worktree, no_skip_worktree),
+                    };
+                    commands::update_index(&repo, files, options)?;
+                }
+                Commands::WriteTree => {
+                    commands::write_tree(&repo)?;
+                }
+                Commands::CatFile {
+                    object,
+                    show_type,
+                    size,
+                    pretty: _,
+                    batch,
+                    batch_check,
+                } => {
+                    if batch || batch_check {
+                        commands::cat_file_batch(&repo, batch_check)?;
+                    } else {
+                        let mode = if show_type {
+                            commands::CatFileMode::Type
+                        } else if size {
+                            commands::CatFileMode::Size
+                        } else {
+                            commands::CatFileMode::Pretty
+                        };
+                        commands::cat_file(&repo, mode, &object.unwrap_or_default())?;
+                    }
+                }
+                Commands::CommitTree {
+                    tree,
+                    parent,
+                    message,
+                    author,
+                } => {
+                    commands::commit_tree(&repo, tree, parent, message, author)?;
+                }
+                Commands::ReadTree { trees, merge } => {
+                    commands::read_tree(&repo, trees, merge)?;
+                }
+                Commands::Review { branch, port } => {
+                    commands::review(&repo, branch, port)?;
+                }
+                Commands::Stats { report, since } => {
+                    commands::stats(&repo, report, since)?;
+                }
+                Commands::VerifyHistory {
+                    range,
+                    no_merges,
+                    signed_off,
+                    conventional,
+                    forbidden_paths,
+                } => {
+                    let options = commands::VerifyHistoryOptions {
+                        no_merges,
+                        signed_off,
+                        conventional,
+                        forbidden_paths,
+                    };
+                    commands::verify_history(&repo, range, options)?;
+                }
+                Commands::Trailers {
+                    range,
+                    key,
+                    json,
+                    require,
+                } => {
+                    commands::trailers(&repo, range, key, json, require)?;
+                }
+                Commands::Reset {
+                    commit,
+                    soft,
+                    mixed: _,
+                    hard,
+                    allow_protected,
+                } => {
+                    let mode = if soft {
+                        ResetMode::Soft
+                    } else if hard {
+                        ResetMode::Hard
+                    } else {
+                        ResetMode::Mixed
+                    };
+                    commands::reset(&repo, commit, mode, allow_protected)?;
+                }
+                Commands::Revert { commit } => {
+                    commands::revert(&repo, commit)?;
+                }
+                Commands::Rev { number } => {
+                    commands::rev(&repo, number)?;
+                }
+                Commands::RevList { revision, count } => {
+                    commands::rev_list(&repo, revision, count)?;
+                }
+                Commands::CherryPick {
+                    commits,
+                    continue_pick,
+                    abort,
+                } => {
+                    commands::cherry_pick(&repo, commits, continue_pick, abort)?;
+                }
+                Commands::Reflog { name } => {
+                    commands::reflog(&repo, name)?;
+                }
+                Commands::Undo { list } => {
+                    commands::undo(&repo, list)?;
+                }
+                Commands::Maintenance { task } => {
+                    commands::maintenance(&repo, task)?;
+                }
+                Commands::LsFiles { stage, unmerged } => {
+                    commands::ls_files(&repo, stage, unmerged)?;
+                }
+                Commands::ForEachRef {
+                    patterns,
+                    format,
+                    sort,
+                    count,
+                } => {
+                    commands::for_each_ref(&repo, patterns, format, sort, count)?;
+                }
+                Commands::Repack { all } => {
+                    commands::repack(&repo, all, None)?;
+                }
+                Commands::Fsck { no_dangling } => {
+                    commands::fsck(&repo, no_dangling)?;
+                }
+                Commands::Gc {
+                    prune,
+                    dry_run,
+                    aggressive,
+                    window,
+                    depth,
+                } => {
+                    let aggressive = aggressive.then_some(commands::Aggressive { window, depth });
+                    commands::gc(&repo, prune, dry_run, aggressive)?;
+                }
+                Commands::CommitGraph { action } => {
+                    commands::commit_graph(&repo, &action)?;
+                }
+                Commands::Ipc { socket } => {
+                    commands::ipc(&repo, socket)?;
+                }
+                Commands::ImportSnapshots { dir, branch } => {
+                    commands::import_snapshots(&repo, dir, branch)?;
+                }
+                Commands::Init { .. }
+                | Commands::Clone { .. }
+                | Commands::Config { .. }
+                | Commands::MergeFile { .. }
+                | Commands::Var { .. }
+                | Commands::Backup { .. }
+                | Commands::Serve { .. }
+                | Commands::External(_) => {
+                    unreachable!()
+                }
             }
         }
     }
 
     Ok(())
 }
+
+fn conflict_side(ours: bool) -> ConflictSide {
+    if ours {
+        ConflictSide::Ours
+    } else {
+        ConflictSide::Theirs
+    }
+}
@@ -0,0 +1,193 @@
+use crate::{Result, commands::diff::DiffType, commands::diff::compute_diff, config::Config};
+
+// Line-based three-way merge of file contents, as in `git merge-file`.
+// Lines of `ours` and `theirs` that match the same base line split the files
+// into stable runs and changed chunks; a chunk changed on only one side (or
+// the same way on both) merges cleanly, anything else is a conflict written
+// out with markers in the configured style.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictStyle {
+    // Ours and theirs only
+    #[default]
+    Merge,
+    // Also the base version, between `|||||||` and `=======`
+    Diff3,
+    // diff3, with lines both sides agree on moved out of the conflict
+    Zdiff3,
+}
+
+impl ConflictStyle {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "merge" => Ok(ConflictStyle::Merge),
+            "diff3" => Ok(ConflictStyle::Diff3),
+            "zdiff3" => Ok(ConflictStyle::Zdiff3),
+            _ => Err(format!(
+                "Unknown conflict style '{}' (expected merge, diff3 or zdiff3)",
+                name
+            )
+            .into()),
+        }
+    }
+
+    // `merge.conflictStyle`, defaulting to `merge`
+    pub fn from_config(config: &Config) -> Result<Self> {
+        match config.get("merge.conflictStyle") {
+            Some(name) => Self::from_name(name),
+            None => Ok(ConflictStyle::default()),
+        }
+    }
+}
+
+// Which side overlapping changes resolve to, instead of conflicting
+// (`merge -X ours` / `-X theirs`). Changes that don't overlap still merge.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Favor {
+    Ours,
+    Theirs,
+}
+
+impl Favor {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name {
+            "ours" => Ok(Favor::Ours),
+            "theirs" => Ok(Favor::Theirs),
+            _ => Err(format!(
+                "Unknown strategy option '{}' (expected ours or theirs)",
+                name
+            )
+            .into()),
+        }
+    }
+}
+
+// Names written after the conflict markers
+pub struct Labels<'a> {
+    pub ours: &'a str,
+    pub base: &'a str,
+    pub theirs: &'a str,
+}
+
+pub struct MergedFile {
+    pub content: String,
+    pub conflicts: usize,
+}
+
+pub fn merge(
+    base: &str,
+    ours: &str,
+    theirs: &str,
+    style: ConflictStyle,
+    labels: &Labels,
+    favor: Option<Favor>,
+) -> MergedFile {
+    let base: Vec<&str> = base.split_inclusive('\n').collect();
+    let ours: Vec<&str> = ours.split_inclusive('\n').collect();
+    let theirs: Vec<&str> = theirs.split_inclusive('\n').collect();
+    let in_ours = matching_lines(&base, &ours);
+    let in_theirs = matching_lines(&base, &theirs);
+
+    let mut out = String::new();
+    let mut conflicts = 0;
+    let (mut b, mut o, mut t) = (0, 0, 0);
+    while b < base.len() || o < ours.len() || t < theirs.len() {
+        // The next base line both sides kept, at or after where we are
+        let sync = (b..base.len()).find_map(|i| match (in_ours[i], in_theirs[i]) {
+            (Some(j), Some(k)) if j >= o && k >= t => Some((i, j, k)),
+            _ => None,
+        });
+        let (next_b, next_o, next_t) = sync.unwrap_or((base.len(), ours.len(), theirs.len()));
+
+        if (next_b, next_o, next_t) == (b, o, t) {
+            out.push_str(base[b]);
+            b += 1;
+            o += 1;
+            t += 1;
+            continue;
+        }
+
+        let base_chunk = &base[b..next_b];
+        let our_chunk = &ours[o..next_o];
+        let their_chunk = &theirs[t..next_t];
+        if our_chunk == base_chunk || our_chunk == their_chunk {
+            out.extend(their_chunk.iter().copied());
+        } else if their_chunk == base_chunk || favor == Some(Favor::Ours) {
+            out.extend(our_chunk.iter().copied());
+        } else if favor == Some(Favor::Theirs) {
+            out.extend(their_chunk.iter().copied());
+        } else {
+            conflicts += 1;
+            write_conflict(&mut out, base_chunk, our_chunk, their_chunk, style, labels);
+        }
+        (b, o, t) = (next_b, next_o, next_t);
+    }
+
+    MergedFile {
+        content: out,
+        conflicts,
+    }
+}
+
+// For each line of `base`, the line of `other` it was kept as, if any
+fn matching_lines(base: &[&str], other: &[&str]) -> Vec<Option<usize>> {
+    let mut matches = vec![None; base.len()];
+    let (mut i, mut j) = (0, 0);
+    for step in compute_diff(base, other) {
+        match step {
+            DiffType::Equal => {
+                matches[i] = Some(j);
+                i += 1;
+                j += 1;
+            }
+            DiffType::Delete => i += 1,
+            DiffType::Insert => j += 1,
+        }
+    }
+    matches
+}
+
+fn write_conflict(
+    out: &mut String,
+    base: &[&str],
+    ours: &[&str],
+    theirs: &[&str],
+    style: ConflictStyle,
+    labels: &Labels,
+) {
+    // zdiff3 keeps what both sides added at either end outside the markers
+    let (prefix, suffix) = match style {
+        ConflictStyle::Zdiff3 => {
+            let prefix = ours.iter().zip(theirs).take_while(|(a, b)| a == b).count();
+            let suffix = ours[prefix..]
+                .iter()
+                .rev()
+                .zip(theirs[prefix..].iter().rev())
+                .take_while(|(a, b)| a == b)
+                .count();
+            (prefix, suffix)
+        }
+        _ => (0, 0),
+    };
+
+    out.extend(ours[..prefix].iter().copied());
+    marker(out, &format!("<<<<<<< {}", labels.ours));
+    out.extend(ours[prefix..ours.len() - suffix].iter().copied());
+    if style != ConflictStyle::Merge {
+        marker(out, &format!("||||||| {}", labels.base));
+        out.extend(base.iter().copied());
+    }
+    marker(out, "=======");
+    out.extend(theirs[prefix..theirs.len() - suffix].iter().copied());
+    marker(out, &format!(">>>>>>> {}", labels.theirs));
+    out.extend(ours[ours.len() - suffix..].iter().copied());
+}
+
+// Markers go on a line of their own, even after a last line with no newline
+fn marker(out: &mut String, marker: &str) {
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+    out.push_str(marker);
+    out.push('\n');
+}
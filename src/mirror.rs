@@ -0,0 +1,107 @@
+use crate::Result;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+// Mirrors keep every ref of one repository identical in another: branches,
+// tags, remote-tracking branches and anything else under refs/, copied
+// verbatim. `clone --mirror` makes one (with `remote.origin.mirror = true`,
+// so later fetches keep it in step) and `push --mirror` updates one. Refs
+// that vanished from the source are deleted from the mirror.
+
+pub struct MirrorUpdate {
+    // Full name, e.g. refs/heads/main
+    pub name: String,
+    // None for a ref the mirror doesn't have yet
+    pub old: Option<String>,
+    // None for a ref the source no longer has
+    pub new: Option<String>,
+}
+
+impl MirrorUpdate {
+    // A line of the summary, in the style of fetch's
+    pub fn summary(&self) -> String {
+        let (flag, change) = match (&self.old, &self.new) {
+            (None, _) => ('*', "[new ref]".to_string()),
+            (Some(_), None) => ('-', "[deleted]".to_string()),
+            (Some(old), Some(new)) => (' ', format!("{}..{}", &old[..7], &new[..7])),
+        };
+        format!(" {} {:<17} {}", flag, change, self.name)
+    }
+}
+
+// Every ref under `refs_dir`, by full name
+pub fn all_refs(refs_dir: &Path) -> Result<BTreeMap<String, String>> {
+    let mut refs = BTreeMap::new();
+    if !refs_dir.is_dir() {
+        return Ok(refs);
+    }
+    for entry in WalkDir::new(refs_dir).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_file() {
+            let relative = entry.path().strip_prefix(refs_dir)?.to_string_lossy();
+            refs.insert(
+                format!("refs/{}", relative.replace('\\', "/")),
+                fs::read_to_string(entry.path())?.trim().to_string(),
+            );
+        }
+    }
+    Ok(refs)
+}
+
+// What makes the refs under `dst_refs` identical to those under `src_refs`
+pub fn plan(src_refs: &Path, dst_refs: &Path) -> Result<Vec<MirrorUpdate>> {
+    let src = all_refs(src_refs)?;
+    let dst = all_refs(dst_refs)?;
+
+    let mut updates = Vec::new();
+    for (name, id) in &src {
+        if dst.get(name) != Some(id) {
+            updates.push(MirrorUpdate {
+                name: name.clone(),
+                old: dst.get(name).cloned(),
+                new: Some(id.clone()),
+            });
+        }
+    }
+    for (name, id) in &dst {
+        if !src.contains_key(name) {
+            updates.push(MirrorUpdate {
+                name: name.clone(),
+                old: Some(id.clone()),
+                new: None,
+            });
+        }
+    }
+    updates.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(updates)
+}
+
+// Write `updates` under `refs_dir`, removing directories deletions emptied
+// (but not refs/heads and the like themselves)
+pub fn apply(refs_dir: &Path, updates: &[MirrorUpdate]) -> Result<()> {
+    for update in updates {
+        let relative = update.name.strip_prefix("refs/").unwrap_or(&update.name);
+        let path = refs_dir.join(relative);
+        match &update.new {
+            Some(id) => {
+                fs::create_dir_all(path.parent().unwrap())?;
+                fs::write(&path, id)?;
+            }
+            None => {
+                fs::remove_file(&path)?;
+                let mut dir = path.parent();
+                while let Some(current) = dir {
+                    if current == refs_dir
+                        || current.parent() == Some(refs_dir)
+                        || fs::remove_dir(current).is_err()
+                    {
+                        break;
+                    }
+                    dir = current.parent();
+                }
+            }
+        }
+    }
+    Ok(())
+}
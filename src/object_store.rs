@@ -1,65 +1,253 @@
-use crate::{Blob, Commit, Repository, Result, Tree};
-use flate2::Compression;
-use flate2::read::ZlibDecoder;
+use crate::{
+    Blob, Commit, Repository, Result, Tag, Tree, TreeEntry,
+    bitmap::PackBitmaps,
+    commit_graph::{self, CommitGraphFile},
+    config::Config,
+    git_object,
+    hash::HashAlgorithm,
+    packfile::{self, DeltaWindow, PackEntry, PackIndex},
+    profile::{self, Phase},
+    worktree,
+};
 use flate2::write::ZlibEncoder;
-use sha1::{Digest, Sha1};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::fs;
-use std::io::{Read, Write};
-use std::path::Path;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+// How objects are encoded on disk, from `core.objectStorage` in the
+// repository's config. `json` is mini_git's own format; `git` writes real
+// loose objects (see git_object) that git can read. The two name the same
+// content differently, so the choice is made at `init` and never changes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ObjectStorage {
+    #[default]
+    Json,
+    Git,
+}
+
+impl ObjectStorage {
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "json" => Ok(ObjectStorage::Json),
+            "git" => Ok(ObjectStorage::Git),
+            _ => Err(format!("Unsupported object storage '{}'", name).into()),
+        }
+    }
+
+    pub fn of_git_dir(git_dir: &Path) -> Result<Self> {
+        let config = match fs::read_to_string(git_dir.join("config")) {
+            Ok(content) => Config::parse(&content),
+            Err(_) => return Ok(ObjectStorage::default()),
+        };
+        match config.get("core.objectStorage") {
+            Some(name) => Self::from_name(name),
+            None => Ok(ObjectStorage::default()),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ObjectStorage::Json => "json",
+            ObjectStorage::Git => "git",
+        }
+    }
+}
+
+impl fmt::Display for ObjectStorage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
 
 pub struct ObjectStore {
-    objects_dir: std::path::PathBuf,
+    objects_dir: PathBuf,
+    alternates: Vec<PathBuf>,
+    algorithm: HashAlgorithm,
+    storage: ObjectStorage,
+    // Indexes of our packs and our alternates', read on first use
+    packs: OnceLock<Vec<PackIndex>>,
+    // objects/info/commit-graph, if written and readable
+    commit_graph: OnceLock<Option<CommitGraphFile>>,
+    // The first of our packs with reachability bitmaps, by its place in
+    // `packs`, and its bitmaps
+    bitmaps: OnceLock<Option<(usize, PackBitmaps)>>,
 }
 
 impl ObjectStore {
     pub fn new(repo: &Repository) -> Self {
+        Self::at(repo.git_dir.join("objects"))
+    }
+
+    // The object format comes from the config of the repository that owns
+    // `objects_dir`; an unreadable format was already rejected when the
+    // repository was opened
+    pub fn at(objects_dir: PathBuf) -> Self {
+        let alternates = read_alternates(&objects_dir);
+        let git_dir = objects_dir.parent();
+        let algorithm = git_dir
+            .and_then(|git_dir| HashAlgorithm::of_git_dir(git_dir).ok())
+            .unwrap_or_default();
+        let storage = git_dir
+            .and_then(|git_dir| ObjectStorage::of_git_dir(git_dir).ok())
+            .unwrap_or_default();
         Self {
-            objects_dir: repo.git_dir.join("objects"),
+            objects_dir,
+            alternates,
+            algorithm,
+            storage,
+            packs: OnceLock::new(),
+            commit_graph: OnceLock::new(),
+            bitmaps: OnceLock::new(),
         }
     }
 
+    pub fn algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn storage(&self) -> ObjectStorage {
+        self.storage
+    }
+
     pub fn init(&self) -> Result<()> {
         fs::create_dir_all(&self.objects_dir)?;
         Ok(())
     }
 
-    pub fn hash_content(content: &[u8]) -> String {
-        let mut hasher = Sha1::new();
-        hasher.update(content);
-        format!("{:x}", hasher.finalize())
+    // The id of a blob holding `content`
+    pub fn hash_content(&self, content: &[u8]) -> String {
+        match self.storage {
+            ObjectStorage::Json => self.algorithm.hash(content),
+            ObjectStorage::Git => self.algorithm.hash(&git_object::wrap("blob", content)),
+        }
     }
 
     pub fn store_blob(&self, content: &[u8]) -> Result<String> {
-        let hash = Self::hash_content(content);
-        let blob = Blob {
-            hash: hash.clone(),
-            content: content.to_vec(),
+        let hash = self.hash_content(content);
+        let serialized = match self.storage {
+            ObjectStorage::Json => serde_json::to_vec(&Blob {
+                hash: hash.clone(),
+                content: content.to_vec(),
+            })?,
+            ObjectStorage::Git => git_object::wrap("blob", content),
         };
-
-        let serialized = serde_json::to_vec(&blob)?;
         self.store_object(&hash, &serialized)?;
         Ok(hash)
     }
 
+    // Build and store a tree from its entries. The hash is taken over the
+    // entries in path order so the same contents always give the same tree.
+    pub fn write_tree(&self, entries: HashMap<String, TreeEntry>) -> Result<Tree> {
+        let tree = Tree {
+            hash: self.tree_hash(&entries)?,
+            entries,
+        };
+
+        self.store_tree(&tree)?;
+        Ok(tree)
+    }
+
+    pub fn tree_hash(&self, entries: &HashMap<String, TreeEntry>) -> Result<String> {
+        match self.storage {
+            ObjectStorage::Json => {
+                let sorted: BTreeMap<&String, &TreeEntry> = entries.iter().collect();
+                Ok(self.hash_content(&serde_json::to_vec(&sorted)?))
+            }
+            ObjectStorage::Git => self.git_tree(&GitDir::from_entries(entries), false),
+        }
+    }
+
     pub fn store_tree(&self, tree: &Tree) -> Result<String> {
-        let serialized = serde_json::to_vec(tree)?;
-        self.store_object(&tree.hash, &serialized)?;
+        match self.storage {
+            ObjectStorage::Json => {
+                let serialized = serde_json::to_vec(tree)?;
+                self.store_object(&tree.hash, &serialized)?;
+            }
+            ObjectStorage::Git => {
+                self.git_tree(&GitDir::from_entries(&tree.entries), true)?;
+            }
+        }
         Ok(tree.hash.clone())
     }
 
+    // Hash one directory level as a git tree, subdirectories first, and
+    // store every level when `store` is set
+    fn git_tree(&self, dir: &GitDir, store: bool) -> Result<String> {
+        let mut items = Vec::new();
+        for (name, entry) in &dir.files {
+            items.push(git_object::TreeItem {
+                mode: entry.mode.clone(),
+                name: name.to_string(),
+                hash: entry.hash.clone(),
+            });
+        }
+        for (name, subdir) in &dir.dirs {
+            items.push(git_object::TreeItem {
+                mode: worktree::DIR_MODE.to_string(),
+                name: name.to_string(),
+                hash: self.git_tree(subdir, store)?,
+            });
+        }
+
+        let raw = git_object::wrap("tree", &git_object::encode_tree(items)?);
+        let hash = self.algorithm.hash(&raw);
+        if store {
+            self.store_object(&hash, &raw)?;
+        }
+        Ok(hash)
+    }
+
+    // The id for a new commit. JSON storage keeps mini_git's original ids,
+    // hashed over `json_id_content`; git storage hashes the encoded commit.
+    pub fn commit_id(&self, commit: &Commit, json_id_content: &str) -> String {
+        match self.storage {
+            ObjectStorage::Json => self.algorithm.hash(json_id_content.as_bytes()),
+            ObjectStorage::Git => self.algorithm.hash(&git_object::wrap(
+                "commit",
+                &git_object::encode_commit(commit),
+            )),
+        }
+    }
+
     pub fn store_commit(&self, commit: &Commit) -> Result<String> {
-        let serialized = serde_json::to_vec(commit)?;
+        let serialized = match self.storage {
+            ObjectStorage::Json => serde_json::to_vec(commit)?,
+            ObjectStorage::Git => git_object::wrap("commit", &git_object::encode_commit(commit)),
+        };
         self.store_object(&commit.hash, &serialized)?;
         Ok(commit.hash.clone())
     }
 
-    fn store_object(&self, hash: &str, content: &[u8]) -> Result<()> {
+    // Tags are named by the hash of their git encoding with either storage,
+    // so `tag.hash` is filled in here
+    pub fn store_tag(&self, tag: &mut Tag) -> Result<String> {
+        let raw = git_object::wrap("tag", &git_object::encode_tag(tag));
+        tag.hash = self.algorithm.hash(&raw);
+        let serialized = match self.storage {
+            ObjectStorage::Json => serde_json::to_vec(tag)?,
+            ObjectStorage::Git => raw,
+        };
+        self.store_object(&tag.hash, &serialized)?;
+        Ok(tag.hash.clone())
+    }
+
+    pub fn store_object(&self, hash: &str, content: &[u8]) -> Result<()> {
+        let _span = profile::span(Phase::ObjectIo);
+        if !is_object_id(hash) {
+            return Err(format!("Invalid object id '{}'", hash).into());
+        }
         let (dir_name, file_name) = hash.split_at(2);
         let obj_dir = self.objects_dir.join(dir_name);
         fs::create_dir_all(&obj_dir)?;
 
         let obj_path = obj_dir.join(file_name);
-        if obj_path.exists() {
+        if obj_path.exists() || self.find_packed(hash).is_some() {
             return Ok(());
         }
 
@@ -72,38 +260,677 @@ impl ObjectStore {
     }
 
     pub fn load_blob(&self, hash: &str) -> Result<Blob> {
-        let content = self.load_object(hash)?;
-        let blob: Blob = serde_json::from_slice(&content)?;
-        Ok(blob)
+        match self.storage {
+            ObjectStorage::Json => self.load_as(hash, "blob"),
+            ObjectStorage::Git => {
+                let (_, content, _) = self.load_git(hash, "blob")?;
+                Ok(Blob {
+                    hash: hash.to_string(),
+                    content,
+                })
+            }
+        }
     }
 
     pub fn load_tree(&self, hash: &str) -> Result<Tree> {
-        let content = self.load_object(hash)?;
-        let tree: Tree = serde_json::from_slice(&content)?;
-        Ok(tree)
+        match self.storage {
+            ObjectStorage::Json => self.load_as(hash, "tree"),
+            ObjectStorage::Git => {
+                let mut entries = HashMap::new();
+                self.flatten_git_tree(hash, "", &mut entries, &mut Vec::new())?;
+                Ok(Tree {
+                    hash: hash.to_string(),
+                    entries,
+                })
+            }
+        }
     }
 
     pub fn load_commit(&self, hash: &str) -> Result<Commit> {
-        let content = self.load_object(hash)?;
-        let commit: Commit = serde_json::from_slice(&content)?;
-        Ok(commit)
+        match self.storage {
+            ObjectStorage::Json => self.load_as(hash, "commit"),
+            ObjectStorage::Git => {
+                let (path, payload, start) = self.load_git(hash, "commit")?;
+                git_object::decode_commit(hash, &payload)
+                    .map_err(|error| corrupt(hash, path, "commit", start, error).into())
+            }
+        }
     }
 
-    fn load_object(&self, hash: &str) -> Result<Vec<u8>> {
-        let (dir_name, file_name) = hash.split_at(2);
-        let obj_path = self.objects_dir.join(dir_name).join(file_name);
+    pub fn load_tag(&self, hash: &str) -> Result<Tag> {
+        match self.storage {
+            ObjectStorage::Json => self.load_as(hash, "tag"),
+            ObjectStorage::Git => {
+                let (path, payload, start) = self.load_git(hash, "tag")?;
+                git_object::decode_tag(hash, &payload)
+                    .map_err(|error| corrupt(hash, path, "tag", start, error).into())
+            }
+        }
+    }
+
+    // What `hash` names once any annotated tags are followed through
+    pub fn peel(&self, hash: &str) -> Result<String> {
+        let mut hash = hash.to_string();
+        while self.read_typed(&hash)?.0 == "tag" {
+            hash = self.load_tag(&hash)?.object;
+        }
+        Ok(hash)
+    }
+
+    pub fn load_object(&self, hash: &str) -> Result<Vec<u8>> {
+        Ok(self.read_object(hash, "object")?.1)
+    }
+
+    // An object's kind and its content as `cat-file` shows it: the payload
+    // with git storage; with JSON storage a blob's bytes, or the stored JSON
+    // for anything else
+    pub fn read_typed(&self, hash: &str) -> Result<(String, Vec<u8>)> {
+        let (path, raw) = self.read_object(hash, "object")?;
+        match self.storage {
+            ObjectStorage::Git => {
+                let (kind, payload, _) = git_object::unwrap(&raw)
+                    .map_err(|error| corrupt(hash, path, "object", 0, error))?;
+                Ok((kind.to_string(), payload.to_vec()))
+            }
+            ObjectStorage::Json => {
+                let fields: serde_json::Map<String, serde_json::Value> =
+                    serde_json::from_slice(&raw).map_err(|e| CorruptObject {
+                        hash: hash.to_string(),
+                        path,
+                        kind: "object",
+                        stage: CorruptionStage::Parse,
+                        offset: json_offset(&raw, e.line(), e.column()),
+                        reason: e.to_string(),
+                    })?;
+                match json_kind(&fields) {
+                    Some("blob") => Ok(("blob".to_string(), self.load_blob(hash)?.content)),
+                    Some(kind) => Ok((kind.to_string(), raw)),
+                    None => Err(format!("Object {} has an unknown type", hash).into()),
+                }
+            }
+        }
+    }
+
+    // Every tree object making up the tree `hash`: just itself with JSON
+    // storage, itself and all its subtrees with git storage
+    pub fn tree_objects(&self, hash: &str) -> Result<Vec<String>> {
+        let mut trees = Vec::new();
+        match self.storage {
+            ObjectStorage::Json => trees.push(hash.to_string()),
+            ObjectStorage::Git => {
+                self.flatten_git_tree(hash, "", &mut HashMap::new(), &mut trees)?;
+            }
+        }
+        Ok(trees)
+    }
+
+    // Add the entries of the git tree `hash` to `entries` under `prefix`,
+    // recording each tree object visited in `trees`
+    fn flatten_git_tree(
+        &self,
+        hash: &str,
+        prefix: &str,
+        entries: &mut HashMap<String, TreeEntry>,
+        trees: &mut Vec<String>,
+    ) -> Result<()> {
+        let (path, payload, start) = self.load_git(hash, "tree")?;
+        let items = git_object::decode_tree(&payload, self.algorithm.raw_len())
+            .map_err(|error| corrupt(hash, path, "tree", start, error))?;
+        trees.push(hash.to_string());
+
+        // An empty subtree is how an empty directory is tracked
+        if items.is_empty() && !prefix.is_empty() {
+            let path = prefix.trim_end_matches('/').to_string();
+            entries.insert(
+                path.clone(),
+                TreeEntry {
+                    mode: worktree::DIR_MODE.to_string(),
+                    hash: hash.to_string(),
+                    name: path,
+                    is_file: false,
+                },
+            );
+        }
+        for item in items {
+            let path = format!("{}{}", prefix, item.name);
+            if item.is_dir() {
+                self.flatten_git_tree(&item.hash, &format!("{}/", path), entries, trees)?;
+            } else {
+                entries.insert(
+                    path.clone(),
+                    TreeEntry {
+                        mode: item.mode,
+                        hash: item.hash,
+                        name: path,
+                        is_file: true,
+                    },
+                );
+            }
+        }
+        Ok(())
+    }
+
+    // The payload of a git-format object, which must be of `kind`, and
+    // where it starts in the object
+    fn load_git(&self, hash: &str, kind: &'static str) -> Result<(PathBuf, Vec<u8>, usize)> {
+        let (path, raw) = self.read_object(hash, kind)?;
+        let (found, payload, start) = match git_object::unwrap(&raw) {
+            Ok(parts) => parts,
+            Err(error) => return Err(corrupt(hash, path, kind, 0, error).into()),
+        };
+        if found != kind {
+            return Err(format!("Object {} is a {}, not a {}", hash, found, kind).into());
+        }
+        Ok((path, payload.to_vec(), start))
+    }
 
-        let compressed = fs::read(obj_path)?;
-        let mut decoder = ZlibDecoder::new(&compressed[..]);
-        let mut content = Vec::new();
-        decoder.read_to_end(&mut content)?;
+    fn load_as<T: DeserializeOwned>(&self, hash: &str, kind: &'static str) -> Result<T> {
+        let (path, content) = self.read_object(hash, kind)?;
+        serde_json::from_slice(&content).map_err(|e| {
+            let location = format!(" at line {} column {}", e.line(), e.column());
+            let reason = e.to_string();
+            let reason = reason.strip_suffix(&location).unwrap_or(&reason);
+            let reason = match e.classify() {
+                serde_json::error::Category::Eof => format!("truncated ({})", reason),
+                _ => reason.to_string(),
+            };
+            CorruptObject {
+                hash: hash.to_string(),
+                path,
+                kind,
+                stage: CorruptionStage::Parse,
+                offset: json_offset(&content, e.line(), e.column()),
+                reason,
+            }
+            .into()
+        })
+    }
+
+    // The decompressed bytes of an object and the file they came from. A
+    // packed object reads back exactly as it would have been stored loose.
+    fn read_object(&self, hash: &str, kind: &'static str) -> Result<(PathBuf, Vec<u8>)> {
+        let _span = profile::span(Phase::ObjectIo);
+        let Some(obj_path) = self.find_object_path(hash) else {
+            let (pack, offset) = self
+                .find_packed(hash)
+                .ok_or(format!("Object {} not found", hash))?;
+            let (found, content) =
+                pack.read_entry(offset)
+                    .map_err(|(offset, reason)| CorruptObject {
+                        hash: hash.to_string(),
+                        path: pack.pack_path.clone(),
+                        kind,
+                        stage: CorruptionStage::Decompress,
+                        offset,
+                        reason,
+                    })?;
+            let content = match self.storage {
+                ObjectStorage::Json => content,
+                ObjectStorage::Git => git_object::wrap(found, &content),
+            };
+            return Ok((pack.pack_path.clone(), content));
+        };
 
-        Ok(content)
+        let compressed = fs::read(&obj_path)?;
+        let content = decompress(&compressed).map_err(|(offset, reason)| CorruptObject {
+            hash: hash.to_string(),
+            path: obj_path.clone(),
+            kind,
+            stage: CorruptionStage::Decompress,
+            offset,
+            reason,
+        })?;
+
+        Ok((obj_path, content))
     }
 
     pub fn object_exists(&self, hash: &str) -> bool {
+        self.find_object_path(hash).is_some() || self.find_packed(hash).is_some()
+    }
+
+    // Look in our own object directory first, then in any alternates
+    fn find_object_path(&self, hash: &str) -> Option<PathBuf> {
+        if !is_object_id(hash) {
+            return None;
+        }
+
         let (dir_name, file_name) = hash.split_at(2);
-        let obj_path = self.objects_dir.join(dir_name).join(file_name);
-        obj_path.exists()
+        std::iter::once(&self.objects_dir)
+            .chain(self.alternates.iter())
+            .map(|dir| dir.join(dir_name).join(file_name))
+            .find(|path| path.exists())
     }
+
+    fn packs(&self) -> &[PackIndex] {
+        self.packs.get_or_init(|| {
+            std::iter::once(&self.objects_dir)
+                .chain(self.alternates.iter())
+                .flat_map(|dir| packfile::load_indexes(dir, self.algorithm.raw_len()))
+                .collect()
+        })
+    }
+
+    // The commit-graph cache, read on first use. One that can't be read is
+    // ignored: walks load the commits themselves instead.
+    pub fn commit_graph(&self) -> Option<&CommitGraphFile> {
+        self.commit_graph
+            .get_or_init(|| {
+                CommitGraphFile::open(&commit_graph::path(&self.objects_dir), self.algorithm).ok()
+            })
+            .as_ref()
+    }
+
+    // Reachability bitmaps (see bitmap) and the pack they index, read on
+    // first use
+    pub fn bitmaps(&self) -> Option<(&PackIndex, &PackBitmaps)> {
+        let (i, bitmaps) = self
+            .bitmaps
+            .get_or_init(|| {
+                let own = self.objects_dir.join("pack");
+                self.packs().iter().enumerate().find_map(|(i, pack)| {
+                    if pack.pack_path.parent() != Some(&own) {
+                        return None;
+                    }
+                    Some((i, PackBitmaps::open(pack, self.algorithm)?))
+                })
+            })
+            .as_ref()?;
+        Some((&self.packs()[*i], bitmaps))
+    }
+
+    pub fn write_commit_graph(&self, commits: &[commit_graph::GraphCommit]) -> Result<()> {
+        commit_graph::write(&self.objects_dir, self.algorithm, commits)
+    }
+
+    pub fn commit_graph_path(&self) -> PathBuf {
+        commit_graph::path(&self.objects_dir)
+    }
+
+    fn find_packed(&self, hash: &str) -> Option<(&PackIndex, u64)> {
+        if hash.len() != self.algorithm.hex_len() {
+            return None;
+        }
+        self.packs()
+            .iter()
+            .find_map(|pack| Some((pack, pack.find(hash)?)))
+    }
+
+    // Packed objects whose id starts with `prefix`
+    pub fn packed_with_prefix(&self, prefix: &str) -> Vec<String> {
+        self.packs()
+            .iter()
+            .flat_map(|pack| pack.ids())
+            .filter(|id| id.starts_with(prefix))
+            .collect()
+    }
+
+    // Ids of the loose objects in our own object directory
+    pub fn loose_objects(&self) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.objects_dir)? {
+            let entry = entry?;
+            let dir_name = entry.file_name().to_string_lossy().to_string();
+            if dir_name.len() != 2 || !entry.path().is_dir() {
+                continue;
+            }
+            for obj_entry in fs::read_dir(entry.path())? {
+                let id = format!("{}{}", dir_name, obj_entry?.file_name().to_string_lossy());
+                if id.len() == self.algorithm.hex_len() && is_object_id(&id) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    // Our own packs, leaving out those of alternates
+    pub fn own_packs(&self) -> Vec<&PackIndex> {
+        self.packs()
+            .iter()
+            .filter(|pack| pack.pack_path.parent() == Some(&self.objects_dir.join("pack")))
+            .collect()
+    }
+
+    // Write the objects `hashes` into a new pack and return its path
+    pub fn write_pack(&self, hashes: &[String], deltas: Option<DeltaWindow>) -> Result<PathBuf> {
+        let mut entries = Vec::with_capacity(hashes.len());
+        for hash in hashes {
+            let (path, raw) = self.read_object(hash, "object")?;
+            let (kind, content) = match self.storage {
+                ObjectStorage::Git => {
+                    let (kind, payload, _) = git_object::unwrap(&raw)
+                        .map_err(|error| corrupt(hash, path, "object", 0, error))?;
+                    let kind = packfile::known_kind(kind)
+                        .ok_or(format!("Object {} has an unknown type '{}'", hash, kind))?;
+                    (kind, payload.to_vec())
+                }
+                ObjectStorage::Json => {
+                    let fields: serde_json::Map<String, serde_json::Value> =
+                        serde_json::from_slice(&raw).map_err(|e| CorruptObject {
+                            hash: hash.clone(),
+                            path,
+                            kind: "object",
+                            stage: CorruptionStage::Parse,
+                            offset: json_offset(&raw, e.line(), e.column()),
+                            reason: e.to_string(),
+                        })?;
+                    let kind =
+                        json_kind(&fields).ok_or(format!("Object {} has an unknown type", hash))?;
+                    (kind, raw)
+                }
+            };
+            entries.push(PackEntry {
+                hash: hash.clone(),
+                kind,
+                content,
+            });
+        }
+        packfile::write_pack(&self.objects_dir, self.algorithm, &entries, deltas)
+    }
+
+    // When the loose copy of an object was last written
+    pub fn loose_modified(&self, hash: &str) -> Result<std::time::SystemTime> {
+        if !is_object_id(hash) {
+            return Err(format!("Invalid object id '{}'", hash).into());
+        }
+        let (dir_name, file_name) = hash.split_at(2);
+        Ok(fs::metadata(self.objects_dir.join(dir_name).join(file_name))?.modified()?)
+    }
+
+    // Delete the loose copy of an object, and its fan-out directory once empty
+    pub fn remove_loose(&self, hash: &str) -> Result<()> {
+        if !is_object_id(hash) {
+            return Err(format!("Invalid object id '{}'", hash).into());
+        }
+        let (dir_name, file_name) = hash.split_at(2);
+        let dir = self.objects_dir.join(dir_name);
+        fs::remove_file(dir.join(file_name))?;
+        if fs::read_dir(&dir)?.next().is_none() {
+            let _ = fs::remove_dir(&dir);
+        }
+        Ok(())
+    }
+}
+
+// The kind of a JSON-stored object, told apart by its fields
+fn json_kind(fields: &serde_json::Map<String, serde_json::Value>) -> Option<&'static str> {
+    if fields.contains_key("content") {
+        Some("blob")
+    } else if fields.contains_key("entries") {
+        Some("tree")
+    } else if fields.contains_key("tree") {
+        Some("commit")
+    } else if fields.contains_key("tagger") {
+        Some("tag")
+    } else {
+        None
+    }
+}
+
+// A git object whose payload (starting at `start`) failed to decode
+fn corrupt(
+    hash: &str,
+    path: PathBuf,
+    kind: &'static str,
+    start: usize,
+    (offset, reason): git_object::DecodeError,
+) -> CorruptObject {
+    CorruptObject {
+        hash: hash.to_string(),
+        path,
+        kind,
+        stage: CorruptionStage::Parse,
+        offset: start + offset,
+        reason,
+    }
+}
+
+// One directory level of a flat tree, for writing it as nested git trees
+#[derive(Default)]
+struct GitDir<'a> {
+    files: Vec<(&'a str, &'a TreeEntry)>,
+    dirs: BTreeMap<&'a str, GitDir<'a>>,
+}
+
+impl<'a> GitDir<'a> {
+    fn from_entries(entries: &'a HashMap<String, TreeEntry>) -> Self {
+        let mut root = GitDir::default();
+        for (path, entry) in entries {
+            let (parents, name) = match path.rsplit_once('/') {
+                Some((parents, name)) => (Some(parents), name),
+                None => (None, path.as_str()),
+            };
+            let mut dir = &mut root;
+            for component in parents.into_iter().flat_map(|parents| parents.split('/')) {
+                dir = dir.dirs.entry(component).or_default();
+            }
+            // Empty directories become empty subtrees
+            if entry.is_file {
+                dir.files.push((name, entry));
+            } else {
+                dir.dirs.entry(name).or_default();
+            }
+        }
+        root
+    }
+}
+
+// Inflate a whole zlib stream. A stream that stops early is an error too, as
+// is anything after its end; failures carry the offset reached in `compressed`.
+fn decompress(compressed: &[u8]) -> std::result::Result<Vec<u8>, (usize, String)> {
+    let mut inflater = Decompress::new(true);
+    let mut content = Vec::with_capacity(compressed.len() * 2);
+    loop {
+        if content.len() == content.capacity() {
+            content.reserve(content.len().max(4096));
+        }
+        let consumed = inflater.total_in() as usize;
+        let produced = inflater.total_out();
+        let status = inflater
+            .decompress_vec(&compressed[consumed..], &mut content, FlushDecompress::None)
+            .map_err(|e| (inflater.total_in() as usize, e.to_string()))?;
+        let offset = inflater.total_in() as usize;
+        match status {
+            Status::StreamEnd if offset < compressed.len() => {
+                return Err((offset, "trailing data after the compressed object".into()));
+            }
+            Status::StreamEnd => return Ok(content),
+            _ if offset == consumed
+                && inflater.total_out() == produced
+                && content.len() < content.capacity() =>
+            {
+                return Err((offset, "compressed data ends early".into()));
+            }
+            _ => {}
+        }
+    }
+}
+
+// Ids come from other objects too, so a corrupt one must not be able to
+// point outside the object directory
+fn is_object_id(hash: &str) -> bool {
+    hash.len() >= 3 && hash.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CorruptionStage {
+    Decompress,
+    Parse,
+}
+
+// An object file that exists but can't be read back. The offset is into the
+// compressed file when decompression fails and into the decompressed object
+// when parsing does.
+#[derive(Debug)]
+pub struct CorruptObject {
+    pub hash: String,
+    pub path: PathBuf,
+    pub kind: &'static str,
+    pub stage: CorruptionStage,
+    pub offset: usize,
+    pub reason: String,
+}
+
+impl fmt::Display for CorruptObject {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let stage = match self.stage {
+            CorruptionStage::Decompress => "cannot decompress",
+            CorruptionStage::Parse => "cannot parse",
+        };
+        write!(
+            f,
+            "Corrupt {} {} ({}): {} at byte {}: {}",
+            self.kind,
+            self.hash,
+            self.path.display(),
+            stage,
+            self.offset,
+            self.reason
+        )
+    }
+}
+
+impl std::error::Error for CorruptObject {}
+
+// serde_json reports 1-based lines and columns; turn them into a byte offset
+fn json_offset(content: &[u8], line: usize, column: usize) -> usize {
+    let line_start = content
+        .split_inclusive(|&b| b == b'\n')
+        .take(line.saturating_sub(1))
+        .map(|line| line.len())
+        .sum::<usize>();
+    (line_start + column.saturating_sub(1)).min(content.len())
+}
+
+// Copy the loose objects and packs of `src_objects`, and of the stores it
+// borrows from, that `dst_objects` has no copy of. Returns how many objects
+// were copied.
+pub fn copy_missing_objects(src_objects: &Path, dst_objects: &Path) -> Result<usize> {
+    if !src_objects.exists() {
+        return Ok(0);
+    }
+
+    fs::create_dir_all(dst_objects)?;
+    let mut copied_count = 0;
+
+    // Objects may also live in the source's alternates, and the destination
+    // doesn't need copies of anything its own alternates already provide
+    let mut src_dirs = vec![src_objects.to_path_buf()];
+    src_dirs.extend(read_alternates(src_objects));
+    let dst_alternates = read_alternates(dst_objects);
+
+    for src_objects in &src_dirs {
+        for entry in fs::read_dir(src_objects)? {
+            let entry = entry?;
+            let src_dir = entry.path();
+
+            if src_dir.is_dir() && entry.file_name() != "info" && entry.file_name() != "pack" {
+                let dir_name = entry.file_name();
+                let dst_dir = dst_objects.join(&dir_name);
+                fs::create_dir_all(&dst_dir)?;
+
+                for obj_entry in fs::read_dir(&src_dir)? {
+                    let obj_entry = obj_entry?;
+                    let src_obj = obj_entry.path();
+                    let dst_obj = dst_dir.join(obj_entry.file_name());
+
+                    let borrowed = dst_alternates
+                        .iter()
+                        .any(|alt| alt.join(&dir_name).join(obj_entry.file_name()).exists());
+
+                    if !dst_obj.exists() && !borrowed {
+                        copy_object_file(&src_obj, &dst_obj)?;
+                        copied_count += 1;
+                    }
+                }
+            }
+        }
+        copied_count += copy_packs(src_objects, dst_objects, &dst_alternates)?;
+    }
+
+    Ok(copied_count)
+}
+
+// Copy an object file between stores via a temporary name, so an interrupted
+// transfer never leaves a truncated object behind. Objects that did arrive
+// are complete and get skipped when the transfer is retried.
+pub fn copy_object_file(src: &Path, dst: &Path) -> Result<()> {
+    // Unique per copy, as concurrent fetches may bring in the same object
+    static COPIES: AtomicUsize = AtomicUsize::new(0);
+    let tmp = dst.with_file_name(format!(
+        "tmp_obj_{}_{}_{}",
+        std::process::id(),
+        COPIES.fetch_add(1, Ordering::Relaxed),
+        dst.file_name().unwrap_or_default().to_string_lossy()
+    ));
+    fs::copy(src, &tmp)?;
+    fs::rename(&tmp, dst)?;
+    Ok(())
+}
+
+// Copy the packs of `src_objects` that `dst_objects` and the stores it
+// borrows from don't have yet, each pack before its index. Returns the number
+// of objects in the packs copied.
+pub fn copy_packs(src_objects: &Path, dst_objects: &Path, borrowed: &[PathBuf]) -> Result<usize> {
+    let mut copied = 0;
+    for pack in ObjectStore::at(src_objects.to_path_buf()).own_packs() {
+        let name = pack.pack_path.file_name().unwrap_or_default();
+        let dst_pack = dst_objects.join("pack").join(name);
+        if dst_pack.exists()
+            || borrowed
+                .iter()
+                .any(|alt| alt.join("pack").join(name).exists())
+        {
+            continue;
+        }
+        fs::create_dir_all(dst_pack.parent().unwrap())?;
+        copy_object_file(&pack.pack_path, &dst_pack)?;
+        copy_object_file(
+            &pack.pack_path.with_extension("idx"),
+            &dst_pack.with_extension("idx"),
+        )?;
+        copied += pack.len();
+    }
+    Ok(copied)
+}
+
+pub fn read_alternates(objects_dir: &Path) -> Vec<PathBuf> {
+    let alternates_path = objects_dir.join("info").join("alternates");
+    let content = match fs::read_to_string(alternates_path) {
+        Ok(content) => content,
+        Err(_) => return Vec::new(),
+    };
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            let path = PathBuf::from(line);
+            if path.is_absolute() {
+                path
+            } else {
+                objects_dir.join(path)
+            }
+        })
+        .filter(|path| path.is_dir())
+        .collect()
+}
+
+pub fn add_alternate(objects_dir: &Path, alternate: &Path) -> Result<()> {
+    let info_dir = objects_dir.join("info");
+    fs::create_dir_all(&info_dir)?;
+
+    let alternates_path = info_dir.join("alternates");
+    let mut content = fs::read_to_string(&alternates_path).unwrap_or_default();
+    let line = alternate.to_string_lossy().to_string();
+    if content.lines().any(|l| l.trim() == line) {
+        return Ok(());
+    }
+
+    content.push_str(&line);
+    content.push('\n');
+    fs::write(alternates_path, content)?;
+    Ok(())
 }
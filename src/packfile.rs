@@ -0,0 +1,618 @@
+use crate::{
+    Result, delta, git_object,
+    hash::HashAlgorithm,
+    index::{bytes_to_hex, hex_to_bytes},
+    profile::{self, Phase},
+};
+use flate2::Compression;
+use flate2::Crc;
+use flate2::bufread::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::borrow::Cow;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+// Packs bundle many objects into objects/pack/pack-<checksum>.pack, with a
+// sorted index of their ids in the matching .idx. Both use git's version 2
+// layout, so a repository with git object storage stays readable by git
+// after packing. The pack is
+//
+//   PACK <version: u32 = 2> <object count: u32>
+//   for each object:
+//     <kind and size, varint>   kind in bits 4-6 of the first byte, size in
+//                               its low 4 bits and 7 bits per later byte
+//     [<base distance, varint>] delta entries only
+//     <zlib stream>
+//   <checksum of everything above>
+//
+// and the index
+//
+//   \377tOc <version: u32 = 2>
+//   <fanout: 256 x u32>         objects whose id starts with a byte <= i
+//   <raw ids, sorted>
+//   <crc32 of each packed entry>
+//   <offset of each entry: u32>  high bit set: index into the 64-bit table
+//   <64-bit offsets>
+//   <pack checksum> <checksum of the index>
+//
+// An entry holds the same content a loose object would, minus the `<kind>
+// <size>\0` header of git storage, or (kind 6, git's OFS_DELTA) a delta
+// against an entry that many bytes earlier in the pack. Packs are only
+// written with deltas when asked to (see DeltaWindow); ref deltas, kind 7,
+// are never written, and only read_pack reads them.
+
+const PACK_SIGNATURE: &[u8] = b"PACK";
+const IDX_SIGNATURE: &[u8] = b"\xfftOc";
+const VERSION: u32 = 2;
+const LARGE_OFFSET: u32 = 0x8000_0000;
+const OFS_DELTA: u8 = 6;
+const REF_DELTA: u8 = 7;
+
+// Pack entry kinds
+fn kind_code(kind: &str) -> Option<u8> {
+    match kind {
+        "commit" => Some(1),
+        "tree" => Some(2),
+        "blob" => Some(3),
+        "tag" => Some(4),
+        _ => None,
+    }
+}
+
+// `kind`, if it's one a pack can hold
+pub fn known_kind(kind: &str) -> Option<&'static str> {
+    kind_code(kind).and_then(kind_name)
+}
+
+fn kind_name(code: u8) -> Option<&'static str> {
+    match code {
+        1 => Some("commit"),
+        2 => Some("tree"),
+        3 => Some("blob"),
+        4 => Some("tag"),
+        _ => None,
+    }
+}
+
+pub struct PackIndex {
+    pub pack_path: PathBuf,
+    raw_len: usize,
+    // Sorted raw ids, `raw_len` bytes each
+    ids: Vec<u8>,
+    offsets: Vec<u64>,
+}
+
+impl PackIndex {
+    pub fn open(idx_path: &Path, raw_len: usize) -> Result<Self> {
+        let data = fs::read(idx_path)?;
+        let invalid =
+            |reason: &str| format!("Invalid pack index {}: {}", idx_path.display(), reason);
+
+        if data.len() < 8 + 256 * 4 || &data[..4] != IDX_SIGNATURE {
+            return Err(invalid("bad signature").into());
+        }
+        if read_u32(&data, 4) != VERSION {
+            return Err(invalid("unsupported version").into());
+        }
+        let count = read_u32(&data, 8 + 255 * 4) as usize;
+        let ids_start = 8 + 256 * 4;
+        let crcs_start = ids_start + count * raw_len;
+        let offsets_start = crcs_start + count * 4;
+        let large_start = offsets_start + count * 4;
+        if data.len() < large_start + 2 * raw_len {
+            return Err(invalid("truncated").into());
+        }
+        let large_count = (data.len() - large_start - 2 * raw_len) / 8;
+
+        let mut offsets = Vec::with_capacity(count);
+        for i in 0..count {
+            let offset = read_u32(&data, offsets_start + i * 4);
+            if offset & LARGE_OFFSET == 0 {
+                offsets.push(offset as u64);
+                continue;
+            }
+            let large = (offset & !LARGE_OFFSET) as usize;
+            if large >= large_count {
+                return Err(invalid("offset out of range").into());
+            }
+            let at = large_start + large * 8;
+            offsets.push(u64::from_be_bytes(data[at..at + 8].try_into().unwrap()));
+        }
+
+        Ok(Self {
+            pack_path: idx_path.with_extension("pack"),
+            raw_len,
+            ids: data[ids_start..crcs_start].to_vec(),
+            offsets,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    fn id(&self, i: usize) -> &[u8] {
+        &self.ids[i * self.raw_len..(i + 1) * self.raw_len]
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = String> + '_ {
+        (0..self.len()).map(|i| bytes_to_hex(self.id(i)))
+    }
+
+    // Offset of the entry for `hash` in the pack
+    pub fn find(&self, hash: &str) -> Option<u64> {
+        self.position(hash).map(|position| self.offsets[position])
+    }
+
+    // Where `hash` comes in the index's sorted ids
+    pub fn position(&self, hash: &str) -> Option<usize> {
+        let raw = hex_to_bytes(hash).ok()?;
+        if raw.len() != self.raw_len {
+            return None;
+        }
+        let (mut low, mut high) = (0, self.len());
+        while low < high {
+            let mid = (low + high) / 2;
+            match self.id(mid).cmp(&raw[..]) {
+                std::cmp::Ordering::Less => low = mid + 1,
+                std::cmp::Ordering::Greater => high = mid,
+                std::cmp::Ordering::Equal => return Some(mid),
+            }
+        }
+        None
+    }
+
+    // The kind and content of the entry at `offset`. Failures carry the
+    // offset in the pack they were found at.
+    pub fn read_entry(
+        &self,
+        offset: u64,
+    ) -> std::result::Result<(&'static str, Vec<u8>), (usize, String)> {
+        let _span = profile::span(Phase::ObjectIo);
+        let at = offset as usize;
+        let mut file = fs::File::open(&self.pack_path).map_err(|e| (0, e.to_string()))?;
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| (at, e.to_string()))?;
+        let mut reader = BufReader::new(file);
+
+        let mut byte = [0u8];
+        let mut header_len = 0;
+        let mut next = |reader: &mut BufReader<fs::File>| {
+            header_len += 1;
+            reader
+                .read_exact(&mut byte)
+                .map(|_| byte[0])
+                .map_err(|_| (at, "truncated entry header".to_string()))
+        };
+        let first = next(&mut reader)?;
+        let code = (first >> 4) & 0x7;
+        let mut size = (first & 0x0f) as u64;
+        let mut shift = 4;
+        let mut more = first & 0x80 != 0;
+        while more {
+            let byte = next(&mut reader)?;
+            if shift > 57 {
+                return Err((at, "entry size too large".to_string()));
+            }
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+            more = byte & 0x80 != 0;
+        }
+        // A delta names its base by how far back in the pack it starts
+        let base = match code {
+            OFS_DELTA => {
+                let mut byte = next(&mut reader)?;
+                let mut distance = (byte & 0x7f) as u64;
+                while byte & 0x80 != 0 {
+                    if distance >= 1 << 56 {
+                        return Err((at, "delta base distance too large".to_string()));
+                    }
+                    byte = next(&mut reader)?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as u64;
+                }
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or((at, "delta base outside the pack".to_string()))?;
+                Some(base)
+            }
+            REF_DELTA => return Err((at, "ref delta entries are not supported".to_string())),
+            _ => None,
+        };
+
+        let data_start = at + header_len;
+        let mut content = Vec::with_capacity(size as usize);
+        ZlibDecoder::new(&mut reader)
+            .take(size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| (data_start, e.to_string()))?;
+        if content.len() as u64 != size {
+            return Err((
+                data_start,
+                format!("entry is {} bytes, header says {}", content.len(), size),
+            ));
+        }
+
+        match base {
+            Some(base) => {
+                let (kind, base) = self.read_entry(base)?;
+                let content = delta::apply_encoded(&base, &content)
+                    .map_err(|e| (data_start, e.to_string()))?;
+                Ok((kind, content))
+            }
+            None => {
+                let kind = kind_name(code).ok_or((at, format!("unknown entry type {}", code)))?;
+                Ok((kind, content))
+            }
+        }
+    }
+}
+
+// The indexes of every pack in `objects_dir`. Unreadable indexes are left
+// out, as if their pack wasn't there.
+pub fn load_indexes(objects_dir: &Path, raw_len: usize) -> Vec<PackIndex> {
+    let Ok(entries) = fs::read_dir(objects_dir.join("pack")) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "idx"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| PackIndex::open(path, raw_len).ok())
+        .filter(|index| index.pack_path.exists())
+        .collect()
+}
+
+pub struct PackEntry {
+    pub hash: String,
+    pub kind: &'static str,
+    pub content: Vec<u8>,
+}
+
+// What a pack entry's content is: whole, or a delta against another entry
+enum Base {
+    Whole(&'static str),
+    Offset(usize),
+    Id(String),
+}
+
+// Every object of a whole pack held in memory, such as the one a bundle
+// carries, with no index to go by. The objects are git's, named by their
+// `<kind> <size>\0`-wrapped content. Deltas may also name their base by id
+// (ref deltas); a base the pack doesn't hold is asked of `external`, as a
+// thin pack leaves out objects the receiver already has.
+pub fn read_pack(
+    data: &[u8],
+    algorithm: HashAlgorithm,
+    external: impl Fn(&str) -> Option<(&'static str, Vec<u8>)>,
+) -> Result<Vec<PackEntry>> {
+    let raw_len = algorithm.raw_len();
+    if data.len() < 12 + raw_len || &data[..4] != PACK_SIGNATURE || read_u32(data, 4) != VERSION {
+        return Err("Not a version 2 pack".into());
+    }
+    let (body, checksum) = data.split_at(data.len() - raw_len);
+    if hex_to_bytes(&algorithm.hash(body))? != checksum {
+        return Err("Pack checksum does not match its contents".into());
+    }
+
+    // (offset, base, content or delta) of each entry, in pack order
+    let count = read_u32(data, 8) as usize;
+    let mut waiting = Vec::with_capacity(count);
+    let mut at = 12;
+    for _ in 0..count {
+        let offset = at;
+        let corrupt = |reason: &str| format!("Corrupt pack entry at offset {}: {}", offset, reason);
+        let mut next = || {
+            let byte = *body.get(at).ok_or(corrupt("truncated entry header"))?;
+            at += 1;
+            Ok::<u8, String>(byte)
+        };
+        let first = next()?;
+        let code = (first >> 4) & 0x7;
+        let mut size = (first & 0x0f) as u64;
+        let mut shift = 4;
+        let mut byte = first;
+        while byte & 0x80 != 0 {
+            byte = next()?;
+            if shift > 57 {
+                return Err(corrupt("entry size too large").into());
+            }
+            size |= ((byte & 0x7f) as u64) << shift;
+            shift += 7;
+        }
+        let base = match code {
+            OFS_DELTA => {
+                let mut byte = next()?;
+                let mut distance = (byte & 0x7f) as usize;
+                while byte & 0x80 != 0 {
+                    if distance >= 1 << 56 {
+                        return Err(corrupt("delta base distance too large").into());
+                    }
+                    byte = next()?;
+                    distance = ((distance + 1) << 7) | (byte & 0x7f) as usize;
+                }
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .ok_or(corrupt("delta base outside the pack"))?;
+                Base::Offset(base)
+            }
+            REF_DELTA => {
+                let id = body
+                    .get(at..at + raw_len)
+                    .ok_or(corrupt("truncated delta base"))?;
+                at += raw_len;
+                Base::Id(bytes_to_hex(id))
+            }
+            code => Base::Whole(
+                kind_name(code).ok_or(corrupt(&format!("unknown entry type {}", code)))?,
+            ),
+        };
+
+        let mut content = Vec::with_capacity(size as usize);
+        let mut decoder = ZlibDecoder::new(&body[at..]);
+        (&mut decoder)
+            .take(size + 1)
+            .read_to_end(&mut content)
+            .map_err(|e| corrupt(&e.to_string()))?;
+        if content.len() as u64 != size {
+            return Err(corrupt(&format!(
+                "entry is {} bytes, header says {}",
+                content.len(),
+                size
+            ))
+            .into());
+        }
+        at += decoder.total_in() as usize;
+        waiting.push((offset, base, content));
+    }
+    if at != body.len() {
+        return Err("Pack has data after its last entry".into());
+    }
+
+    // An offset delta's base comes before it, so one pass in pack order
+    // resolves those; ref deltas may need more
+    let mut entries: Vec<PackEntry> = Vec::with_capacity(count);
+    let mut by_offset: HashMap<usize, usize> = HashMap::new();
+    let mut by_id: HashMap<String, usize> = HashMap::new();
+    while !waiting.is_empty() {
+        let before = waiting.len();
+        let mut blocked = Vec::new();
+        for (offset, base, data) in waiting {
+            let found = match &base {
+                Base::Whole(kind) => Some((*kind, None)),
+                Base::Offset(base) => by_offset.get(base).map(|&i| {
+                    (
+                        entries[i].kind,
+                        Some(Cow::Borrowed(&entries[i].content[..])),
+                    )
+                }),
+                Base::Id(id) => match by_id.get(id) {
+                    Some(&i) => Some((
+                        entries[i].kind,
+                        Some(Cow::Borrowed(&entries[i].content[..])),
+                    )),
+                    None => external(id).map(|(kind, content)| (kind, Some(Cow::Owned(content)))),
+                },
+            };
+            let Some((kind, base_content)) = found else {
+                blocked.push((offset, base, data));
+                continue;
+            };
+            let content = match base_content {
+                Some(base_content) => delta::apply_encoded(&base_content, &data)?,
+                None => data,
+            };
+            let hash = algorithm.hash(&git_object::wrap(kind, &content));
+            by_offset.insert(offset, entries.len());
+            by_id.insert(hash.clone(), entries.len());
+            entries.push(PackEntry {
+                hash,
+                kind,
+                content,
+            });
+        }
+        if blocked.len() == before {
+            return Err(format!("Pack has {} delta(s) whose base is missing", before).into());
+        }
+        waiting = blocked;
+    }
+    Ok(entries)
+}
+
+// How hard write_pack looks for deltas: each object is tried against the
+// `window` objects of its kind written just before it, and no chain of
+// deltas on deltas grows longer than `depth`
+#[derive(Debug, Clone, Copy)]
+pub struct DeltaWindow {
+    pub window: usize,
+    pub depth: usize,
+}
+
+// Write `entries` as a new pack in `objects_dir`/pack and return the path of
+// its .pack file. The index is written last, so a reader never finds an index
+// without its pack. With `deltas`, entries are ordered by kind and then size,
+// largest first as git does, so that most deltas only drop content.
+pub fn write_pack(
+    objects_dir: &Path,
+    algorithm: HashAlgorithm,
+    entries: &[PackEntry],
+    deltas: Option<DeltaWindow>,
+) -> Result<PathBuf> {
+    let mut pack = PACK_SIGNATURE.to_vec();
+    pack.extend_from_slice(&VERSION.to_be_bytes());
+    pack.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+
+    let mut order: Vec<&PackEntry> = entries.iter().collect();
+    if deltas.is_some() {
+        order.sort_by(|a, b| {
+            (a.kind, b.content.len(), &a.hash).cmp(&(b.kind, a.content.len(), &b.hash))
+        });
+    }
+    // (entry, offset, chain depth) of the candidate bases
+    let mut window: VecDeque<(&PackEntry, usize, usize)> = VecDeque::new();
+
+    // (raw id, crc32, offset) of each entry
+    let mut indexed = Vec::with_capacity(entries.len());
+    for entry in order {
+        let code = kind_code(entry.kind).ok_or(format!(
+            "Cannot pack {}: unknown object type '{}'",
+            entry.hash, entry.kind
+        ))?;
+        let offset = pack.len();
+        if window
+            .front()
+            .is_some_and(|(base, _, _)| base.kind != entry.kind)
+        {
+            window.clear();
+        }
+        let found = deltas.and_then(|deltas| best_delta(entry, &window, deltas.depth));
+        let depth = match &found {
+            Some((delta, base_offset, depth)) => {
+                pack.extend_from_slice(&entry_header(OFS_DELTA, delta.len() as u64));
+                pack.extend_from_slice(&base_distance((offset - base_offset) as u64));
+                depth + 1
+            }
+            None => {
+                pack.extend_from_slice(&entry_header(code, entry.content.len() as u64));
+                0
+            }
+        };
+        let data = found.as_ref().map_or(&entry.content, |(delta, _, _)| delta);
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        pack.extend_from_slice(&encoder.finish()?);
+        if let Some(deltas) = deltas {
+            window.push_back((entry, offset, depth));
+            if window.len() > deltas.window {
+                window.pop_front();
+            }
+        }
+
+        let mut crc = Crc::new();
+        crc.update(&pack[offset..]);
+        indexed.push((hex_to_bytes(&entry.hash)?, crc.sum(), offset as u64));
+    }
+    let checksum = hex_to_bytes(&algorithm.hash(&pack))?;
+    pack.extend_from_slice(&checksum);
+
+    indexed.sort_by(|a, b| a.0.cmp(&b.0));
+    indexed.dedup_by(|a, b| a.0 == b.0);
+
+    let mut idx = IDX_SIGNATURE.to_vec();
+    idx.extend_from_slice(&VERSION.to_be_bytes());
+    let mut fanout = [0u32; 256];
+    for (id, _, _) in &indexed {
+        fanout[id[0] as usize] += 1;
+    }
+    let mut total = 0;
+    for count in fanout {
+        total += count;
+        idx.extend_from_slice(&total.to_be_bytes());
+    }
+    for (id, _, _) in &indexed {
+        idx.extend_from_slice(id);
+    }
+    for (_, crc, _) in &indexed {
+        idx.extend_from_slice(&crc.to_be_bytes());
+    }
+    let mut large = Vec::new();
+    for (_, _, offset) in &indexed {
+        let small = if *offset < LARGE_OFFSET as u64 {
+            *offset as u32
+        } else {
+            large.push(*offset);
+            LARGE_OFFSET | (large.len() - 1) as u32
+        };
+        idx.extend_from_slice(&small.to_be_bytes());
+    }
+    for offset in large {
+        idx.extend_from_slice(&offset.to_be_bytes());
+    }
+    idx.extend_from_slice(&checksum);
+    idx.extend_from_slice(&hex_to_bytes(&algorithm.hash(&idx))?);
+
+    let pack_dir = objects_dir.join("pack");
+    fs::create_dir_all(&pack_dir)?;
+    let pack_path = pack_dir.join(format!("pack-{}.pack", bytes_to_hex(&checksum)));
+    write_new(&pack_path, &pack)?;
+    write_new(&pack_path.with_extension("idx"), &idx)?;
+    Ok(pack_path)
+}
+
+// The smallest delta of `entry` against a base in `window`, with that base's
+// offset and chain depth. A delta has to save at least half the content to
+// be worth the extra work of reading it back.
+fn best_delta(
+    entry: &PackEntry,
+    window: &VecDeque<(&PackEntry, usize, usize)>,
+    max_depth: usize,
+) -> Option<(Vec<u8>, usize, usize)> {
+    let mut best: Option<(Vec<u8>, usize, usize)> = None;
+    for (base, offset, depth) in window.iter().rev() {
+        if *depth >= max_depth {
+            continue;
+        }
+        let ops = delta::compute(&base.content, &entry.content);
+        let Ok(encoded) = delta::encode(base.content.len(), entry.content.len(), &ops) else {
+            continue;
+        };
+        let limit = best
+            .as_ref()
+            .map_or(entry.content.len() / 2, |(best, _, _)| best.len());
+        if encoded.len() < limit {
+            best = Some((encoded, *offset, *depth));
+        }
+    }
+    best
+}
+
+// How far back a delta's base starts: 7 bits per byte, most significant
+// first, each continued byte standing for one more than its value
+fn base_distance(mut distance: u64) -> Vec<u8> {
+    let mut bytes = vec![(distance & 0x7f) as u8];
+    distance >>= 7;
+    while distance > 0 {
+        distance -= 1;
+        bytes.push(0x80 | (distance & 0x7f) as u8);
+        distance >>= 7;
+    }
+    bytes.reverse();
+    bytes
+}
+
+fn entry_header(code: u8, mut size: u64) -> Vec<u8> {
+    let mut header = vec![(code << 4) | (size & 0x0f) as u8];
+    size >>= 4;
+    while size > 0 {
+        *header.last_mut().unwrap() |= 0x80;
+        header.push((size & 0x7f) as u8);
+        size >>= 7;
+    }
+    header
+}
+
+// Write via a temporary name so the file appears complete or not at all
+fn write_new(path: &Path, content: &[u8]) -> Result<()> {
+    if path.exists() {
+        return Ok(());
+    }
+    let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+    fs::write(&tmp, content)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+// A big-endian u32 at `at`, as pack indexes and commit-graphs store them
+pub(crate) fn read_u32(data: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes(data[at..at + 4].try_into().unwrap())
+}
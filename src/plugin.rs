@@ -0,0 +1,72 @@
+use crate::{Result, utils};
+use std::env;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Extensions: `mini_git foo <args>`, where foo is not a built-in command, runs
+// the first `mini_git-foo` executable on PATH with the remaining arguments.
+// Inside a repository it gets the repository's location in the environment,
+// so it doesn't have to search for it:
+//
+//   MINI_GIT_DIR        the .mini_git directory
+//   MINI_GIT_WORK_TREE  the top of the working tree
+//   MINI_GIT_PREFIX     the current directory relative to the top ("" there)
+//
+// and MINI_GIT_EXEC_PATH names this mini_git, for calling back into it.
+
+const PREFIX: &str = "mini_git-";
+
+// The executable implementing `name`, if one is on PATH
+pub fn find(name: &str) -> Option<PathBuf> {
+    // A name with a separator would reach outside PATH
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return None;
+    }
+    let file_name = format!("{}{}{}", PREFIX, name, env::consts::EXE_SUFFIX);
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(&file_name))
+        .find(|path| utils::is_executable(path))
+}
+
+// Run the extension named by `args[0]` with the rest of `args`. On Unix the
+// extension replaces this process; elsewhere we exit with its status.
+pub fn run(args: Vec<String>) -> Result<()> {
+    let (name, args) = args.split_first().ok_or("No command given")?;
+    let program = find(name).ok_or(format!(
+        "'{}' is not a mini_git command, and there is no {}{} on PATH",
+        name, PREFIX, name
+    ))?;
+
+    let mut command = Command::new(&program);
+    command.args(args);
+    if let Ok(exe) = env::current_exe() {
+        command.env("MINI_GIT_EXEC_PATH", exe);
+    }
+    if let Ok(repo) = utils::get_repository(None) {
+        let prefix = env::current_dir()?
+            .strip_prefix(&repo.work_dir)
+            .map(|relative| relative.to_string_lossy().replace('\\', "/"))
+            .unwrap_or_default();
+        command
+            .env("MINI_GIT_DIR", &repo.git_dir)
+            .env("MINI_GIT_WORK_TREE", &repo.work_dir)
+            .env("MINI_GIT_PREFIX", prefix);
+    }
+    exec(command, &program)
+}
+
+#[cfg(unix)]
+fn exec(mut command: Command, program: &Path) -> Result<()> {
+    use std::os::unix::process::CommandExt;
+    // Only returns on failure
+    let e = command.exec();
+    Err(format!("Failed to run {}: {}", program.display(), e).into())
+}
+
+#[cfg(not(unix))]
+fn exec(mut command: Command, program: &Path) -> Result<()> {
+    let status = command
+        .status()
+        .map_err(|e| format!("Failed to run {}: {}", program.display(), e))?;
+    std::process::exit(status.code().unwrap_or(1));
+}
@@ -0,0 +1,147 @@
+use crate::{Commit, Result};
+use chrono::{DateTime, NaiveDate, Utc};
+
+// Format strings with `%(<field>)` placeholders, as taken by
+// `for-each-ref --format`. `%%` is a literal percent sign; any other text is
+// copied as is. What a field expands to is up to the caller, which can start
+// from the commit fields below.
+
+pub fn expand(format: &str, mut field: impl FnMut(&str) -> Result<String>) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix('%') {
+            out.push('%');
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix('(') {
+            let end = after
+                .find(')')
+                .ok_or(format!("Unterminated field in format '{}'", format))?;
+            out.push_str(&field(&after[..end])?);
+            rest = &after[end + 1..];
+        } else {
+            out.push('%');
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// Every field name in `format`, so they can be checked before any output
+pub fn fields(format: &str) -> Vec<String> {
+    let mut names = Vec::new();
+    let _ = expand(format, |name| {
+        names.push(name.to_string());
+        Ok(String::new())
+    });
+    names
+}
+
+pub fn date(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format("%Y-%m-%d %H:%M:%S UTC").to_string()
+}
+
+// `YYYY-MM-DD` (midnight UTC) or an RFC 3339 timestamp, as given to --<option>
+pub fn parse_date(option: &str, value: &str) -> Result<DateTime<Utc>> {
+    if let Ok(timestamp) = DateTime::parse_from_rfc3339(value) {
+        return Ok(timestamp.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid --{} date '{}': use YYYY-MM-DD", option, value))?;
+    Ok(date.and_hms_opt(0, 0, 0).unwrap().and_utc())
+}
+
+// The fields describing a commit, or None for a name that isn't one. Commits
+// record a single identity, so the committer fields repeat the author's.
+pub fn commit_field(commit: &Commit, name: &str) -> Option<String> {
+    let (author_name, author_email) = split_identity(&commit.author);
+    let (subject, body) = subject_and_body(&commit.message);
+    Some(match name {
+        "authorname" | "committername" => author_name.to_string(),
+        "authoremail" | "committeremail" => format!("<{}>", author_email),
+        "authordate" | "committerdate" => date(&commit.timestamp),
+        "subject" => subject.to_string(),
+        "body" => body.to_string(),
+        "tree" => commit.tree.clone(),
+        "parent" => commit.parents.join(" "),
+        _ => return None,
+    })
+}
+
+// The first line of a message, and the rest after any blank lines
+pub fn subject_and_body(message: &str) -> (&str, &str) {
+    match message.split_once('\n') {
+        Some((subject, body)) => (subject, body.trim_start_matches('\n')),
+        None => (message, ""),
+    }
+}
+
+// Expand the placeholders of `log --format`, git's short ones: %H and %h
+// the commit id in full and abbreviated, %T/%t the tree's, %P/%p the
+// parents', %an, %ae and %ad the author's name, email and date, %s the
+// subject, %b the body, %n a newline and %% a percent sign. `%(<field>)`
+// takes any of the commit fields above. Anything else is copied as is.
+pub fn format_commit(format: &str, commit: &Commit) -> Result<String> {
+    let short = |id: &String| id[..id.len().min(7)].to_string();
+    let (author_name, author_email) = split_identity(&commit.author);
+    let (subject, body) = subject_and_body(&commit.message);
+    let placeholders = [
+        ("H", commit.hash.clone()),
+        ("h", short(&commit.hash)),
+        ("T", commit.tree.clone()),
+        ("t", short(&commit.tree)),
+        ("P", commit.parents.join(" ")),
+        (
+            "p",
+            commit
+                .parents
+                .iter()
+                .map(short)
+                .collect::<Vec<_>>()
+                .join(" "),
+        ),
+        ("an", author_name.to_string()),
+        ("ae", author_email.to_string()),
+        ("ad", date(&commit.timestamp)),
+        ("s", subject.to_string()),
+        ("b", body.to_string()),
+        ("n", "\n".to_string()),
+        ("%", "%".to_string()),
+    ];
+
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(at) = rest.find('%') {
+        out.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+        if let Some(after) = rest.strip_prefix('(') {
+            let end = after
+                .find(')')
+                .ok_or(format!("Unterminated field in format '{}'", format))?;
+            let name = &after[..end];
+            out.push_str(
+                &commit_field(commit, name).ok_or(format!("Unknown commit field '{}'", name))?,
+            );
+            rest = &after[end + 1..];
+        } else if let Some((key, value)) =
+            placeholders.iter().find(|(key, _)| rest.starts_with(key))
+        {
+            out.push_str(value);
+            rest = &rest[key.len()..];
+        } else {
+            out.push('%');
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+// `Name <email>` into its two halves
+pub fn split_identity(identity: &str) -> (&str, &str) {
+    match identity.split_once('<') {
+        Some((name, email)) => (name.trim(), email.trim_end().trim_end_matches('>')),
+        None => (identity.trim(), ""),
+    }
+}
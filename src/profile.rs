@@ -0,0 +1,126 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Timings for `--profile`. Code that hashes, touches object files, walks
+// trees or diffs holds a Span for the duration; time is charged to the
+// innermost span only, so a tree walk that hashes files reports the hashing
+// under "hashing" and the rest under "tree traversal". With profiling off a
+// span is a single relaxed load.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Hashing,
+    ObjectIo,
+    TreeTraversal,
+    Diffing,
+}
+
+const PHASES: [Phase; 4] = [
+    Phase::Hashing,
+    Phase::ObjectIo,
+    Phase::TreeTraversal,
+    Phase::Diffing,
+];
+
+impl Phase {
+    fn name(self) -> &'static str {
+        match self {
+            Phase::Hashing => "hashing",
+            Phase::ObjectIo => "object io",
+            Phase::TreeTraversal => "tree traversal",
+            Phase::Diffing => "diffing",
+        }
+    }
+}
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static NANOS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+static CALLS: [AtomicU64; 4] = [const { AtomicU64::new(0) }; 4];
+
+thread_local! {
+    // Open spans on this thread, each with the time it last started running
+    static STACK: RefCell<Vec<(Phase, Instant)>> = const { RefCell::new(Vec::new()) };
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+fn charge(phase: Phase, since: Instant, now: Instant) {
+    NANOS[phase as usize].fetch_add((now - since).as_nanos() as u64, Ordering::Relaxed);
+}
+
+pub struct Span {
+    active: bool,
+}
+
+pub fn span(phase: Phase) -> Span {
+    if !enabled() {
+        return Span { active: false };
+    }
+    let now = Instant::now();
+    STACK.with_borrow_mut(|stack| {
+        // Pause the enclosing span
+        if let Some((outer, since)) = stack.last() {
+            charge(*outer, *since, now);
+        }
+        stack.push((phase, now));
+    });
+    CALLS[phase as usize].fetch_add(1, Ordering::Relaxed);
+    Span { active: true }
+}
+
+impl Drop for Span {
+    fn drop(&mut self) {
+        if !self.active {
+            return;
+        }
+        let now = Instant::now();
+        STACK.with_borrow_mut(|stack| {
+            if let Some((phase, since)) = stack.pop() {
+                charge(phase, since, now);
+            }
+            // Resume the enclosing span
+            if let Some((_, since)) = stack.last_mut() {
+                *since = now;
+            }
+        });
+    }
+}
+
+// Print the totals to stderr, so they never mix with the command's output.
+// Spans on worker threads add up, so phases can sum to more than the total.
+pub fn report(command: &str, total: Duration) {
+    if !enabled() {
+        return;
+    }
+    let mut accounted = Duration::ZERO;
+    eprintln!("profile: {}", command);
+    eprintln!("  {:<16}{:>12}{:>10}", "phase", "time", "calls");
+    for phase in PHASES {
+        let time = Duration::from_nanos(NANOS[phase as usize].load(Ordering::Relaxed));
+        let calls = CALLS[phase as usize].load(Ordering::Relaxed);
+        accounted += time;
+        eprintln!(
+            "  {:<16}{:>12}{:>10}",
+            phase.name(),
+            format_duration(time),
+            calls
+        );
+    }
+    eprintln!(
+        "  {:<16}{:>12}",
+        "other",
+        format_duration(total.saturating_sub(accounted))
+    );
+    eprintln!("  {:<16}{:>12}", "total", format_duration(total));
+}
+
+fn format_duration(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
@@ -0,0 +1,484 @@
+use crate::{
+    Result,
+    bitmap::Reachable,
+    delta::{self, DeltaOp},
+    hash::HashAlgorithm,
+    index::{bytes_to_hex, hex_to_bytes},
+    object_store::{ObjectStorage, ObjectStore},
+    worktree,
+};
+use flate2::Compression;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+// Transfer protocol spoken by `mini_git serve --stdio`: one JSON message per
+// line. The client sends a Request and reads Responses until it is answered.
+// A push request is followed by the pushed objects and a `done` message.
+//
+// Remotes reach a server through an `ext::<command>` URL, where the command
+// speaks the protocol on its stdin/stdout, e.g.
+//   ext::ssh host mini_git serve --stdio /srv/project
+//
+// A connection opens with a `hello` carrying the client's protocol version
+// and capabilities; the server answers with its own, and only capabilities
+// both sides list are used. Unknown capabilities are ignored, and a server
+// that predates `hello` answers with an error, which leaves the connection
+// at version 0 with no capabilities.
+
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// Objects travel zlib-compressed and hex-encoded
+const ZLIB: &str = "zlib";
+// Objects may be deltas against objects reachable from the receiver's tips
+const THIN: &str = "thin";
+// Pushes may carry a signed certificate
+pub const PUSH_CERT: &str = "push-cert";
+
+pub fn capabilities(format: HashAlgorithm, storage: ObjectStorage) -> Vec<String> {
+    vec![
+        format!("object-format={}", format),
+        format!("object-storage={}", storage),
+        ZLIB.to_string(),
+        THIN.to_string(),
+        PUSH_CERT.to_string(),
+    ]
+}
+
+// What both ends of a connection agreed on
+#[derive(Debug, Default)]
+pub struct Session {
+    pub version: u32,
+    pub capabilities: Vec<String>,
+}
+
+impl Session {
+    // Peers that don't announce a format predate SHA-256 and use SHA-1, and
+    // those that don't announce a storage use JSON
+    pub fn negotiate(
+        version: u32,
+        theirs: &[String],
+        format: HashAlgorithm,
+        storage: ObjectStorage,
+    ) -> Result<Self> {
+        let their_format = theirs
+            .iter()
+            .find_map(|c| c.strip_prefix("object-format="))
+            .unwrap_or(HashAlgorithm::Sha1.name());
+        if their_format != format.name() {
+            return Err(format!(
+                "Remote uses object format {}, this repository uses {}",
+                their_format, format
+            )
+            .into());
+        }
+        let their_storage = theirs
+            .iter()
+            .find_map(|c| c.strip_prefix("object-storage="))
+            .unwrap_or(ObjectStorage::Json.name());
+        if their_storage != storage.name() {
+            return Err(format!(
+                "Remote uses object storage {}, this repository uses {}",
+                their_storage, storage
+            )
+            .into());
+        }
+
+        Ok(Session {
+            version: version.min(PROTOCOL_VERSION),
+            capabilities: capabilities(format, storage)
+                .into_iter()
+                .filter(|c| theirs.contains(c))
+                .collect(),
+        })
+    }
+
+    pub fn has(&self, capability: &str) -> bool {
+        self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "command", rename_all = "kebab-case")]
+pub enum Request {
+    Hello {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    ListRefs,
+    Fetch {
+        wants: Vec<String>,
+        haves: Vec<String>,
+    },
+    Push {
+        refname: String,
+        old: Option<String>,
+        new: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cert: Option<String>,
+        // Move the branch even when `new` doesn't descend from `old`
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        force: bool,
+    },
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Response {
+    Capabilities {
+        version: u32,
+        capabilities: Vec<String>,
+    },
+    Refs {
+        refs: BTreeMap<String, String>,
+    },
+    Object {
+        hash: String,
+        #[serde(flatten)]
+        payload: Payload,
+    },
+    Done {
+        count: usize,
+    },
+    Ok {
+        message: String,
+    },
+    Error {
+        message: String,
+    },
+}
+
+// Decompressed object content in exactly one of three forms: plain, zlib
+// compressed (with `zlib`), or a delta against an object the receiver has
+// (with `thin`)
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Payload {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub content: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub zlib: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub delta: Option<Delta>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Delta {
+    pub base: String,
+    pub ops: Vec<DeltaOp>,
+}
+
+pub fn send<T: Serialize>(writer: &mut impl Write, message: &T) -> Result<()> {
+    writeln!(writer, "{}", serde_json::to_string(message)?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+// `None` once the other side has closed the connection
+pub fn receive<T: DeserializeOwned>(reader: &mut impl BufRead) -> Result<Option<T>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(serde_json::from_str(&line)?))
+}
+
+// An object to send, and the object the receiver already has that it may be
+// sent as a delta against
+pub type Outgoing = (String, Option<String>);
+
+pub fn send_object(
+    writer: &mut impl Write,
+    session: &Session,
+    object_store: &ObjectStore,
+    (hash, base): &Outgoing,
+) -> Result<()> {
+    let content = object_store.load_object(hash)?;
+
+    let mut payload = if session.has(ZLIB) {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&content)?;
+        Payload {
+            zlib: Some(bytes_to_hex(&encoder.finish()?)),
+            ..Default::default()
+        }
+    } else {
+        let content = String::from_utf8(content.clone())
+            .map_err(|_| format!("Object {} is not valid UTF-8", hash))?;
+        Payload {
+            content: Some(content),
+            ..Default::default()
+        }
+    };
+
+    // Thin transfer: only use the delta when it actually comes out smaller
+    if session.has(THIN)
+        && let Some(base) = base
+    {
+        let ops = delta::compute(&object_store.load_object(base)?, &content);
+        let thin = Payload {
+            delta: Some(Delta {
+                base: base.clone(),
+                ops,
+            }),
+            ..Default::default()
+        };
+        if serde_json::to_vec(&thin)?.len() < serde_json::to_vec(&payload)?.len() {
+            payload = thin;
+        }
+    }
+
+    send(
+        writer,
+        &Response::Object {
+            hash: hash.to_string(),
+            payload,
+        },
+    )
+}
+
+// Delta bases are resolved against `object_store`, which must already hold them
+pub fn decode_object(payload: Payload, object_store: &ObjectStore) -> Result<Vec<u8>> {
+    match payload {
+        Payload {
+            delta: Some(Delta { base, ops }),
+            ..
+        } => delta::apply(&object_store.load_object(&base)?, &ops),
+        Payload {
+            content: Some(content),
+            ..
+        } => Ok(content.into_bytes()),
+        Payload {
+            zlib: Some(zlib), ..
+        } => {
+            let compressed = hex_to_bytes(&zlib)?;
+            let mut decoder = ZlibDecoder::new(&compressed[..]);
+            let mut content = Vec::new();
+            decoder.read_to_end(&mut content)?;
+            Ok(content)
+        }
+        _ => Err("Object without content".into()),
+    }
+}
+
+pub fn is_remote_url(url: &str) -> bool {
+    url.starts_with("ext::")
+}
+
+// Files and trees at the tips the receiver has, used as delta bases
+#[derive(Default)]
+struct Bases {
+    paths: HashMap<String, String>,
+    tree: Option<String>,
+}
+
+// Objects reachable from `wants` but not from any of `haves` the store knows.
+// What the haves reach comes from reachability bitmaps where there are any.
+pub fn objects_to_send(
+    object_store: &ObjectStore,
+    wants: &[String],
+    haves: &[String],
+) -> Result<Vec<Outgoing>> {
+    let mut seen = Reachable::new(object_store);
+    let mut bases = Bases::default();
+    for have in haves {
+        if !object_store.object_exists(have) {
+            continue;
+        }
+        let have = object_store.peel(have)?;
+        let tree = worktree::load_commit_tree(object_store, &have)?;
+        bases.tree.get_or_insert(tree.hash);
+        for (path, entry) in tree.entries {
+            bases.paths.entry(path).or_insert(entry.hash);
+        }
+        seen.add_history(&have, true)?;
+    }
+
+    let mut objects = Vec::new();
+    for want in wants {
+        // Annotated tags go along with the commit they tag
+        let mut want = want.clone();
+        while object_store.read_typed(&want)?.0 == "tag" {
+            if seen.insert(&want)? {
+                objects.push((want.clone(), None));
+            }
+            want = object_store.load_tag(&want)?.object;
+        }
+        collect_reachable(object_store, &want, &bases, &mut seen, &mut objects)?;
+    }
+    Ok(objects)
+}
+
+fn collect_reachable(
+    object_store: &ObjectStore,
+    commit: &str,
+    bases: &Bases,
+    seen: &mut Reachable,
+    out: &mut Vec<Outgoing>,
+) -> Result<()> {
+    let mut pending = vec![commit.to_string()];
+    while let Some(hash) = pending.pop() {
+        if !seen.insert(&hash)? {
+            continue;
+        }
+        let commit = object_store.load_commit(&hash)?;
+        out.push((hash, None));
+
+        if seen.insert(&commit.tree)? {
+            let tree = object_store.load_tree(&commit.tree)?;
+            // With git storage, subdirectories are trees of their own
+            for subtree in object_store.tree_objects(&commit.tree)?.into_iter().skip(1) {
+                if seen.insert(&subtree)? {
+                    out.push((subtree, None));
+                }
+            }
+            out.push((commit.tree, bases.tree.clone()));
+            for (path, entry) in tree.entries {
+                if seen.insert(&entry.hash)? {
+                    out.push((entry.hash, bases.paths.get(&path).cloned()));
+                }
+            }
+        }
+        pending.extend(commit.parents);
+    }
+    Ok(())
+}
+
+// Client side of a connection to a server spawned from an `ext::` URL
+pub struct Connection {
+    child: Child,
+    reader: BufReader<ChildStdout>,
+    writer: Option<ChildStdin>,
+    pub session: Session,
+}
+
+impl Connection {
+    pub fn open(url: &str, object_store: &ObjectStore) -> Result<Self> {
+        let (format, storage) = (object_store.algorithm(), object_store.storage());
+        let command = url
+            .strip_prefix("ext::")
+            .ok_or(format!("Unsupported remote URL '{}'", url))?;
+        let mut args = command.split_whitespace();
+        let program = args.next().ok_or("Empty ext:: command")?;
+
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .map_err(|e| format!("Failed to run '{}': {}", program, e))?;
+        let reader = BufReader::new(child.stdout.take().unwrap());
+        let writer = child.stdin.take();
+
+        let mut connection = Connection {
+            child,
+            reader,
+            writer,
+            session: Session::default(),
+        };
+        connection.request(&Request::Hello {
+            version: PROTOCOL_VERSION,
+            capabilities: capabilities(format, storage),
+        })?;
+        match receive(&mut connection.reader)? {
+            Some(Response::Capabilities {
+                version,
+                capabilities,
+            }) => connection.session = Session::negotiate(version, &capabilities, format, storage)?,
+            // Servers older than `hello` reject it and carry on; they only
+            // know SHA-1 and JSON storage
+            Some(Response::Error { .. }) => {
+                connection.session = Session::negotiate(0, &[], format, storage)?;
+            }
+            Some(other) => return Err(unexpected(&other)),
+            None => return Err("Remote hung up unexpectedly".into()),
+        }
+        Ok(connection)
+    }
+
+    fn request(&mut self, request: &Request) -> Result<()> {
+        send(self.writer.as_mut().unwrap(), request)
+    }
+
+    fn response(&mut self) -> Result<Response> {
+        match receive(&mut self.reader)? {
+            Some(Response::Error { message }) => Err(format!("Remote error: {}", message).into()),
+            Some(response) => Ok(response),
+            None => Err("Remote hung up unexpectedly".into()),
+        }
+    }
+
+    pub fn list_refs(&mut self) -> Result<BTreeMap<String, String>> {
+        self.request(&Request::ListRefs)?;
+        match self.response()? {
+            Response::Refs { refs } => Ok(refs),
+            other => Err(unexpected(&other)),
+        }
+    }
+
+    // Receive the objects needed for `wants` into `object_store`
+    pub fn fetch(
+        &mut self,
+        object_store: &ObjectStore,
+        wants: Vec<String>,
+        haves: Vec<String>,
+    ) -> Result<usize> {
+        self.request(&Request::Fetch { wants, haves })?;
+        loop {
+            match self.response()? {
+                Response::Object { hash, payload } => {
+                    let content = decode_object(payload, object_store)?;
+                    object_store.store_object(&hash, &content)?
+                }
+                Response::Done { count } => return Ok(count),
+                other => return Err(unexpected(&other)),
+            }
+        }
+    }
+
+    // Send a `Request::Push` and the objects it needs
+    pub fn push(
+        &mut self,
+        object_store: &ObjectStore,
+        push: &Request,
+        objects: &[Outgoing],
+    ) -> Result<String> {
+        if let Request::Push { cert: Some(_), .. } = push
+            && !self.session.has(PUSH_CERT)
+        {
+            return Err("Remote does not support signed pushes".into());
+        }
+        self.request(push)?;
+
+        let writer = self.writer.as_mut().unwrap();
+        for object in objects {
+            send_object(writer, &self.session, object_store, object)?;
+        }
+        send(
+            writer,
+            &Response::Done {
+                count: objects.len(),
+            },
+        )?;
+
+        match self.response()? {
+            Response::Ok { message } => Ok(message),
+            other => Err(unexpected(&other)),
+        }
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        // Closing stdin lets the server's request loop finish
+        self.writer.take();
+        let _ = self.child.wait();
+    }
+}
+
+fn unexpected(response: &Response) -> Box<dyn std::error::Error> {
+    format!("Unexpected response from remote: {:?}", response).into()
+}
@@ -0,0 +1,193 @@
+use crate::{Repository, Result, config::Config, hash::HashAlgorithm};
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+// Signed pushes (`push --signed`): the pusher signs a certificate naming the
+// ref update with their SSH key (`user.signingKey`, through `ssh-keygen -Y
+// sign`) and sends it along with the push. The receiving repository checks
+// the signature against its `gpg.ssh.allowedSignersFile`, stores the
+// certificate as a blob and tells its pre-receive hook about it:
+//
+//   MINI_GIT_PUSH_CERT         id of the certificate blob
+//   MINI_GIT_PUSH_CERT_SIGNER  principal the signing key belongs to
+//   MINI_GIT_PUSH_CERT_STATUS  G (good), B (bad) or N (not checked: no
+//                              allowed signers file)
+//
+// A certificate reads:
+//
+//   certificate version 0.1
+//   pusher <name> <<email>> <seconds> <zone>
+//   pushee <remote url>
+//
+//   <old id> <new id> <ref>
+//   -----BEGIN SSH SIGNATURE-----
+//   ...
+
+const VERSION_LINE: &str = "certificate version 0.1";
+const SIGNATURE_START: &str = "-----BEGIN SSH SIGNATURE-----";
+// Signatures made for pushes can't be passed off as signatures of anything else
+const NAMESPACE: &str = "mini_git-push";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CertStatus {
+    Good,
+    Bad,
+    Unchecked,
+}
+
+impl CertStatus {
+    pub fn letter(self) -> &'static str {
+        match self {
+            CertStatus::Good => "G",
+            CertStatus::Bad => "B",
+            CertStatus::Unchecked => "N",
+        }
+    }
+}
+
+pub struct Verification {
+    pub status: CertStatus,
+    pub signer: Option<String>,
+}
+
+// Sign a certificate for moving `refname` from `old` to `new` on `pushee`
+pub fn create(
+    repo: &Repository,
+    pushee: &str,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+) -> Result<String> {
+    let config = Config::load(repo)?;
+    let key = config
+        .get("user.signingKey")
+        .ok_or("push --signed needs user.signingKey set to an SSH key file")?;
+    let null_id = HashAlgorithm::of_repo(repo)?.null_id();
+    let now = chrono::Local::now();
+
+    let payload = format!(
+        "{}\npusher {} {} {}\npushee {}\n\n{} {} {}\n",
+        VERSION_LINE,
+        config.author_ident(),
+        now.timestamp(),
+        now.format("%z"),
+        pushee,
+        old.unwrap_or(&null_id),
+        new,
+        refname
+    );
+    let signature = ssh_keygen(
+        &config,
+        &["-Y", "sign", "-n", NAMESPACE, "-f", key],
+        &payload,
+    )
+    .map_err(|e| format!("Failed to sign the push certificate: {}", e))?;
+    Ok(payload + &signature)
+}
+
+// Whether `cert` is for exactly this update, so it can't be replayed to
+// vouch for a different one
+pub fn covers(repo: &Repository, cert: &str, refname: &str, old: Option<&str>, new: &str) -> bool {
+    let null_id = HashAlgorithm::of_repo(repo).unwrap_or_default().null_id();
+    let expected = format!("{} {} {}", old.unwrap_or(&null_id), new, refname);
+    let (payload, _) = split(cert);
+    payload.starts_with(VERSION_LINE)
+        && payload
+            .split_once("\n\n")
+            .is_some_and(|(_, updates)| updates.lines().any(|line| line == expected))
+}
+
+// Check the signature against `gpg.ssh.allowedSignersFile`
+pub fn verify(repo: &Repository, cert: &str) -> Result<Verification> {
+    let config = Config::load(repo)?;
+    let Some(allowed) = config.get("gpg.ssh.allowedSignersFile") else {
+        return Ok(Verification {
+            status: CertStatus::Unchecked,
+            signer: None,
+        });
+    };
+    let (payload, signature) = split(cert);
+    let bad = Verification {
+        status: CertStatus::Bad,
+        signer: None,
+    };
+    let Some(signature) = signature else {
+        return Ok(bad);
+    };
+
+    // ssh-keygen reads the signature from a file
+    let sig_path = repo
+        .git_dir
+        .join(format!("push-cert-{}.sig", std::process::id()));
+    fs::write(&sig_path, signature)?;
+    let result = verify_signature(&config, allowed, &sig_path, payload);
+    fs::remove_file(&sig_path)?;
+
+    Ok(match result {
+        Some(signer) => Verification {
+            status: CertStatus::Good,
+            signer: Some(signer),
+        },
+        None => bad,
+    })
+}
+
+// The principal whose allowed key made the signature, if it is valid
+fn verify_signature(
+    config: &Config,
+    allowed: &str,
+    sig_path: &Path,
+    payload: &str,
+) -> Option<String> {
+    let sig_path = sig_path.to_str()?;
+    let principals = ssh_keygen(
+        config,
+        &["-Y", "find-principals", "-s", sig_path, "-f", allowed],
+        "",
+    )
+    .ok()?;
+    let signer = principals.lines().next()?.to_string();
+    ssh_keygen(
+        config,
+        &[
+            "-Y", "verify", "-f", allowed, "-I", &signer, "-n", NAMESPACE, "-s", sig_path,
+        ],
+        payload,
+    )
+    .ok()?;
+    Some(signer)
+}
+
+// The signed text, and the signature after it
+fn split(cert: &str) -> (&str, Option<&str>) {
+    match cert.find(SIGNATURE_START) {
+        Some(start) => (&cert[..start], Some(&cert[start..])),
+        None => (cert, None),
+    }
+}
+
+// Run `gpg.ssh.program` (ssh-keygen) with `input` on stdin and return its
+// stdout
+fn ssh_keygen(config: &Config, args: &[&str], input: &str) -> Result<String> {
+    let program = config.get("gpg.ssh.program").unwrap_or("ssh-keygen");
+    let mut child = Command::new(program)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("Failed to run {}: {}", program, e))?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr)
+            .trim()
+            .to_string()
+            .into());
+    }
+    Ok(String::from_utf8(output.stdout)?)
+}
@@ -0,0 +1,158 @@
+use crate::{Repository, Result, config::Config, hash::HashAlgorithm, utils};
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+
+// Reflogs: `.mini_git/logs/<ref>` (and `logs/HEAD`) get a line for every
+// movement of the ref, in git's format:
+//
+//   <old id> <new id> <name> <<email>> <seconds> <zone>\t<message>
+//
+// Lines are appended, so the newest entry is last; `<ref>@{n}` counts back
+// from it, with `@{0}` being where the ref points now.
+
+#[derive(Debug, Clone)]
+pub struct ReflogEntry {
+    pub old: String,
+    pub new: String,
+    pub ident: String,
+    pub timestamp: i64,
+    pub zone: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    fn parse(line: &str) -> Option<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let (old, rest) = head.split_once(' ')?;
+        let (new, rest) = rest.split_once(' ')?;
+        let (rest, zone) = rest.rsplit_once(' ')?;
+        let (ident, timestamp) = rest.rsplit_once(' ')?;
+        Some(ReflogEntry {
+            old: old.to_string(),
+            new: new.to_string(),
+            ident: ident.to_string(),
+            timestamp: timestamp.parse().ok()?,
+            zone: zone.to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+fn log_path(repo: &Repository, refname: &str) -> PathBuf {
+    repo.git_dir.join("logs").join(refname)
+}
+
+// Record that `refname` ("HEAD" or a full `refs/...` name) moved from `old`
+// (None when it was created) to `new`
+pub fn append(
+    repo: &Repository,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    let ident = Config::load(repo)?.author_ident();
+    let now = chrono::Local::now();
+    let null_id = HashAlgorithm::of_repo(repo)?.null_id();
+
+    // Messages are one line, as in git
+    let message = message.lines().next().unwrap_or("");
+    let line = format!(
+        "{} {} {} {} {}\t{}\n",
+        old.unwrap_or(&null_id),
+        new,
+        ident,
+        now.timestamp(),
+        now.format("%z"),
+        message
+    );
+
+    let path = log_path(repo, refname);
+    fs::create_dir_all(path.parent().unwrap())?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+// Log a ref movement, and the HEAD movement it implies when the ref is the
+// checked-out branch
+pub fn append_branch(
+    repo: &Repository,
+    refname: &str,
+    old: Option<&str>,
+    new: &str,
+    message: &str,
+) -> Result<()> {
+    append(repo, refname, old, new, message)?;
+    if refname != "HEAD" && utils::head_ref(repo)? == refname {
+        append(repo, "HEAD", old, new, message)?;
+    }
+    Ok(())
+}
+
+// Oldest first; a missing log is empty
+pub fn read(repo: &Repository, refname: &str) -> Result<Vec<ReflogEntry>> {
+    match fs::read_to_string(log_path(repo, refname)) {
+        Ok(content) => Ok(content.lines().filter_map(ReflogEntry::parse).collect()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// A deleted branch takes its log with it, as in git, along with the
+// directories of a nested name (below logs/refs/heads and the like) that
+// would keep a branch of the parent's name from getting a log
+pub fn remove(repo: &Repository, refname: &str) -> Result<()> {
+    let path = log_path(repo, refname);
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+        _ => {}
+    }
+    let logs = repo.git_dir.join("logs");
+    let mut dir = path.parent();
+    while let Some(current) = dir {
+        let depth = current
+            .strip_prefix(&logs)
+            .map_or(0, |p| p.components().count());
+        if depth <= 2 || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+// Where `refname` pointed `n` movements ago
+pub fn nth(repo: &Repository, refname: &str, n: usize) -> Result<String> {
+    let entries = read(repo, refname)?;
+    if entries.is_empty() {
+        return Err(format!("Log for '{}' is empty", refname).into());
+    }
+    entries
+        .iter()
+        .rev()
+        .nth(n)
+        .map(|entry| entry.new.clone())
+        .ok_or(format!("Log for '{}' only has {} entries", refname, entries.len()).into())
+}
+
+// The full ref a user-facing name's log lives under: HEAD, `refs/...`, a
+// branch, or a remote-tracking branch
+pub fn resolve_refname(repo: &Repository, name: &str) -> Result<String> {
+    if name == "HEAD" || name.starts_with("refs/") {
+        return Ok(name.to_string());
+    }
+    for candidate in [
+        format!("refs/heads/{}", name),
+        format!("refs/remotes/{}", name),
+    ] {
+        if repo.git_dir.join(&candidate).is_file() || log_path(repo, &candidate).is_file() {
+            return Ok(candidate);
+        }
+    }
+    Err(format!("Unknown ref '{}'", name).into())
+}
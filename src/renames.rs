@@ -0,0 +1,175 @@
+use crate::{Result, config::Config};
+use std::collections::HashMap;
+
+// Rename detection for status and diff. A deleted path and an added one are a
+// rename when they hold the same content, or, failing that, when enough of
+// their content is the same: the similarity score is the share of the larger
+// file made up of lines the two have in common. Exact renames are cheap to
+// find by hash; scoring every deleted file against every added one is not, so
+// it is skipped, with a warning, when deletions x additions exceeds the
+// square of the rename limit.
+
+const DEFAULT_THRESHOLD: usize = 50;
+const DEFAULT_LIMIT: usize = 1000;
+
+pub struct RenameOptions {
+    // Minimum similarity, in percent, for a pair to count as a rename
+    pub threshold: usize,
+    // Candidates on either side beyond which only exact renames are found;
+    // 0 means no limit
+    pub limit: usize,
+}
+
+impl RenameOptions {
+    // `find_renames` is the `--find-renames` value (`60%` or `60`); the
+    // limit comes from `limit_key` (e.g. status.renameLimit) if set, else
+    // diff.renameLimit
+    pub fn new(config: &Config, limit_key: &str, find_renames: Option<&str>) -> Result<Self> {
+        let threshold = match find_renames {
+            Some(value) => value
+                .trim_end_matches('%')
+                .parse::<usize>()
+                .ok()
+                .filter(|threshold| *threshold <= 100)
+                .ok_or(format!(
+                    "Invalid rename threshold '{}' (expected a percentage)",
+                    value
+                ))?,
+            None => DEFAULT_THRESHOLD,
+        };
+        let limit = match [limit_key, "diff.renameLimit"]
+            .iter()
+            .find_map(|key| config.get(key).map(|value| (key, value)))
+        {
+            Some((key, value)) => value
+                .parse()
+                .map_err(|_| format!("Invalid {} '{}'", key, value))?,
+            None => DEFAULT_LIMIT,
+        };
+        Ok(RenameOptions { threshold, limit })
+    }
+}
+
+// A path on one side of a comparison, with the id of its content
+pub struct Candidate {
+    pub path: String,
+    pub hash: String,
+}
+
+pub struct Rename {
+    pub from: String,
+    pub to: String,
+    // Similarity in percent; 100 for identical content
+    pub score: usize,
+}
+
+// Pair `deleted` paths with `added` ones. `content` loads a candidate's
+// content, and is only called when inexact renames are looked for.
+pub fn detect(
+    deleted: &[Candidate],
+    added: &[Candidate],
+    options: &RenameOptions,
+    mut content: impl FnMut(&Candidate) -> Result<Vec<u8>>,
+) -> Result<Vec<Rename>> {
+    let mut renames = Vec::new();
+    let mut deleted_used = vec![false; deleted.len()];
+    let mut added_used = vec![false; added.len()];
+
+    // Identical content first
+    let mut by_hash: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, candidate) in added.iter().enumerate().rev() {
+        by_hash.entry(&candidate.hash).or_default().push(i);
+    }
+    for (i, from) in deleted.iter().enumerate() {
+        if let Some(to) = by_hash.get_mut(from.hash.as_str()).and_then(Vec::pop) {
+            deleted_used[i] = true;
+            added_used[to] = true;
+            renames.push(Rename {
+                from: from.path.clone(),
+                to: added[to].path.clone(),
+                score: 100,
+            });
+        }
+    }
+
+    let sources: Vec<usize> = (0..deleted.len()).filter(|&i| !deleted_used[i]).collect();
+    let targets: Vec<usize> = (0..added.len()).filter(|&i| !added_used[i]).collect();
+    if sources.is_empty() || targets.is_empty() {
+        return Ok(renames);
+    }
+    if options.limit > 0 && sources.len() * targets.len() > options.limit * options.limit {
+        eprintln!("warning: inexact rename detection was skipped due to too many files.");
+        eprintln!(
+            "warning: you may want to set your diff.renameLimit variable to at least {} and retry the command.",
+            sources.len().max(targets.len())
+        );
+        return Ok(renames);
+    }
+
+    let mut source_contents = Vec::with_capacity(sources.len());
+    for &i in &sources {
+        source_contents.push(content(&deleted[i])?);
+    }
+    let mut target_contents = Vec::with_capacity(targets.len());
+    for &i in &targets {
+        target_contents.push(content(&added[i])?);
+    }
+
+    // Every pair similar enough, best first; each path is used at most once
+    let mut pairs = Vec::new();
+    for (s, old) in source_contents.iter().enumerate() {
+        let old_lines = line_counts(old);
+        for (t, new) in target_contents.iter().enumerate() {
+            let (small, large) = (old.len().min(new.len()), old.len().max(new.len()));
+            // Sharing every line of the smaller file can't reach the threshold
+            if large > 0 && small * 100 < options.threshold * large {
+                continue;
+            }
+            let score = similarity(&old_lines, large, new);
+            if score >= options.threshold {
+                pairs.push((score, s, t));
+            }
+        }
+    }
+    pairs.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)).then(a.2.cmp(&b.2)));
+    for (score, s, t) in pairs {
+        let (from, to) = (sources[s], targets[t]);
+        if deleted_used[from] || added_used[to] {
+            continue;
+        }
+        deleted_used[from] = true;
+        added_used[to] = true;
+        renames.push(Rename {
+            from: deleted[from].path.clone(),
+            to: added[to].path.clone(),
+            score,
+        });
+    }
+    Ok(renames)
+}
+
+fn line_counts(content: &[u8]) -> HashMap<&[u8], usize> {
+    let mut counts = HashMap::new();
+    for line in content.split_inclusive(|&byte| byte == b'\n') {
+        *counts.entry(line).or_default() += 1;
+    }
+    counts
+}
+
+// Percentage of the larger file's bytes in lines both files have
+fn similarity(old_lines: &HashMap<&[u8], usize>, large: usize, new: &[u8]) -> usize {
+    if large == 0 {
+        return 100;
+    }
+    let mut remaining = old_lines.clone();
+    let mut common = 0;
+    for line in new.split_inclusive(|&byte| byte == b'\n') {
+        if let Some(count) = remaining.get_mut(line)
+            && *count > 0
+        {
+            *count -= 1;
+            common += line.len();
+        }
+    }
+    common * 100 / large
+}
@@ -0,0 +1,154 @@
+use crate::{Repository, Result, config::Config};
+use std::path::{Path, PathBuf};
+use unicode_normalization::UnicodeNormalization;
+
+// Paths in the index and in trees are `/`-separated Strings relative to the
+// top of the working tree. On Unix a file name is any sequence of bytes, so
+// bytes that aren't valid UTF-8 are kept as a NUL followed by two hex digits.
+// No real file name can contain a NUL, so the escape is unambiguous and turns
+// back into the original bytes whenever the path touches the filesystem.
+
+const ESCAPE: char = '\0';
+
+// The repository form of a path relative to the working tree, composed to
+// NFC when `precompose` is set (see precompose_unicode)
+pub fn from_path(path: &Path, precompose: bool) -> String {
+    let mut out = String::new();
+    for component in path.components() {
+        let std::path::Component::Normal(name) = component else {
+            continue;
+        };
+        if !out.is_empty() {
+            out.push('/');
+        }
+        push_name(&mut out, name);
+    }
+    if precompose { compose(out) } else { out }
+}
+
+// `core.precomposeUnicode`: macOS hands back file names in decomposed (NFD)
+// form whichever form they were created in, so a file added as NFC would
+// look deleted and its NFD twin untracked. With the setting on, names read
+// from disk and paths given on the command line are composed to NFC before
+// they meet the index. On by default on macOS; elsewhere it only makes sense
+// on filesystems that treat both forms as the same name.
+pub fn precompose_unicode(repo: &Repository) -> Result<bool> {
+    Ok(Config::load(repo)?
+        .get_bool("core.precomposeUnicode")
+        .unwrap_or(cfg!(target_os = "macos")))
+}
+
+// Command line paths, composed the same way as names read from disk
+pub fn precompose_args(repo: &Repository, paths: Vec<String>) -> Result<Vec<String>> {
+    if !precompose_unicode(repo)? {
+        return Ok(paths);
+    }
+    Ok(paths.into_iter().map(compose).collect())
+}
+
+fn compose(path: String) -> String {
+    if path.is_ascii() {
+        path
+    } else {
+        path.nfc().collect()
+    }
+}
+
+#[cfg(unix)]
+fn push_name(out: &mut String, name: &std::ffi::OsStr) {
+    use std::os::unix::ffi::OsStrExt;
+
+    out.push_str(&from_bytes(name.as_bytes()));
+}
+
+#[cfg(not(unix))]
+fn push_name(out: &mut String, name: &std::ffi::OsStr) {
+    out.push_str(&name.to_string_lossy());
+}
+
+// The repository form of a raw name, e.g. from a git tree object
+pub fn from_bytes(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for chunk in bytes.utf8_chunks() {
+        out.push_str(chunk.valid());
+        for byte in chunk.invalid() {
+            out.push(ESCAPE);
+            out.push_str(&format!("{:02x}", byte));
+        }
+    }
+    out
+}
+
+// Where a repository path lives under `work_dir`
+#[cfg(unix)]
+pub fn to_path(work_dir: &Path, path: &str) -> PathBuf {
+    use std::os::unix::ffi::OsStringExt;
+
+    if !path.contains(ESCAPE) {
+        return work_dir.join(path);
+    }
+    work_dir.join(std::ffi::OsString::from_vec(decode(path)))
+}
+
+#[cfg(not(unix))]
+pub fn to_path(work_dir: &Path, path: &str) -> PathBuf {
+    work_dir.join(path)
+}
+
+// The raw bytes of a repository path, escapes undone
+pub fn decode(path: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path;
+    while let Some(i) = rest.find(ESCAPE) {
+        bytes.extend_from_slice(&rest.as_bytes()[..i]);
+        let hex = rest.get(i + 1..i + 3).unwrap_or_default();
+        match u8::from_str_radix(hex, 16) {
+            Ok(byte) => {
+                bytes.push(byte);
+                rest = &rest[i + 3..];
+            }
+            Err(_) => {
+                bytes.push(0);
+                rest = &rest[i + 1..];
+            }
+        }
+    }
+    bytes.extend_from_slice(rest.as_bytes());
+    bytes
+}
+
+// A path for display. Paths with control characters, quotes, backslashes or
+// bytes that aren't UTF-8 are printed C-style in double quotes, with octal
+// escapes for the raw bytes, so they survive a terminal and can be parsed back.
+pub fn quote(path: &str) -> String {
+    let needs_quoting = |c: char| c.is_control() || c == '"' || c == '\\';
+    if !path.chars().any(needs_quoting) {
+        return path.to_string();
+    }
+
+    let bytes = decode(path);
+    let mut out = String::from("\"");
+    for chunk in bytes.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => out.push_str("\\\""),
+                '\\' => out.push_str("\\\\"),
+                '\t' => out.push_str("\\t"),
+                '\n' => out.push_str("\\n"),
+                '\r' => out.push_str("\\r"),
+                c if c.is_control() => {
+                    let mut buf = [0; 4];
+                    for byte in c.encode_utf8(&mut buf).bytes() {
+                        out.push_str(&format!("\\{:03o}", byte));
+                    }
+                }
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            out.push_str(&format!("\\{:03o}", byte));
+        }
+    }
+    out.push('"');
+    out
+}
@@ -0,0 +1,80 @@
+use crate::{Repository, Result, config::Config, object_store::ObjectStore, utils};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+
+// Sequential revision numbers, for people used to svn or p4: r1 is the first
+// commit on the mainline branch (`revnum.branch`, default main), r2 the next,
+// and so on along its first-parent history. `.mini_git/revnums` holds one
+// commit id per line, line n being rn. It is only created and extended when
+// a number is asked for, by appending the mainline commits it doesn't list
+// yet, so numbers are never reused: after a rewrite of the branch the new
+// commits get new numbers and the old ones keep theirs.
+
+const MAP_FILE: &str = "revnums";
+
+pub struct RevisionMap {
+    commits: Vec<String>,
+}
+
+impl RevisionMap {
+    // The map, brought up to date with the mainline branch
+    pub fn load(repo: &Repository) -> Result<Self> {
+        let path = repo.git_dir.join(MAP_FILE);
+        let mut commits: Vec<String> = match fs::read_to_string(&path) {
+            Ok(content) => content.lines().map(str::to_string).collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let branch = Config::load(repo)?
+            .get("revnum.branch")
+            .unwrap_or("main")
+            .to_string();
+        let Some(tip) = utils::get_branch_commit(repo, &branch)? else {
+            return Ok(RevisionMap { commits });
+        };
+
+        // Walk back along first parents to the newest commit already numbered
+        let object_store = ObjectStore::new(repo);
+        let known: HashSet<&String> = commits.iter().collect();
+        let mut new_commits = Vec::new();
+        let mut next = Some(tip);
+        while let Some(hash) = next {
+            if known.contains(&hash) {
+                break;
+            }
+            next = object_store.load_commit(&hash)?.parent().cloned();
+            new_commits.push(hash);
+        }
+
+        if !new_commits.is_empty() {
+            commits.extend(new_commits.into_iter().rev());
+            let temp = repo.git_dir.join(format!("{}.lock", MAP_FILE));
+            fs::write(&temp, commits.join("\n") + "\n")?;
+            fs::rename(&temp, &path)?;
+        }
+        Ok(RevisionMap { commits })
+    }
+
+    // The commit numbered `number`
+    pub fn commit(&self, number: usize) -> Option<&String> {
+        self.commits.get(number.checked_sub(1)?)
+    }
+
+    // Revision numbers by commit id
+    pub fn numbers(&self) -> HashMap<&str, usize> {
+        self.commits
+            .iter()
+            .enumerate()
+            .map(|(i, hash)| (hash.as_str(), i + 1))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.commits.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.commits.is_empty()
+    }
+}
@@ -0,0 +1,280 @@
+use crate::{Repository, Result, commands::config::edit_file, config::Config, utils};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+// Todo lists for history editing. Each step names an action and a commit,
+// `<action> <commit> <subject>`, the subject only being there for the reader.
+// `rebase -i` hands the list to the user's editor before running it, so
+// steps may be reordered, deleted or have their action changed; blank lines
+// and lines starting with '#' are ignored.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    // Use the commit as it is
+    Pick,
+    // Use the commit, but edit its message
+    Reword,
+    // Fold the commit into the one before it, combining their messages
+    Squash,
+    // Fold the commit into the one before it, keeping only that message
+    Fixup,
+    // Leave the commit out
+    Drop,
+}
+
+impl Action {
+    fn parse(word: &str) -> Option<Self> {
+        match word {
+            "pick" | "p" => Some(Action::Pick),
+            "reword" | "r" => Some(Action::Reword),
+            "squash" | "s" => Some(Action::Squash),
+            "fixup" | "f" => Some(Action::Fixup),
+            "drop" | "d" => Some(Action::Drop),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Action::Pick => "pick",
+            Action::Reword => "reword",
+            Action::Squash => "squash",
+            Action::Fixup => "fixup",
+            Action::Drop => "drop",
+        }
+    }
+
+    // Whether the step folds into the commit before it
+    pub fn folds(self) -> bool {
+        matches!(self, Action::Squash | Action::Fixup)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub action: Action,
+    pub commit: String,
+    pub subject: String,
+}
+
+impl Step {
+    pub fn pick(commit: &str, message: &str) -> Self {
+        Step {
+            action: Action::Pick,
+            commit: commit.to_string(),
+            subject: message.lines().next().unwrap_or("").to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.action.name(), self.commit, self.subject)
+    }
+}
+
+// Move each `fixup! <subject>` and `squash! <subject>` commit to just after
+// the commit it names, by subject or id, marked to fold into it. Repeated
+// prefixes (`fixup! fixup! ...`) name the same commit; a step whose target
+// isn't in the list stays where it is, as does one with a blank target or a
+// subject prefix more than one commit shares.
+pub fn autosquash(steps: Vec<Step>) -> Vec<Step> {
+    let mut target_of: Vec<Option<usize>> = vec![None; steps.len()];
+    let mut actions: Vec<Action> = steps.iter().map(|step| step.action).collect();
+    for (i, step) in steps.iter().enumerate() {
+        let (action, mut target) = match step.subject.split_once("! ") {
+            Some(("fixup", rest)) => (Action::Fixup, rest),
+            Some(("squash", rest)) => (Action::Squash, rest),
+            _ => continue,
+        };
+        while let Some(rest) = target
+            .strip_prefix("fixup! ")
+            .or_else(|| target.strip_prefix("squash! "))
+        {
+            target = rest;
+        }
+        if target.trim().is_empty() {
+            continue;
+        }
+        let earlier = &steps[..i];
+        let found = earlier
+            .iter()
+            .position(|other| other.subject == target)
+            .or_else(|| {
+                (target.len() >= 4)
+                    .then(|| {
+                        earlier
+                            .iter()
+                            .position(|other| other.commit.starts_with(target))
+                    })
+                    .flatten()
+            })
+            .or_else(|| {
+                let mut matches = (0..i).filter(|&j| earlier[j].subject.starts_with(target));
+                match (matches.next(), matches.next()) {
+                    (Some(j), None) => Some(j),
+                    _ => None,
+                }
+            });
+        if let Some(j) = found {
+            // Fold into what the target folds into, so chains stay together
+            target_of[i] = Some(target_of[j].unwrap_or(j));
+            actions[i] = action;
+        }
+    }
+
+    let mut followers: Vec<Vec<usize>> = vec![Vec::new(); steps.len()];
+    for (i, target) in target_of.iter().enumerate() {
+        if let Some(j) = target {
+            followers[*j].push(i);
+        }
+    }
+    let mut steps: Vec<Option<Step>> = steps.into_iter().map(Some).collect();
+    let mut sorted = Vec::with_capacity(steps.len());
+    for i in 0..steps.len() {
+        if target_of[i].is_some() {
+            continue;
+        }
+        for k in std::iter::once(i).chain(followers[i].iter().copied()) {
+            let mut step = steps[k].take().unwrap();
+            step.action = actions[k];
+            sorted.push(step);
+        }
+    }
+    sorted
+}
+
+const TODO_HELP: &str = "
+# Commands:
+# p, pick <commit>   = use commit
+# r, reword <commit> = use commit, but edit the commit message
+# s, squash <commit> = use commit, but meld into previous commit
+# f, fixup <commit>  = like \"squash\", but discard this commit's message
+# d, drop <commit>   = remove commit
+#
+# These lines can be reordered; they are executed from top to bottom.
+# If you remove a line here THAT COMMIT WILL BE LOST.
+# However, if you remove everything, the rebase will be aborted.
+";
+
+// Parse a todo list. Commits may be abbreviated or given as any revision;
+// they are stored resolved.
+pub fn parse_todo(repo: &Repository, text: &str) -> Result<Vec<Step>> {
+    let mut steps = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut words = line.splitn(3, char::is_whitespace);
+        let word = words.next().unwrap_or("");
+        let action =
+            Action::parse(word).ok_or(format!("Unknown action '{}' in '{}'", word, line))?;
+        let revision = words
+            .next()
+            .ok_or(format!("Missing commit in '{}'", line))?;
+        let commit = utils::resolve_revision(repo, revision)
+            .map_err(|_| format!("'{}' does not name a commit in '{}'", revision, line))?;
+        steps.push(Step {
+            action,
+            commit,
+            subject: words.next().unwrap_or("").trim().to_string(),
+        });
+    }
+    Ok(steps)
+}
+
+pub fn format_todo(steps: &[Step]) -> String {
+    steps.iter().map(|step| format!("{}\n", step)).collect()
+}
+
+// Let the user edit `steps` in their editor, via `file`, and read back what
+// they left
+pub fn edit_todo(repo: &Repository, file: &Path, steps: &[Step], onto: &str) -> Result<Vec<Step>> {
+    let header = format!("\n# Rebase {} commit(s) onto {}\n", steps.len(), &onto[..8]);
+    fs::write(file, format_todo(steps) + &header + TODO_HELP)?;
+    edit_file(&Config::load(repo)?.editor(), file)?;
+    let steps = parse_todo(repo, &fs::read_to_string(file)?)?;
+
+    // Squashes need something to fold into
+    let first_kept = steps.iter().find(|step| step.action != Action::Drop);
+    if let Some(step) = first_kept.filter(|step| step.action.folds()) {
+        return Err(format!(
+            "Cannot '{}' {} without a previous commit",
+            step.action.name(),
+            &step.commit[..8]
+        )
+        .into());
+    }
+    Ok(steps)
+}
+
+// Let the user edit a commit message in their editor, via `file`. Comment
+// lines are dropped, and an empty message is refused.
+pub fn edit_message(repo: &Repository, file: &Path, message: &str, note: &str) -> Result<String> {
+    fs::write(
+        file,
+        format!(
+            "{}\n\n# {}\n# Lines starting with '#' are ignored; an empty message aborts.\n",
+            message.trim_end(),
+            note
+        ),
+    )?;
+    edit_file(&Config::load(repo)?.editor(), file)?;
+    let edited = fs::read_to_string(file)?;
+    let lines: Vec<&str> = edited
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .map(str::trim_end)
+        .collect();
+    let message = lines.join("\n").trim().to_string();
+    if message.is_empty() {
+        return Err("Aborting: the commit message is empty".into());
+    }
+    Ok(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn step(commit: &str, subject: &str) -> Step {
+        Step::pick(commit, subject)
+    }
+
+    fn lines(steps: &[Step]) -> Vec<String> {
+        steps
+            .iter()
+            .map(|step| format!("{} {}", step.action.name(), step.commit))
+            .collect()
+    }
+
+    #[test]
+    fn autosquash_moves_fixups_under_their_targets() {
+        let steps = vec![
+            step("aaaa1111", "Add parser"),
+            step("bbbb2222", "Add lexer"),
+            step("cccc3333", "fixup! Add parser"),
+            step("dddd4444", "squash! bbbb2222"),
+            step("eeee5555", "fixup! fixup! Add parser"),
+            step("ffff6666", "fixup! Something not in the list"),
+            step("gggg7777", "fixup! "),
+            step("hhhh8888", "squash!    "),
+            step("iiii9999", "fixup! Add"),
+        ];
+        assert_eq!(
+            lines(&autosquash(steps)),
+            [
+                "pick aaaa1111",
+                "fixup cccc3333",
+                "fixup eeee5555",
+                "pick bbbb2222",
+                "squash dddd4444",
+                "pick ffff6666",
+                "pick gggg7777",
+                "pick hhhh8888",
+                "pick iiii9999",
+            ]
+        );
+    }
+}
@@ -0,0 +1,213 @@
+use crate::{Repository, Result, ignore::IGNORE_FILE, utils};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Project templates for `init --with <name>`: files to seed the new working
+// tree with, and hooks for .mini_git/hooks/. User templates are directories
+// under `$MINI_GIT_TEMPLATE_DIR` or `~/.mini_git/templates`, laid out like
+// the tree they seed, with hooks in a top-level `hooks/` directory. A user
+// template shadows a bundled one of the same name.
+
+const RUST_IGNORE: &str = "\
+# Build output
+target/
+
+# Backups left by rustfmt
+*.rs.bk
+";
+
+const RUST_PRE_COMMIT: &str = "\
+#!/bin/sh
+# Check formatting before each commit (from the rust template)
+if [ -f Cargo.toml ] && command -v cargo >/dev/null 2>&1; then
+    exec cargo fmt --check
+fi
+";
+
+const PYTHON_IGNORE: &str = "\
+# Bytecode
+__pycache__/
+*.pyc
+
+# Virtual environments and packaging output
+.venv/
+*.egg-info/
+build/
+dist/
+";
+
+const PYTHON_PRE_COMMIT: &str = "\
+#!/bin/sh
+# Refuse commits with Python syntax errors (from the python template)
+files=$(find . -name '*.py' -not -path './.mini_git/*' -not -path './.venv/*')
+[ -z \"$files\" ] || exec python3 -m py_compile $files
+";
+
+// Name, then (path, content) pairs; `hooks/` paths are hooks
+const BUNDLED: &[(&str, &[(&str, &str)])] = &[
+    (
+        "python",
+        &[
+            (IGNORE_FILE, PYTHON_IGNORE),
+            ("hooks/pre-commit", PYTHON_PRE_COMMIT),
+        ],
+    ),
+    (
+        "rust",
+        &[
+            (IGNORE_FILE, RUST_IGNORE),
+            ("hooks/pre-commit", RUST_PRE_COMMIT),
+        ],
+    ),
+];
+
+pub struct TemplateFile {
+    // Relative, `/`-separated
+    pub path: String,
+    pub content: Vec<u8>,
+    pub executable: bool,
+}
+
+pub struct Template {
+    pub name: String,
+    pub files: Vec<TemplateFile>,
+    pub hooks: Vec<TemplateFile>,
+}
+
+// `$MINI_GIT_TEMPLATE_DIR`, or `~/.mini_git/templates`
+pub fn template_dir() -> Option<PathBuf> {
+    if let Ok(path) = std::env::var("MINI_GIT_TEMPLATE_DIR") {
+        return Some(PathBuf::from(path));
+    }
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".mini_git").join("templates"))
+}
+
+pub fn load(name: &str) -> Result<Template> {
+    if name.is_empty() || name.starts_with('.') || name.contains(['/', '\\']) {
+        return Err(format!("Invalid template name '{}'", name).into());
+    }
+
+    let mut entries = Vec::new();
+    if let Some(dir) = template_dir().map(|dir| dir.join(name))
+        && dir.is_dir()
+    {
+        read_dir_files(&dir, "", &mut entries)?;
+    } else if let Some((_, files)) = BUNDLED.iter().find(|(bundled, _)| *bundled == name) {
+        for (path, content) in files.iter() {
+            entries.push(TemplateFile {
+                path: path.to_string(),
+                content: content.as_bytes().to_vec(),
+                executable: path.starts_with("hooks/"),
+            });
+        }
+    } else {
+        return Err(format!(
+            "Unknown template '{}' (available: {})",
+            name,
+            available().join(", ")
+        )
+        .into());
+    }
+
+    let (hooks, files): (Vec<_>, Vec<_>) = entries
+        .into_iter()
+        .partition(|file| file.path.starts_with("hooks/"));
+    let hooks = hooks
+        .into_iter()
+        .map(|hook| TemplateFile {
+            path: hook.path["hooks/".len()..].to_string(),
+            ..hook
+        })
+        .collect();
+    Ok(Template {
+        name: name.to_string(),
+        files,
+        hooks,
+    })
+}
+
+// Bundled and user template names, sorted
+pub fn available() -> Vec<String> {
+    let mut names: Vec<String> = BUNDLED.iter().map(|(name, _)| name.to_string()).collect();
+    if let Some(dir) = template_dir()
+        && let Ok(entries) = fs::read_dir(dir)
+    {
+        for entry in entries.flatten() {
+            if entry.path().is_dir() {
+                names.push(entry.file_name().to_string_lossy().to_string());
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
+}
+
+// Write the template into a new repository and return the working tree
+// paths it provides. Files that already exist are left as they are.
+pub fn apply(repo: &Repository, template: &Template) -> Result<Vec<String>> {
+    let mut paths = Vec::new();
+    for file in &template.files {
+        let target = repo.work_dir.join(&file.path);
+        if target.exists() {
+            println!("Keeping existing {}", file.path);
+        } else {
+            write_file(&target, file)?;
+        }
+        paths.push(file.path.clone());
+    }
+
+    let hooks_dir = repo.git_dir.join("hooks");
+    for hook in &template.hooks {
+        write_file(&hooks_dir.join(&hook.path), hook)?;
+    }
+    Ok(paths)
+}
+
+fn read_dir_files(dir: &Path, prefix: &str, out: &mut Vec<TemplateFile>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(dir)?.collect::<std::io::Result<_>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().to_string();
+        let path = format!("{}{}", prefix, name);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            // A template can't carry a repository of its own
+            if name != ".mini_git" {
+                read_dir_files(&entry.path(), &format!("{}/", path), out)?;
+            }
+        } else if file_type.is_file() {
+            out.push(TemplateFile {
+                path,
+                content: fs::read(entry.path())?,
+                executable: utils::is_executable(&entry.path()),
+            });
+        }
+    }
+    Ok(())
+}
+
+fn write_file(target: &Path, file: &TemplateFile) -> Result<()> {
+    if let Some(parent) = target.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(target, &file.content)?;
+    if file.executable {
+        set_executable(target)?;
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn set_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    fs::set_permissions(path, permissions)?;
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_executable(_path: &Path) -> Result<()> {
+    Ok(())
+}
@@ -0,0 +1,90 @@
+use crate::{Result, bundle, protocol};
+use flate2::read::GzDecoder;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+// Where a remote URL leads: a server reached through an `ext::` command
+// (see protocol), a bundle file (see bundle), an archived repository, or
+// else a repository on disk. Archives are `.tar`, `.tar.gz`/`.tgz` or
+// `.tar.zst` files holding a repository's git directory, bare or under the
+// directory of its work tree.
+pub enum Transport {
+    Local(PathBuf),
+    Ext(String),
+    Bundle(PathBuf),
+    Archive(PathBuf),
+}
+
+impl Transport {
+    pub fn for_url(url: &str) -> Self {
+        if protocol::is_remote_url(url) {
+            return Transport::Ext(url.to_string());
+        }
+        let path = PathBuf::from(url);
+        if path.is_file() && compression(&path).is_some() {
+            Transport::Archive(path)
+        } else if path.is_file() && bundle::is_bundle(&path) {
+            Transport::Bundle(path)
+        } else {
+            Transport::Local(path)
+        }
+    }
+}
+
+enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+fn compression(path: &Path) -> Option<Compression> {
+    let name = path.file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".tar") {
+        Some(Compression::None)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Compression::Gzip)
+    } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+        Some(Compression::Zstd)
+    } else {
+        None
+    }
+}
+
+// Unpack the archive at `archive` into `into` and return the git directory
+// it held: the shallowest directory with a HEAD file and objects/ in it.
+// Entries that would land outside `into` are refused by the unpacking.
+pub fn unpack_archive(archive: &Path, into: &Path) -> Result<PathBuf> {
+    let file = BufReader::new(File::open(archive)?);
+    let reader: Box<dyn Read> = match compression(archive) {
+        Some(Compression::None) => Box::new(file),
+        Some(Compression::Gzip) => Box::new(GzDecoder::new(file)),
+        Some(Compression::Zstd) => Box::new(zstd::Decoder::with_buffer(file)?),
+        None => return Err(format!("'{}' is not an archive", archive.display()).into()),
+    };
+    fs::create_dir_all(into)?;
+    tar::Archive::new(reader)
+        .unpack(into)
+        .map_err(|e| format!("Cannot unpack '{}': {}", archive.display(), e))?;
+
+    WalkDir::new(into)
+        .max_depth(3)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry.file_type().is_dir()
+                && entry.path().join("HEAD").is_file()
+                && entry.path().join("objects").is_dir()
+        })
+        .min_by_key(|entry| entry.depth())
+        .map(|entry| entry.into_path())
+        .ok_or(
+            format!(
+                "'{}' does not hold a mini-git repository",
+                archive.display()
+            )
+            .into(),
+        )
+}
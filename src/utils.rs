@@ -1,4 +1,9 @@
-use crate::{Index, IndexEntry, Repository, Result};
+use crate::{
+    CachedDirectory, Index, IndexEntry, Repository, Result, UntrackedCache,
+    index::LockedIndex,
+    profile::{self, Phase},
+    repo_path,
+};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -21,6 +26,9 @@ pub fn get_repository(work_dir: Option<PathBuf>) -> Result<Repository> {
     let work_dir = work_dir.unwrap_or_else(|| std::env::current_dir().unwrap());
 
     if let Some(git_dir) = find_git_dir(&work_dir) {
+        // Refuse to work with objects we can't name correctly
+        crate::hash::HashAlgorithm::of_git_dir(&git_dir)?;
+        crate::object_store::ObjectStorage::of_git_dir(&git_dir)?;
         Ok(Repository {
             git_dir: git_dir.clone(),
             work_dir: git_dir.parent().unwrap().to_path_buf(),
@@ -31,23 +39,74 @@ pub fn get_repository(work_dir: Option<PathBuf>) -> Result<Repository> {
 }
 
 pub fn load_index(repo: &Repository) -> Result<Index> {
-    let index_path = repo.git_dir.join("index");
-    if index_path.exists() {
-        let content = fs::read_to_string(index_path)?;
-        let index: Index = serde_json::from_str(&content)?;
-        Ok(index)
+    crate::index::read_index(repo)
+}
+
+// Read the index to change it, holding its lock until it is saved
+pub fn lock_index(repo: &Repository) -> Result<LockedIndex> {
+    crate::index::lock_index(repo).map_err(|e| index_write_error(repo, e))
+}
+
+pub fn save_locked_index(repo: &Repository, index: LockedIndex) -> Result<()> {
+    index.save(repo).map_err(|e| index_write_error(repo, e))
+}
+
+// Replace the index outright, with content not read from it
+pub fn save_index(repo: &Repository, index: &Index) -> Result<()> {
+    crate::index::write_index(repo, index).map_err(|e| index_write_error(repo, e))
+}
+
+fn index_write_error(
+    repo: &Repository,
+    e: Box<dyn std::error::Error>,
+) -> Box<dyn std::error::Error> {
+    if is_read_only_error(e.as_ref()) {
+        format!(
+            "Cannot write the index: {} is read-only",
+            repo.git_dir.display()
+        )
+        .into()
     } else {
-        Ok(Index {
-            entries: std::collections::HashMap::new(),
+        e
+    }
+}
+
+// Index paths at or under `pathspec` (`.` for all of them), sorted
+pub fn matching_paths(index: &Index, pathspec: &str) -> Vec<String> {
+    let pathspec = pathspec.trim_end_matches('/');
+    let mut paths: Vec<String> = index
+        .entries
+        .keys()
+        .filter(|path| {
+            pathspec == "."
+                || *path == pathspec
+                || path
+                    .strip_prefix(pathspec)
+                    .is_some_and(|rest| rest.starts_with('/'))
         })
+        .cloned()
+        .collect();
+    paths.sort();
+    paths
+}
+
+// Save an index whose only changes are cached data (stat info, directory
+// listings). That's an optimisation, so a repository on read-only media, or
+// one whose index another command is writing, just goes without it.
+pub fn refresh_index(repo: &Repository, index: &Index) -> Result<()> {
+    match crate::index::write_index_if_idle(repo, index) {
+        Err(e) if is_read_only_error(e.as_ref()) => Ok(()),
+        result => result,
     }
 }
 
-pub fn save_index(repo: &Repository, index: &Index) -> Result<()> {
-    let index_path = repo.git_dir.join("index");
-    let content = serde_json::to_string_pretty(index)?;
-    fs::write(index_path, content)?;
-    Ok(())
+pub fn is_read_only_error(error: &(dyn std::error::Error + 'static)) -> bool {
+    error.downcast_ref::<std::io::Error>().is_some_and(|e| {
+        matches!(
+            e.kind(),
+            std::io::ErrorKind::PermissionDenied | std::io::ErrorKind::ReadOnlyFilesystem
+        )
+    })
 }
 
 pub fn get_current_branch(repo: &Repository) -> Result<String> {
@@ -64,6 +123,33 @@ pub fn get_current_branch(repo: &Repository) -> Result<String> {
     }
 }
 
+// The checked-out branch, or None when HEAD is detached
+pub fn head_branch(repo: &Repository) -> Result<Option<String>> {
+    match fs::read_to_string(repo.git_dir.join("HEAD")) {
+        Ok(head) if !head.starts_with("ref: ") => Ok(None),
+        Ok(_) => get_current_branch(repo).map(Some),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Some("main".to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// The ref a new commit moves: the checked-out branch, or HEAD itself when
+// detached
+pub fn head_ref(repo: &Repository) -> Result<String> {
+    Ok(match head_branch(repo)? {
+        Some(branch) => format!("refs/heads/{}", branch),
+        None => "HEAD".to_string(),
+    })
+}
+
+// Point HEAD's commit at `commit`, moving the branch if one is checked out
+pub fn update_head_commit(repo: &Repository, commit: &str) -> Result<()> {
+    match head_branch(repo)? {
+        Some(branch) => update_branch(repo, &branch, commit),
+        None => Ok(fs::write(repo.git_dir.join("HEAD"), commit)?),
+    }
+}
+
 pub fn update_head(repo: &Repository, branch: &str) -> Result<()> {
     let head_path = repo.git_dir.join("HEAD");
     fs::write(head_path, format!("ref: refs/heads/{}", branch))?;
@@ -85,6 +171,576 @@ pub fn update_branch(repo: &Repository, branch: &str, commit: &str) -> Result<()
     fs::create_dir_all(&refs_heads)?;
 
     let branch_path = refs_heads.join(branch);
+    if let Some(parent) = branch_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
     fs::write(branch_path, commit)?;
     Ok(())
 }
+
+// Move the remote-tracking branch `<remote>/<branch>` to `commit` (None
+// deletes it) and record the movement in its reflog, so an earlier remote
+// tip can still be found after the remote rewrites history
+pub fn update_remote_ref(
+    repo: &Repository,
+    remote: &str,
+    branch: &str,
+    commit: Option<&str>,
+    message: &str,
+) -> Result<()> {
+    let refname = format!("refs/remotes/{}/{}", remote, branch);
+    let path = repo.git_dir.join(&refname);
+    let old = fs::read_to_string(&path).ok().map(|c| c.trim().to_string());
+    match commit {
+        Some(commit) => {
+            fs::create_dir_all(path.parent().unwrap())?;
+            fs::write(&path, commit)?;
+            if old.as_deref() != Some(commit) {
+                crate::reflog::append(repo, &refname, old.as_deref(), commit, message)?;
+            }
+        }
+        None => {
+            fs::remove_file(&path)?;
+            crate::reflog::remove(repo, &refname)?;
+        }
+    }
+    Ok(())
+}
+
+// Ref namespace selected with MINI_GIT_NAMESPACE. Nested namespaces are
+// written `a/b`, as in git.
+pub fn ref_namespace() -> Option<String> {
+    std::env::var("MINI_GIT_NAMESPACE")
+        .ok()
+        .map(|ns| ns.trim_matches('/').to_string())
+        .filter(|ns| !ns.is_empty())
+}
+
+// The refs directory a repository exposes to clients: `refs/` normally, or
+// `refs/namespaces/<ns>/refs/` when a namespace is active. Several logical
+// repositories can then share one object store with separate refs.
+pub fn served_refs_dir(git_dir: &Path) -> PathBuf {
+    let mut refs = git_dir.join("refs");
+    if let Some(namespace) = ref_namespace() {
+        for component in namespace.split('/').filter(|c| !c.is_empty()) {
+            refs = refs.join("namespaces").join(component).join("refs");
+        }
+    }
+    refs
+}
+
+// Reject names that can't be stored or addressed as refs, following git's
+// check-ref-format rules. `kind` is used in the message ("branch", "remote")
+pub fn validate_ref_name(kind: &str, name: &str) -> Result<()> {
+    let problem = if name.is_empty() {
+        Some("it is empty")
+    } else if name.starts_with('-') {
+        Some("it starts with '-'")
+    } else if name == "@" || name == "HEAD" {
+        Some("it is reserved")
+    } else if name.contains("..") {
+        Some("it contains '..'")
+    } else if name.contains("@{") {
+        Some("it contains '@{'")
+    } else if name.contains("//") || name.starts_with('/') || name.ends_with('/') {
+        Some("it has an empty path component")
+    } else if name.ends_with('.') || name.ends_with(".lock") {
+        Some("it ends with '.' or '.lock'")
+    } else if name.split('/').any(|component| component.starts_with('.')) {
+        Some("a path component starts with '.'")
+    } else if name.chars().any(|c| c.is_control() || c == ' ') {
+        Some("it contains whitespace or control characters")
+    } else if name.contains(['~', '^', ':', '?', '*', '[', '\\']) {
+        Some("it contains one of '~^:?*[\\'")
+    } else {
+        None
+    };
+
+    match problem {
+        Some(reason) => Err(format!("'{}' is not a valid {} name: {}", name, kind, reason).into()),
+        None => Ok(()),
+    }
+}
+
+// Branches with `branch.<name>.protect = true` need an explicit
+// --allow-protected before anything destructive happens to them
+pub fn check_branch_protection(
+    repo: &Repository,
+    branch: &str,
+    action: &str,
+    allow_protected: bool,
+) -> Result<()> {
+    let config = crate::config::Config::load(repo)?;
+    let protected = config
+        .get_bool(&format!("branch.{}.protect", branch))
+        .unwrap_or(false);
+
+    if protected && !allow_protected {
+        return Err(format!(
+            "Branch '{}' is protected; refusing to {}. Use --allow-protected to override.",
+            branch, action
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Every ref whose full name starts with `prefix` (e.g. `refs/heads/`), as
+// (name, object id) pairs sorted by name
+pub fn list_refs(repo: &Repository, prefix: &str) -> Result<Vec<(String, String)>> {
+    let refs_dir = repo.git_dir.join("refs");
+    let mut refs = Vec::new();
+    for entry in walkdir::WalkDir::new(&refs_dir)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+    {
+        let relative = entry.path().strip_prefix(&refs_dir)?;
+        let name = format!("refs/{}", relative.to_string_lossy().replace('\\', "/"));
+        if name.starts_with(prefix) {
+            refs.push((name, fs::read_to_string(entry.path())?.trim().to_string()));
+        }
+    }
+    refs.sort();
+    Ok(refs)
+}
+
+// The URL configured for remote `remote_name`
+pub fn get_remote_url(repo: &Repository, remote_name: &str) -> Result<String> {
+    crate::config::Config::load(repo)?
+        .get(&format!("remote.{}.url", remote_name))
+        .map(str::to_string)
+        .ok_or(format!("Remote '{}' not found", remote_name).into())
+}
+
+// Remote-tracking branch configured as the upstream of `branch` through
+// `branch.<name>.remote` and `branch.<name>.merge`, e.g. `origin/main`
+pub fn get_upstream(repo: &Repository, branch: &str) -> Result<Option<String>> {
+    let config = crate::config::Config::load(repo)?;
+    let remote = config.get(&format!("branch.{}.remote", branch));
+    let merge = config.get(&format!("branch.{}.merge", branch));
+
+    Ok(match (remote, merge) {
+        (Some(remote), Some(merge)) => {
+            let merge = merge.strip_prefix("refs/heads/").unwrap_or(merge);
+            Some(format!("{}/{}", remote, merge))
+        }
+        _ => None,
+    })
+}
+
+pub fn get_head_commit(repo: &Repository) -> Result<Option<String>> {
+    match head_branch(repo)? {
+        Some(branch) => get_branch_commit(repo, &branch),
+        None => Ok(Some(
+            fs::read_to_string(repo.git_dir.join("HEAD"))?
+                .trim()
+                .to_string(),
+        )),
+    }
+}
+
+// The commits refs (after peeling tags) and HEAD point at: the tips whose
+// history caches such as the commit-graph and reachability bitmaps cover
+pub fn tip_commits(repo: &Repository) -> Result<Vec<String>> {
+    let object_store = crate::object_store::ObjectStore::new(repo);
+    let mut tips = Vec::new();
+    for (_, hash) in list_refs(repo, "refs/")? {
+        let target = object_store.peel(&hash)?;
+        if object_store.read_typed(&target)?.0 == "commit" {
+            tips.push(target);
+        }
+    }
+    tips.extend(get_head_commit(repo)?);
+    tips.sort();
+    tips.dedup();
+    Ok(tips)
+}
+
+// Commits in `<from>..<to>` (or everything reachable from a single
+// revision), newest first
+pub fn commit_range(repo: &Repository, range: &str) -> Result<Vec<String>> {
+    let object_store = crate::object_store::ObjectStore::new(repo);
+    let (exclude, include) = match range.split_once("..") {
+        Some((from, to)) => (
+            Some(if from.is_empty() { "HEAD" } else { from }),
+            if to.is_empty() { "HEAD" } else { to },
+        ),
+        None => (None, range),
+    };
+
+    let mut graph = crate::graph::CommitGraph::new(&object_store);
+    let excluded = match exclude {
+        Some(exclude) => {
+            let node = graph.node(&resolve_revision(repo, exclude)?)?;
+            graph.reachable(node)?
+        }
+        None => crate::graph::NodeSet::default(),
+    };
+
+    let tip = graph.node(&resolve_revision(repo, include)?)?;
+    Ok(graph
+        .walk(tip, &excluded)?
+        .into_iter()
+        .map(|node| graph.hex(node))
+        .collect())
+}
+
+// `Key: value` lines from the last paragraph of a commit message
+pub fn parse_trailers(message: &str) -> Vec<(String, String)> {
+    let Some(paragraph) = message.trim_end().rsplit("\n\n").next() else {
+        return Vec::new();
+    };
+    // The subject line alone is never a trailer block
+    if !message.trim_end().contains("\n\n") {
+        return Vec::new();
+    }
+
+    let mut trailers = Vec::new();
+    for line in paragraph.lines() {
+        match line.split_once(':') {
+            Some((key, value))
+                if !key.is_empty()
+                    && key.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') =>
+            {
+                trailers.push((key.to_string(), value.trim().to_string()));
+            }
+            _ => return Vec::new(),
+        }
+    }
+    trailers
+}
+
+// Resolve a revision such as `HEAD`, `main`, `HEAD~2`, `feature^` or an
+// (abbreviated) commit hash into a full commit hash
+pub fn resolve_revision(repo: &Repository, rev: &str) -> Result<String> {
+    let base_end = rev.find(['~', '^']).unwrap_or(rev.len());
+    let (base, mut suffix) = rev.split_at(base_end);
+
+    let mut commit =
+        resolve_base_revision(repo, base)?.ok_or(format!("Unknown revision '{}'", rev))?;
+
+    let object_store = crate::object_store::ObjectStore::new(repo);
+    while !suffix.is_empty() {
+        let op = suffix.as_bytes()[0];
+        let digits: String = suffix[1..]
+            .chars()
+            .take_while(|c| c.is_ascii_digit())
+            .collect();
+        suffix = &suffix[1 + digits.len()..];
+
+        let count = if digits.is_empty() {
+            1
+        } else {
+            digits.parse::<usize>()?
+        };
+        // `~N` follows first parents N times; `^N` selects the Nth parent
+        // and `^0` is the commit itself
+        if op == b'~' {
+            for _ in 0..count {
+                commit = object_store
+                    .load_commit(&commit)?
+                    .parent()
+                    .cloned()
+                    .ok_or(format!("Revision '{}' goes past the root commit", rev))?;
+            }
+        } else if count > 0 {
+            let parents = object_store.load_commit(&commit)?.parents;
+            commit = parents
+                .get(count - 1)
+                .cloned()
+                .ok_or(format!("Revision '{}' has no parent {}", rev, count))?;
+        }
+    }
+
+    Ok(commit)
+}
+
+fn resolve_base_revision(repo: &Repository, base: &str) -> Result<Option<String>> {
+    // `<branch>@{u}`, `@{upstream}`, `@{-N}` and `<ref>@{n}`
+    if let Some((branch, spec)) = base.split_once("@{")
+        && let Some(spec) = spec.strip_suffix('}')
+    {
+        return resolve_at_brace(repo, branch, spec).map(Some);
+    }
+
+    if base == "HEAD" || base == "@" {
+        let head = fs::read_to_string(repo.git_dir.join("HEAD"))?;
+        let head = head.trim();
+        return match head.strip_prefix("ref: refs/heads/") {
+            Some(branch) => get_branch_commit(repo, branch),
+            None => Ok(Some(head.to_string())),
+        };
+    }
+
+    if let Some(commit) = get_branch_commit(repo, base)? {
+        return Ok(Some(commit));
+    }
+
+    // Tags, remote-tracking branches, and partially or fully qualified names
+    // such as `tags/v1.0` or `refs/remotes/origin/main`. An annotated tag
+    // stands for the commit it tags.
+    let refs = repo.git_dir.join("refs");
+    let mut candidates = vec![
+        refs.join("tags").join(base),
+        refs.join("remotes").join(base),
+        refs.join(base),
+    ];
+    if let Some(name) = base.strip_prefix("refs/") {
+        candidates.push(refs.join(name));
+    }
+    for candidate in candidates {
+        if candidate.is_file() {
+            let hash = fs::read_to_string(candidate)?.trim().to_string();
+            let object_store = crate::object_store::ObjectStore::new(repo);
+            if object_store.object_exists(&hash) {
+                return object_store.peel(&hash).map(Some);
+            }
+            return Ok(Some(hash));
+        }
+    }
+
+    find_object_by_prefix(repo, base)
+}
+
+fn resolve_at_brace(repo: &Repository, branch: &str, spec: &str) -> Result<String> {
+    if let Some(n) = spec.strip_prefix('-') {
+        let n: usize = n
+            .parse()
+            .map_err(|_| format!("Invalid revision '@{{{}}}'", spec))?;
+        let previous = previous_checkout(repo, n)?;
+        return Ok(get_branch_commit(repo, &previous)?.unwrap_or(previous));
+    }
+
+    // `<ref>@{n}`: where the ref was n movements ago; plain `@{n}` is the
+    // current branch (or HEAD, when detached)
+    if let Ok(n) = spec.parse::<usize>() {
+        let refname = match branch {
+            "" => head_ref(repo)?,
+            _ => crate::reflog::resolve_refname(repo, branch)?,
+        };
+        return crate::reflog::nth(repo, &refname, n);
+    }
+
+    if spec != "u" && spec != "upstream" {
+        return Err(format!("Unsupported revision '@{{{}}}'", spec).into());
+    }
+
+    let branch = if branch.is_empty() || branch == "HEAD" {
+        head_branch(repo)?.ok_or("HEAD is detached, so it has no upstream")?
+    } else {
+        branch.to_string()
+    };
+    let upstream = get_upstream(repo, &branch)?
+        .ok_or(format!("No upstream configured for branch '{}'", branch))?;
+
+    let upstream_ref = repo.git_dir.join("refs").join("remotes").join(&upstream);
+    if !upstream_ref.is_file() {
+        return Err(format!(
+            "Upstream branch '{}' of '{}' has not been fetched",
+            upstream, branch
+        )
+        .into());
+    }
+    Ok(fs::read_to_string(upstream_ref)?.trim().to_string())
+}
+
+// The branch (or detached commit) that was checked out `n` switches ago
+pub fn previous_checkout(repo: &Repository, n: usize) -> Result<String> {
+    crate::reflog::read(repo, "HEAD")?
+        .iter()
+        .rev()
+        .filter_map(|entry| {
+            let moves = entry.message.strip_prefix("checkout: moving from ")?;
+            moves.split_once(" to ").map(|(from, _)| from.to_string())
+        })
+        .nth(n.saturating_sub(1))
+        .filter(|_| n > 0)
+        .ok_or(format!("Not enough checkout history for @{{-{}}}", n).into())
+}
+
+pub fn find_object_by_prefix(repo: &Repository, prefix: &str) -> Result<Option<String>> {
+    if prefix.len() < 4 || !prefix.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Ok(None);
+    }
+
+    let prefix = prefix.to_lowercase();
+    let object_store = crate::object_store::ObjectStore::new(repo);
+    if prefix.len() == object_store.algorithm().hex_len() && object_store.object_exists(&prefix) {
+        return Ok(Some(prefix));
+    }
+
+    let mut dirs = vec![repo.git_dir.join("objects")];
+    dirs.extend(crate::object_store::read_alternates(&dirs[0]));
+
+    let (dir_name, file_prefix) = prefix.split_at(2);
+    let mut matches = Vec::new();
+    for objects_dir in dirs {
+        let fan_out = objects_dir.join(dir_name);
+        if !fan_out.is_dir() {
+            continue;
+        }
+
+        for entry in fs::read_dir(fan_out)? {
+            let name = entry?.file_name().to_string_lossy().to_string();
+            let hash = format!("{}{}", dir_name, name);
+            if name.starts_with(file_prefix) && !matches.contains(&hash) {
+                matches.push(hash);
+            }
+        }
+    }
+    for hash in object_store.packed_with_prefix(&prefix) {
+        if !matches.contains(&hash) {
+            matches.push(hash);
+        }
+    }
+
+    match matches.len() {
+        0 => Ok(None),
+        1 => Ok(matches.pop()),
+        _ => Err(format!("Short object ID {} is ambiguous", prefix).into()),
+    }
+}
+
+// List every file in the working tree (relative, `/`-separated), skipping
+// the repository directory. When a cache is supplied, directories whose mtime
+// hasn't changed are served from it and the rest are re-read and recorded.
+pub fn list_working_files(
+    repo: &Repository,
+    mut cache: Option<&mut UntrackedCache>,
+) -> Result<Vec<String>> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut files = Vec::new();
+    let mut pending = vec![String::new()];
+    let mut seen_dirs = std::collections::HashSet::new();
+    let precompose = repo_path::precompose_unicode(repo)?;
+
+    while let Some(rel_dir) = pending.pop() {
+        let dir_path = if rel_dir.is_empty() {
+            repo.work_dir.clone()
+        } else {
+            repo_path::to_path(&repo.work_dir, &rel_dir)
+        };
+        if dir_path == repo.git_dir {
+            continue;
+        }
+
+        let (mtime_secs, mtime_nanos) = dir_mtime(&dir_path)?;
+        let cached = cache
+            .as_deref()
+            .and_then(|cache| cache.directories.get(&rel_dir))
+            .filter(|entry| entry.mtime_secs == mtime_secs && entry.mtime_nanos == mtime_nanos)
+            .cloned();
+
+        let listing = match cached {
+            Some(listing) => listing,
+            None => {
+                let listing = read_directory(&dir_path, mtime_secs, mtime_nanos, precompose)?;
+                if let Some(cache) = cache.as_deref_mut() {
+                    cache.directories.insert(rel_dir.clone(), listing.clone());
+                }
+                listing
+            }
+        };
+
+        let join = |name: &str| {
+            if rel_dir.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", rel_dir, name)
+            }
+        };
+        files.extend(listing.files.iter().map(|name| join(name)));
+        pending.extend(listing.subdirs.iter().map(|name| join(name)));
+        seen_dirs.insert(rel_dir);
+    }
+
+    // Forget directories that no longer exist
+    if let Some(cache) = cache {
+        cache.directories.retain(|dir, _| seen_dirs.contains(dir));
+    }
+
+    files.sort();
+    Ok(files)
+}
+
+fn dir_mtime(path: &Path) -> Result<(u64, u32)> {
+    Ok(metadata_mtime(&fs::metadata(path)?))
+}
+
+fn read_directory(
+    path: &Path,
+    mtime_secs: u64,
+    mtime_nanos: u32,
+    precompose: bool,
+) -> Result<CachedDirectory> {
+    let mut files = Vec::new();
+    let mut subdirs = Vec::new();
+
+    for entry in fs::read_dir(path)? {
+        let entry = entry?;
+        let name = repo_path::from_path(Path::new(&entry.file_name()), precompose);
+        let file_type = entry.file_type()?;
+        if file_type.is_dir() {
+            if name != ".mini_git" {
+                subdirs.push(name);
+            }
+        } else if file_type.is_file() {
+            files.push(name);
+        }
+    }
+
+    Ok(CachedDirectory {
+        mtime_secs,
+        mtime_nanos,
+        files,
+        subdirs,
+    })
+}
+
+pub fn record_stat(entry: &mut IndexEntry, file_path: &Path) -> Result<()> {
+    let metadata = fs::metadata(file_path)?;
+    let (secs, nanos) = metadata_mtime(&metadata);
+    entry.mtime_secs = secs;
+    entry.mtime_nanos = nanos;
+    entry.size = metadata.len();
+    Ok(())
+}
+
+// True when the file's size and mtime match what was recorded at staging
+// time, meaning its content doesn't need to be re-hashed
+pub fn stat_matches(entry: &IndexEntry, file_path: &Path) -> bool {
+    if entry.mtime_secs == 0 && entry.mtime_nanos == 0 {
+        return false;
+    }
+
+    match fs::metadata(file_path) {
+        Ok(metadata) => {
+            metadata.len() == entry.size
+                && metadata_mtime(&metadata) == (entry.mtime_secs, entry.mtime_nanos)
+        }
+        Err(_) => false,
+    }
+}
+
+fn metadata_mtime(metadata: &fs::Metadata) -> (u64, u32) {
+    let since_epoch = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(std::time::UNIX_EPOCH).ok())
+        .unwrap_or_default();
+    (since_epoch.as_secs(), since_epoch.subsec_nanos())
+}
+
+// A regular file the current user could run; any file counts where there
+// are no permission bits to go by
+#[cfg(unix)]
+pub fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path).is_ok_and(|meta| meta.is_file() && meta.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,488 @@
+use crate::{
+    Index, IndexEntry, Repository, Result, Tree, TreeEntry,
+    config::Config,
+    ignore::IgnoreRules,
+    object_store::ObjectStore,
+    profile::{self, Phase},
+    repo_path, utils,
+};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+use std::thread;
+
+// Shared helpers for moving content between the index, tree objects and the
+// working directory. Checkouts only ever touch tracked paths: files listed
+// in the current index that the target tree doesn't have are removed, files
+// in the target tree are written, and everything else is left alone.
+//
+// Empty directories are tracked too, as entries with mode DIR_MODE pointing
+// at the empty tree. Such an entry only means "this directory exists"; it is
+// dropped once a file inside the directory is added.
+//
+// Files are written by a pool of `checkout.workers` threads (default: one
+// per CPU) once a checkout touches at least
+// `checkout.thresholdForParallelism` files; smaller ones stay on the calling
+// thread.
+
+const DEFAULT_PARALLEL_THRESHOLD: usize = 100;
+
+pub const DIR_MODE: &str = "40000";
+
+// The entry recording an empty directory at `path`
+pub fn empty_dir_entry(object_store: &ObjectStore, path: &str) -> Result<IndexEntry> {
+    Ok(IndexEntry {
+        hash: object_store.write_tree(HashMap::new())?.hash,
+        mode: DIR_MODE.to_string(),
+        path: path.to_string(),
+        ..Default::default()
+    })
+}
+
+// Entries added with `add -N` have no content staged yet and are left out
+pub fn tree_from_index(object_store: &ObjectStore, index: &Index) -> Result<Tree> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut tree_entries = HashMap::new();
+    for (path, entry) in index.entries.iter().filter(|(_, e)| !e.intent_to_add) {
+        tree_entries.insert(
+            path.clone(),
+            TreeEntry {
+                mode: entry.mode.clone(),
+                hash: entry.hash.clone(),
+                name: path.clone(),
+                is_file: !entry.is_dir(),
+            },
+        );
+    }
+
+    object_store.write_tree(tree_entries)
+}
+
+// Snapshot every file in the working directory, tracked or not, leaving out
+// ignored untracked files
+pub fn tree_from_working_dir(repo: &Repository, object_store: &ObjectStore) -> Result<Tree> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut tree_entries = HashMap::new();
+    let index = utils::load_index(repo)?;
+    let rules = IgnoreRules::load(repo)?;
+
+    for relative_path in utils::list_working_files(repo, None)? {
+        if !index.entries.contains_key(&relative_path) && rules.is_ignored(&relative_path) {
+            continue;
+        }
+        let file_path = repo_path::to_path(&repo.work_dir, &relative_path);
+        let content = fs::read(&file_path)?;
+        let hash = object_store.store_blob(&content)?;
+
+        tree_entries.insert(
+            relative_path.clone(),
+            TreeEntry {
+                mode: file_mode(&file_path)?,
+                hash,
+                name: relative_path,
+                is_file: true,
+            },
+        );
+    }
+
+    object_store.write_tree(tree_entries)
+}
+
+pub fn index_from_tree(tree: &Tree) -> Index {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut index = Index::default();
+
+    for (path, tree_entry) in &tree.entries {
+        index.entries.insert(
+            path.clone(),
+            IndexEntry {
+                hash: tree_entry.hash.clone(),
+                mode: tree_entry.mode.clone(),
+                path: path.clone(),
+                ..Default::default()
+            },
+        );
+    }
+
+    index
+}
+
+pub fn load_commit_tree(object_store: &ObjectStore, commit_hash: &str) -> Result<Tree> {
+    let commit = object_store.load_commit(commit_hash)?;
+    object_store.load_tree(&commit.tree)
+}
+
+// Make the working directory and index match `tree`, discarding local edits
+// to tracked files. Tracked files missing from the tree are deleted (pruning
+// directories left empty); untracked files are never touched.
+pub fn reset_to_tree(repo: &Repository, object_store: &ObjectStore, tree: &Tree) -> Result<()> {
+    update_working_tree(repo, object_store, tree, false)
+}
+
+// Like `reset_to_tree`, but local edits to files whose content is the same in
+// the current index and `tree` are carried over, as when switching branches.
+// Callers should run `check_checkout_safety` first.
+pub fn switch_to_tree(repo: &Repository, object_store: &ObjectStore, tree: &Tree) -> Result<()> {
+    update_working_tree(repo, object_store, tree, true)
+}
+
+fn update_working_tree(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    tree: &Tree,
+    keep_local_changes: bool,
+) -> Result<()> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut old_index = utils::lock_index(repo)?;
+
+    for path in old_index.entries.keys() {
+        if !tree.entries.contains_key(path) {
+            remove_working_file(repo, path)?;
+        }
+    }
+
+    let mut index = Index {
+        untracked_cache: old_index.untracked_cache.take(),
+        ..Default::default()
+    };
+    let mut to_write = Vec::new();
+    for (path, tree_entry) in &tree.entries {
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if !tree_entry.is_file {
+            fs::create_dir_all(&file_path)?;
+            index.entries.insert(
+                path.clone(),
+                IndexEntry {
+                    hash: tree_entry.hash.clone(),
+                    mode: tree_entry.mode.clone(),
+                    path: path.clone(),
+                    ..Default::default()
+                },
+            );
+            continue;
+        }
+
+        let old_entry = old_index
+            .entries
+            .get(path)
+            .filter(|entry| entry.hash == tree_entry.hash && entry.mode == tree_entry.mode);
+
+        if let Some(old_entry) = old_entry
+            && (utils::stat_matches(old_entry, &file_path)
+                || (keep_local_changes && file_path.is_file()))
+        {
+            index.entries.insert(path.clone(), old_entry.clone());
+            continue;
+        }
+
+        to_write.push((path, tree_entry));
+    }
+
+    write_files(repo, object_store, &to_write)?;
+    for (path, tree_entry) in to_write {
+        let mut entry = IndexEntry {
+            hash: tree_entry.hash.clone(),
+            mode: tree_entry.mode.clone(),
+            path: path.clone(),
+            ..Default::default()
+        };
+        utils::record_stat(&mut entry, &repo_path::to_path(&repo.work_dir, path))?;
+        index.entries.insert(path.clone(), entry);
+    }
+
+    *old_index = index;
+    utils::save_locked_index(repo, old_index)
+}
+
+// Write every file of `tree` into the working directory without removing
+// anything or touching the index
+pub fn write_tree_files(repo: &Repository, object_store: &ObjectStore, tree: &Tree) -> Result<()> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let mut files = Vec::new();
+    for (path, entry) in &tree.entries {
+        if entry.is_file {
+            files.push((path, entry));
+        } else {
+            fs::create_dir_all(repo_path::to_path(&repo.work_dir, path))?;
+        }
+    }
+    write_files(repo, object_store, &files)
+}
+
+// Load and write `files`, possibly in parallel. Every file is attempted even
+// when some fail; failures are reported together, sorted by path, so the
+// error doesn't depend on thread scheduling.
+fn write_files(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    files: &[(&String, &TreeEntry)],
+) -> Result<()> {
+    let workers = checkout_workers(repo, files.len())?;
+    let queue = Mutex::new(files.iter());
+    let failures = Mutex::new(Vec::new());
+
+    let work = || {
+        loop {
+            let next = queue.lock().unwrap().next();
+            let Some((path, entry)) = next else {
+                break;
+            };
+            // Box<dyn Error> isn't Send, so errors cross threads as text
+            if let Err(e) = write_file(
+                object_store,
+                &repo_path::to_path(&repo.work_dir, path),
+                entry,
+            ) {
+                failures
+                    .lock()
+                    .unwrap()
+                    .push((path.to_string(), e.to_string()));
+            }
+        }
+    };
+    if workers <= 1 {
+        work();
+    } else {
+        thread::scope(|scope| {
+            for _ in 0..workers {
+                scope.spawn(work);
+            }
+        });
+    }
+
+    let mut failures = failures.into_inner().unwrap();
+    match failures.len() {
+        0 => Ok(()),
+        1 => {
+            let (path, error) = failures.remove(0);
+            Err(format!("Failed to write {}: {}", path, error).into())
+        }
+        count => {
+            failures.sort();
+            let mut message = format!("Failed to write {} files:", count);
+            for (path, error) in failures {
+                message.push_str(&format!("\n\t{}: {}", path, error));
+            }
+            Err(message.into())
+        }
+    }
+}
+
+fn checkout_workers(repo: &Repository, file_count: usize) -> Result<usize> {
+    let config = Config::load(repo)?;
+    let threshold = config
+        .get("checkout.thresholdForParallelism")
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(DEFAULT_PARALLEL_THRESHOLD);
+    if file_count < threshold {
+        return Ok(1);
+    }
+
+    // Like git, a value below one means one worker per CPU
+    let workers = config
+        .get("checkout.workers")
+        .and_then(|value| value.parse::<i64>().ok())
+        .filter(|&workers| workers >= 1)
+        .map(|workers| workers as usize)
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()));
+    Ok(workers.min(file_count))
+}
+
+// Refuse to switch to `target` when that would discard uncommitted edits to
+// tracked files or overwrite untracked files
+pub fn check_checkout_safety(repo: &Repository, target: &Tree) -> Result<()> {
+    let object_store = ObjectStore::new(repo);
+    let index = utils::load_index(repo)?;
+
+    let mut overwritten_tracked = Vec::new();
+    let mut overwritten_untracked = Vec::new();
+
+    for (path, entry) in &index.entries {
+        let changes_in_target = target
+            .entries
+            .get(path)
+            .is_none_or(|target_entry| target_entry.hash != entry.hash);
+        if changes_in_target && is_modified(repo, &object_store, entry)? {
+            overwritten_tracked.push(path.clone());
+        }
+    }
+
+    for (path, target_entry) in &target.entries {
+        if index.entries.contains_key(path) {
+            continue;
+        }
+        let file_path = repo_path::to_path(&repo.work_dir, path);
+        if target_entry.is_file
+            && file_path.is_file()
+            && object_store.hash_content(&fs::read(&file_path)?) != target_entry.hash
+        {
+            overwritten_untracked.push(path.clone());
+        }
+    }
+
+    if overwritten_tracked.is_empty() && overwritten_untracked.is_empty() {
+        return Ok(());
+    }
+
+    overwritten_tracked.sort();
+    overwritten_untracked.sort();
+    let mut message = String::new();
+    if !overwritten_tracked.is_empty() {
+        message.push_str("Your local changes to the following files would be overwritten:\n");
+        for path in &overwritten_tracked {
+            message.push_str(&format!("\t{}\n", path));
+        }
+    }
+    if !overwritten_untracked.is_empty() {
+        message.push_str("The following untracked working tree files would be overwritten:\n");
+        for path in &overwritten_untracked {
+            message.push_str(&format!("\t{}\n", path));
+        }
+    }
+    message.push_str("Please commit your changes or stash them before you switch branches.");
+    Err(message.into())
+}
+
+// Whether there is work a command replacing the index and working tree
+// would lose: staged changes, edits to tracked files and, with
+// `include_untracked`, untracked files that aren't ignored
+pub fn has_local_changes(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    index: &Index,
+    include_untracked: bool,
+) -> Result<bool> {
+    let head_entries = match utils::get_head_commit(repo)? {
+        Some(head) => load_commit_tree(object_store, &head)?.entries,
+        None => HashMap::new(),
+    };
+    let staged = head_entries
+        .keys()
+        .any(|path| !index.entries.contains_key(path))
+        || index.entries.iter().any(|(path, entry)| {
+            head_entries
+                .get(path)
+                .is_none_or(|head_entry| head_entry.hash != entry.hash)
+        });
+    if staged || !modified_files(repo, index)?.is_empty() {
+        return Ok(true);
+    }
+
+    Ok(include_untracked && !crate::ignore::untracked_files(repo, index)?.is_empty())
+}
+
+// Tracked files whose working copy differs from the index (including deleted ones)
+pub fn modified_files(repo: &Repository, index: &Index) -> Result<Vec<String>> {
+    let _span = profile::span(Phase::TreeTraversal);
+    let object_store = ObjectStore::new(repo);
+    let mut modified = Vec::new();
+    for (path, entry) in &index.entries {
+        if is_modified(repo, &object_store, entry)? {
+            modified.push(path.clone());
+        }
+    }
+    modified.sort();
+    Ok(modified)
+}
+
+pub fn is_modified(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    entry: &IndexEntry,
+) -> Result<bool> {
+    if entry.ignores_worktree() {
+        return Ok(false);
+    }
+    let file_path = repo_path::to_path(&repo.work_dir, &entry.path);
+    if entry.is_dir() {
+        return Ok(!file_path.is_dir());
+    }
+    if !file_path.is_file() {
+        return Ok(true);
+    }
+    if utils::stat_matches(entry, &file_path) {
+        return Ok(false);
+    }
+    Ok(object_store.hash_content(&fs::read(&file_path)?) != entry.hash)
+}
+
+pub fn remove_working_file(repo: &Repository, path: &str) -> Result<()> {
+    let file_path = repo_path::to_path(&repo.work_dir, path);
+    if file_path.is_file() {
+        fs::remove_file(&file_path)?;
+    }
+
+    // Prune directories the removal left empty, stopping at the work tree
+    // root. A tracked empty directory is removed the same way.
+    let mut dir = if file_path.is_dir() {
+        Some(file_path.as_path())
+    } else {
+        file_path.parent()
+    };
+    while let Some(current) = dir {
+        if current == repo.work_dir || fs::remove_dir(current).is_err() {
+            break;
+        }
+        dir = current.parent();
+    }
+    Ok(())
+}
+
+// Write one blob to `path` in the working tree, as checkout would
+pub fn write_blob(
+    repo: &Repository,
+    object_store: &ObjectStore,
+    path: &str,
+    hash: &str,
+    mode: &str,
+) -> Result<()> {
+    let entry = TreeEntry {
+        mode: mode.to_string(),
+        hash: hash.to_string(),
+        name: path.to_string(),
+        is_file: true,
+    };
+    write_file(
+        object_store,
+        &repo_path::to_path(&repo.work_dir, path),
+        &entry,
+    )
+}
+
+fn write_file(object_store: &ObjectStore, file_path: &Path, entry: &TreeEntry) -> Result<()> {
+    let blob = object_store.load_blob(&entry.hash)?;
+
+    if let Some(parent) = file_path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(file_path, &blob.content)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = fs::metadata(file_path)?.permissions();
+        let mode = if entry.mode == "100755" { 0o755 } else { 0o644 };
+        if permissions.mode() & 0o777 != mode {
+            permissions.set_mode(mode);
+            fs::set_permissions(file_path, permissions)?;
+        }
+    }
+
+    Ok(())
+}
+
+// Index mode for a working tree file: 100755 if executable, else 100644
+pub fn file_mode(file_path: &Path) -> Result<String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if fs::metadata(file_path)?.permissions().mode() & 0o111 != 0 {
+            return Ok("100755".to_string());
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = file_path;
+
+    Ok("100644".to_string())
+}